@@ -0,0 +1,18 @@
+-- The tables the application started out with, so a fresh database can be built from the
+-- migrations alone. Existing databases already have them.
+CREATE TABLE IF NOT EXISTS links (
+    id TEXT PRIMARY KEY,
+    target_url TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS link_statistics (
+    id SERIAL PRIMARY KEY,
+    link_id TEXT NOT NULL REFERENCES links(id),
+    referer TEXT,
+    user_agent TEXT
+);
+
+CREATE TABLE IF NOT EXISTS settings (
+    id TEXT PRIMARY KEY,
+    encrypted_global_api_key TEXT NOT NULL
+);
@@ -0,0 +1,21 @@
+ALTER TABLE link_statistics
+    ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS link_statistics_link_id_created_at_idx
+    ON link_statistics (link_id, created_at);
+
+CREATE TABLE IF NOT EXISTS campaigns (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS campaign_links (
+    campaign_id TEXT NOT NULL REFERENCES campaigns (id) ON DELETE CASCADE,
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    PRIMARY KEY (campaign_id, link_id)
+);
+
+CREATE INDEX IF NOT EXISTS campaign_links_link_id_idx ON campaign_links (link_id);
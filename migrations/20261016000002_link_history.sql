@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS link_history (
+    id BIGSERIAL PRIMARY KEY,
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    old_target_url TEXT NOT NULL,
+    new_target_url TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS link_history_link_id_changed_at_idx
+    ON link_history (link_id, changed_at DESC);
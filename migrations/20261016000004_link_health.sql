@@ -0,0 +1,12 @@
+ALTER TABLE links ADD COLUMN IF NOT EXISTS active BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE IF NOT EXISTS link_health (
+    link_id TEXT PRIMARY KEY REFERENCES links (id) ON DELETE CASCADE,
+    status_code INTEGER,
+    error TEXT,
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_success_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS link_health_checked_at_idx ON link_health (checked_at);
@@ -0,0 +1,6 @@
+ALTER TABLE links ADD COLUMN IF NOT EXISTS click_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE links l
+SET click_count = s.clicks
+FROM (SELECT link_id, COUNT(*) AS clicks FROM link_statistics GROUP BY link_id) s
+WHERE s.link_id = l.id;
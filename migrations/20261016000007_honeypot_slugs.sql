@@ -0,0 +1,6 @@
+-- Slugs that are never handed out. Hits on them come from clients guessing ids.
+CREATE TABLE IF NOT EXISTS honeypot_slugs (
+    slug TEXT PRIMARY KEY,
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
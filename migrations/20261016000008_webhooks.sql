@@ -0,0 +1,36 @@
+ALTER TABLE links ADD COLUMN IF NOT EXISTS workspace_id TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS links_workspace_id_idx ON links (workspace_id);
+
+-- Deleting a link removes its recorded clicks as well.
+ALTER TABLE link_statistics
+    DROP CONSTRAINT IF EXISTS link_statistics_link_id_fkey,
+    ADD CONSTRAINT link_statistics_link_id_fkey
+        FOREIGN KEY (link_id) REFERENCES links (id) ON DELETE CASCADE;
+
+-- An empty events array subscribes the endpoint to every event type.
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id TEXT PRIMARY KEY,
+    workspace_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_endpoints_workspace_id_idx ON webhook_endpoints (workspace_id);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    endpoint_id TEXT NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_endpoint_id_created_at_idx
+    ON webhook_deliveries (endpoint_id, created_at DESC);
@@ -0,0 +1,4 @@
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS redirect_type INTEGER NOT NULL DEFAULT 307
+        CHECK (redirect_type IN (301, 302, 307, 308));
@@ -0,0 +1,2 @@
+-- Links in privacy mode only count clicks; no referer or user agent is kept for them.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS privacy_mode BOOLEAN NOT NULL DEFAULT false;
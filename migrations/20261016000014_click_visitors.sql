@@ -0,0 +1,6 @@
+-- Salted hash of the visitor's IP and user agent. The salt changes daily and is never stored,
+-- so hashes only match within the same day.
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS visitor_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS link_statistics_link_id_created_at_idx
+    ON link_statistics (link_id, created_at);
@@ -0,0 +1,2 @@
+-- Links that do not track clicks skip the link_statistics insert on redirect.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS track_clicks BOOLEAN NOT NULL DEFAULT true;
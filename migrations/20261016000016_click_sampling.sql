@@ -0,0 +1,6 @@
+-- Hot links may record only one in `sample_rate` clicks; every stored click then stands for
+-- `weight` clicks.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS sample_rate INTEGER NOT NULL DEFAULT 1 CHECK (sample_rate >= 1);
+
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS weight INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,7 @@
+-- Set by the expiry sweeper once a link's expiry has been processed.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS expired_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS links_pending_expiry_idx
+    ON links (expires_at) WHERE expires_at IS NOT NULL AND expired_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS links_expired_at_idx ON links (expired_at) WHERE expired_at IS NOT NULL;
@@ -0,0 +1,32 @@
+ALTER TABLE links ADD COLUMN IF NOT EXISTS last_clicked_at TIMESTAMPTZ;
+
+UPDATE links l
+SET last_clicked_at = s.last_clicked_at
+FROM (
+    SELECT link_id, max(created_at) AS last_clicked_at FROM link_statistics GROUP BY link_id
+) s
+WHERE s.link_id = l.id AND l.last_clicked_at IS NULL;
+
+-- Cold copies of links and their clicks and history, outside of the redirect path. Columns
+-- added to the originals have to be added here as well.
+CREATE TABLE IF NOT EXISTS archived_links (
+    LIKE links INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+    campaign_ids TEXT[] NOT NULL DEFAULT '{}',
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS archived_link_statistics (
+    LIKE link_statistics INCLUDING DEFAULTS,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS archived_link_statistics_link_id_idx
+    ON archived_link_statistics (link_id);
+
+CREATE TABLE IF NOT EXISTS archived_link_history (
+    LIKE link_history INCLUDING DEFAULTS,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS archived_link_history_link_id_idx ON archived_link_history (link_id);
@@ -0,0 +1,3 @@
+-- Set while a link's statistics are shared publicly under /:id/stats/:token.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS stats_token TEXT;
+ALTER TABLE archived_links ADD COLUMN IF NOT EXISTS stats_token TEXT;
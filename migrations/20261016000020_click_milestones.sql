@@ -0,0 +1,11 @@
+-- Click counts to announce for a link (the configured defaults when empty) and those already
+-- announced.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS click_milestones BIGINT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS sent_milestones BIGINT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE archived_links
+    ADD COLUMN IF NOT EXISTS click_milestones BIGINT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS sent_milestones BIGINT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS links_click_count_idx ON links (click_count);
@@ -0,0 +1,9 @@
+-- Requests per API key (identified by a prefix of its hash), actor and endpoint per UTC day.
+CREATE TABLE IF NOT EXISTS api_usage (
+    day DATE NOT NULL,
+    key_id TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, key_id, actor, endpoint)
+);
@@ -0,0 +1,12 @@
+-- Human-readable link titles, typed in or fetched from the target's <title>. title_checked_at is
+-- set once the fetcher has looked at a link, whether or not it found a title.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS title TEXT,
+    ADD COLUMN IF NOT EXISTS title_checked_at TIMESTAMPTZ;
+
+ALTER TABLE archived_links
+    ADD COLUMN IF NOT EXISTS title TEXT,
+    ADD COLUMN IF NOT EXISTS title_checked_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS links_title_pending_idx ON links (created_at)
+    WHERE title IS NULL AND title_checked_at IS NULL;
@@ -0,0 +1,10 @@
+-- Preferred language (from Accept-Language) and User-Agent client hints of each click.
+ALTER TABLE link_statistics
+    ADD COLUMN IF NOT EXISTS language TEXT,
+    ADD COLUMN IF NOT EXISTS platform TEXT,
+    ADD COLUMN IF NOT EXISTS mobile BOOLEAN;
+
+ALTER TABLE archived_link_statistics
+    ADD COLUMN IF NOT EXISTS language TEXT,
+    ADD COLUMN IF NOT EXISTS platform TEXT,
+    ADD COLUMN IF NOT EXISTS mobile BOOLEAN;
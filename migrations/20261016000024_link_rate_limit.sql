@@ -0,0 +1,6 @@
+-- Redirects per second a link may serve (per instance); unlimited when NULL.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS rate_limit INT CHECK (rate_limit > 0);
+
+ALTER TABLE archived_links
+    ADD COLUMN IF NOT EXISTS rate_limit INT;
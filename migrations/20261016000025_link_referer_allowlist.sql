@@ -0,0 +1,9 @@
+-- Hotlink protection: when allowed_referers is not empty, only visitors coming from these
+-- domains (or their subdomains) are redirected, others are sent to referer_fallback_url.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS allowed_referers TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS referer_fallback_url TEXT;
+
+ALTER TABLE archived_links
+    ADD COLUMN IF NOT EXISTS allowed_referers TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS referer_fallback_url TEXT;
@@ -0,0 +1,2 @@
+-- Blocks of sequential link ids; every instance using ID_STRATEGY=sequential takes one at startup.
+CREATE SEQUENCE IF NOT EXISTS link_id_blocks;
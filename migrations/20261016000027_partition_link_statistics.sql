@@ -0,0 +1,52 @@
+-- Clicks are partitioned by month of created_at, so clicks past the retention are dropped a whole
+-- partition at a time instead of deleted row by row. The clicks recorded so far become a single
+-- partition covering everything before next month; monthly ones follow it.
+ALTER TABLE link_statistics RENAME TO link_statistics_legacy;
+-- Partitions need the partition key in their primary key.
+ALTER TABLE link_statistics_legacy
+    DROP CONSTRAINT link_statistics_pkey,
+    ADD CONSTRAINT link_statistics_legacy_pkey PRIMARY KEY (id, created_at);
+ALTER INDEX link_statistics_link_id_created_at_idx RENAME TO link_statistics_legacy_link_id_created_at_idx;
+ALTER TABLE link_statistics_legacy
+    RENAME CONSTRAINT link_statistics_link_id_fkey TO link_statistics_legacy_link_id_fkey;
+
+CREATE TABLE link_statistics (
+    id INTEGER NOT NULL DEFAULT nextval('link_statistics_id_seq'),
+    link_id TEXT NOT NULL REFERENCES links(id) ON DELETE CASCADE,
+    referer TEXT,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    visitor_hash TEXT,
+    weight INTEGER NOT NULL DEFAULT 1,
+    language TEXT,
+    platform TEXT,
+    mobile BOOLEAN,
+    PRIMARY KEY (id, created_at)
+) PARTITION BY RANGE (created_at);
+
+ALTER SEQUENCE link_statistics_id_seq OWNED BY link_statistics.id;
+ALTER TABLE link_statistics_legacy ALTER COLUMN id SET DEFAULT nextval('link_statistics_id_seq');
+
+CREATE INDEX link_statistics_link_id_created_at_idx ON link_statistics (link_id, created_at);
+
+DO $$
+DECLARE
+    next_month TIMESTAMPTZ := date_trunc('month', now() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+        + INTERVAL '1 month';
+    month_start TIMESTAMPTZ;
+BEGIN
+    EXECUTE format(
+        'ALTER TABLE link_statistics ATTACH PARTITION link_statistics_legacy FOR VALUES FROM (MINVALUE) TO (%L)',
+        next_month
+    );
+    -- The maintenance job creates further ones; these cover the time until it first runs.
+    FOR i IN 0..1 LOOP
+        month_start := next_month + make_interval(months => i);
+        EXECUTE format(
+            'CREATE TABLE link_statistics_p%s PARTITION OF link_statistics FOR VALUES FROM (%L) TO (%L)',
+            to_char(month_start AT TIME ZONE 'UTC', 'YYYYMM'),
+            month_start,
+            month_start + INTERVAL '1 month'
+        );
+    END LOOP;
+END $$;
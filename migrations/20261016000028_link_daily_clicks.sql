@@ -0,0 +1,12 @@
+-- Clicks and unique visitors per link and UTC day, rolled up from link_statistics. Kept when the
+-- raw clicks are pruned. Recent days are rolled up continuously, older ones through
+-- POST /admin/jobs/rollups.
+CREATE TABLE IF NOT EXISTS link_daily_clicks (
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    clicks BIGINT NOT NULL,
+    unique_visitors BIGINT NOT NULL,
+    PRIMARY KEY (link_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS link_daily_clicks_day_idx ON link_daily_clicks (day);
@@ -0,0 +1,12 @@
+-- Housekeeping started through the admin API, polled until it finishes.
+CREATE TABLE IF NOT EXISTS maintenance_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    params JSONB NOT NULL DEFAULT '{}',
+    status TEXT NOT NULL DEFAULT 'running',
+    result JSONB,
+    error TEXT,
+    actor TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
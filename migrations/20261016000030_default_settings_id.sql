@@ -0,0 +1,7 @@
+-- The global API key used to be looked up under the misspelled id DEFUALT_SETTINGS. The old row
+-- is kept for instances still running the old lookup during a rollout; delete it afterwards.
+INSERT INTO settings (id, encrypted_global_api_key)
+SELECT 'DEFAULT_SETTINGS', encrypted_global_api_key
+FROM settings
+WHERE id = 'DEFUALT_SETTINGS'
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,6 @@
+-- Extra headers sent with a link's redirects, as `name: value` lines with lowercase names.
+ALTER TABLE links
+    ADD COLUMN IF NOT EXISTS response_headers TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE archived_links
+    ADD COLUMN IF NOT EXISTS response_headers TEXT[] NOT NULL DEFAULT '{}';
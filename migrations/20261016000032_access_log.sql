@@ -0,0 +1,12 @@
+-- Every request served, kept when ACCESS_LOG=database for deployments that must keep records.
+CREATE TABLE IF NOT EXISTS access_log (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    client_ip TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS access_log_created_at_idx ON access_log (created_at);
@@ -0,0 +1,11 @@
+-- Clicks per link, UTC day and referring host, rolled up from link_statistics along with
+-- link_daily_clicks. Clicks without a referer (or one without a host) are counted under ''.
+CREATE TABLE IF NOT EXISTS link_daily_referers (
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    referer_host TEXT NOT NULL,
+    clicks BIGINT NOT NULL,
+    PRIMARY KEY (link_id, day, referer_host)
+);
+
+CREATE INDEX IF NOT EXISTS link_daily_referers_day_idx ON link_daily_referers (day);
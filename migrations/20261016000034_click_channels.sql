@@ -0,0 +1,4 @@
+-- How a click reached the short URL, from its `src` query parameter: `qr` for scans of the
+-- generated QR codes, `direct` when none was given.
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS channel TEXT NOT NULL DEFAULT 'direct';
+ALTER TABLE archived_link_statistics ADD COLUMN IF NOT EXISTS channel TEXT NOT NULL DEFAULT 'direct';
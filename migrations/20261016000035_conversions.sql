@@ -0,0 +1,19 @@
+-- Conversions reported by destinations through POST /api/conversions, one per click token so
+-- reporting a click again changes nothing.
+CREATE TABLE IF NOT EXISTS conversions (
+    token TEXT PRIMARY KEY,
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    clicked_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS conversions_link_id_idx ON conversions (link_id);
+
+CREATE TABLE IF NOT EXISTS archived_conversions (
+    token TEXT PRIMARY KEY,
+    link_id TEXT NOT NULL,
+    clicked_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS archived_conversions_link_id_idx ON archived_conversions (link_id);
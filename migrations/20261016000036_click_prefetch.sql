@@ -0,0 +1,4 @@
+-- Hits by email scanners and browser prefetches rather than people. They are kept apart from
+-- clicks in statistics, rollups and click counters.
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS prefetch BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE archived_link_statistics ADD COLUMN IF NOT EXISTS prefetch BOOLEAN NOT NULL DEFAULT false;
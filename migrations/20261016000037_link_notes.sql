@@ -0,0 +1,8 @@
+-- Free-text notes on a link and who created it, so teams know whom to ask before editing it.
+-- Links created before this have no creator.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS notes TEXT;
+ALTER TABLE links ADD COLUMN IF NOT EXISTS created_by TEXT;
+ALTER TABLE archived_links ADD COLUMN IF NOT EXISTS notes TEXT;
+ALTER TABLE archived_links ADD COLUMN IF NOT EXISTS created_by TEXT;
+
+CREATE INDEX IF NOT EXISTS links_workspace_created_by_idx ON links (workspace_id, created_by);
@@ -0,0 +1,78 @@
+-- Rollups serving the statistics endpoints, so they read recorded clicks of the last
+-- STATISTICS_RAW_WINDOW_HOURS only. Rolled up along with link_daily_clicks.
+ALTER TABLE link_daily_clicks ADD COLUMN IF NOT EXISTS prefetches BIGINT NOT NULL DEFAULT 0;
+
+-- Clicks per link and UTC hour, without prefetches.
+CREATE TABLE IF NOT EXISTS link_hourly_clicks (
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    hour TIMESTAMPTZ NOT NULL,
+    clicks BIGINT NOT NULL,
+    PRIMARY KEY (link_id, hour)
+);
+
+-- Clicks and prefetches per link, UTC day, referer and user agent. A missing referer or user
+-- agent is stored as ''. Both can be long, so uniqueness is enforced on their hashes.
+CREATE TABLE IF NOT EXISTS link_daily_sources (
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    referer TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    clicks BIGINT NOT NULL,
+    prefetches BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS link_daily_sources_key_idx
+    ON link_daily_sources (link_id, day, md5(referer), md5(user_agent));
+CREATE INDEX IF NOT EXISTS link_daily_sources_day_idx ON link_daily_sources (day);
+
+-- Clicks per link, UTC day and value of a breakdown dimension (language, platform, device,
+-- channel), without prefetches.
+CREATE TABLE IF NOT EXISTS link_daily_breakdown (
+    link_id TEXT NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    dimension TEXT NOT NULL,
+    value TEXT NOT NULL,
+    clicks BIGINT NOT NULL,
+    PRIMARY KEY (link_id, day, dimension, value)
+);
+
+-- Roll up the clicks recorded so far, which the endpoints stop reading past the window.
+UPDATE link_daily_clicks d
+SET prefetches = p.prefetches
+FROM (
+    SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE AS day, SUM(weight) AS prefetches
+    FROM link_statistics
+    WHERE prefetch
+    GROUP BY 1, 2
+) p
+WHERE d.link_id = p.link_id AND d.day = p.day;
+
+INSERT INTO link_hourly_clicks (link_id, hour, clicks)
+SELECT link_id, date_trunc('hour', created_at), SUM(weight)
+FROM link_statistics
+WHERE NOT prefetch
+GROUP BY 1, 2
+ON CONFLICT DO NOTHING;
+
+INSERT INTO link_daily_sources (link_id, day, referer, user_agent, clicks, prefetches)
+SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE, COALESCE(referer, ''),
+    COALESCE(user_agent, ''),
+    COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+    COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+FROM link_statistics
+GROUP BY 1, 2, 3, 4
+ON CONFLICT DO NOTHING;
+
+INSERT INTO link_daily_breakdown (link_id, day, dimension, value, clicks)
+SELECT s.link_id, (s.created_at AT TIME ZONE 'UTC')::DATE, d.dimension, d.value, SUM(s.weight)
+FROM link_statistics s
+CROSS JOIN LATERAL (
+    VALUES
+        ('language', COALESCE(s.language, 'unknown')),
+        ('platform', COALESCE(s.platform, 'unknown')),
+        ('device', CASE s.mobile WHEN true THEN 'mobile' WHEN false THEN 'desktop' ELSE 'unknown' END),
+        ('channel', s.channel)
+) AS d (dimension, value)
+WHERE NOT s.prefetch
+GROUP BY 1, 2, 3, 4
+ON CONFLICT DO NOTHING;
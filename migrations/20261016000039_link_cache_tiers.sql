@@ -0,0 +1,4 @@
+-- Named caching tier of a link's redirects, from CACHE_TIERS. Without one redirects are cached
+-- for REDIRECT_CACHE_MAX_AGE_SECS.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS cache_tier TEXT;
+ALTER TABLE archived_links ADD COLUMN IF NOT EXISTS cache_tier TEXT;
@@ -0,0 +1,7 @@
+-- Settings a workspace chooses for itself. Workspaces without a row use the defaults.
+CREATE TABLE IF NOT EXISTS workspace_settings (
+    workspace_id TEXT PRIMARY KEY,
+    -- Off for workspaces whose compliance rules forbid processing visitor addresses.
+    geo_enrichment BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+-- Weekly email summaries workspaces opt into.
+ALTER TABLE workspace_settings ADD COLUMN IF NOT EXISTS weekly_report BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE workspace_settings ADD COLUMN IF NOT EXISTS report_recipients TEXT[] NOT NULL DEFAULT '{}';
+-- Monday of the last week reported, so each week is reported once.
+ALTER TABLE workspace_settings ADD COLUMN IF NOT EXISTS report_sent_for DATE;
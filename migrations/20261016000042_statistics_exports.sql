@@ -0,0 +1,7 @@
+-- Days of clicks uploaded to the statistics export bucket, and where to.
+CREATE TABLE IF NOT EXISTS statistics_exports (
+    day DATE PRIMARY KEY,
+    object_key TEXT NOT NULL,
+    clicks BIGINT NOT NULL,
+    exported_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,21 @@
+-- Target changes rolled out gradually: `percent` of the link's redirects go to `target_url`, the
+-- rest to the link's current target, until `ends_at` makes it the link's target.
+CREATE TABLE IF NOT EXISTS link_rollouts (
+    link_id TEXT PRIMARY KEY REFERENCES links (id) ON DELETE CASCADE,
+    target_url TEXT NOT NULL,
+    percent INTEGER NOT NULL CHECK (percent BETWEEN 1 AND 99),
+    started_by TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ends_at TIMESTAMPTZ NOT NULL,
+    -- Probes of both targets while the rollout runs, to compare their error rates.
+    stable_checks INTEGER NOT NULL DEFAULT 0,
+    stable_failures INTEGER NOT NULL DEFAULT 0,
+    canary_checks INTEGER NOT NULL DEFAULT 0,
+    canary_failures INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS link_rollouts_ends_at_idx ON link_rollouts (ends_at);
+
+-- Which target of a rollout a click was sent to, `stable` or `canary`; NULL outside rollouts.
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS variant TEXT;
+ALTER TABLE archived_link_statistics ADD COLUMN IF NOT EXISTS variant TEXT;
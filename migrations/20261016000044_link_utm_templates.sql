@@ -0,0 +1,4 @@
+-- The UTM parameters a link's redirects carry, as `name=value` lines. When set, any other utm_*
+-- parameters of the destination are dropped; NULL leaves them alone.
+ALTER TABLE links ADD COLUMN IF NOT EXISTS utm_template TEXT[];
+ALTER TABLE archived_links ADD COLUMN IF NOT EXISTS utm_template TEXT[];
@@ -0,0 +1,7 @@
+-- The identifier appended to the destination of a recorded click as `CLICK_ID_PARAM`, for
+-- destinations to join their own data back to it. NULL while click IDs are off.
+ALTER TABLE link_statistics ADD COLUMN IF NOT EXISTS click_id TEXT;
+ALTER TABLE archived_link_statistics ADD COLUMN IF NOT EXISTS click_id TEXT;
+
+CREATE INDEX IF NOT EXISTS link_statistics_click_id_idx
+    ON link_statistics (click_id) WHERE click_id IS NOT NULL;
@@ -0,0 +1,10 @@
+-- Branded pages answered in place of the built-in ones when a redirect on `domain` fails with
+-- `status`.
+CREATE TABLE IF NOT EXISTS domain_error_pages (
+    domain TEXT NOT NULL,
+    status INTEGER NOT NULL CHECK (status IN (404, 410, 429)),
+    html TEXT NOT NULL,
+    updated_by TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (domain, status)
+);
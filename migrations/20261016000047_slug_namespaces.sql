@@ -0,0 +1,8 @@
+-- First path segments reserved by a workspace: only its members create links under them, like
+-- `eng/launch`, served at `/eng/launch`.
+CREATE TABLE IF NOT EXISTS slug_namespaces (
+    prefix TEXT PRIMARY KEY,
+    workspace_id TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
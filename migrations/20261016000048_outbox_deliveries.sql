@@ -0,0 +1,20 @@
+-- What dispatched outbox events still have to do: a post to every subscribed webhook endpoint,
+-- notifications of created links and CDN purges of changed ones. Each is retried from here until
+-- it succeeds or its attempts are used up, so a restart in between loses none of them.
+CREATE TABLE IF NOT EXISTS outbox_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL CHECK (kind IN ('webhook', 'notification', 'cdn_purge')),
+    -- Webhook endpoint id or notification channel.
+    target TEXT,
+    event_id TEXT,
+    event_type TEXT,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_deliveries_due_idx
+    ON outbox_deliveries (next_attempt_at) WHERE finished_at IS NULL;
+CREATE INDEX IF NOT EXISTS outbox_deliveries_finished_at_idx ON outbox_deliveries (finished_at);
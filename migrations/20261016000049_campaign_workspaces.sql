@@ -0,0 +1,13 @@
+ALTER TABLE campaigns ADD COLUMN IF NOT EXISTS workspace_id TEXT NOT NULL DEFAULT 'default';
+
+UPDATE campaigns c
+SET workspace_id = l.workspace_id
+FROM (
+    SELECT DISTINCT ON (cl.campaign_id) cl.campaign_id, l.workspace_id
+    FROM campaign_links cl
+    JOIN links l ON l.id = cl.link_id
+    ORDER BY cl.campaign_id, cl.link_id
+) l
+WHERE l.campaign_id = c.id;
+
+CREATE INDEX IF NOT EXISTS campaigns_workspace_id_idx ON campaigns (workspace_id);
@@ -0,0 +1,243 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    client_ip::ClientIp,
+    config::{AccessLogConfig, SharedConfig},
+};
+
+/// Requests kept in memory while the sink is failing, before new ones are dropped.
+const MAX_PENDING: usize = 100_000;
+
+/// Where requests are recorded, from `ACCESS_LOG`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogSink {
+    /// JSON lines appended to `ACCESS_LOG_PATH`, rotated at `ACCESS_LOG_MAX_BYTES`.
+    File,
+    /// The `access_log` table, pruned after `ACCESS_LOG_RETENTION_DAYS`.
+    Database,
+    None,
+}
+
+impl FromStr for AccessLogSink {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "file" => Ok(Self::File),
+            "database" => Ok(Self::Database),
+            "none" => Ok(Self::None),
+            _ => Err("expected file, database or none".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogEntry {
+    pub time: DateTime<Utc>,
+    pub method: String,
+    /// Without the query string, which may carry tokens.
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: i32,
+    pub client_ip: String,
+}
+
+/// Buffers served requests and writes them in batches, independent of what tracing keeps.
+#[derive(Debug, Default)]
+pub struct AccessLog {
+    pending: Mutex<Vec<AccessLogEntry>>,
+}
+
+impl AccessLog {
+    pub fn record(&self, entry: AccessLogEntry) {
+        let mut pending = self.pending.lock().expect("Access log lock poisoned");
+        if pending.len() >= MAX_PENDING {
+            counter!("access_log_dropped").increment(1);
+            return;
+        }
+        pending.push(entry);
+    }
+
+    /// Writes the buffered requests to the configured sink. A batch that fails is put back to be
+    /// written with the next flush.
+    pub async fn flush(&self, pool: &PgPool, config: &AccessLogConfig) -> Result<(), String> {
+        let batch = std::mem::take(&mut *self.pending.lock().expect("Access log lock poisoned"));
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let written = match config.sink {
+            AccessLogSink::File => write_file(&batch, config)
+                .await
+                .map_err(|err| err.to_string()),
+            AccessLogSink::Database => write_rows(pool, &batch)
+                .await
+                .map_err(|err| err.to_string()),
+            AccessLogSink::None => Ok(()),
+        };
+        if written.is_err() {
+            let mut pending = self.pending.lock().expect("Access log lock poisoned");
+            let room = MAX_PENDING.saturating_sub(pending.len());
+            pending.splice(..0, batch.into_iter().take(room));
+        }
+        written
+    }
+}
+
+/// Records every request when `ACCESS_LOG` names a sink.
+pub async fn log_access(
+    State(log): State<Arc<AccessLog>>,
+    State(config): State<SharedConfig>,
+    ClientIp(client): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    if config.current().access_log.sink == AccessLogSink::None {
+        return next.run(req).await;
+    }
+    let started = Instant::now();
+    let time = Utc::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let response = next.run(req).await;
+    log.record(AccessLogEntry {
+        time,
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: i32::try_from(started.elapsed().as_millis()).unwrap_or(i32::MAX),
+        client_ip: client.to_string(),
+    });
+    response
+}
+
+/// `path.1` for `path`, the rotated files counting up from the newest.
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+/// Appends the batch as JSON lines, first rotating the file when it would grow past
+/// `ACCESS_LOG_MAX_BYTES`. `ACCESS_LOG_MAX_FILES` rotated files are kept.
+async fn write_file(batch: &[AccessLogEntry], config: &AccessLogConfig) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for entry in batch {
+        lines.push_str(&serde_json::to_string(entry).expect("Access log entries always serialize"));
+        lines.push('\n');
+    }
+    let path = Path::new(&config.path);
+    let size = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err),
+    };
+    if size > 0 && size + lines.len() as u64 > config.max_bytes {
+        if config.max_files == 0 {
+            tokio::fs::remove_file(path).await?;
+        } else {
+            for index in (1..config.max_files).rev() {
+                match tokio::fs::rename(rotated(path, index), rotated(path, index + 1)).await {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            tokio::fs::rename(path, rotated(path, 1)).await?;
+        }
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(lines.as_bytes()).await?;
+    file.flush().await
+}
+
+async fn write_rows(pool: &PgPool, batch: &[AccessLogEntry]) -> Result<(), sqlx::Error> {
+    let mut times = Vec::with_capacity(batch.len());
+    let mut methods = Vec::with_capacity(batch.len());
+    let mut paths = Vec::with_capacity(batch.len());
+    let mut statuses = Vec::with_capacity(batch.len());
+    let mut latencies = Vec::with_capacity(batch.len());
+    let mut client_ips = Vec::with_capacity(batch.len());
+    for entry in batch {
+        times.push(entry.time);
+        methods.push(entry.method.clone());
+        paths.push(entry.path.clone());
+        statuses.push(entry.status as i16);
+        latencies.push(entry.latency_ms);
+        client_ips.push(entry.client_ip.clone());
+    }
+    sqlx::query!(
+        r#"
+            INSERT INTO access_log (created_at, method, path, status, latency_ms, client_ip)
+            SELECT * FROM UNNEST($1::TIMESTAMPTZ[], $2::TEXT[], $3::TEXT[], $4::SMALLINT[], $5::INTEGER[], $6::TEXT[])
+        "#,
+        &times,
+        &methods,
+        &paths,
+        &statuses,
+        &latencies,
+        &client_ips
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Deletes rows of the `access_log` table past `ACCESS_LOG_RETENTION_DAYS`.
+async fn prune(pool: &PgPool, config: &AccessLogConfig) -> Result<u64, sqlx::Error> {
+    let Some(retention) = config.retention else {
+        return Ok(0);
+    };
+    let deleted = sqlx::query!(
+        "DELETE FROM access_log WHERE created_at < now() - make_interval(secs => $1)",
+        retention.as_secs_f64()
+    )
+    .execute(pool)
+    .await?;
+    Ok(deleted.rows_affected())
+}
+
+/// Writes the buffered requests every `ACCESS_LOG_FLUSH_INTERVAL_MS`, and prunes the table
+/// about once an hour.
+pub fn spawn(pool: PgPool, config: SharedConfig, log: Arc<AccessLog>) {
+    tokio::spawn(async move {
+        let mut pruned_at: Option<Instant> = None;
+        loop {
+            let access_log = config.current().access_log.clone();
+            tokio::time::sleep(access_log.flush_interval).await;
+            if let Err(err) = log.flush(&pool, &access_log).await {
+                tracing::error!("Writing the access log failed: {}", err);
+            }
+            if access_log.sink != AccessLogSink::Database
+                || pruned_at.is_some_and(|at| at.elapsed().as_secs() < 3600)
+            {
+                continue;
+            }
+            pruned_at = Some(Instant::now());
+            match prune(&pool, &access_log).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!("Pruned {} access log entries", deleted),
+                Err(err) => tracing::error!("Pruning the access log failed: {}", err),
+            }
+        }
+    });
+}
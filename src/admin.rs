@@ -0,0 +1,33 @@
+use axum::{extract::State, http::StatusCode};
+use sqlx::PgPool;
+
+use crate::{config::SharedConfig, utils::internal_error};
+
+pub async fn reload_settings(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    config.reload(&pool).await.map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(pool: PgPool, config: SharedConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                tracing::error!("Could not listen for SIGHUP: {}", err);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            if let Err(err) = config.reload(&pool).await {
+                tracing::error!("Reloading configuration on SIGHUP failed: {}", err);
+            }
+        }
+    });
+}
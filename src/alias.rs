@@ -0,0 +1,80 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    auth::Workspace, config::SharedConfig, slug::check_custom_slug, utils::internal_error,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AliasStatus {
+    Available,
+    /// Kept for routes of the service or honeypots, or in a namespace of another workspace.
+    Reserved,
+    /// Used by a link, live or archived.
+    Taken,
+    /// Malformed, confusable or on the blocklist.
+    Invalid,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasAvailability {
+    slug: String,
+    status: AliasStatus,
+    /// Why the slug cannot be used, as `PUT /api/links/:id` would answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// `GET /api/aliases/:slug/availability`: whether a custom slug can be used, so clients can tell
+/// while it is being typed rather than when the link is saved. Availability is not held, the
+/// slug may be taken before it is used.
+pub async fn get_alias_availability(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(slug): Path<String>,
+) -> Result<Json<AliasAvailability>, (StatusCode, String)> {
+    if let Err((status, reason)) = check_custom_slug(&slug, &workspace, &config.current()) {
+        let status = if matches!(status, StatusCode::CONFLICT | StatusCode::FORBIDDEN) {
+            AliasStatus::Reserved
+        } else {
+            AliasStatus::Invalid
+        };
+        return Ok(Json(AliasAvailability {
+            slug,
+            status,
+            reason: Some(reason),
+        }));
+    }
+    let taken = tokio::time::timeout(
+        tokio::time::Duration::from_millis(300),
+        sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (SELECT 1 FROM links WHERE id = $1)
+                    OR EXISTS (SELECT 1 FROM archived_links WHERE id = $1) AS "taken!"
+            "#,
+            &slug
+        )
+        .fetch_one(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    let (status, reason) = if taken {
+        (AliasStatus::Taken, Some("Slug Taken".to_string()))
+    } else {
+        (AliasStatus::Available, None)
+    };
+    Ok(Json(AliasAvailability {
+        slug,
+        status,
+        reason,
+    }))
+}
@@ -0,0 +1,299 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    audit,
+    auth::Actor,
+    config::{ArchiveConfig, SharedConfig},
+    link_cache, partition, rollup,
+    route::{fetch_link_details, LinkDetails},
+    utils::internal_error,
+};
+
+/// Moves the links with the given ids out of `links`, together with their clicks, conversions,
+/// history and campaign memberships. Health records are dropped, they are rebuilt by the next check.
+async fn archive_links(conn: &mut PgConnection, ids: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO archived_links (
+                id, target_url, created_at, updated_at, active, click_count, workspace_id, tags,
+                expires_at, redirect_type, privacy_mode, track_clicks, sample_rate, expired_at,
+                last_clicked_at, stats_token, click_milestones, sent_milestones, title,
+                title_checked_at, rate_limit, allowed_referers, referer_fallback_url, response_headers,
+                notes, created_by, cache_tier, utm_template, campaign_ids
+            )
+            SELECT
+                l.id, l.target_url, l.created_at, l.updated_at, l.active, l.click_count,
+                l.workspace_id, l.tags, l.expires_at, l.redirect_type, l.privacy_mode,
+                l.track_clicks, l.sample_rate, l.expired_at, l.last_clicked_at, l.stats_token,
+                l.click_milestones, l.sent_milestones, l.title, l.title_checked_at,
+                l.rate_limit, l.allowed_referers, l.referer_fallback_url, l.response_headers,
+                l.notes, l.created_by, l.cache_tier, l.utm_template,
+                COALESCE(
+                    array_agg(cl.campaign_id) FILTER (WHERE cl.campaign_id IS NOT NULL),
+                    '{}'
+                )
+            FROM links l
+            LEFT JOIN campaign_links cl ON cl.link_id = l.id
+            WHERE l.id = ANY($1)
+            GROUP BY l.id
+        "#,
+        ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO archived_link_statistics
+                (id, link_id, referer, user_agent, created_at, visitor_hash, weight, language,
+                platform, mobile, channel, prefetch, variant, click_id)
+            SELECT id, link_id, referer, user_agent, created_at, visitor_hash, weight, language,
+                platform, mobile, channel, prefetch, variant, click_id
+            FROM link_statistics
+            WHERE link_id = ANY($1)
+        "#,
+        ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO archived_link_history
+                (id, link_id, old_target_url, new_target_url, actor, changed_at)
+            SELECT id, link_id, old_target_url, new_target_url, actor, changed_at
+            FROM link_history
+            WHERE link_id = ANY($1)
+        "#,
+        ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO archived_conversions (token, link_id, clicked_at, created_at)
+            SELECT token, link_id, clicked_at, created_at
+            FROM conversions
+            WHERE link_id = ANY($1)
+        "#,
+        ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    // Clicks, conversions, history and memberships go with the link through their cascading foreign keys.
+    sqlx::query!("DELETE FROM links WHERE id = ANY($1)", ids)
+        .execute(&mut *conn)
+        .await?;
+    link_cache::publish(&mut *conn, ids).await?;
+    Ok(())
+}
+
+/// The reverse of [`archive_links`] for one link. Returns false when the link is not archived.
+/// Clicks recorded before `retained_since` are past the retention and not restored.
+async fn restore_link(
+    conn: &mut PgConnection,
+    id: &str,
+    retained_since: Option<DateTime<Utc>>,
+) -> Result<bool, sqlx::Error> {
+    let Some(campaign_ids) = sqlx::query_scalar!(
+        r#"
+            WITH restored AS (
+                DELETE FROM archived_links WHERE id = $1 RETURNING *
+            ), inserted AS (
+                INSERT INTO links (
+                    id, target_url, created_at, updated_at, active, click_count, workspace_id,
+                    tags, expires_at, redirect_type, privacy_mode, track_clicks, sample_rate,
+                    expired_at, last_clicked_at, stats_token, click_milestones, sent_milestones,
+                    title, title_checked_at, rate_limit, allowed_referers, referer_fallback_url,
+                    response_headers, notes, created_by, cache_tier, utm_template
+                )
+                SELECT
+                    id, target_url, created_at, updated_at, active, click_count, workspace_id,
+                    tags, expires_at, redirect_type, privacy_mode, track_clicks, sample_rate,
+                    expired_at, last_clicked_at, stats_token, click_milestones, sent_milestones,
+                    title, title_checked_at, rate_limit, allowed_referers, referer_fallback_url,
+                    response_headers, notes, created_by, cache_tier, utm_template
+                FROM restored
+            )
+            SELECT campaign_ids FROM restored
+        "#,
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(false);
+    };
+    sqlx::query!(
+        r#"
+            INSERT INTO campaign_links (campaign_id, link_id)
+            SELECT c.id, $1 FROM campaigns c WHERE c.id = ANY($2)
+        "#,
+        id,
+        &campaign_ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+            WITH restored AS (
+                DELETE FROM archived_link_statistics WHERE link_id = $1 RETURNING *
+            )
+            INSERT INTO link_statistics
+                (id, link_id, referer, user_agent, created_at, visitor_hash, weight, language,
+                platform, mobile, channel, prefetch, variant, click_id)
+            SELECT id, link_id, referer, user_agent, created_at, visitor_hash, weight, language,
+                platform, mobile, channel, prefetch, variant, click_id
+            FROM restored
+            WHERE $2::TIMESTAMPTZ IS NULL OR created_at >= $2
+        "#,
+        id,
+        retained_since
+    )
+    .execute(&mut *conn)
+    .await?;
+    rollup::roll_up_link(&mut *conn, id).await?;
+    sqlx::query!(
+        r#"
+            WITH restored AS (
+                DELETE FROM archived_link_history WHERE link_id = $1 RETURNING *
+            )
+            INSERT INTO link_history (id, link_id, old_target_url, new_target_url, actor, changed_at)
+            SELECT id, link_id, old_target_url, new_target_url, actor, changed_at
+            FROM restored
+        "#,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+            WITH restored AS (
+                DELETE FROM archived_conversions WHERE link_id = $1 RETURNING *
+            )
+            INSERT INTO conversions (token, link_id, clicked_at, created_at)
+            SELECT token, link_id, clicked_at, created_at
+            FROM restored
+        "#,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+/// Archives one batch of links that have not been clicked for the configured period. Returns how
+/// many were archived.
+async fn archive_batch(pool: &PgPool, archive: &ArchiveConfig) -> Result<usize, sqlx::Error> {
+    let Some(unused_for) = archive.unused_for else {
+        return Ok(0);
+    };
+    let mut tx = pool.begin().await?;
+    let ids = sqlx::query_scalar!(
+        r#"
+            SELECT id
+            FROM links
+            WHERE COALESCE(last_clicked_at, created_at) < now() - make_interval(secs => $1)
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        "#,
+        unused_for.as_secs_f64(),
+        archive.batch_size
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    archive_links(&mut tx, &ids).await?;
+    tx.commit().await?;
+    counter!("links_archived").increment(ids.len() as u64);
+    Ok(ids.len())
+}
+
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let archive = config.current().archive.clone();
+            match archive_batch(&pool, &archive).await {
+                // Keep going without pausing while there is a backlog.
+                Ok(count) if count > 0 => {
+                    tracing::info!("Archived {} unused links", count);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!("Archiving unused links failed: {}", err),
+            }
+            tokio::time::sleep(archive.interval).await;
+        }
+    });
+}
+
+pub struct ArchivedTarget {
+    pub target_url: String,
+    pub redirect_type: i32,
+    pub workspace_id: String,
+}
+
+/// Where an archived link leads, for slugs missing from `links`. Archived links keep working,
+/// they are just no longer counted.
+pub async fn archived_target(
+    pool: &PgPool,
+    id: &str,
+) -> Result<Option<ArchivedTarget>, sqlx::Error> {
+    let target = sqlx::query_as!(
+        ArchivedTarget,
+        r#"
+            SELECT target_url, redirect_type, workspace_id
+            FROM archived_links
+            WHERE id = $1 AND active AND (expires_at IS NULL OR expires_at > now())
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if target.is_some() {
+        counter!("archived_link_redirects").increment(1);
+    }
+    Ok(target)
+}
+
+/// Brings an archived link back into `links` with its clicks and history.
+pub async fn unarchive_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Result<Json<LinkDetails>, (StatusCode, String)> {
+    let retained_since = partition::retained_since(&config.current().statistics);
+    let unarchive_timeout = tokio::time::Duration::from_secs(30);
+    let restored = tokio::time::timeout(unarchive_timeout, async {
+        let mut tx = pool.begin().await?;
+        let taken = sqlx::query_scalar!("SELECT id FROM links WHERE id = $1", &id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if taken.is_some() {
+            return Ok(Err((StatusCode::CONFLICT, "Slug Taken".to_string())));
+        }
+        if !restore_link(&mut tx, &id, retained_since).await? {
+            return Ok(Err((StatusCode::NOT_FOUND, "Not Found".to_string())));
+        }
+        audit::record(&mut tx, &actor, "link.unarchive", Some(&id), json!({})).await?;
+        let link = fetch_link_details(&mut *tx, &id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(link))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)??;
+    tracing::info!("{} unarchived link {}", actor, id);
+    Ok(Json(restored))
+}
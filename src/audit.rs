@@ -0,0 +1,101 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{utils::internal_error, validation::ApiError};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub subject: Option<String>,
+    pub details: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Records an administrative action. Meant to run in the transaction of the action itself so
+/// neither happens without the other.
+pub async fn record(
+    conn: &mut PgConnection,
+    actor: &str,
+    action: &str,
+    subject: Option<&str>,
+    details: Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO audit_log (actor, action, subject, details)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        actor,
+        action,
+        subject,
+        details
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Gives every refused target among the problems of `err` an appeal reference, recorded in the
+/// audit log along with the URL and why it was refused, so support can look it up when users
+/// report a false positive. `submitted` holds the input the problems are about, by field name.
+pub async fn record_refused_targets(
+    pool: &PgPool,
+    actor: &str,
+    subject: Option<&str>,
+    submitted: &Value,
+    mut err: ApiError,
+) -> ApiError {
+    let ApiError::Fields(_, errors) = &mut err else {
+        return err;
+    };
+    for error in errors.iter_mut().filter(|error| error.code.is_some()) {
+        let reference = format!("APL-{:012X}", rand::random::<u64>() >> 16);
+        let details = json!({
+            "appealReference": reference,
+            "code": error.code,
+            "field": error.field,
+            "url": submitted[error.field.as_str()],
+        });
+        let recorded = tokio::time::timeout(tokio::time::Duration::from_millis(300), async {
+            let mut conn = pool.acquire().await?;
+            record(&mut conn, actor, "link.target_refused", subject, details).await
+        })
+        .await;
+        match recorded {
+            Ok(Ok(())) => error.appeal_reference = Some(reference),
+            Ok(Err(err)) => tracing::error!("Recording a refused target failed: {}", err),
+            Err(_) => tracing::error!("Recording a refused target timed out"),
+        }
+    }
+    err
+}
+
+/// The most recent audit log entries.
+pub async fn list_audit_log(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    let fetch_audit_log_timeout = tokio::time::Duration::from_millis(300);
+    let entries = tokio::time::timeout(
+        fetch_audit_log_timeout,
+        sqlx::query_as!(
+            AuditEntry,
+            r#"
+                SELECT id, actor, action, subject, details, created_at
+                FROM audit_log
+                ORDER BY created_at DESC, id DESC
+                LIMIT 100
+            "#
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(entries))
+}
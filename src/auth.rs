@@ -1,14 +1,99 @@
+use std::{str::FromStr, sync::Arc};
+
 use axum::{
+    async_trait,
     extract::{Request, State},
     http::StatusCode,
     middleware::Next,
     response::IntoResponse,
 };
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 use metrics::counter;
+use serde::Deserialize;
 use sha3::{Digest, Sha3_256};
 use sqlx::PgPool;
 
-use crate::utils::internal_error;
+use crate::{
+    config::Config,
+    metering::UsageMeter,
+    utils::{internal_error, route_template},
+};
+
+/// Who performed an authenticated call. Callers sharing the global API key can
+/// identify themselves through the `x-actor` header.
+#[derive(Clone, Debug)]
+pub struct Actor(pub String);
+
+/// The workspace an authenticated call acts in, taken from the `x-workspace` header.
+#[derive(Clone, Debug)]
+pub struct Workspace(pub String);
+
+fn header_or<'a>(req: &'a Request, name: &str, default: &'a str) -> &'a str {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .unwrap_or(default)
+}
+
+/// How API calls are authenticated, from `AUTH_METHOD`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The global API key stored in the `settings` table.
+    Database,
+    /// The key from `AUTH_API_KEY`.
+    Static,
+    /// HS256 tokens signed with `AUTH_JWT_SECRET`.
+    Jwt,
+    /// Everything is allowed. Only meant for local development.
+    None,
+}
+
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "database" => Ok(Self::Database),
+            "static" => Ok(Self::Static),
+            "jwt" => Ok(Self::Jwt),
+            "none" => Ok(Self::None),
+            _ => Err("expected database, static, jwt or none".to_string()),
+        }
+    }
+}
+
+/// Who a credential belongs to, as far as the authenticator knows. Whatever it leaves unset is
+/// taken from the `x-actor` and `x-workspace` headers.
+#[derive(Debug, Default)]
+pub struct Principal {
+    pub actor: Option<String>,
+    pub workspace: Option<String>,
+}
+
+pub enum AuthError {
+    Missing,
+    /// Refused, with the reason to log.
+    Rejected(String),
+    /// The check itself failed; the response to send.
+    Internal((StatusCode, String)),
+}
+
+/// Checks the credential sent with an API call: the `x-api` header, the bearer token or the `key`
+/// query parameter of `/api/shorten`.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, credential: Option<&str>) -> Result<Principal, AuthError>;
+}
+
+pub type SharedAuthenticator = Arc<dyn Authenticator>;
+
+/// The `settings` row holding the digest of the global API key.
+pub const SETTINGS_ID: &str = "DEFAULT_SETTINGS";
+
+fn digest(key: &str) -> String {
+    format!("{:x}", Sha3_256::digest(key.as_bytes()))
+}
 
 struct Settings {
     #[allow(dead_code)]
@@ -16,44 +101,180 @@ struct Settings {
     encrypted_global_api_key: String,
 }
 
+pub struct DatabaseKey {
+    pub pool: PgPool,
+}
+
+#[async_trait]
+impl Authenticator for DatabaseKey {
+    async fn authenticate(&self, credential: Option<&str>) -> Result<Principal, AuthError> {
+        let api_key = credential.ok_or(AuthError::Missing)?;
+        let fetch_setting_timeout = tokio::time::Duration::from_millis(300);
+        let setting: Settings = tokio::time::timeout(
+            fetch_setting_timeout,
+            sqlx::query_as!(
+                Settings,
+                "SELECT id, encrypted_global_api_key FROM settings WHERE id = $1",
+                SETTINGS_ID
+            )
+            .fetch_optional(&self.pool),
+        )
+        .await
+        .map_err(|err| AuthError::Internal(internal_error(err)))?
+        .map_err(|err| AuthError::Internal(internal_error(err)))?
+        .ok_or_else(|| {
+            tracing::error!(
+                "No {} row in settings, every API key is refused",
+                SETTINGS_ID
+            );
+            AuthError::Internal((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "API Key Not Configured".into(),
+            ))
+        })?;
+
+        if setting.encrypted_global_api_key != digest(api_key) {
+            return Err(AuthError::Rejected("Incorrect key supplied".into()));
+        }
+        Ok(Principal::default())
+    }
+}
+
+pub struct StaticKey {
+    digest: String,
+}
+
+impl StaticKey {
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            digest: digest(api_key),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for StaticKey {
+    async fn authenticate(&self, credential: Option<&str>) -> Result<Principal, AuthError> {
+        let api_key = credential.ok_or(AuthError::Missing)?;
+        if digest(api_key) != self.digest {
+            return Err(AuthError::Rejected("Incorrect key supplied".into()));
+        }
+        Ok(Principal::default())
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    workspace: Option<String>,
+}
+
+/// Tokens must carry an `exp`; `sub` becomes the actor and a `workspace` claim the workspace.
+pub struct Jwt {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl Jwt {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            key: DecodingKey::from_secret(secret.as_bytes()),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for Jwt {
+    async fn authenticate(&self, credential: Option<&str>) -> Result<Principal, AuthError> {
+        let token = credential.ok_or(AuthError::Missing)?;
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|err| AuthError::Rejected(format!("Invalid token: {err}")))?
+            .claims;
+        Ok(Principal {
+            actor: claims.sub,
+            workspace: claims.workspace,
+        })
+    }
+}
+
+pub struct NoAuth;
+
+#[async_trait]
+impl Authenticator for NoAuth {
+    async fn authenticate(&self, _credential: Option<&str>) -> Result<Principal, AuthError> {
+        Ok(Principal::default())
+    }
+}
+
+/// The authenticator picked by `AUTH_METHOD`.
+pub fn from_config(pool: PgPool, config: &Config) -> SharedAuthenticator {
+    match config.auth.method {
+        AuthMethod::Database => Arc::new(DatabaseKey { pool }),
+        AuthMethod::Static => Arc::new(StaticKey::new(
+            config.auth.api_key.as_deref().unwrap_or_default(),
+        )),
+        AuthMethod::Jwt => Arc::new(Jwt::new(
+            config.auth.jwt_secret.as_deref().unwrap_or_default(),
+        )),
+        AuthMethod::None => {
+            tracing::warn!("Authentication is disabled, every API call is allowed");
+            Arc::new(NoAuth)
+        }
+    }
+}
+
 pub async fn auth(
-    State(pool): State<PgPool>,
-    req: Request,
+    State(authenticator): State<SharedAuthenticator>,
+    State(meter): State<Arc<UsageMeter>>,
+    mut req: Request,
     next: Next,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let labels = [("uri", format!("{}!", req.uri()))];
-    let api_key = req
+    // Bearer tokens are accepted as well for clients written against other shorteners' APIs.
+    let credential = req
         .headers()
         .get("x-api")
         .map(|v| v.to_str().unwrap_or_default())
-        .ok_or_else(|| {
-            tracing::error!("Unauthorized call to API: No key header received");
-            counter!("unauthorized_calls_count", &labels).increment(1);
-
-            (StatusCode::UNAUTHORIZED, "Unauthorized".into())
-        })?;
-    let fetch_setting_timeout = tokio::time::Duration::from_millis(300);
-    let setting: Settings = tokio::time::timeout(
-        fetch_setting_timeout,
-        sqlx::query_as!(
-            Settings,
-            "SELECT id, encrypted_global_api_key FROM settings WHERE id = $1",
-            "DEFUALT_SETTINGS"
-        )
-        .fetch_one(&pool),
-    )
-    .await
-    .map_err(internal_error)?
-    .map_err(internal_error)?;
+        .or_else(|| {
+            req.headers()
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        });
+    let principal = authenticate(authenticator.as_ref(), credential, &labels).await?;
 
-    let mut hasher = Sha3_256::new();
-    hasher.update(api_key.as_bytes());
-    let provided_api_key = hasher.finalize();
-
-    if setting.encrypted_global_api_key != format!("{provided_api_key:x}") {
-        tracing::error!("Unauthorized call to API: Incorrect key supplied");
-        counter!("unauthorized_calls_count", &labels).increment(1);
-        return Err((StatusCode::UNAUTHORIZED, "Unauthorized".into()));
-    }
+    let actor = principal
+        .actor
+        .unwrap_or_else(|| header_or(&req, "x-actor", "global-api-key").to_string());
+    let workspace = principal
+        .workspace
+        .unwrap_or_else(|| header_or(&req, "x-workspace", "default").to_string());
+    let route = route_template(&req);
+    meter.record(
+        credential.unwrap_or_default(),
+        &actor,
+        format!("{} {}", req.method(), route),
+    );
+    req.extensions_mut().insert(Actor(actor));
+    req.extensions_mut().insert(Workspace(workspace));
     Ok(next.run(req).await)
 }
+
+/// Runs the authenticator, logging and counting refused calls. Used directly by endpoints that
+/// take the key from somewhere other than the headers.
+pub async fn authenticate(
+    authenticator: &dyn Authenticator,
+    credential: Option<&str>,
+    labels: &[(&'static str, String)],
+) -> Result<Principal, (StatusCode, String)> {
+    let reason = match authenticator.authenticate(credential).await {
+        Ok(principal) => return Ok(principal),
+        Err(AuthError::Internal(response)) => return Err(response),
+        Err(AuthError::Missing) => "No key header received".to_string(),
+        Err(AuthError::Rejected(reason)) => reason,
+    };
+    tracing::error!("Unauthorized call to API: {}", reason);
+    counter!("unauthorized_calls_count", labels).increment(1);
+    Err((StatusCode::UNAUTHORIZED, "Unauthorized".into()))
+}
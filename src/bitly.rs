@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{Actor, Workspace},
+    config::SharedConfig,
+    db::CircuitBreaker,
+    id::SharedIdGenerator,
+    rollup,
+    route::insert_link,
+    target::serialize_display_url,
+    utils::short_url,
+};
+
+#[derive(Serialize)]
+pub struct BitlyError {
+    pub message: String,
+    pub description: String,
+    pub resource: &'static str,
+}
+
+type BitlyResult<T> = Result<Json<T>, (StatusCode, Json<BitlyError>)>;
+
+fn bitly_error(
+    resource: &'static str,
+    status: StatusCode,
+    description: String,
+) -> (StatusCode, Json<BitlyError>) {
+    let message = match status {
+        StatusCode::NOT_FOUND => "NOT_FOUND",
+        StatusCode::BAD_REQUEST | StatusCode::CONFLICT => "INVALID_ARG_LONG_URL",
+        StatusCode::FORBIDDEN => "FORBIDDEN",
+        StatusCode::SERVICE_UNAVAILABLE => "TEMPORARILY_UNAVAILABLE",
+        _ => "INTERNAL_ERROR",
+    };
+    (
+        status,
+        Json(BitlyError {
+            message: message.to_string(),
+            description,
+            resource,
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ShortenRequest {
+    pub long_url: String,
+}
+
+#[derive(Serialize)]
+pub struct Bitlink {
+    pub id: String,
+    pub link: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub long_url: String,
+    pub created_at: DateTime<Utc>,
+    pub archived: bool,
+    pub tags: Vec<String>,
+    pub custom_bitlinks: Vec<String>,
+    pub deeplinks: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ClicksParams {
+    pub unit: Option<String>,
+    /// Number of units to report, all of them when negative.
+    pub units: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct LinkClicks {
+    pub clicks: i64,
+    pub date: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ClicksResponse {
+    pub link_clicks: Vec<LinkClicks>,
+    pub units: i32,
+    pub unit: String,
+    pub unit_reference: DateTime<Utc>,
+}
+
+/// Bitly v4 `POST /v4/shorten`. Together with [`bitlink_clicks`] this is enough of the Bitly API
+/// for existing clients to switch over by changing their base URL and token.
+#[allow(clippy::too_many_arguments)]
+pub async fn shorten(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(breaker): State<Arc<CircuitBreaker>>,
+    State(ids): State<SharedIdGenerator>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    headers: HeaderMap,
+    Json(request): Json<ShortenRequest>,
+) -> Result<(StatusCode, Json<Bitlink>), (StatusCode, Json<BitlyError>)> {
+    let config = config.current();
+    let link = insert_link(
+        &pool,
+        &config,
+        &breaker,
+        ids.as_ref(),
+        &workspace,
+        &actor,
+        &request.long_url,
+    )
+    .await
+    .map_err(|(status, description)| bitly_error("bitlinks", status, description))?;
+    // Bitlink ids are `<domain>/<slug>`: the short URL without its scheme.
+    let link_url = short_url(&config, &headers, &link.id);
+    Ok((
+        StatusCode::CREATED,
+        Json(Bitlink {
+            id: link_url
+                .split_once("://")
+                .map_or(link_url.as_str(), |(_, bitlink)| bitlink)
+                .to_string(),
+            link: link_url,
+            long_url: link.target_url,
+            created_at: Utc::now(),
+            archived: false,
+            tags: Vec::new(),
+            custom_bitlinks: Vec::new(),
+            deeplinks: Vec::new(),
+        }),
+    ))
+}
+
+/// `GET /v4/bitlinks/{bitlink}/clicks`, with the bitlink either URL-encoded or as two segments.
+/// Clicks before the raw window come from hourly rollups, so `minute` units only cover the window.
+pub async fn bitlink_clicks(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Path(path): Path<Vec<(String, String)>>,
+    Query(params): Query<ClicksParams>,
+) -> BitlyResult<ClicksResponse> {
+    let bitlink = path
+        .into_iter()
+        .map(|(_, segment)| segment)
+        .collect::<Vec<_>>()
+        .join("/");
+    let slug = bitlink.rsplit('/').next().unwrap_or_default().to_string();
+    let unit = params.unit.unwrap_or_else(|| "day".into());
+    if !matches!(unit.as_str(), "minute" | "hour" | "day" | "week" | "month") {
+        return Err(bitly_error(
+            "bitlinks",
+            StatusCode::BAD_REQUEST,
+            format!("Unknown unit {unit}"),
+        ));
+    }
+    let units = params.units.unwrap_or(-1);
+    let unit_reference = Utc::now();
+    let raw_since = rollup::raw_since(&config.current().statistics);
+
+    let fetch_clicks_timeout = tokio::time::Duration::from_millis(1000);
+    let internal = |err: &dyn std::error::Error| {
+        tracing::error!("{}", err);
+        bitly_error(
+            "bitlinks",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            err.to_string(),
+        )
+    };
+    let exists = tokio::time::timeout(
+        fetch_clicks_timeout,
+        sqlx::query_scalar!("SELECT id FROM links WHERE id = $1", &slug).fetch_optional(&pool),
+    )
+    .await
+    .map_err(|err| internal(&err))?
+    .map_err(|err| internal(&err))?;
+    if exists.is_none() {
+        return Err(bitly_error(
+            "bitlinks",
+            StatusCode::NOT_FOUND,
+            format!("Bitlink {bitlink} not found"),
+        ));
+    }
+
+    let link_clicks = tokio::time::timeout(
+        fetch_clicks_timeout,
+        sqlx::query_as!(
+            LinkClicks,
+            r#"
+                SELECT date_trunc($2, clicked_at) AS "date!", SUM(clicks)::BIGINT AS "clicks!"
+                FROM (
+                    SELECT hour AS clicked_at, clicks
+                    FROM link_hourly_clicks
+                    WHERE link_id = $1 AND hour < $5 AND $2 <> 'minute'
+                    UNION ALL
+                    SELECT created_at, weight
+                    FROM link_statistics
+                    WHERE link_id = $1 AND NOT prefetch AND created_at >= $5
+                ) AS clicks
+                WHERE $3::INT < 0
+                    OR clicked_at >= date_trunc($2, $4::TIMESTAMPTZ) - (($3::INT - 1) || ' ' || $2)::INTERVAL
+                GROUP BY 1
+                ORDER BY 1 DESC
+            "#,
+            &slug,
+            &unit,
+            units,
+            unit_reference,
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(|err| internal(&err))?
+    .map_err(|err| internal(&err))?;
+    Ok(Json(ClicksResponse {
+        link_clicks,
+        units,
+        unit,
+        unit_reference,
+    }))
+}
@@ -0,0 +1,432 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::auth::Workspace;
+use crate::config::SharedConfig;
+use crate::conversion::conversion_rate;
+use crate::rollup;
+use crate::utils::{generate_id, internal_error};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Campaign {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub link_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewCampaign {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub link_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignLinks {
+    pub link_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignLinkClicks {
+    pub link_id: String,
+    pub clicks: i64,
+    pub conversions: i64,
+    pub conversion_rate: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyClicks {
+    pub day: DateTime<Utc>,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignStatistics {
+    pub campaign_id: String,
+    pub total_clicks: i64,
+    pub total_conversions: i64,
+    pub conversion_rate: f64,
+    pub links: Vec<CampaignLinkClicks>,
+    pub time_series: Vec<DailyClicks>,
+}
+
+fn campaign_not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "Campaign Not Found".into())
+}
+
+/// Answers 404 naming the first of `link_ids` that is not a link of the workspace.
+async fn ensure_links_exist(
+    pool: &PgPool,
+    link_ids: &[String],
+    workspace: &str,
+) -> Result<(), (StatusCode, String)> {
+    let fetch_links_timeout = tokio::time::Duration::from_millis(300);
+    let known_links = tokio::time::timeout(
+        fetch_links_timeout,
+        sqlx::query_scalar!(
+            "SELECT id FROM links WHERE id = ANY($1) AND workspace_id = $2",
+            link_ids,
+            workspace
+        )
+        .fetch_all(pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    match link_ids.iter().find(|id| !known_links.contains(id)) {
+        Some(missing) => Err((StatusCode::NOT_FOUND, format!("Link {missing} Not Found"))),
+        None => Ok(()),
+    }
+}
+
+async fn fetch_campaign(
+    pool: &PgPool,
+    id: &str,
+    workspace: &str,
+) -> Result<Campaign, (StatusCode, String)> {
+    let fetch_campaign_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        fetch_campaign_timeout,
+        sqlx::query_as!(
+            Campaign,
+            r#"
+                SELECT
+                    c.id,
+                    c.name,
+                    c.description,
+                    COALESCE(
+                        array_agg(cl.link_id ORDER BY cl.link_id) FILTER (WHERE cl.link_id IS NOT NULL),
+                        '{}'
+                    ) AS "link_ids!",
+                    c.created_at,
+                    c.updated_at
+                FROM campaigns c
+                LEFT JOIN campaign_links cl ON cl.campaign_id = c.id
+                WHERE c.id = $1 AND c.workspace_id = $2
+                GROUP BY c.id
+            "#,
+            id,
+            workspace
+        )
+        .fetch_optional(pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(campaign_not_found)
+}
+
+pub async fn create_campaign(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Json(new_campaign): Json<NewCampaign>,
+) -> Result<Json<Campaign>, (StatusCode, String)> {
+    ensure_links_exist(&pool, &new_campaign.link_ids, &workspace).await?;
+
+    let new_campaign_id = generate_id();
+    let insert_campaign_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(insert_campaign_timeout, async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO campaigns (id, name, description, workspace_id) VALUES ($1, $2, $3, $4)",
+            &new_campaign_id,
+            &new_campaign.name,
+            new_campaign.description.as_deref(),
+            &workspace
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO campaign_links (campaign_id, link_id)
+                SELECT $1, link_id FROM UNNEST($2::text[]) AS link_id
+                ON CONFLICT DO NOTHING
+            "#,
+            &new_campaign_id,
+            &new_campaign.link_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    tracing::debug!(
+        "Created new campaign with id {} grouping {} links",
+        new_campaign_id,
+        new_campaign.link_ids.len()
+    );
+    Ok(Json(
+        fetch_campaign(&pool, &new_campaign_id, &workspace).await?,
+    ))
+}
+
+pub async fn list_campaigns(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<Json<Vec<Campaign>>, (StatusCode, String)> {
+    let list_campaigns_timeout = tokio::time::Duration::from_millis(300);
+    let campaigns = tokio::time::timeout(
+        list_campaigns_timeout,
+        sqlx::query_as!(
+            Campaign,
+            r#"
+                SELECT
+                    c.id,
+                    c.name,
+                    c.description,
+                    COALESCE(
+                        array_agg(cl.link_id ORDER BY cl.link_id) FILTER (WHERE cl.link_id IS NOT NULL),
+                        '{}'
+                    ) AS "link_ids!",
+                    c.created_at,
+                    c.updated_at
+                FROM campaigns c
+                LEFT JOIN campaign_links cl ON cl.campaign_id = c.id
+                WHERE c.workspace_id = $1
+                GROUP BY c.id
+                ORDER BY c.created_at DESC
+            "#,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(campaigns))
+}
+
+pub async fn get_campaign(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<Json<Campaign>, (StatusCode, String)> {
+    Ok(Json(fetch_campaign(&pool, &id, &workspace).await?))
+}
+
+pub async fn update_campaign(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+    Json(update): Json<CampaignUpdate>,
+) -> Result<Json<Campaign>, (StatusCode, String)> {
+    let update_campaign_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        update_campaign_timeout,
+        sqlx::query!(
+            r#"
+                UPDATE campaigns
+                SET name = COALESCE($1, name),
+                    description = COALESCE($2, description),
+                    updated_at = now()
+                WHERE id = $3 AND workspace_id = $4
+                RETURNING id
+            "#,
+            update.name.as_deref(),
+            update.description.as_deref(),
+            &id,
+            &workspace
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(campaign_not_found)?;
+
+    tracing::debug!("Updated campaign with id {}", id);
+    Ok(Json(fetch_campaign(&pool, &id, &workspace).await?))
+}
+
+pub async fn delete_campaign(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let delete_campaign_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        delete_campaign_timeout,
+        sqlx::query!(
+            "DELETE FROM campaigns WHERE id = $1 AND workspace_id = $2 RETURNING id",
+            &id,
+            &workspace
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(campaign_not_found)?;
+
+    tracing::debug!("Deleted campaign with id {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_campaign_links(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+    Json(campaign_links): Json<CampaignLinks>,
+) -> Result<Json<Campaign>, (StatusCode, String)> {
+    fetch_campaign(&pool, &id, &workspace).await?;
+    ensure_links_exist(&pool, &campaign_links.link_ids, &workspace).await?;
+
+    let add_links_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        add_links_timeout,
+        sqlx::query!(
+            r#"
+                INSERT INTO campaign_links (campaign_id, link_id)
+                SELECT $1, link_id FROM UNNEST($2::text[]) AS link_id
+                ON CONFLICT DO NOTHING
+            "#,
+            &id,
+            &campaign_links.link_ids
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    tracing::debug!(
+        "Added {} links to campaign with id {}",
+        campaign_links.link_ids.len(),
+        id
+    );
+    Ok(Json(fetch_campaign(&pool, &id, &workspace).await?))
+}
+
+pub async fn remove_campaign_link(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path((id, link_id)): Path<(String, String)>,
+) -> Result<Json<Campaign>, (StatusCode, String)> {
+    fetch_campaign(&pool, &id, &workspace).await?;
+    let remove_link_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        remove_link_timeout,
+        sqlx::query!(
+            "DELETE FROM campaign_links WHERE campaign_id = $1 AND link_id = $2",
+            &id,
+            &link_id
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    tracing::debug!("Removed link {} from campaign with id {}", link_id, id);
+    Ok(Json(fetch_campaign(&pool, &id, &workspace).await?))
+}
+
+pub async fn get_campaign_statistics(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<Json<CampaignStatistics>, (StatusCode, String)> {
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    fetch_campaign(&pool, &id, &workspace).await?;
+
+    let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
+    let links = tokio::time::timeout(
+        fetch_statistics_timeout,
+        sqlx::query!(
+            r#"
+                SELECT
+                    cl.link_id,
+                    l.click_count AS clicks,
+                    (SELECT COUNT(*) FROM conversions c WHERE c.link_id = cl.link_id) AS "conversions!"
+                FROM campaign_links cl
+                JOIN links l ON l.id = cl.link_id
+                WHERE cl.campaign_id = $1
+                ORDER BY 2 DESC, cl.link_id
+            "#,
+            &id
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    let links: Vec<CampaignLinkClicks> = links
+        .into_iter()
+        .map(|link| CampaignLinkClicks {
+            conversion_rate: conversion_rate(link.conversions, link.clicks),
+            link_id: link.link_id,
+            clicks: link.clicks,
+            conversions: link.conversions,
+        })
+        .collect();
+
+    let time_series = tokio::time::timeout(
+        fetch_statistics_timeout,
+        sqlx::query_as!(
+            DailyClicks,
+            r#"
+                SELECT day AS "day!", SUM(clicks)::BIGINT AS "clicks!"
+                FROM (
+                    SELECT d.day::TIMESTAMP AT TIME ZONE 'UTC' AS day, d.clicks
+                    FROM link_daily_clicks d
+                    JOIN campaign_links cl ON cl.link_id = d.link_id
+                    WHERE cl.campaign_id = $1 AND d.day < $2 AND d.clicks > 0
+                    UNION ALL
+                    SELECT date_trunc('day', s.created_at, 'UTC'), s.weight
+                    FROM link_statistics s
+                    JOIN campaign_links cl ON cl.link_id = s.link_id
+                    WHERE cl.campaign_id = $1 AND NOT s.prefetch AND s.created_at >= $3
+                ) AS daily_clicks
+                GROUP BY 1
+                ORDER BY 1
+            "#,
+            &id,
+            raw_since.date_naive(),
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    tracing::debug!("Statistics for campaign with id {} requested", id);
+    let total_clicks = links.iter().map(|link| link.clicks).sum();
+    let total_conversions = links.iter().map(|link| link.conversions).sum();
+    Ok(Json(CampaignStatistics {
+        total_clicks,
+        total_conversions,
+        conversion_rate: conversion_rate(total_conversions, total_clicks),
+        campaign_id: id,
+        links,
+        time_series,
+    }))
+}
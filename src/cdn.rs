@@ -0,0 +1,273 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    async_trait,
+    http::{HeaderName, HeaderValue},
+    response::Response,
+};
+use metrics::counter;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::{
+    config::{CdnConfig, Config},
+    outbound,
+};
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+/// Cloudflare purges at most this many URLs or tags per call.
+const CLOUDFLARE_MAX_PURGED: usize = 30;
+const FASTLY_API: &str = "https://api.fastly.com";
+/// Fastly purges at most this many surrogate keys per call.
+const FASTLY_MAX_KEYS: usize = 256;
+
+/// Lists the keys of a cached response for Fastly, separated by spaces.
+const SURROGATE_KEY: HeaderName = HeaderName::from_static("surrogate-key");
+/// Lists the keys of a cached response for Cloudflare, separated by commas.
+const CACHE_TAG: HeaderName = HeaderName::from_static("cache-tag");
+
+/// Where redirects are cached in front of the shortener, from `CDN_PROVIDER`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CdnProvider {
+    Cloudflare,
+    Fastly,
+    /// Nothing to purge: cached redirects expire after `REDIRECT_CACHE_MAX_AGE_SECS`.
+    None,
+}
+
+impl FromStr for CdnProvider {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "cloudflare" => Ok(Self::Cloudflare),
+            "fastly" => Ok(Self::Fastly),
+            "none" => Ok(Self::None),
+            _ => Err("expected cloudflare, fastly or none".to_string()),
+        }
+    }
+}
+
+/// The cached redirects of changed links, by short URL and by surrogate key. Purging either
+/// evicts them; by key also covers the same link requested with a query string.
+#[derive(Clone, Debug, Default)]
+pub struct PurgeRequest {
+    /// Empty without `BASE_URL`.
+    pub urls: Vec<String>,
+    /// Empty unless redirects are tagged with `CDN_SURROGATE_KEYS`.
+    pub keys: Vec<String>,
+}
+
+/// Evicts cached responses from a CDN, so changed or deleted links stop redirecting to their
+/// old target before the cache expires.
+#[async_trait]
+pub trait CdnPurger: Send + Sync {
+    async fn purge(&self, request: &PurgeRequest) -> Result<(), String>;
+}
+
+pub type SharedCdnPurger = Arc<dyn CdnPurger>;
+
+pub struct Cloudflare {
+    client: Client,
+    zone_id: String,
+    api_token: String,
+}
+
+impl Cloudflare {
+    pub fn new(zone_id: &str, api_token: &str, client: Client) -> Self {
+        Self {
+            client,
+            zone_id: zone_id.to_string(),
+            api_token: api_token.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CdnPurger for Cloudflare {
+    /// Purges by cache tag when redirects carry them, by URL otherwise.
+    async fn purge(&self, request: &PurgeRequest) -> Result<(), String> {
+        let (field, purged) = if request.keys.is_empty() {
+            ("files", &request.urls)
+        } else {
+            ("tags", &request.keys)
+        };
+        for chunk in purged.chunks(CLOUDFLARE_MAX_PURGED) {
+            let response = self
+                .client
+                .post(format!(
+                    "{CLOUDFLARE_API}/zones/{}/purge_cache",
+                    self.zone_id
+                ))
+                .bearer_auth(&self.api_token)
+                .header("Content-Type", "application/json")
+                .body(json!({ field: chunk }).to_string())
+                .send()
+                .await
+                .map_err(|err| err.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("Cloudflare answered {}", response.status()));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Fastly {
+    client: Client,
+    api_token: String,
+    /// Needed to purge by surrogate key.
+    service_id: Option<String>,
+}
+
+impl Fastly {
+    pub fn new(api_token: &str, service_id: Option<&str>, client: Client) -> Self {
+        Self {
+            client,
+            api_token: api_token.to_string(),
+            service_id: service_id.map(str::to_string),
+        }
+    }
+}
+
+#[async_trait]
+impl CdnPurger for Fastly {
+    /// Purges by surrogate key when redirects carry them and the service is known. Otherwise
+    /// one URL per call, addressed without its scheme.
+    async fn purge(&self, request: &PurgeRequest) -> Result<(), String> {
+        if let Some(service_id) = self
+            .service_id
+            .as_deref()
+            .filter(|_| !request.keys.is_empty())
+        {
+            for keys in request.keys.chunks(FASTLY_MAX_KEYS) {
+                let response = self
+                    .client
+                    .post(format!("{FASTLY_API}/service/{service_id}/purge"))
+                    .header("Fastly-Key", &self.api_token)
+                    .header("Surrogate-Key", keys.join(" "))
+                    .send()
+                    .await
+                    .map_err(|err| err.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("Fastly answered {}", response.status()));
+                }
+            }
+            return Ok(());
+        }
+        for url in &request.urls {
+            let address = url
+                .split_once("://")
+                .map_or(url.as_str(), |(_, address)| address);
+            let response = self
+                .client
+                .post(format!("{FASTLY_API}/purge/{address}"))
+                .header("Fastly-Key", &self.api_token)
+                .send()
+                .await
+                .map_err(|err| err.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("Fastly answered {} for {}", response.status(), url));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The purger picked by `CDN_PROVIDER`, if any.
+pub fn from_config(config: &Config) -> Option<SharedCdnPurger> {
+    let cdn = &config.cdn;
+    let api_token = cdn.api_token.as_deref().unwrap_or_default();
+    let client = outbound::api_client(&config.outbound, cdn.timeout);
+    match cdn.provider {
+        CdnProvider::Cloudflare => Some(Arc::new(Cloudflare::new(
+            cdn.cloudflare_zone_id.as_deref().unwrap_or_default(),
+            api_token,
+            client,
+        ))),
+        CdnProvider::Fastly => Some(Arc::new(Fastly::new(
+            api_token,
+            cdn.fastly_service_id.as_deref(),
+            client,
+        ))),
+        CdnProvider::None => None,
+    }
+}
+
+/// The surrogate key of a link's redirects.
+pub fn link_key(link_id: &str) -> String {
+    format!("link-{link_id}")
+}
+
+/// The surrogate key of the redirects of every link in a workspace.
+pub fn workspace_key(workspace_id: &str) -> String {
+    format!("workspace-{workspace_id}")
+}
+
+/// Tags a redirect with the surrogate keys of its links and their workspaces when
+/// `CDN_SURROGATE_KEYS` is on, as `Surrogate-Key` for Fastly and `Cache-Tag` for Cloudflare. A
+/// redirect through a chain of links carries the keys of all of them. Both CDNs strip the headers
+/// before responding to visitors.
+pub fn add_surrogate_keys<'a>(
+    response: &mut Response,
+    cdn: &CdnConfig,
+    links: impl IntoIterator<Item = (&'a str, &'a str)>,
+) {
+    if !cdn.surrogate_keys {
+        return;
+    }
+    let mut keys = Vec::new();
+    for (link_id, workspace_id) in links {
+        for key in [link_key(link_id), workspace_key(workspace_id)] {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&keys.join(" ")) {
+        headers.insert(SURROGATE_KEY, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&keys.join(",")) {
+        headers.insert(CACHE_TAG, value);
+    }
+}
+
+/// What to purge for the links in `link_ids`: their short URLs under `BASE_URL` and their
+/// surrogate keys, as far as either is known.
+pub fn purge_request(config: &Config, link_ids: &[String]) -> PurgeRequest {
+    PurgeRequest {
+        urls: config
+            .base_url
+            .as_deref()
+            .map(|base_url| {
+                link_ids
+                    .iter()
+                    .map(|id| format!("{base_url}/{id}"))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        keys: if config.cdn.surrogate_keys {
+            link_ids.iter().map(|id| link_key(id)).collect()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+/// Purges `request`, logging and counting the outcome.
+pub async fn purge(purger: &dyn CdnPurger, request: &PurgeRequest) -> Result<(), String> {
+    let links = request.urls.len().max(request.keys.len());
+    match purger.purge(request).await {
+        Ok(()) => {
+            tracing::debug!("Purged {} links from the CDN", links);
+            counter!("cdn_purged_links").increment(links as u64);
+            Ok(())
+        }
+        Err(err) => {
+            tracing::warn!("Purging {} links from the CDN failed: {}", links, err);
+            counter!("cdn_purge_failures").increment(1);
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{
+    auth::Workspace,
+    config::{ClickSamplingConfig, SharedConfig},
+    rollup,
+    route::ensure_workspace_link_exists,
+    utils::internal_error,
+};
+
+/// Hashes visitors with a salt that is replaced every UTC day and only ever lives in memory.
+/// The same visitor gets the same hash for one day, after which the old salt is gone and the
+/// hashes can no longer be linked to them. Every instance draws its own salt, so uniques are
+/// exact per instance.
+#[derive(Debug, Default)]
+pub struct VisitorHasher {
+    salt: Mutex<Option<(NaiveDate, [u8; 32])>>,
+}
+
+impl VisitorHasher {
+    fn salt(&self, today: NaiveDate) -> [u8; 32] {
+        let mut salt = self.salt.lock().expect("Visitor salt lock poisoned");
+        match *salt {
+            Some((day, current)) if day == today => current,
+            _ => {
+                let mut fresh = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut fresh);
+                *salt = Some((today, fresh));
+                fresh
+            }
+        }
+    }
+
+    pub fn hash(&self, ip: IpAddr, user_agent: Option<&str>) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt(Utc::now().date_naive()));
+        match ip {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+        hasher.update(user_agent.unwrap_or_default());
+        format!("{:x}", hasher.finalize())[..32].to_string()
+    }
+}
+
+const MAX_LANGUAGE_LENGTH: usize = 35;
+const MAX_PLATFORM_LENGTH: usize = 50;
+const MAX_CHANNEL_LENGTH: usize = 32;
+
+/// Channel of clicks on short URLs without a `src` query parameter.
+pub const DIRECT_CHANNEL: &str = "direct";
+/// Channel of the short URLs in generated QR codes.
+pub const QR_CHANNEL: &str = "qr";
+
+/// The acquisition channel of a click, from the `src` query parameter of the short URL
+/// (`/:id?src=qr`), lowercased. Missing or malformed values count as direct, so print and digital
+/// variants of a link can be compared without creating a link per channel.
+pub fn channel(query: Option<&str>) -> String {
+    query
+        .and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(name, _)| name == "src")
+                .map(|(_, value)| value.to_ascii_lowercase())
+        })
+        .filter(|channel| {
+            !channel.is_empty()
+                && channel.len() <= MAX_CHANNEL_LENGTH
+                && channel
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .unwrap_or_else(|| DIRECT_CHANNEL.to_string())
+}
+
+/// What a click's headers tell about the visitor's locale and device, without any script on
+/// the visitor's side. Browsers send `Sec-CH-UA-Platform` and `Sec-CH-UA-Mobile` unasked.
+#[derive(Debug, Default)]
+pub struct ClientHints {
+    /// The most preferred language tag, lowercased, like `en-us`.
+    pub language: Option<String>,
+    pub platform: Option<String>,
+    pub mobile: Option<bool>,
+}
+
+impl ClientHints {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        let language = header("accept-language").and_then(|accept| {
+            accept
+                .split(',')
+                .map(|entry| {
+                    let mut parts = entry.split(';');
+                    let tag = parts.next().unwrap_or_default().trim();
+                    let quality = parts
+                        .find_map(|param| param.trim().strip_prefix("q="))
+                        .and_then(|quality| quality.parse::<f32>().ok())
+                        .unwrap_or(1.0);
+                    (tag, quality)
+                })
+                .filter(|(tag, quality)| {
+                    *quality > 0.0
+                        && tag.len() <= MAX_LANGUAGE_LENGTH
+                        && !tag.is_empty()
+                        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                })
+                // The first of equally preferred languages wins.
+                .fold(None, |best: Option<(&str, f32)>, candidate| match best {
+                    Some(best) if best.1 >= candidate.1 => Some(best),
+                    _ => Some(candidate),
+                })
+                .map(|(tag, _)| tag.to_ascii_lowercase())
+        });
+        let platform = header("sec-ch-ua-platform")
+            .map(|platform| platform.trim_matches('"').to_string())
+            .filter(|platform| !platform.is_empty() && platform.len() <= MAX_PLATFORM_LENGTH);
+        let mobile = match header("sec-ch-ua-mobile") {
+            Some("?1") => Some(true),
+            Some("?0") => Some(false),
+            _ => None,
+        };
+        Self {
+            language,
+            platform,
+            mobile,
+        }
+    }
+}
+
+const SAMPLING_WINDOW: Duration = Duration::from_secs(60);
+/// Tracked links above which counters of finished windows are dropped.
+const MAX_TRACKED_LINKS: usize = 10_000;
+
+/// Counts redirects per link over one-minute windows to find links hot enough to sample.
+#[derive(Debug, Default)]
+pub struct ClickSampler {
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl ClickSampler {
+    /// Counts a redirect of `link_id` and returns how many clicks each recorded one should stand
+    /// for: the configured sample rate while the link is above the threshold, 1 otherwise.
+    pub fn sample_rate(&self, link_id: &str, config: &ClickSamplingConfig) -> i32 {
+        let Some(threshold) = config.threshold_per_minute else {
+            return 1;
+        };
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("Click sampler lock poisoned");
+        if windows.len() > MAX_TRACKED_LINKS {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < SAMPLING_WINDOW);
+        }
+        let (started, count) = windows.entry(link_id.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= SAMPLING_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        if *count > threshold {
+            config.rate
+        } else {
+            1
+        }
+    }
+}
+
+/// Whether to record a click that stands for `weight` clicks when recorded: one in `weight` are.
+pub fn is_sampled(weight: i32) -> bool {
+    weight <= 1 || rand::thread_rng().gen_range(0..weight) == 0
+}
+
+/// A fresh identifier for a recorded click, 22 URL-safe characters.
+pub fn new_click_id() -> String {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+/// `target_url` with `click_id` appended as `param`, replacing a parameter of that name the
+/// target already has. Targets that are not URLs are left alone.
+pub fn tag_target(target_url: &str, param: &str, click_id: &str) -> String {
+    let Ok(mut url) = url::Url::parse(target_url) else {
+        return target_url.to_string();
+    };
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != param)
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(kept)
+        .append_pair(param, click_id);
+    url.into()
+}
+
+/// Longest period click statistics are filtered to at once.
+pub const MAX_STATISTICS_RANGE_DAYS: i64 = 366;
+
+/// Clicks at or after the first instant and before the second.
+pub type Period = (DateTime<Utc>, DateTime<Utc>);
+
+/// `?from=&to=` of statistics endpoints, as RFC 3339 instants: clicks at or after `from` and
+/// before `to`.
+#[derive(Deserialize)]
+pub struct StatisticsRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl StatisticsRange {
+    /// The period to count clicks in, all time when neither bound is given. A missing `to` is
+    /// now, a missing `from` the longest range allowed before `to`.
+    pub fn bounds(&self) -> Result<Option<Period>, (StatusCode, String)> {
+        if self.from.is_none() && self.to.is_none() {
+            return Ok(None);
+        }
+        let longest = chrono::Duration::days(MAX_STATISTICS_RANGE_DAYS);
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to - longest);
+        if from >= to {
+            return Err((StatusCode::BAD_REQUEST, "from must be before to".into()));
+        }
+        if to - from > longest {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("The range must not be longer than {MAX_STATISTICS_RANGE_DAYS} days"),
+            ));
+        }
+        Ok(Some((from, to)))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyClicks {
+    pub date: DateTime<Utc>,
+    pub clicks: i64,
+    pub unique_visitors: i64,
+    /// Hits by email scanners and prefetching browsers, left out of the clicks.
+    pub prefetches: i64,
+}
+
+/// Clicks and unique visitors of a link per UTC day. Visitor hashes only match within a day, so
+/// uniques are not summed over longer periods. Days before the raw window come from rollups.
+pub async fn get_daily_statistics(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Vec<DailyClicks>>, (StatusCode, String)> {
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    ensure_workspace_link_exists(&pool, &link_id, &workspace).await?;
+    let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
+    let daily_clicks = tokio::time::timeout(
+        fetch_statistics_timeout,
+        sqlx::query_as!(
+            DailyClicks,
+            r#"
+                SELECT
+                    day::TIMESTAMP AT TIME ZONE 'UTC' AS "date!",
+                    clicks AS "clicks!",
+                    unique_visitors AS "unique_visitors!",
+                    prefetches AS "prefetches!"
+                FROM link_daily_clicks
+                WHERE link_id = $1 AND day < $2
+                UNION ALL
+                SELECT
+                    date_trunc('day', created_at, 'UTC'),
+                    COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+                    COUNT(DISTINCT visitor_hash) FILTER (WHERE NOT prefetch),
+                    COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+                FROM link_statistics
+                WHERE link_id = $1 AND created_at >= $3
+                GROUP BY 1
+                ORDER BY 1 DESC
+            "#,
+            &link_id,
+            raw_since.date_naive(),
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::debug!("Daily statistics for link with id {} requested", link_id);
+    Ok(Json(daily_clicks))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyClicks {
+    /// ISO day of the week: 1 is Monday, 7 Sunday.
+    pub day_of_week: i32,
+    /// 0 to 23.
+    pub hour: i32,
+    pub clicks: i64,
+}
+
+/// Clicks of a link by UTC hour of the day and day of the week, over all time or between `from`
+/// and `to`. Hours without clicks are left out. Hours before the raw window count whole when they
+/// overlap the range.
+pub async fn get_hourly_statistics(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(link_id): Path<String>,
+    Query(range): Query<StatisticsRange>,
+) -> Result<Json<Vec<HourlyClicks>>, (StatusCode, String)> {
+    let bounds = range.bounds()?;
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    ensure_workspace_link_exists(&pool, &link_id, &workspace).await?;
+    let fetch_statistics_timeout = tokio::time::Duration::from_millis(1000);
+    let hourly_clicks = tokio::time::timeout(
+        fetch_statistics_timeout,
+        sqlx::query_as!(
+            HourlyClicks,
+            r#"
+                SELECT
+                    EXTRACT(ISODOW FROM clicked_at AT TIME ZONE 'UTC')::INT AS "day_of_week!",
+                    EXTRACT(HOUR FROM clicked_at AT TIME ZONE 'UTC')::INT AS "hour!",
+                    SUM(clicks)::BIGINT AS "clicks!"
+                FROM (
+                    SELECT hour AS clicked_at, clicks
+                    FROM link_hourly_clicks
+                    WHERE link_id = $1
+                        AND hour < $4
+                        AND ($2::TIMESTAMPTZ IS NULL OR hour + INTERVAL '1 hour' > $2)
+                        AND ($3::TIMESTAMPTZ IS NULL OR hour < $3)
+                    UNION ALL
+                    SELECT created_at, weight
+                    FROM link_statistics
+                    WHERE link_id = $1
+                        AND NOT prefetch
+                        AND created_at >= $4
+                        AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+                        AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+                ) AS hourly_clicks
+                GROUP BY 1, 2
+                ORDER BY 1, 2
+            "#,
+            &link_id,
+            bounds.map(|(from, _)| from),
+            bounds.map(|(_, to)| to),
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::debug!("Hourly statistics for link with id {} requested", link_id);
+    Ok(Json(hourly_clicks))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakdownEntry {
+    /// `unknown` for clicks that did not tell.
+    pub value: String,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickBreakdown {
+    pub languages: Vec<BreakdownEntry>,
+    pub platforms: Vec<BreakdownEntry>,
+    pub devices: Vec<BreakdownEntry>,
+    /// By `src` of the short URL, `direct` without one.
+    pub channels: Vec<BreakdownEntry>,
+}
+
+/// Clicks of a link by preferred language, platform, device type (`mobile` or `desktop`) and
+/// acquisition channel. Days before the raw window come from rollups.
+pub async fn get_click_breakdown(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(link_id): Path<String>,
+) -> Result<Json<ClickBreakdown>, (StatusCode, String)> {
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    ensure_workspace_link_exists(&pool, &link_id, &workspace).await?;
+    let fetch_breakdown_timeout = tokio::time::Duration::from_millis(1000);
+    let rows = tokio::time::timeout(
+        fetch_breakdown_timeout,
+        sqlx::query!(
+            r#"
+                SELECT
+                    dimension AS "dimension!",
+                    value AS "value!",
+                    SUM(clicks)::BIGINT AS "clicks!"
+                FROM (
+                    SELECT dimension, value, clicks
+                    FROM link_daily_breakdown
+                    WHERE link_id = $1 AND day < $2
+                    UNION ALL
+                    SELECT d.dimension, d.value, s.weight
+                    FROM link_statistics s
+                    CROSS JOIN LATERAL (
+                        VALUES
+                            ('language', COALESCE(s.language, 'unknown')),
+                            ('platform', COALESCE(s.platform, 'unknown')),
+                            (
+                                'device',
+                                CASE s.mobile WHEN true THEN 'mobile' WHEN false THEN 'desktop' ELSE 'unknown' END
+                            ),
+                            ('channel', s.channel)
+                    ) AS d (dimension, value)
+                    WHERE s.link_id = $1 AND NOT s.prefetch AND s.created_at >= $3
+                ) AS breakdown
+                GROUP BY 1, 2
+                ORDER BY 3 DESC, 2
+            "#,
+            &link_id,
+            raw_since.date_naive(),
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    let mut breakdown = ClickBreakdown {
+        languages: Vec::new(),
+        platforms: Vec::new(),
+        devices: Vec::new(),
+        channels: Vec::new(),
+    };
+    for row in rows {
+        let entries = match row.dimension.as_str() {
+            "language" => &mut breakdown.languages,
+            "platform" => &mut breakdown.platforms,
+            "channel" => &mut breakdown.channels,
+            _ => &mut breakdown.devices,
+        };
+        entries.push(BreakdownEntry {
+            value: row.value,
+            clicks: row.clicks,
+        });
+    }
+    tracing::debug!("Click breakdown for link with id {} requested", link_id);
+    Ok(Json(breakdown))
+}
+
+/// A click recorded with a click ID, as `GET /api/clicks/:click_id` tells about it.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentifiedClick {
+    pub click_id: String,
+    pub link_id: String,
+    pub created_at: DateTime<Utc>,
+    pub referer: Option<String>,
+    pub channel: String,
+    pub language: Option<String>,
+    pub platform: Option<String>,
+    pub mobile: Option<bool>,
+    /// Which target of a rollout the click was sent to, if one ran.
+    pub variant: Option<String>,
+}
+
+/// The click a destination was sent with `click_id`, for joining the destination's data back to
+/// it. Only clicks of the caller's workspace are found, and only while they are kept raw.
+pub async fn get_click(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(click_id): Path<String>,
+) -> Result<Json<IdentifiedClick>, (StatusCode, String)> {
+    let fetch_click_timeout = tokio::time::Duration::from_millis(300);
+    let click = tokio::time::timeout(
+        fetch_click_timeout,
+        sqlx::query_as!(
+            IdentifiedClick,
+            r#"
+                SELECT s.click_id AS "click_id!", s.link_id, s.created_at, s.referer, s.channel,
+                    s.language, s.platform, s.mobile, s.variant
+                FROM link_statistics s
+                JOIN links l ON l.id = s.link_id
+                WHERE s.click_id = $1 AND l.workspace_id = $2
+            "#,
+            &click_id,
+            &workspace
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, "Click Not Found".to_string()))?;
+    tracing::debug!("Click with id {} requested", click_id);
+    Ok(Json(click))
+}
@@ -0,0 +1,132 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+use ipnet::IpNet;
+
+use crate::config::SharedConfig;
+
+/// A network like those of `TRUSTED_PROXIES`; a bare address stands for itself.
+#[derive(Clone, Copy, Debug)]
+pub struct IpRange(IpNet);
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.contains(&ip)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ipnet::AddrParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.parse::<IpAddr>() {
+            Ok(ip) => Ok(Self(IpNet::from(ip))),
+            Err(_) => raw.parse().map(Self),
+        }
+    }
+}
+
+fn is_trusted(ip: IpAddr, proxies: &[IpRange]) -> bool {
+    proxies.iter().any(|proxy| proxy.contains(ip))
+}
+
+/// Reads one `for=` value of a `Forwarded` header or one `X-Forwarded-For` entry. Ports,
+/// brackets and quotes are dropped; obfuscated identifiers and `unknown` yield `None`.
+fn parse_node(raw: &str) -> Option<IpAddr> {
+    let node = raw.trim().trim_matches('"');
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split(']').next()?.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        let (host, _port) = node.rsplit_once(':')?;
+        host.parse().ok()
+    })
+}
+
+/// The hops recorded by proxies, nearest last. `Forwarded` wins over `X-Forwarded-For`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value))
+            })
+            .collect();
+    }
+    values("x-forwarded-for")
+        .iter()
+        .map(|entry| parse_node(entry))
+        .collect()
+}
+
+/// Walks the forwarding chain from the peer backwards while the hops are trusted proxies.
+/// The first untrusted hop is the client; proxies are never taken at their word about anything
+/// further away than that.
+fn resolve(peer: IpAddr, headers: &HeaderMap, proxies: &[IpRange]) -> IpAddr {
+    let mut client = peer;
+    if !is_trusted(client, proxies) {
+        return client;
+    }
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(hop, proxies) {
+            break;
+        }
+    }
+    client
+}
+
+/// The address of the client, seen through the configured trusted proxies. Use this instead of
+/// the peer address wherever clients are told apart. A router served without connection info is
+/// a wiring bug and answers 500, rather than telling clients apart wrongly.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    SharedConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            tracing::error!(
+                "No connection info on the request; serve the router with `into_make_service_with_connect_info::<SocketAddr>()`"
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Missing Connection Info".to_string(),
+            ));
+        };
+        let peer = peer.ip();
+        let config = SharedConfig::from_ref(state);
+        let proxies = &config.current().trusted_proxies;
+        Ok(Self(resolve(peer, &parts.headers, proxies)))
+    }
+}
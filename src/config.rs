@@ -0,0 +1,1214 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Display},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::http::HeaderValue;
+use sqlx::PgPool;
+
+use crate::{
+    access_log::AccessLogSink,
+    auth::AuthMethod,
+    cdn::CdnProvider,
+    client_ip::IpRange,
+    db::RetryPolicy,
+    email::{self, SmtpTls},
+    error_report::SentryDsn,
+    id::IdStrategy,
+    logging,
+    notify::NotificationKind,
+    route::RedirectPage,
+    utils::is_valid_slug,
+};
+
+/// Used unless `SLUG_BLOCKLIST` names the words itself.
+const DEFAULT_SLUG_BLOCKLIST: [&str; 12] = [
+    "bitch", "cock", "cunt", "dick", "fag", "fuck", "nazi", "piss", "shit", "slut", "twat", "whore",
+];
+
+/// Used unless `PREFETCH_SCANNER_USER_AGENTS` names the user agents itself.
+const DEFAULT_SCANNER_USER_AGENTS: [&str; 6] = [
+    "barracuda",
+    "proofpoint",
+    "mimecast",
+    "forcepoint",
+    "ironport",
+    "trendmicro",
+];
+
+const DEFAULT_UNFURL_USER_AGENTS: [&str; 8] = [
+    "slackbot",
+    "twitterbot",
+    "facebookexternalhit",
+    "discordbot",
+    "telegrambot",
+    "whatsapp",
+    "linkedinbot",
+    "skypeuripreview",
+];
+
+/// Shorter random slugs collide too often, longer ones exceed a SHA-256 digest.
+const MIN_ID_LENGTH: usize = 6;
+const MAX_ID_LENGTH: usize = 32;
+/// Slugs are at most 64 characters, most of which should stay random.
+const MAX_ID_PREFIX_LENGTH: usize = 16;
+/// Rollups of a day are only final once it is over, so statistics need the recorded clicks of
+/// at least the current day.
+const MIN_STATISTICS_RAW_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Settings read from the environment (and `.env`), overridable at runtime through the
+/// `runtime_settings` table.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub auth: AuthConfig,
+    pub maintenance: MaintenanceConfig,
+    /// Answer every write with 405 and write nothing to the database, clicks included, so the
+    /// instance can serve redirects from a read replica. Meant for the environment of those
+    /// instances, not as a runtime setting. Background jobs are left out when set at startup.
+    pub read_only: bool,
+    pub db_retry: RetryPolicy,
+    /// Consecutive database failures that open the circuit breaker. Read once at startup.
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_open_duration: Duration,
+    /// Requests handled at once before new ones are shed with 503. Read once at startup.
+    pub max_concurrent_requests: usize,
+    /// Port the server listens on. Read once at startup.
+    pub port: u16,
+    /// How long `/health` fails on shutdown before connections are closed, so load balancers
+    /// stop sending requests first.
+    pub shutdown_grace: Duration,
+    /// How long a request may take before it is answered 504.
+    pub request_timeout: Duration,
+    /// The same for bulk endpoints (export, import, purges), including streaming the export.
+    pub bulk_request_timeout: Duration,
+    /// Proxies whose `Forwarded`/`X-Forwarded-For` headers are believed. Without any, clients
+    /// are told apart by the address they connect from.
+    pub trusted_proxies: Vec<IpRange>,
+    pub rate_limit: RateLimitConfig,
+    /// Scheme and host (and path prefix, if any) short links are served under, without a
+    /// trailing slash. Taken from each request's `Host` header when unset.
+    pub base_url: Option<String>,
+    pub https: HttpsConfig,
+    pub redirect_cache_control: String,
+    /// `Cache-Control` of the redirects of links in each named tier, so stable links can be
+    /// cached aggressively and often edited ones barely at all. Links in no tier, or in one no
+    /// longer configured, use `redirect_cache_control`.
+    pub cache_tiers: BTreeMap<String, String>,
+    pub redirect_page: RedirectPage,
+    /// Where visitors of paused links are sent; they are answered 404 when unset.
+    pub paused_link_url: Option<String>,
+    /// How long the interstitial page is shown before refreshing to the target.
+    pub interstitial_delay_secs: u32,
+    /// How many short links further a link targeting another short link is followed before the
+    /// redirect is sent. 0 redirects to the next short link instead.
+    pub link_chain_max_depth: usize,
+    pub cdn: CdnConfig,
+    pub error_reporting: ErrorReportingConfig,
+    /// Only count clicks, without keeping referer or user agent, for every link. Links can also
+    /// opt in one by one.
+    pub privacy_mode: bool,
+    /// Count clicks carrying `DNT: 1` or `Sec-GPC: 1` without referer, user agent or visitor hash.
+    pub honor_do_not_track: bool,
+    /// Query parameter a fresh click ID is appended to the destination of recorded clicks as,
+    /// like `lsid`, for destinations to join their data back to the click. Off when unset.
+    pub click_id_param: Option<String>,
+    pub click_sampling: ClickSamplingConfig,
+    pub prefetch: PrefetchConfig,
+    pub click_writer: ClickWriterConfig,
+    pub access_log: AccessLogConfig,
+    pub link_cache: LinkCacheConfig,
+    /// Icon served for `/favicon.ico`; without one the request is answered with 204.
+    pub favicon_path: Option<String>,
+    /// Served as `/robots.txt`. Short links are kept out of search indexes by default.
+    pub robots_txt: String,
+    /// Scheme added to targets submitted without one; such targets are rejected when unset.
+    pub default_target_scheme: Option<String>,
+    /// Reject targets whose domain mixes scripts in one label, like a Cyrillic `а` in `pаypal.com`.
+    pub reject_homograph_domains: bool,
+    /// Lowercase domains that links may not point to, subdomains included.
+    pub blocked_domains: Vec<String>,
+    /// Lowercase words slugs may not contain. Generated slugs spelling one are regenerated,
+    /// custom ones rejected.
+    pub slug_blocklist: Vec<String>,
+    /// How slugs of new links are generated. Read once at startup.
+    pub id_strategy: IdStrategy,
+    /// Length of `nanoid` and `hash` slugs.
+    pub id_length: usize,
+    /// Put in front of generated slugs. Every region taking writes gets its own, so regions
+    /// never generate the same slug and keep creating links while another one is down.
+    pub id_prefix: Option<String>,
+    /// `ID_PREFIX` of the other regions. Custom slugs may not start with any of the prefixes,
+    /// and links of other regions missing here may still be replicating.
+    pub id_peer_prefixes: Vec<String>,
+    /// Slugs from the `honeypot_slugs` table. Nothing legitimate links to them.
+    pub honeypot_slugs: HashSet<String>,
+    /// Owning workspaces of the namespaces in the `slug_namespaces` table, by prefix.
+    pub slug_namespaces: HashMap<String, String>,
+    /// Pages from the `domain_error_pages` table, by lowercase domain and status.
+    pub error_pages: HashMap<(String, u16), String>,
+    /// How long clients hitting a honeypot are banned; no ban when unset.
+    pub honeypot_ban_duration: Option<Duration>,
+    pub health_check: HealthCheckConfig,
+    /// Timeout of the reachability check of links created with `validate`.
+    pub target_check_timeout: Duration,
+    pub signed_links: SignedLinksConfig,
+    pub conversions: ConversionConfig,
+    pub webhooks: WebhookConfig,
+    pub outbox: OutboxConfig,
+    pub expiry: ExpiryConfig,
+    pub rollouts: RolloutConfig,
+    pub archive: ArchiveConfig,
+    pub statistics: StatisticsConfig,
+    pub notifications: NotificationConfig,
+    pub milestones: MilestoneConfig,
+    pub titles: TitleConfig,
+    pub unfurl: UnfurlConfig,
+    pub email: EmailConfig,
+    pub weekly_reports: WeeklyReportConfig,
+    pub s3: S3Config,
+    pub statistics_export: StatisticsExportConfig,
+    pub logging: LoggingConfig,
+    pub outbound: OutboundConfig,
+}
+
+/// Read once at startup.
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    pub method: AuthMethod,
+    /// The key of the `static` method.
+    pub api_key: Option<String>,
+    /// The HS256 secret of the `jwt` method.
+    pub jwt_secret: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MaintenanceConfig {
+    /// Rejects writes with 503 while redirects keep being served.
+    pub enabled: bool,
+    pub retry_after_secs: u64,
+    /// While enabled, serve only redirects of links in the link cache and answer the others with
+    /// 503, so the database can be taken away entirely.
+    pub cache_only: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    /// Requests a client may make per window; unlimited when unset.
+    pub requests: Option<u32>,
+    pub window: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct CdnConfig {
+    /// Where redirects are purged when links change, see [`CdnProvider`]. The purger is built
+    /// once at startup.
+    pub provider: CdnProvider,
+    /// The API token (Cloudflare) or key (Fastly) allowed to purge.
+    pub api_token: Option<String>,
+    pub cloudflare_zone_id: Option<String>,
+    /// Lets Fastly purge by surrogate key instead of by URL.
+    pub fastly_service_id: Option<String>,
+    pub timeout: Duration,
+    /// Tag redirects with the surrogate keys of their link and workspace. On by default with a
+    /// provider, so purges cover every variant of a short URL.
+    pub surrogate_keys: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorReportingConfig {
+    /// Panics and server errors are sent to this Sentry project. The reporter is built once at
+    /// startup.
+    pub sentry_dsn: Option<String>,
+    /// Or posted as JSON to this URL, for other error trackers.
+    pub webhook_url: Option<String>,
+    /// Tells reports of staging and production instances apart.
+    pub environment: String,
+    /// Reports sent per minute at most, so an outage doesn't flood the tracker.
+    pub max_per_minute: u32,
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClickSamplingConfig {
+    /// Redirects per minute above which a link's clicks are sampled; never sampled when unset.
+    /// Links can also be sampled permanently through their own sample rate.
+    pub threshold_per_minute: Option<u32>,
+    /// Record one in this many clicks of hot links.
+    pub rate: i32,
+}
+
+/// How hits by email scanners and prefetching browsers are told apart from clicks.
+#[derive(Clone, Debug)]
+pub struct PrefetchConfig {
+    /// Lowercase fragments of the user agents of link scanners.
+    pub user_agents: Vec<String>,
+    /// Networks link scanners request from.
+    pub ranges: Vec<IpRange>,
+    /// A `GET` this soon after a `HEAD` of the same link by the same client is a scanner
+    /// following up on its check; off when zero.
+    pub head_window: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClickWriterConfig {
+    pub flush_interval: Duration,
+    /// Most clicks written at once. A full batch is written without waiting for the interval.
+    pub batch_size: usize,
+    /// Batches of at least this many clicks are written with `COPY` instead of an insert.
+    pub copy_threshold: usize,
+    /// Clicks buffered while the database is unavailable, further ones are dropped.
+    pub buffer_limit: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct AccessLogConfig {
+    pub sink: AccessLogSink,
+    /// File the `file` sink appends to.
+    pub path: String,
+    /// Size at which the file is rotated to `<path>.1`.
+    pub max_bytes: u64,
+    /// Rotated files kept besides the current one.
+    pub max_files: usize,
+    /// How long the `database` sink keeps requests; forever when unset.
+    pub retention: Option<Duration>,
+    pub flush_interval: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpsConfig {
+    /// Redirect plain-HTTP requests to HTTPS before anything else is done with them. Requests
+    /// count as HTTPS when a proxy in front says so in `X-Forwarded-Proto` or `Forwarded`.
+    pub enforce: bool,
+    /// Sent as `Strict-Transport-Security` on HTTPS responses when set.
+    pub hsts_max_age: Option<Duration>,
+    pub hsts_include_subdomains: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkCacheConfig {
+    /// Links kept in memory for redirects; nothing is cached at 0.
+    pub capacity: usize,
+    /// How long a cached link is used, in case a change to it was never announced.
+    pub ttl: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct StatisticsConfig {
+    /// How long clicks are kept; forever when unset. Clicks are dropped a month at a time, once
+    /// every click of the month is older than this.
+    pub retention: Option<Duration>,
+    /// Months of partitions created ahead of time.
+    pub partitions_ahead: u32,
+    /// Pause between partition maintenance runs.
+    pub maintenance_interval: Duration,
+    /// Pause between rollups of the current day.
+    pub rollup_interval: Duration,
+    /// How far back statistics endpoints read recorded clicks; older days come from rollups.
+    pub raw_window: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    pub interval: Duration,
+    pub batch_size: i64,
+    /// Pause between two checks so destinations are not hammered.
+    pub request_delay: Duration,
+    pub request_timeout: Duration,
+    /// Deactivate links whose target answered 404/410 this many times in a row.
+    pub auto_disable_after: Option<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SignedLinksConfig {
+    /// HMAC key for signed slugs. Issuing and resolving them is disabled without one.
+    pub key: Option<String>,
+    pub max_ttl_secs: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConversionConfig {
+    /// HMAC key for click tokens. Redirects carry no token and conversions are refused without one.
+    pub key: Option<String>,
+    /// Query parameter the click token is appended to targets as.
+    pub param: String,
+    /// How long after the click a conversion still counts.
+    pub window: Duration,
+}
+
+/// Retries of outbox deliveries: webhook posts, and the notifications and CDN purges of
+/// dispatched events.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub retry_base_delay: Duration,
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutboxConfig {
+    /// Pause between polls while the outbox is empty.
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExpiryConfig {
+    /// Pause between sweeps once every expired link has been processed.
+    pub interval: Duration,
+    pub batch_size: i64,
+    /// How long expired links are kept (answering 410) before they are deleted; kept forever
+    /// when unset.
+    pub retention: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchiveConfig {
+    /// Links not clicked for this long are moved to the archive; nothing is archived when unset.
+    pub unused_for: Option<Duration>,
+    /// Pause between runs once every unused link has been archived.
+    pub interval: Duration,
+    pub batch_size: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct NotificationConfig {
+    /// Incoming webhook URLs notifications are posted to; either can be left unset.
+    pub slack_webhook_url: Option<String>,
+    pub discord_webhook_url: Option<String>,
+    /// Enabled notifications, see [`NotificationKind`]; all of them when unset.
+    pub events: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MilestoneConfig {
+    /// Click counts announced for links without milestones of their own.
+    pub defaults: Vec<i64>,
+    /// Pause between checks once every reached milestone has been announced.
+    pub interval: Duration,
+    pub batch_size: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TitleConfig {
+    /// Fetch the `<title>` of new links' targets in the background.
+    pub enabled: bool,
+    /// Pause between runs once every new link has been looked at.
+    pub interval: Duration,
+    pub batch_size: i64,
+    pub timeout: Duration,
+}
+
+/// Link previews for chat apps and social networks unfurling short links.
+#[derive(Clone, Debug)]
+pub struct UnfurlConfig {
+    /// Answer unfurl bots with a page of OpenGraph and Twitter card tags instead of a redirect.
+    pub enabled: bool,
+    /// Lowercase fragments of the user agents of unfurl bots.
+    pub user_agents: Vec<String>,
+    /// Fill the preview in with the destination's own tags, fetched through the SSRF-safe client.
+    pub proxy_destination: bool,
+    pub timeout: Duration,
+    /// How long the tags of a destination are kept in memory, fetched or not.
+    pub cache_ttl: Duration,
+}
+
+/// The SMTP server emails are sent through; none are sent without `SMTP_HOST`.
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    pub host: Option<String>,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Authenticated with `AUTH PLAIN` when both are set.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender of every email, required with `SMTP_HOST`.
+    pub from: Option<String>,
+    /// Name the service introduces itself with in `EHLO`.
+    pub hello_name: String,
+    /// For a whole email, from connecting to the server's acceptance.
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct WeeklyReportConfig {
+    /// Pause between checks for workspaces due a report of the past week.
+    pub interval: Duration,
+    /// Links listed in each report.
+    pub top_links: i64,
+    pub batch_size: i64,
+}
+
+/// The S3-compatible bucket statistics are exported to.
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// Like `https://s3.eu-west-1.amazonaws.com`, or the address of another S3-compatible store.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Address the bucket in the path rather than the host, as most S3-compatible stores want.
+    pub path_style: bool,
+}
+
+/// Gradual rollouts of new link targets.
+#[derive(Clone, Debug)]
+pub struct RolloutConfig {
+    /// Pause between promotions of finished rollouts, and between probes of rolling out targets
+    /// when `HEALTH_CHECK_ENABLED` is set.
+    pub interval: Duration,
+    /// Longest a rollout may run.
+    pub max_duration: Duration,
+}
+
+/// Daily exports of clicks to `S3_BUCKET`; none without it.
+#[derive(Clone, Debug)]
+pub struct StatisticsExportConfig {
+    /// Put before every object key, like `analytics/`.
+    pub prefix: String,
+    /// Pause between checks for days not exported yet.
+    pub interval: Duration,
+    /// For the upload of a whole day.
+    pub timeout: Duration,
+}
+
+/// Requests the service makes itself: title fetches, health checks, webhooks, notifications and
+/// CDN purges. Timeouts of whole requests are set per use.
+#[derive(Clone, Debug)]
+pub struct OutboundConfig {
+    /// Egress proxy every outbound request goes through. Read once at startup for CDN purges.
+    pub proxy: Option<String>,
+    pub connect_timeout: Duration,
+    /// Redirects followed by title fetches, each destination vetted like the first.
+    pub max_redirects: usize,
+    pub user_agent: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggingConfig {
+    /// Level of the service's own logs.
+    pub level: String,
+    /// Overrides by target, comma-separated `target=level` directives like
+    /// `link_shortener::outbox=debug,sqlx=warn`.
+    pub levels: String,
+    /// Share of requests traced, and of redirects logged, from 0 to 1.
+    pub trace_sample_rate: f64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            levels: String::new(),
+            trace_sample_rate: 0.01,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// The levels as an `EnvFilter` would take them from `RUST_LOG`.
+    pub fn directives(&self) -> String {
+        let mut directives = format!("link_shortener={}", self.level);
+        if !self.levels.trim().is_empty() {
+            directives.push(',');
+            directives.push_str(self.levels.trim());
+        }
+        directives
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Invalid(Vec<String>),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Invalid(problems) => {
+                write!(f, "Invalid configuration: {}", problems.join("; "))
+            }
+            ConfigError::Database(err) => write!(f, "Could not load settings: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<sqlx::Error> for ConfigError {
+    fn from(err: sqlx::Error) -> Self {
+        ConfigError::Database(err)
+    }
+}
+
+/// Where configuration values are looked up: runtime overrides first, then the environment.
+/// Values that fail to parse are collected instead of silently replaced by defaults.
+#[derive(Default)]
+struct Source {
+    overrides: HashMap<String, String>,
+    problems: RefCell<Vec<String>>,
+}
+
+impl Source {
+    fn get<T>(&self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .overrides
+            .get(key)
+            .cloned()
+            .or_else(|| std::env::var(key).ok())
+            .filter(|value| !value.is_empty())?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(err) => {
+                self.problems
+                    .borrow_mut()
+                    .push(format!("{key} has an invalid value {value:?}: {err}"));
+                None
+            }
+        }
+    }
+
+    fn get_or<T>(&self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get(key).unwrap_or(default)
+    }
+
+    fn get_parsed_list<T>(&self, key: &str) -> Option<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let items = self.get::<String>(key)?;
+        let mut parsed = Vec::new();
+        for item in items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match item.parse() {
+                Ok(value) => parsed.push(value),
+                Err(err) => self
+                    .problems
+                    .borrow_mut()
+                    .push(format!("{key} has an invalid item {item:?}: {err}")),
+            }
+        }
+        Some(parsed)
+    }
+
+    fn get_list(&self, key: &str) -> Vec<String> {
+        self.get::<String>(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|item| item.trim().to_lowercase())
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Config {
+    fn from_source(source: &Source) -> Result<Self, ConfigError> {
+        let cache_max_age: u32 = source.get_or("REDIRECT_CACHE_MAX_AGE_SECS", 300);
+        let cdn_provider = source.get_or("CDN_PROVIDER", CdnProvider::None);
+        let redirect_cache_control = format!(
+            "public, max-age={cache_max_age}, s-maxage={cache_max_age}, stale-while-revalidate={cache_max_age}, stale-if-error={cache_max_age}"
+        );
+        let mut cache_tiers = BTreeMap::from([
+            (
+                "immutable".to_string(),
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+            ("standard".to_string(), redirect_cache_control.clone()),
+            ("realtime".to_string(), "no-cache".to_string()),
+        ]);
+        // `;`-separated, as Cache-Control values have commas of their own.
+        for entry in source
+            .get::<String>("CACHE_TIERS")
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            match entry.split_once('=') {
+                Some((name, value))
+                    if is_valid_slug(name.trim())
+                        && !value.trim().is_empty()
+                        && HeaderValue::from_str(value.trim()).is_ok() =>
+                {
+                    cache_tiers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                }
+                _ => source.problems.borrow_mut().push(format!(
+                    "CACHE_TIERS has an invalid entry {entry:?}, expected name=Cache-Control value"
+                )),
+            }
+        }
+        let config = Self {
+            auth: AuthConfig {
+                method: source.get_or("AUTH_METHOD", AuthMethod::Database),
+                api_key: source.get("AUTH_API_KEY"),
+                jwt_secret: source.get("AUTH_JWT_SECRET"),
+            },
+            maintenance: MaintenanceConfig {
+                enabled: source.get_or("MAINTENANCE_MODE", false),
+                retry_after_secs: source.get_or("MAINTENANCE_RETRY_AFTER_SECS", 120),
+                cache_only: source.get_or("MAINTENANCE_CACHE_ONLY", false),
+            },
+            read_only: source.get_or("READ_ONLY", false),
+            db_retry: RetryPolicy {
+                max_attempts: source.get_or("DB_RETRY_ATTEMPTS", 3),
+                base_delay: Duration::from_millis(source.get_or("DB_RETRY_BASE_DELAY_MS", 20)),
+                max_delay: Duration::from_millis(source.get_or("DB_RETRY_MAX_DELAY_MS", 100)),
+            },
+            circuit_breaker_threshold: source.get_or("CIRCUIT_BREAKER_FAILURE_THRESHOLD", 5),
+            circuit_breaker_open_duration: Duration::from_secs(
+                source.get_or("CIRCUIT_BREAKER_OPEN_SECS", 10),
+            ),
+            max_concurrent_requests: source.get_or("MAX_CONCURRENT_REQUESTS", 512),
+            port: source.get_or("PORT", 3000),
+            shutdown_grace: Duration::from_secs(source.get_or("SHUTDOWN_GRACE_SECS", 10)),
+            request_timeout: Duration::from_secs(source.get_or("REQUEST_TIMEOUT_SECS", 30).max(1)),
+            bulk_request_timeout: Duration::from_secs(
+                source.get_or("BULK_REQUEST_TIMEOUT_SECS", 30 * 60).max(1),
+            ),
+            trusted_proxies: source
+                .get_parsed_list("TRUSTED_PROXIES")
+                .unwrap_or_default(),
+            rate_limit: RateLimitConfig {
+                requests: source.get("RATE_LIMIT_REQUESTS"),
+                window: Duration::from_secs(source.get_or("RATE_LIMIT_WINDOW_SECS", 60).max(1)),
+            },
+            base_url: source
+                .get::<url::Url>("BASE_URL")
+                .map(|url| url.as_str().trim_end_matches('/').to_string()),
+            paused_link_url: source
+                .get::<url::Url>("PAUSED_LINK_URL")
+                .map(|url| url.to_string()),
+            https: HttpsConfig {
+                enforce: source.get_or("FORCE_HTTPS", false),
+                hsts_max_age: source.get("HSTS_MAX_AGE_SECS").map(Duration::from_secs),
+                hsts_include_subdomains: source.get_or("HSTS_INCLUDE_SUBDOMAINS", false),
+            },
+            redirect_cache_control,
+            cache_tiers,
+            redirect_page: source.get_or("REDIRECT_PAGE", RedirectPage::None),
+            interstitial_delay_secs: source.get_or("REDIRECT_INTERSTITIAL_DELAY_SECS", 0),
+            link_chain_max_depth: source.get_or("LINK_CHAIN_MAX_DEPTH", 5),
+            cdn: CdnConfig {
+                provider: cdn_provider,
+                api_token: source.get("CDN_API_TOKEN"),
+                cloudflare_zone_id: source.get("CLOUDFLARE_ZONE_ID"),
+                fastly_service_id: source.get("FASTLY_SERVICE_ID"),
+                timeout: Duration::from_millis(source.get_or("CDN_PURGE_TIMEOUT_MS", 5000)),
+                surrogate_keys: source
+                    .get_or("CDN_SURROGATE_KEYS", cdn_provider != CdnProvider::None),
+            },
+            error_reporting: ErrorReportingConfig {
+                sentry_dsn: source.get("SENTRY_DSN"),
+                webhook_url: source.get("ERROR_REPORT_URL"),
+                environment: source.get_or("ERROR_REPORT_ENVIRONMENT", "production".to_string()),
+                max_per_minute: source.get_or("ERROR_REPORT_MAX_PER_MINUTE", 60),
+                timeout: Duration::from_millis(source.get_or("ERROR_REPORT_TIMEOUT_MS", 5000)),
+            },
+            privacy_mode: source.get_or("PRIVACY_MODE", false),
+            honor_do_not_track: source.get_or("HONOR_DO_NOT_TRACK", false),
+            click_id_param: source.get("CLICK_ID_PARAM"),
+            click_sampling: ClickSamplingConfig {
+                threshold_per_minute: source.get("CLICK_SAMPLING_THRESHOLD_PER_MINUTE"),
+                rate: source.get_or("CLICK_SAMPLING_RATE", 100i32).max(1),
+            },
+            prefetch: PrefetchConfig {
+                user_agents: Some(source.get_list("PREFETCH_SCANNER_USER_AGENTS"))
+                    .filter(|user_agents| !user_agents.is_empty())
+                    .unwrap_or_else(|| DEFAULT_SCANNER_USER_AGENTS.map(str::to_string).to_vec()),
+                ranges: source
+                    .get_parsed_list("PREFETCH_SCANNER_RANGES")
+                    .unwrap_or_default(),
+                head_window: Duration::from_secs(source.get_or("PREFETCH_HEAD_WINDOW_SECS", 10)),
+            },
+            click_writer: ClickWriterConfig {
+                flush_interval: Duration::from_millis(
+                    source.get_or("CLICK_FLUSH_INTERVAL_MS", 1000),
+                ),
+                batch_size: source.get_or("CLICK_BATCH_SIZE", 5000usize).max(1),
+                copy_threshold: source.get_or("CLICK_COPY_THRESHOLD", 500),
+                buffer_limit: source.get_or("CLICK_BUFFER_LIMIT", 100_000),
+            },
+            access_log: AccessLogConfig {
+                sink: source.get_or("ACCESS_LOG", AccessLogSink::None),
+                path: source.get_or("ACCESS_LOG_PATH", "access.log".to_string()),
+                max_bytes: source.get_or("ACCESS_LOG_MAX_BYTES", 100 * 1024 * 1024),
+                max_files: source.get_or("ACCESS_LOG_MAX_FILES", 5),
+                retention: source
+                    .get("ACCESS_LOG_RETENTION_DAYS")
+                    .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+                flush_interval: Duration::from_millis(
+                    source.get_or("ACCESS_LOG_FLUSH_INTERVAL_MS", 1000),
+                ),
+            },
+            link_cache: LinkCacheConfig {
+                capacity: source.get_or("LINK_CACHE_CAPACITY", 10_000),
+                ttl: Duration::from_secs(source.get_or("LINK_CACHE_TTL_SECS", 60)),
+            },
+            favicon_path: source.get("FAVICON_PATH"),
+            // Environment variables cannot hold line breaks everywhere, so `\n` is accepted too.
+            robots_txt: source
+                .get::<String>("ROBOTS_TXT")
+                .map(|robots_txt| robots_txt.replace("\\n", "\n"))
+                .unwrap_or_else(|| "User-agent: *\nDisallow: /\n".to_string()),
+            // `none` turns the default off.
+            default_target_scheme: Some(
+                source.get_or("DEFAULT_TARGET_SCHEME", "https".to_string()),
+            )
+            .filter(|scheme| scheme != "none"),
+            reject_homograph_domains: source.get_or("REJECT_HOMOGRAPH_DOMAINS", false),
+            blocked_domains: source.get_list("BLOCKED_DOMAINS"),
+            slug_blocklist: Some(source.get_list("SLUG_BLOCKLIST"))
+                .filter(|words| !words.is_empty())
+                .unwrap_or_else(|| DEFAULT_SLUG_BLOCKLIST.map(str::to_string).to_vec()),
+            id_strategy: source.get_or("ID_STRATEGY", IdStrategy::Random),
+            id_length: source.get_or("ID_LENGTH", 10),
+            id_prefix: source.get("ID_PREFIX"),
+            id_peer_prefixes: source
+                .get_parsed_list("ID_PEER_PREFIXES")
+                .unwrap_or_default(),
+            honeypot_slugs: HashSet::new(),
+            slug_namespaces: HashMap::new(),
+            error_pages: HashMap::new(),
+            honeypot_ban_duration: source.get("HONEYPOT_BAN_SECS").map(Duration::from_secs),
+            health_check: HealthCheckConfig {
+                enabled: source.get_or("HEALTH_CHECK_ENABLED", false),
+                interval: Duration::from_secs(source.get_or("HEALTH_CHECK_INTERVAL_SECS", 60)),
+                batch_size: source.get_or("HEALTH_CHECK_BATCH_SIZE", 50),
+                request_delay: Duration::from_millis(source.get_or("HEALTH_CHECK_DELAY_MS", 250)),
+                request_timeout: Duration::from_millis(
+                    source.get_or("HEALTH_CHECK_TIMEOUT_MS", 5000),
+                ),
+                auto_disable_after: source.get("HEALTH_CHECK_AUTO_DISABLE_AFTER"),
+            },
+            target_check_timeout: Duration::from_millis(
+                source.get_or("TARGET_CHECK_TIMEOUT_MS", 3000),
+            ),
+            signed_links: SignedLinksConfig {
+                key: source.get("LINK_SIGNING_KEY"),
+                max_ttl_secs: source.get_or("SIGNED_LINK_MAX_TTL_SECS", 30 * 24 * 60 * 60),
+            },
+            conversions: ConversionConfig {
+                key: source.get("CONVERSION_KEY"),
+                param: source.get_or("CONVERSION_PARAM", "click_token".to_string()),
+                window: Duration::from_secs(
+                    source.get_or::<u64>("CONVERSION_WINDOW_DAYS", 30) * 24 * 60 * 60,
+                ),
+            },
+            webhooks: WebhookConfig {
+                max_attempts: source.get_or("WEBHOOK_MAX_ATTEMPTS", 5),
+                retry_base_delay: Duration::from_millis(
+                    source.get_or("WEBHOOK_RETRY_BASE_DELAY_MS", 1000),
+                ),
+                timeout: Duration::from_millis(source.get_or("WEBHOOK_TIMEOUT_MS", 5000)),
+            },
+            outbox: OutboxConfig {
+                poll_interval: Duration::from_millis(
+                    source.get_or("OUTBOX_POLL_INTERVAL_MS", 1000),
+                ),
+                batch_size: source.get_or("OUTBOX_BATCH_SIZE", 100),
+            },
+            expiry: ExpiryConfig {
+                interval: Duration::from_secs(source.get_or("EXPIRY_SWEEP_INTERVAL_SECS", 60)),
+                batch_size: source.get_or("EXPIRY_SWEEP_BATCH_SIZE", 500),
+                retention: source
+                    .get("EXPIRED_LINK_RETENTION_DAYS")
+                    .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+            },
+            archive: ArchiveConfig {
+                unused_for: source
+                    .get("ARCHIVE_UNUSED_AFTER_DAYS")
+                    .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+                interval: Duration::from_secs(source.get_or("ARCHIVE_INTERVAL_SECS", 60 * 60)),
+                batch_size: source.get_or("ARCHIVE_BATCH_SIZE", 500),
+            },
+            statistics: StatisticsConfig {
+                retention: source
+                    .get("STATISTICS_RETENTION_DAYS")
+                    .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+                partitions_ahead: source.get_or("STATISTICS_PARTITIONS_AHEAD", 2),
+                maintenance_interval: Duration::from_secs(
+                    source.get_or("STATISTICS_MAINTENANCE_INTERVAL_SECS", 60 * 60),
+                ),
+                rollup_interval: Duration::from_secs(source.get_or("ROLLUP_INTERVAL_SECS", 300)),
+                raw_window: Duration::from_secs(
+                    source.get_or("STATISTICS_RAW_WINDOW_HOURS", 48) * 60 * 60,
+                ),
+            },
+            notifications: NotificationConfig {
+                slack_webhook_url: source.get("SLACK_WEBHOOK_URL"),
+                discord_webhook_url: source.get("DISCORD_WEBHOOK_URL"),
+                events: source.get_parsed_list("NOTIFY_EVENTS").unwrap_or_else(|| {
+                    NotificationKind::ALL
+                        .iter()
+                        .map(|kind| kind.as_str().to_string())
+                        .collect()
+                }),
+            },
+            milestones: MilestoneConfig {
+                defaults: source
+                    .get_parsed_list("NOTIFY_CLICK_MILESTONES")
+                    .unwrap_or_else(|| vec![100, 1_000, 10_000, 100_000, 1_000_000]),
+                interval: Duration::from_secs(source.get_or("MILESTONE_CHECK_INTERVAL_SECS", 60)),
+                batch_size: source.get_or("MILESTONE_CHECK_BATCH_SIZE", 500),
+            },
+            titles: TitleConfig {
+                enabled: source.get_or("FETCH_TITLES", false),
+                interval: Duration::from_secs(source.get_or("TITLE_FETCH_INTERVAL_SECS", 10)),
+                batch_size: source.get_or("TITLE_FETCH_BATCH_SIZE", 20),
+                timeout: Duration::from_millis(source.get_or("TITLE_FETCH_TIMEOUT_MS", 5000)),
+            },
+            unfurl: UnfurlConfig {
+                enabled: source.get_or("UNFURL_PREVIEWS", false),
+                user_agents: Some(source.get_list("UNFURL_BOT_USER_AGENTS"))
+                    .filter(|user_agents| !user_agents.is_empty())
+                    .unwrap_or_else(|| DEFAULT_UNFURL_USER_AGENTS.map(str::to_string).to_vec()),
+                proxy_destination: source.get_or("UNFURL_PROXY_DESTINATION", false),
+                timeout: Duration::from_millis(source.get_or("UNFURL_TIMEOUT_MS", 2000)),
+                cache_ttl: Duration::from_secs(source.get_or("UNFURL_CACHE_TTL_SECS", 3600)),
+            },
+            email: EmailConfig {
+                host: source.get("SMTP_HOST"),
+                port: source.get_or("SMTP_PORT", 587),
+                tls: source.get_or("SMTP_TLS", SmtpTls::StartTls),
+                username: source.get("SMTP_USERNAME"),
+                password: source.get("SMTP_PASSWORD"),
+                from: source.get("EMAIL_FROM"),
+                hello_name: source.get_or("SMTP_HELLO_NAME", "localhost".to_string()),
+                timeout: Duration::from_secs(source.get_or("SMTP_TIMEOUT_SECS", 30)),
+            },
+            weekly_reports: WeeklyReportConfig {
+                interval: Duration::from_secs(source.get_or("WEEKLY_REPORT_INTERVAL_SECS", 3600)),
+                top_links: source.get_or("WEEKLY_REPORT_TOP_LINKS", 5),
+                batch_size: source.get_or("WEEKLY_REPORT_BATCH_SIZE", 20),
+            },
+            s3: S3Config {
+                endpoint: source.get_or("S3_ENDPOINT", "https://s3.amazonaws.com".to_string()),
+                region: source.get_or("S3_REGION", "us-east-1".to_string()),
+                bucket: source.get("S3_BUCKET"),
+                access_key_id: source.get("S3_ACCESS_KEY_ID"),
+                secret_access_key: source.get("S3_SECRET_ACCESS_KEY"),
+                path_style: source.get_or("S3_PATH_STYLE", false),
+            },
+            rollouts: RolloutConfig {
+                interval: Duration::from_secs(source.get_or("ROLLOUT_INTERVAL_SECS", 60)),
+                max_duration: Duration::from_secs(
+                    source.get_or("ROLLOUT_MAX_DURATION_SECS", 30 * 24 * 60 * 60),
+                ),
+            },
+            statistics_export: StatisticsExportConfig {
+                prefix: source.get_or("STATISTICS_EXPORT_PREFIX", String::new()),
+                interval: Duration::from_secs(
+                    source.get_or("STATISTICS_EXPORT_INTERVAL_SECS", 3600),
+                ),
+                timeout: Duration::from_secs(source.get_or("STATISTICS_EXPORT_TIMEOUT_SECS", 300)),
+            },
+            outbound: OutboundConfig {
+                proxy: source
+                    .get::<url::Url>("OUTBOUND_PROXY")
+                    .map(|url| url.to_string()),
+                connect_timeout: Duration::from_millis(
+                    source.get_or("OUTBOUND_CONNECT_TIMEOUT_MS", 2000),
+                ),
+                max_redirects: source.get_or("OUTBOUND_MAX_REDIRECTS", 5),
+                user_agent: source.get_or(
+                    "OUTBOUND_USER_AGENT",
+                    concat!("link-shortener/", env!("CARGO_PKG_VERSION")).to_string(),
+                ),
+            },
+            logging: LoggingConfig {
+                level: source.get_or("LOG_LEVEL", LoggingConfig::default().level),
+                levels: source.get_or("LOG_LEVELS", String::new()),
+                trace_sample_rate: source.get_or(
+                    "TRACE_SAMPLE_RATE",
+                    LoggingConfig::default().trace_sample_rate,
+                ),
+            },
+        };
+        if let Some(scheme) = config
+            .default_target_scheme
+            .as_deref()
+            .filter(|scheme| !matches!(*scheme, "http" | "https"))
+        {
+            source.problems.borrow_mut().push(format!(
+                "DEFAULT_TARGET_SCHEME must be http, https or none, not {scheme:?}"
+            ));
+        }
+        if config.conversions.param.is_empty()
+            || !config
+                .conversions
+                .param
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            source
+                .problems
+                .borrow_mut()
+                .push("CONVERSION_PARAM must be letters, digits, '_' or '-'".to_string());
+        }
+        if config.email.host.is_some()
+            && !config
+                .email
+                .from
+                .as_deref()
+                .is_some_and(email::is_valid_address)
+        {
+            source.problems.borrow_mut().push(
+                "SMTP_HOST needs EMAIL_FROM, an address like reports@example.com".to_string(),
+            );
+        }
+        if config.s3.bucket.is_some() {
+            if config.s3.access_key_id.is_none() || config.s3.secret_access_key.is_none() {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push("S3_BUCKET needs S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY".to_string());
+            }
+            if url::Url::parse(&config.s3.endpoint).map_or(true, |endpoint| !endpoint.has_host()) {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push("S3_ENDPOINT must be an http or https URL".to_string());
+            }
+        }
+        if config.base_url.as_deref().is_some_and(|base_url| {
+            !base_url.starts_with("https://") && !base_url.starts_with("http://")
+        }) {
+            source
+                .problems
+                .borrow_mut()
+                .push("BASE_URL must be an http or https URL".to_string());
+        }
+        match config.auth.method {
+            AuthMethod::Static if config.auth.api_key.is_none() => source
+                .problems
+                .borrow_mut()
+                .push("AUTH_METHOD static needs AUTH_API_KEY".to_string()),
+            AuthMethod::Jwt if config.auth.jwt_secret.is_none() => source
+                .problems
+                .borrow_mut()
+                .push("AUTH_METHOD jwt needs AUTH_JWT_SECRET".to_string()),
+            _ => {}
+        }
+        if config.cdn.provider != CdnProvider::None {
+            // Purges run in the background, without a request `Host` to build short URLs from.
+            let purges_by_key = config.cdn.surrogate_keys
+                && (config.cdn.provider == CdnProvider::Cloudflare
+                    || config.cdn.fastly_service_id.is_some());
+            if config.base_url.is_none() && !purges_by_key {
+                source.problems.borrow_mut().push(
+                    "CDN_PROVIDER needs BASE_URL unless it purges by surrogate key (CDN_SURROGATE_KEYS, and FASTLY_SERVICE_ID for fastly)".to_string(),
+                );
+            }
+            if config.cdn.api_token.is_none() {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push("CDN_PROVIDER needs CDN_API_TOKEN".to_string());
+            }
+            if config.cdn.provider == CdnProvider::Cloudflare
+                && config.cdn.cloudflare_zone_id.is_none()
+            {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push("CDN_PROVIDER cloudflare needs CLOUDFLARE_ZONE_ID".to_string());
+            }
+        }
+        if let Some(dsn) = &config.error_reporting.sentry_dsn {
+            if let Err(err) = SentryDsn::parse(dsn) {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push(format!("SENTRY_DSN is not a valid DSN: {err}"));
+            }
+            if config.error_reporting.webhook_url.is_some() {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push("Set either SENTRY_DSN or ERROR_REPORT_URL, not both".to_string());
+            }
+        }
+        if config.statistics.raw_window < MIN_STATISTICS_RAW_WINDOW {
+            source
+                .problems
+                .borrow_mut()
+                .push("STATISTICS_RAW_WINDOW_HOURS must be at least 24".to_string());
+        }
+        if !(MIN_ID_LENGTH..=MAX_ID_LENGTH).contains(&config.id_length) {
+            source.problems.borrow_mut().push(format!(
+                "ID_LENGTH must be between {MIN_ID_LENGTH} and {MAX_ID_LENGTH}"
+            ));
+        }
+        for prefix in config.id_prefix.iter().chain(&config.id_peer_prefixes) {
+            if !is_valid_slug(prefix) || prefix.len() > MAX_ID_PREFIX_LENGTH {
+                source.problems.borrow_mut().push(format!(
+                    "ID_PREFIX and ID_PEER_PREFIXES must be slugs of at most {MAX_ID_PREFIX_LENGTH} characters, not {prefix:?}"
+                ));
+            }
+        }
+        if config
+            .id_prefix
+            .as_ref()
+            .is_some_and(|prefix| config.id_peer_prefixes.contains(prefix))
+        {
+            source
+                .problems
+                .borrow_mut()
+                .push("ID_PEER_PREFIXES must not contain ID_PREFIX".to_string());
+        }
+        for event in &config.notifications.events {
+            if !NotificationKind::ALL
+                .iter()
+                .any(|kind| kind.as_str() == event)
+            {
+                source
+                    .problems
+                    .borrow_mut()
+                    .push(format!("NOTIFY_EVENTS has an unknown event {event:?}"));
+            }
+        }
+        if let Some(proxy) = config.outbound.proxy.as_deref().filter(|proxy| {
+            !proxy.starts_with("http://") && !proxy.starts_with("https://")
+                || reqwest::Proxy::all(*proxy).is_err()
+        }) {
+            source.problems.borrow_mut().push(format!(
+                "OUTBOUND_PROXY must be an http or https URL, not {proxy:?}"
+            ));
+        }
+        if let Err(err) = tracing_subscriber::EnvFilter::try_new(config.logging.directives()) {
+            source
+                .problems
+                .borrow_mut()
+                .push(format!("LOG_LEVEL or LOG_LEVELS is invalid: {err}"));
+        }
+        if !(0.0..=1.0).contains(&config.logging.trace_sample_rate) {
+            source
+                .problems
+                .borrow_mut()
+                .push("TRACE_SAMPLE_RATE must be between 0 and 1".to_string());
+        }
+        let problems = source.problems.take();
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// Builds the configuration from the environment, the `runtime_settings` overrides and the
+    /// `blocked_domains`, `honeypot_slugs`, `slug_namespaces` and `domain_error_pages` tables.
+    pub async fn load(pool: &PgPool) -> Result<Self, ConfigError> {
+        let overrides = sqlx::query!("SELECT key, value FROM runtime_settings")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|setting| (setting.key, setting.value))
+            .collect();
+        let mut config = Self::from_source(&Source {
+            overrides,
+            ..Default::default()
+        })?;
+
+        let blocked_domains = sqlx::query_scalar!("SELECT domain FROM blocked_domains")
+            .fetch_all(pool)
+            .await?;
+        config.blocked_domains.extend(
+            blocked_domains
+                .into_iter()
+                .map(|domain| domain.to_lowercase()),
+        );
+        config.honeypot_slugs = sqlx::query_scalar!("SELECT slug FROM honeypot_slugs")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+        config.slug_namespaces = Self::load_slug_namespaces(pool).await?;
+        config.error_pages = sqlx::query!("SELECT domain, status, html FROM domain_error_pages")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|page| ((page.domain, page.status as u16), page.html))
+            .collect();
+        Ok(config)
+    }
+
+    async fn load_slug_namespaces(pool: &PgPool) -> Result<HashMap<String, String>, sqlx::Error> {
+        Ok(
+            sqlx::query!("SELECT prefix, workspace_id FROM slug_namespaces")
+                .fetch_all(pool)
+                .await?
+                .into_iter()
+                .map(|namespace| (namespace.prefix, namespace.workspace_id))
+                .collect(),
+        )
+    }
+
+    pub fn is_blocked_host(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.blocked_domains.iter().any(|domain| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+}
+
+/// The current configuration, swapped atomically on reload.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        logging::apply(&config.logging);
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.0.read().expect("Config lock poisoned").clone()
+    }
+
+    /// Re-reads `.env` (its values win over the inherited environment from here on) and the
+    /// database-backed settings.
+    pub async fn reload(&self, pool: &PgPool) -> Result<(), ConfigError> {
+        dotenvy::dotenv_override().ok();
+        let config = Config::load(pool).await?;
+        logging::apply(&config.logging);
+        *self.0.write().expect("Config lock poisoned") = Arc::new(config);
+        tracing::info!("Configuration reloaded");
+        Ok(())
+    }
+
+    /// Re-reads the `slug_namespaces` table only, leaving the rest of the configuration as it was
+    /// loaded.
+    pub async fn reload_slug_namespaces(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let slug_namespaces = Config::load_slug_namespaces(pool).await?;
+        let mut current = self.0.write().expect("Config lock poisoned");
+        let mut config = Config::clone(&current);
+        config.slug_namespaces = slug_namespaces;
+        *current = Arc::new(config);
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use metrics::counter;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha3::Sha3_256;
+use sqlx::PgPool;
+
+use crate::{
+    config::{ConversionConfig, SharedConfig},
+    route::ensure_link_exists,
+    utils::internal_error,
+};
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// Length of the truncated MAC appended to click tokens.
+const SIGNATURE_LEN: usize = 16;
+const NONCE_LEN: usize = 8;
+
+/// The click a token was issued for.
+#[derive(Debug)]
+struct TokenClick {
+    link_id: String,
+    clicked_at: DateTime<Utc>,
+}
+
+fn mac(key: &str) -> HmacSha3 {
+    HmacSha3::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length")
+}
+
+/// A token for a click of `link_id` now, `<payload>.<signature>`. Payload layout: click time as
+/// big-endian unix seconds, a random nonce telling clicks in the same second apart and the link
+/// id.
+fn issue(key: &str, link_id: &str) -> String {
+    let mut payload = Utc::now().timestamp().to_be_bytes().to_vec();
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    payload.extend_from_slice(&nonce);
+    payload.extend_from_slice(link_id.as_bytes());
+
+    let mut mac = mac(key);
+    mac.update(&payload);
+    let signature = mac.finalize().into_bytes();
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(&payload),
+        URL_SAFE_NO_PAD.encode(&signature[..SIGNATURE_LEN])
+    )
+}
+
+/// Checks the signature of a click token without touching the database.
+fn verify(key: &str, token: &str) -> Option<TokenClick> {
+    let (payload, signature) = token.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    if signature.len() != SIGNATURE_LEN || payload.len() <= 8 + NONCE_LEN {
+        return None;
+    }
+
+    let mut mac = mac(key);
+    mac.update(&payload);
+    mac.verify_truncated_left(&signature).ok()?;
+
+    let (clicked_at, rest) = payload.split_at(8);
+    let clicked_at = i64::from_be_bytes(clicked_at.try_into().expect("Split at eight bytes"));
+    Some(TokenClick {
+        link_id: String::from_utf8(rest[NONCE_LEN..].to_vec()).ok()?,
+        clicked_at: DateTime::from_timestamp(clicked_at, 0)?,
+    })
+}
+
+/// `target_url` with a fresh click token of `link_id` appended as `CONVERSION_PARAM`, for the
+/// destination to report a conversion with. `None` when conversions are not configured or the
+/// target is not a URL.
+pub fn tag_target(target_url: &str, link_id: &str, config: &ConversionConfig) -> Option<String> {
+    let key = config.key.as_deref()?;
+    let mut url = url::Url::parse(target_url).ok()?;
+    url.query_pairs_mut()
+        .append_pair(&config.param, &issue(key, link_id));
+    Some(url.into())
+}
+
+/// Conversions per click, 0 without clicks.
+pub fn conversion_rate(conversions: i64, clicks: i64) -> f64 {
+    if clicks == 0 {
+        0.0
+    } else {
+        conversions as f64 / clicks as f64
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewConversion {
+    /// The click token the destination was redirected with.
+    pub token: String,
+}
+
+/// `POST /api/conversions`: records that the click a token was issued for converted. Called by
+/// destinations without an API key, the token vouches for the click. Reporting a click again
+/// changes nothing.
+pub async fn record_conversion(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Json(conversion): Json<NewConversion>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let config = config.current();
+    let Some(key) = config.conversions.key.as_deref() else {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "Conversions Not Configured".into(),
+        ));
+    };
+    let Some(click) = verify(key, &conversion.token) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid Token".into()));
+    };
+    let age = (Utc::now() - click.clicked_at).to_std().unwrap_or_default();
+    if age > config.conversions.window {
+        return Err((StatusCode::GONE, "Conversion Window Closed".into()));
+    }
+    ensure_link_exists(&pool, &click.link_id).await?;
+
+    let record_conversion_timeout = tokio::time::Duration::from_millis(300);
+    let recorded = tokio::time::timeout(
+        record_conversion_timeout,
+        sqlx::query!(
+            r#"
+                INSERT INTO conversions (token, link_id, clicked_at)
+                SELECT $1, $2, $3
+                WHERE EXISTS (SELECT 1 FROM links WHERE id = $2)
+                ON CONFLICT (token) DO NOTHING
+            "#,
+            &conversion.token,
+            &click.link_id,
+            click.clicked_at
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if recorded.rows_affected() > 0 {
+        counter!("conversions_recorded").increment(1);
+        tracing::debug!("Recorded a conversion of link with id {}", click.link_id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionStatistics {
+    pub clicks: i64,
+    pub conversions: i64,
+    pub conversion_rate: f64,
+}
+
+/// Clicks, conversions and their ratio of a link over all time.
+pub async fn get_conversion_statistics(
+    State(pool): State<PgPool>,
+    Path(link_id): Path<String>,
+) -> Result<Json<ConversionStatistics>, (StatusCode, String)> {
+    let fetch_statistics_timeout = tokio::time::Duration::from_millis(300);
+    let statistics = tokio::time::timeout(
+        fetch_statistics_timeout,
+        sqlx::query!(
+            r#"
+                SELECT
+                    l.click_count,
+                    (SELECT COUNT(*) FROM conversions c WHERE c.link_id = l.id) AS "conversions!"
+                FROM links l
+                WHERE l.id = $1
+            "#,
+            &link_id
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    tracing::debug!(
+        "Conversion statistics for link with id {} requested",
+        link_id
+    );
+    Ok(Json(ConversionStatistics {
+        clicks: statistics.click_count,
+        conversions: statistics.conversions,
+        conversion_rate: conversion_rate(statistics.conversions, statistics.click_count),
+    }))
+}
@@ -0,0 +1,137 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{auth::Workspace, utils::internal_error};
+
+/// Days covered by the sparkline and the top lists.
+const DASHBOARD_DAYS: u64 = 30;
+/// Length of the top links and top referrers lists.
+const TOP_ENTRIES: i64 = 5;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopLink {
+    pub id: String,
+    pub target_url: String,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopReferer {
+    /// `None` for clicks without a referer.
+    pub host: Option<String>,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DayClicks {
+    pub day: NaiveDate,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dashboard {
+    pub links_created: i64,
+    /// Rolled up clicks of the workspace's links, over all time.
+    pub total_clicks: i64,
+    pub top_links: Vec<TopLink>,
+    pub top_referers: Vec<TopReferer>,
+    /// One entry per UTC day, oldest first and ending today, including days without clicks.
+    pub daily_clicks: Vec<DayClicks>,
+}
+
+/// Totals of the workspace for its dashboard, in one request. Clicks come from the daily
+/// rollups, so the most recent ones show up within `ROLLUP_INTERVAL_SECS`. The top lists cover
+/// the last 30 days.
+pub async fn get_dashboard(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<Json<Dashboard>, (StatusCode, String)> {
+    let today = Utc::now().date_naive();
+    let since = today - Days::new(DASHBOARD_DAYS - 1);
+    let totals = sqlx::query!(
+        r#"
+            SELECT
+                (SELECT COUNT(*) FROM links WHERE workspace_id = $1) AS "links_created!",
+                (
+                    SELECT COALESCE(SUM(d.clicks), 0)
+                    FROM link_daily_clicks d
+                    JOIN links l ON l.id = d.link_id
+                    WHERE l.workspace_id = $1
+                )::BIGINT AS "total_clicks!"
+        "#,
+        &workspace
+    )
+    .fetch_one(&pool);
+    let top_links = sqlx::query_as!(
+        TopLink,
+        r#"
+            SELECT l.id, l.target_url, SUM(d.clicks)::BIGINT AS "clicks!"
+            FROM link_daily_clicks d
+            JOIN links l ON l.id = d.link_id
+            WHERE l.workspace_id = $1 AND d.day >= $2
+            GROUP BY l.id
+            HAVING SUM(d.clicks) > 0
+            ORDER BY 3 DESC, 1
+            LIMIT $3
+        "#,
+        &workspace,
+        since,
+        TOP_ENTRIES
+    )
+    .fetch_all(&pool);
+    let top_referers = sqlx::query_as!(
+        TopReferer,
+        r#"
+            SELECT NULLIF(r.referer_host, '') AS host, SUM(r.clicks)::BIGINT AS "clicks!"
+            FROM link_daily_referers r
+            JOIN links l ON l.id = r.link_id
+            WHERE l.workspace_id = $1 AND r.day >= $2
+            GROUP BY r.referer_host
+            ORDER BY 2 DESC, 1
+            LIMIT $3
+        "#,
+        &workspace,
+        since,
+        TOP_ENTRIES
+    )
+    .fetch_all(&pool);
+    let daily_clicks = sqlx::query_as!(
+        DayClicks,
+        r#"
+            SELECT days.day::DATE AS "day!", COALESCE(SUM(d.clicks), 0)::BIGINT AS "clicks!"
+            FROM generate_series($2::DATE, $3::DATE, INTERVAL '1 day') AS days (day)
+            LEFT JOIN (
+                link_daily_clicks d JOIN links l ON l.id = d.link_id AND l.workspace_id = $1
+            ) ON d.day = days.day::DATE
+            GROUP BY 1
+            ORDER BY 1
+        "#,
+        &workspace,
+        since,
+        today
+    )
+    .fetch_all(&pool);
+
+    let fetch_dashboard_timeout = tokio::time::Duration::from_millis(1000);
+    let (totals, top_links, top_referers, daily_clicks) =
+        tokio::time::timeout(fetch_dashboard_timeout, async {
+            tokio::try_join!(totals, top_links, top_referers, daily_clicks)
+        })
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?;
+    tracing::debug!("Dashboard of workspace {} requested", workspace);
+    Ok(Json(Dashboard {
+        links_created: totals.links_created,
+        total_clicks: totals.total_clicks,
+        top_links,
+        top_referers,
+        daily_clicks,
+    }))
+}
@@ -0,0 +1,171 @@
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use metrics::{counter, gauge};
+use rand::Rng;
+use tokio::time::error::Elapsed;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Errors worth retrying because they usually clear up on their own, like dropped connections
+/// during a failover or serialization conflicts.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| {
+            matches!(
+                code.as_ref(),
+                // serialization_failure, deadlock_detected
+                "40001" | "40P01"
+                // admin_shutdown, crash_shutdown, cannot_connect_now
+                | "57P01" | "57P02" | "57P03"
+                // too_many_connections
+                | "53300"
+            ) || code.starts_with("08")
+        }),
+        _ => false,
+    }
+}
+
+impl RetryPolicy {
+    /// Full jitter: a random delay up to the exponentially growing cap.
+    fn delay(&self, attempt: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let millis = cap.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+
+    /// Runs `operation` until it succeeds, fails with a non-transient error or the attempts are
+    /// used up.
+    pub async fn run<T, F, Fut>(&self, name: &str, mut operation: F) -> Result<T, sqlx::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.max_attempts && is_transient(&err) => {
+                    tracing::warn!(
+                        "Transient database error during {} (attempt {}): {}",
+                        name,
+                        attempt,
+                        err
+                    );
+                    let labels = [("operation", name.to_string())];
+                    counter!("db_retries", &labels).increment(1);
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe went through at `probed_at`. Probes can be dropped before their outcome is
+    /// recorded, on a client disconnect or a request timeout, so another one is let through once
+    /// `open_duration` has passed without an answer.
+    HalfOpen {
+        probed_at: Instant,
+    },
+}
+
+/// Stops sending queries to a database that keeps failing, so requests fail fast instead of
+/// queueing up behind timeouts. After `open_duration` a single probe is let through; its outcome
+/// decides whether the circuit closes again. A probe that never reports back is replaced by a new
+/// one after another `open_duration`.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(BreakerState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Whether a call may go to the database. Returns the time left until the next probe when
+    /// the circuit is open.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().expect("Circuit breaker lock poisoned");
+        let now = Instant::now();
+        let next_probe = match *state {
+            BreakerState::Closed { .. } => return Ok(()),
+            BreakerState::Open { until } => until,
+            BreakerState::HalfOpen { probed_at } => probed_at + self.open_duration,
+        };
+        if now >= next_probe {
+            *state = BreakerState::HalfOpen { probed_at: now };
+            Ok(())
+        } else {
+            counter!("db_circuit_rejections").increment(1);
+            Err(next_probe - now)
+        }
+    }
+
+    /// Feeds the outcome of a guarded call back into the breaker. Only timeouts and transient
+    /// errors count as failures; a missing row or a constraint violation means the database is
+    /// healthy.
+    pub fn record<T>(&self, outcome: &Result<Result<T, sqlx::Error>, Elapsed>) {
+        let failed = match outcome {
+            Err(_) => true,
+            Ok(Err(err)) => is_transient(err),
+            Ok(Ok(_)) => false,
+        };
+        let mut state = self.state.lock().expect("Circuit breaker lock poisoned");
+        *state = match (&*state, failed) {
+            // Outcomes of calls let through before the circuit opened do not close it.
+            (BreakerState::Open { until }, _) => BreakerState::Open { until: *until },
+            (_, false) => BreakerState::Closed {
+                consecutive_failures: 0,
+            },
+            (
+                BreakerState::Closed {
+                    consecutive_failures,
+                },
+                true,
+            ) if consecutive_failures + 1 < self.failure_threshold => BreakerState::Closed {
+                consecutive_failures: consecutive_failures + 1,
+            },
+            (_, true) => {
+                tracing::error!(
+                    "Database circuit opened for {:?} after repeated failures",
+                    self.open_duration
+                );
+                BreakerState::Open {
+                    until: Instant::now() + self.open_duration,
+                }
+            }
+        };
+        let open = matches!(*state, BreakerState::Open { .. });
+        gauge!("db_circuit_open").set(if open { 1.0 } else { 0.0 });
+    }
+}
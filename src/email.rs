@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+use lettre::{
+    message::{
+        header::{ContentTransferEncoding, ContentType},
+        Mailbox, MultiPart, SinglePart,
+    },
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        extension::ClientId,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::EmailConfig;
+
+/// How the connection to `SMTP_HOST` is secured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpTls {
+    /// Upgraded with `STARTTLS` after connecting, usually on port 587.
+    StartTls,
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Plain text, for relays on the same host or network only.
+    None,
+}
+
+impl FromStr for SmtpTls {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            _ => Err("expected starttls, tls or none".to_string()),
+        }
+    }
+}
+
+/// An email with a plain text and an HTML version of the same content.
+#[derive(Debug)]
+pub struct Email {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// Whether `address` can be handed to an SMTP server as a recipient: `local@domain`, without
+/// whitespace or anything that would break out of a command or header.
+pub fn is_valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    address.len() <= 254
+        && !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && address
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>' | '(' | ')' | ',' | ';' | '"'))
+}
+
+/// Sends `email` through `SMTP_HOST`, authenticating when `SMTP_USERNAME` is set.
+pub async fn send(config: &EmailConfig, email: &Email) -> Result<(), String> {
+    let (Some(host), Some(from)) = (config.host.as_deref(), config.from.as_deref()) else {
+        return Err("SMTP_HOST and EMAIL_FROM are not set".to_string());
+    };
+    if let Some(address) = email.to.iter().find(|to| !is_valid_address(to)) {
+        return Err(format!("invalid recipient {address:?}"));
+    }
+    let message = message(config, from, email)?;
+    let transport = match config.tls {
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+        SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            host,
+        )),
+    }
+    .map_err(|err| err.to_string())?
+    .port(config.port)
+    .hello_name(ClientId::Domain(config.hello_name.clone()))
+    .timeout(Some(config.timeout));
+    let transport = match (&config.username, &config.password) {
+        (Some(username), Some(password)) => transport
+            .credentials(Credentials::new(username.clone(), password.clone()))
+            .authentication(vec![Mechanism::Plain]),
+        _ => transport,
+    };
+    tokio::time::timeout(config.timeout, transport.build().send(message))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// The email with both versions base64-encoded, as some relays mangle long lines otherwise.
+fn message(config: &EmailConfig, from: &str, email: &Email) -> Result<Message, String> {
+    let mailbox = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|err| format!("invalid address {address:?}: {err}"))
+    };
+    let part = |content_type: ContentType, content: &str| {
+        SinglePart::builder()
+            .header(content_type)
+            .header(ContentTransferEncoding::Base64)
+            .body(content.to_string())
+    };
+    let mut builder = Message::builder()
+        .from(mailbox(from)?)
+        .subject(&email.subject)
+        .message_id(Some(format!(
+            "<{:032x}@{}>",
+            rand::random::<u128>(),
+            config.hello_name
+        )));
+    for to in &email.to {
+        builder = builder.to(mailbox(to)?);
+    }
+    builder
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(part(ContentType::TEXT_PLAIN, &email.text))
+                .singlepart(part(ContentType::TEXT_HTML, &email.html)),
+        )
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,220 @@
+use std::str::FromStr;
+
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{header, uri::Authority, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::Actor,
+    config::SharedConfig,
+    utils::{accepts_json, internal_error, request_host, route_template},
+};
+
+/// Statuses of failed redirects a domain may answer with a page of its own.
+pub const STATUSES: [u16; 3] = [404, 410, 429];
+pub const MAX_PAGE_BYTES: usize = 256 * 1024;
+
+/// The host a request was sent to, lowercased and without its port.
+fn request_domain(headers: &HeaderMap) -> String {
+    let host = request_host(headers);
+    Authority::from_str(&host)
+        .map(|authority| authority.host().to_string())
+        .unwrap_or(host)
+        .to_ascii_lowercase()
+}
+
+/// A domain as given for a page: a bare host, without port or credentials.
+fn parse_domain(domain: &str) -> Result<String, (StatusCode, String)> {
+    let domain = domain.trim().to_ascii_lowercase();
+    match Authority::from_str(&domain) {
+        Ok(authority) if authority.as_str() == authority.host() => Ok(domain),
+        _ => Err((StatusCode::BAD_REQUEST, "Invalid Domain".into())),
+    }
+}
+
+fn check_status(status: u16) -> Result<i32, (StatusCode, String)> {
+    if !STATUSES.contains(&status) {
+        let statuses: Vec<String> = STATUSES.iter().map(u16::to_string).collect();
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Status must be one of {}", statuses.join(", ")),
+        ));
+    }
+    Ok(i32::from(status))
+}
+
+/// Answers failed redirects with the page the requested domain set for their status, if any, so
+/// every brand served by the instance keeps its look. Clients asking for JSON get the built-in
+/// answer.
+pub async fn brand_error_pages(
+    State(config): State<SharedConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let brandable = matches!(*req.method(), Method::GET | Method::HEAD)
+        && matches!(route_template(&req).as_str(), "/:id" | "/:id/:slug")
+        && !accepts_json(req.headers());
+    let domain = request_domain(req.headers());
+    let mut response = next.run(req).await;
+    if !brandable {
+        return response;
+    }
+    let config = config.current();
+    let Some(page) = config
+        .error_pages
+        .get(&(domain, response.status().as_u16()))
+    else {
+        return response;
+    };
+    let headers = response.headers_mut();
+    headers.remove(header::CONTENT_LENGTH);
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    *response.body_mut() = Body::from(page.clone());
+    response
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorPage {
+    pub domain: String,
+    pub status: i32,
+    /// Size of the page in bytes.
+    pub size: i32,
+    pub updated_by: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The branded error pages of every domain, without their HTML.
+pub async fn list_error_pages(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<ErrorPage>>, (StatusCode, String)> {
+    let list_pages_timeout = tokio::time::Duration::from_millis(300);
+    let pages = tokio::time::timeout(
+        list_pages_timeout,
+        sqlx::query_as!(
+            ErrorPage,
+            r#"
+                SELECT domain, status, octet_length(html) AS "size!", updated_by, updated_at
+                FROM domain_error_pages
+                ORDER BY domain, status
+            "#
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(pages))
+}
+
+/// Sets the HTML answered on `domain` in place of the built-in page for `status`, one of
+/// [`STATUSES`]. Takes effect on this instance right away, on the others with their next reload.
+pub async fn set_error_page(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Path((domain, status)): Path<(String, u16)>,
+    html: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let domain = parse_domain(&domain)?;
+    let status = check_status(status)?;
+    if html.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Empty Page".into()));
+    }
+    if html.len() > MAX_PAGE_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Pages are at most {MAX_PAGE_BYTES} bytes"),
+        ));
+    }
+    let set_page_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(set_page_timeout, async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO domain_error_pages (domain, status, html, updated_by)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (domain, status) DO UPDATE
+                SET html = EXCLUDED.html, updated_by = EXCLUDED.updated_by, updated_at = now()
+            "#,
+            &domain,
+            status,
+            &html,
+            &actor
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut tx,
+            &actor,
+            "error_page.set",
+            Some(&domain),
+            json!({ "status": status, "size": html.len() }),
+        )
+        .await?;
+        tx.commit().await
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    config.reload(&pool).await.map_err(internal_error)?;
+    tracing::info!("{} set the {} page of {}", actor, status, domain);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Goes back to the built-in page for `status` on `domain`.
+pub async fn delete_error_page(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Path((domain, status)): Path<(String, u16)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let domain = parse_domain(&domain)?;
+    let status = check_status(status)?;
+    let delete_page_timeout = tokio::time::Duration::from_millis(300);
+    let deleted = tokio::time::timeout(delete_page_timeout, async {
+        let mut tx = pool.begin().await?;
+        let deleted = sqlx::query!(
+            "DELETE FROM domain_error_pages WHERE domain = $1 AND status = $2",
+            &domain,
+            status
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if deleted > 0 {
+            audit::record(
+                &mut tx,
+                &actor,
+                "error_page.delete",
+                Some(&domain),
+                json!({ "status": status }),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(deleted)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if deleted == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    config.reload(&pool).await.map_err(internal_error)?;
+    tracing::info!("{} removed the {} page of {}", actor, status, domain);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,398 @@
+use std::{
+    any::Any,
+    panic::PanicHookInfo,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    config::{Config, SharedConfig},
+    outbound,
+    utils::route_template,
+};
+
+/// Identifies a request in logs, error reports and the response, taken from the client or
+/// proxy when it sends a usable one.
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longest error message read from a failed response.
+const MAX_MESSAGE_BYTES: usize = 8 * 1024;
+/// Routes whose `:id` is not a link.
+const NON_LINK_ROUTES: [&str; 3] = ["/campaigns/", "/webhooks/", "/admin/jobs/"];
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// The request an error happened in.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestContext {
+    pub method: String,
+    /// The matched route, `/:id` rather than the slug.
+    pub route: String,
+    pub request_id: String,
+    pub link_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorReport {
+    pub message: String,
+    /// Missing for panics.
+    pub status: Option<u16>,
+    /// Where the code panicked.
+    pub location: Option<String>,
+    /// Missing for panics outside of requests.
+    pub request: Option<RequestContext>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl ErrorReport {
+    fn is_panic(&self) -> bool {
+        self.status.is_none()
+    }
+}
+
+/// Sends panics and server errors to an error tracker, since logs alone are easily missed.
+#[async_trait]
+pub trait ErrorReporter: Send + Sync {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String>;
+}
+
+pub type SharedErrorReporter = Arc<dyn ErrorReporter>;
+
+/// Marks the response to a panicked handler, whose panic the hook has reported already.
+#[derive(Clone, Copy)]
+struct Panicked;
+
+/// The parts of a Sentry DSN, `https://<public key>@<host>/<project id>`.
+#[derive(Debug)]
+pub struct SentryDsn {
+    store_url: String,
+    public_key: String,
+}
+
+impl SentryDsn {
+    pub fn parse(dsn: &str) -> Result<Self, String> {
+        let url = url::Url::parse(dsn).map_err(|err| err.to_string())?;
+        if url.username().is_empty() {
+            return Err("the public key is missing".into());
+        }
+        let path = url.path().trim_end_matches('/');
+        let (prefix, project_id) = path.rsplit_once('/').unwrap_or_default();
+        if project_id.is_empty() {
+            return Err("the project id is missing".into());
+        }
+        let host = url.host_str().ok_or("the host is missing")?;
+        let port = url
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+        Ok(Self {
+            store_url: format!(
+                "{}://{host}{port}{prefix}/api/{project_id}/store/",
+                url.scheme()
+            ),
+            public_key: url.username().to_string(),
+        })
+    }
+}
+
+/// Reports to Sentry through its store endpoint.
+pub struct Sentry {
+    client: Client,
+    dsn: SentryDsn,
+    environment: String,
+}
+
+impl Sentry {
+    pub fn new(dsn: SentryDsn, environment: &str, client: Client) -> Self {
+        Self {
+            client,
+            dsn,
+            environment: environment.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ErrorReporter for Sentry {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        let mut tags = json!({});
+        if let Some(status) = report.status {
+            tags["status"] = status.into();
+        }
+        if let Some(request) = &report.request {
+            tags["route"] = request.route.clone().into();
+            tags["method"] = request.method.clone().into();
+            tags["request_id"] = request.request_id.clone().into();
+            if let Some(link_id) = &request.link_id {
+                tags["link_id"] = link_id.clone().into();
+            }
+        }
+        let event = json!({
+            "event_id": random_id(),
+            "timestamp": report.occurred_at.to_rfc3339(),
+            "platform": "other",
+            "level": if report.is_panic() { "fatal" } else { "error" },
+            "logger": "link-shortener",
+            "release": concat!("link-shortener@", env!("CARGO_PKG_VERSION")),
+            "environment": self.environment,
+            "message": { "formatted": report.message },
+            "tags": tags,
+            "extra": { "location": report.location },
+        });
+        let auth = format!(
+            "Sentry sentry_version=7, sentry_client=link-shortener/{}, sentry_key={}",
+            env!("CARGO_PKG_VERSION"),
+            self.dsn.public_key
+        );
+        let response = self
+            .client
+            .post(&self.dsn.store_url)
+            .header("X-Sentry-Auth", auth)
+            .header("Content-Type", "application/json")
+            .body(event.to_string())
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Sentry answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Posts each report as JSON, for trackers other than Sentry.
+pub struct ErrorWebhook {
+    client: Client,
+    url: String,
+    environment: String,
+}
+
+impl ErrorWebhook {
+    pub fn new(url: &str, environment: &str, client: Client) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+            environment: environment.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ErrorReporter for ErrorWebhook {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        let mut body = serde_json::to_value(report).map_err(|err| err.to_string())?;
+        body["environment"] = self.environment.clone().into();
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Error webhook answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// The reporter picked by `SENTRY_DSN` or `ERROR_REPORT_URL`, if any.
+pub fn from_config(config: &Config) -> Option<SharedErrorReporter> {
+    let reporting = &config.error_reporting;
+    let client = outbound::api_client(&config.outbound, reporting.timeout);
+    if let Some(dsn) = reporting.sentry_dsn.as_deref() {
+        let dsn = SentryDsn::parse(dsn).ok()?;
+        return Some(Arc::new(Sentry::new(dsn, &reporting.environment, client)));
+    }
+    let url = reporting.webhook_url.as_deref()?;
+    Some(Arc::new(ErrorWebhook::new(
+        url,
+        &reporting.environment,
+        client,
+    )))
+}
+
+/// Hands reports to the configured reporter in the background, at most
+/// `ERROR_REPORT_MAX_PER_MINUTE` of them.
+#[derive(Default)]
+pub struct ErrorReporting {
+    reporter: Option<SharedErrorReporter>,
+    /// Start of the current minute and reports sent in it.
+    budget: Mutex<Option<(Instant, u32)>>,
+}
+
+impl ErrorReporting {
+    pub fn new(reporter: Option<SharedErrorReporter>) -> Self {
+        Self {
+            reporter,
+            budget: Mutex::default(),
+        }
+    }
+
+    fn take_budget(&self, max_per_minute: u32) -> bool {
+        let mut budget = self
+            .budget
+            .lock()
+            .expect("Error report budget lock poisoned");
+        let now = Instant::now();
+        let (since, sent) = match *budget {
+            Some((since, sent)) if now.duration_since(since) < Duration::from_secs(60) => {
+                (since, sent)
+            }
+            _ => (now, 0),
+        };
+        *budget = Some((since, sent + 1));
+        sent < max_per_minute
+    }
+
+    pub fn capture(&self, report: ErrorReport, max_per_minute: u32) {
+        let Some(reporter) = self.reporter.clone() else {
+            return;
+        };
+        if !self.take_budget(max_per_minute) {
+            counter!("error_reports_dropped").increment(1);
+            return;
+        }
+        // Panics may happen outside of the runtime, those are only logged.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            match reporter.report(&report).await {
+                Ok(()) => counter!("error_reports_sent").increment(1),
+                Err(err) => {
+                    counter!("error_reports_failed").increment(1);
+                    tracing::warn!("Reporting an error failed: {}", err);
+                }
+            }
+        });
+    }
+}
+
+fn random_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// The request id sent along, when it is short and plain enough to pass on.
+fn forwarded_request_id(req: &Request) -> Option<String> {
+    let id = req.headers().get(&REQUEST_ID)?.to_str().ok()?;
+    let plain = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    (!id.is_empty() && id.len() <= 64 && id.chars().all(plain)).then(|| id.to_string())
+}
+
+/// The link a request is about, from the `:id` of its route.
+fn link_id(route: &str, path: &str) -> Option<String> {
+    if NON_LINK_ROUTES
+        .iter()
+        .any(|prefix| route.starts_with(prefix))
+    {
+        return None;
+    }
+    route
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(template, _)| matches!(*template, ":id" | ":link_id"))
+        .map(|(_, segment)| segment.to_string())
+}
+
+/// Gives every request an id, echoed as `X-Request-Id`, and reports responses failing with a
+/// server error to the error tracker along with the request they failed. 503s are left out,
+/// they are answered on purpose while overloaded or in maintenance.
+pub async fn report_errors(
+    State(reporting): State<Arc<ErrorReporting>>,
+    State(config): State<SharedConfig>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let request_id = forwarded_request_id(&req).unwrap_or_else(random_id);
+    let route = route_template(&req);
+    let context = RequestContext {
+        method: req.method().to_string(),
+        link_id: link_id(&route, req.uri().path()),
+        route,
+        request_id: request_id.clone(),
+    };
+    let header = HeaderValue::from_str(&request_id).expect("Request ids are plain ASCII");
+    req.headers_mut().insert(REQUEST_ID, header.clone());
+
+    let mut response = CONTEXT.scope(context.clone(), next.run(req)).await;
+    response.headers_mut().insert(REQUEST_ID, header);
+    let status = response.status();
+    if !status.is_server_error()
+        || status == StatusCode::SERVICE_UNAVAILABLE
+        || response.extensions().get::<Panicked>().is_some()
+    {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, MAX_MESSAGE_BYTES).await.unwrap_or_default();
+    let message = Some(String::from_utf8_lossy(&body).into_owned())
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| status.to_string());
+    reporting.capture(
+        ErrorReport {
+            message,
+            status: Some(status.as_u16()),
+            location: None,
+            request: Some(context),
+            occurred_at: Utc::now(),
+        },
+        config.current().error_reporting.max_per_minute,
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Answers a request whose handler panicked with a 500, instead of dropping the connection.
+pub fn handle_panic(_: Box<dyn Any + Send + 'static>) -> Response {
+    counter!("handler_panics_total").increment(1);
+    let mut response = (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+        .into_response();
+    response.extensions_mut().insert(Panicked);
+    response
+}
+
+/// Reports panics, with the request they happened in when there was one, before the default
+/// hook prints them.
+pub fn report_panics(reporting: Arc<ErrorReporting>, config: SharedConfig) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info: &PanicHookInfo<'_>| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        reporting.capture(
+            ErrorReport {
+                message,
+                status: None,
+                location: info.location().map(ToString::to_string),
+                request: CONTEXT.try_with(Clone::clone).ok(),
+                occurred_at: Utc::now(),
+            },
+            config.current().error_reporting.max_per_minute,
+        );
+        default_hook(info);
+    }));
+}
@@ -0,0 +1,116 @@
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::Response,
+};
+use sqlx::PgPool;
+
+use crate::{
+    config::{ExpiryConfig, SharedConfig},
+    link_cache, outbox,
+    route::Link,
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+const GONE_PAGE: &str = "<!DOCTYPE html>\n<html>\n<head><title>Link expired</title></head>\n<body>\n<h1>This link has expired</h1>\n<p>The short link you followed was only valid for a limited time and no longer leads anywhere.</p>\n</body>\n</html>\n";
+
+/// Answer for slugs that existed but expired, so they are not mistaken for mistyped ones.
+pub fn gone_response() -> Response {
+    Response::builder()
+        .status(StatusCode::GONE)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(GONE_PAGE))
+        .expect("This response should always be constructable")
+}
+
+/// Marks one batch of newly expired links, announcing them with `link.expired` events, and
+/// deletes one batch of links expired for longer than the retention. Returns how many links
+/// were touched.
+async fn sweep(pool: &PgPool, expiry: &ExpiryConfig) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let expired = sqlx::query!(
+        r#"
+            UPDATE links
+            SET expired_at = now()
+            WHERE id IN (
+                SELECT id
+                FROM links
+                WHERE expires_at <= now() AND expired_at IS NULL
+                ORDER BY expires_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, target_url, workspace_id
+        "#,
+        expiry.batch_size
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut events: Vec<LinkEvent> = expired
+        .into_iter()
+        .map(|link| {
+            LinkEvent::new(
+                LinkEventKind::Expired,
+                link.workspace_id,
+                Link {
+                    id: link.id,
+                    target_url: link.target_url,
+                },
+            )
+        })
+        .collect();
+
+    if let Some(retention) = expiry.retention {
+        let deleted = sqlx::query!(
+            r#"
+                DELETE FROM links
+                WHERE id IN (
+                    SELECT id
+                    FROM links
+                    WHERE expired_at < now() - make_interval(secs => $1)
+                        AND expires_at <= now()
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, target_url, workspace_id
+            "#,
+            retention.as_secs_f64(),
+            expiry.batch_size
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        events.extend(deleted.into_iter().map(|link| {
+            LinkEvent::new(
+                LinkEventKind::Deleted,
+                link.workspace_id,
+                Link {
+                    id: link.id,
+                    target_url: link.target_url,
+                },
+            )
+        }));
+    }
+    outbox::enqueue_all(&mut tx, &events).await?;
+    let ids: Vec<String> = events.iter().map(|event| event.link.id.clone()).collect();
+    link_cache::publish(&mut *tx, &ids).await?;
+    tx.commit().await?;
+    Ok(events.len())
+}
+
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let expiry = config.current().expiry.clone();
+            match sweep(&pool, &expiry).await {
+                // Keep going without pausing while there is a backlog.
+                Ok(count) if count > 0 => {
+                    tracing::debug!("Expiry sweep processed {} links", count);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!("Sweeping expired links failed: {}", err),
+            }
+            tokio::time::sleep(expiry.interval).await;
+        }
+    });
+}
@@ -0,0 +1,286 @@
+use std::io;
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{response_headers, utm};
+
+const PAGE_SIZE: i64 = 1000;
+const PAGE_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+
+/// Exported sections, in export order.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Section {
+    Settings,
+    Links,
+    Clicks,
+}
+
+impl Section {
+    const ALL: [Section; 3] = [Section::Settings, Section::Links, Section::Clicks];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Section::Settings => "setting",
+            Section::Links => "link",
+            Section::Clicks => "click",
+        }
+    }
+}
+
+/// Position after the last exported record. Handed out with every line so an interrupted
+/// export can be resumed from there.
+#[derive(Debug)]
+struct Cursor {
+    section: Section,
+    after: String,
+}
+
+impl Cursor {
+    fn encode(section: Section, after: &str) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", section.as_str(), after))
+    }
+
+    fn decode(raw: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(raw).ok()?).ok()?;
+        let (section, after) = decoded.split_once(':')?;
+        let section = Section::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == section)?;
+        Some(Self {
+            section,
+            after: after.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportLine<T> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    cursor: String,
+    data: T,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedSetting {
+    key: String,
+    value: String,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedLink {
+    id: String,
+    target_url: String,
+    title: Option<String>,
+    workspace_id: String,
+    tags: Vec<String>,
+    active: bool,
+    expires_at: Option<DateTime<Utc>>,
+    redirect_type: i32,
+    privacy_mode: bool,
+    track_clicks: bool,
+    sample_rate: i32,
+    rate_limit: Option<i32>,
+    allowed_referers: Vec<String>,
+    referer_fallback_url: Option<String>,
+    #[serde(serialize_with = "response_headers::serialize")]
+    response_headers: Vec<String>,
+    notes: Option<String>,
+    cache_tier: Option<String>,
+    #[serde(serialize_with = "utm::serialize")]
+    utm_template: Option<Vec<String>>,
+    created_by: Option<String>,
+    click_milestones: Vec<i64>,
+    click_count: i64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedClick {
+    id: i32,
+    link_id: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    visitor_hash: Option<String>,
+    weight: i32,
+    language: Option<String>,
+    platform: Option<String>,
+    mobile: Option<bool>,
+    channel: String,
+    prefetch: bool,
+    variant: Option<String>,
+    click_id: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+type Chunks = mpsc::Sender<Result<String, io::Error>>;
+
+fn render<T: Serialize>(section: Section, rows: Vec<T>, key: impl Fn(&T) -> String) -> String {
+    let mut chunk = String::new();
+    for row in rows {
+        let line = ExportLine {
+            kind: section.as_str(),
+            cursor: Cursor::encode(section, &key(&row)),
+            data: row,
+        };
+        chunk.push_str(&serde_json::to_string(&line).expect("Export records always serialize"));
+        chunk.push('\n');
+    }
+    chunk
+}
+
+/// Exports one page of `section` after `after`. Returns the key of its last record, or `None`
+/// once the section is exhausted.
+async fn export_page(
+    pool: &PgPool,
+    section: Section,
+    after: &str,
+    chunks: &Chunks,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let (chunk, last) = match section {
+        Section::Settings => {
+            let rows = tokio::time::timeout(
+                PAGE_TIMEOUT,
+                sqlx::query_as!(
+                    ExportedSetting,
+                    r#"
+                        SELECT key, value, updated_at
+                        FROM runtime_settings
+                        WHERE key > $1
+                        ORDER BY key
+                        LIMIT $2
+                    "#,
+                    after,
+                    PAGE_SIZE
+                )
+                .fetch_all(pool),
+            )
+            .await??;
+            let last = rows.last().map(|row| row.key.clone());
+            (render(section, rows, |row| row.key.clone()), last)
+        }
+        Section::Links => {
+            let rows = tokio::time::timeout(
+                PAGE_TIMEOUT,
+                sqlx::query_as!(
+                    ExportedLink,
+                    r#"
+                        SELECT id, target_url, title, workspace_id, tags, active, expires_at, redirect_type, privacy_mode, track_clicks, sample_rate, rate_limit, allowed_referers, referer_fallback_url, response_headers, notes, cache_tier, utm_template, created_by, click_milestones, click_count, created_at, updated_at
+                        FROM links
+                        WHERE id > $1
+                        ORDER BY id
+                        LIMIT $2
+                    "#,
+                    after,
+                    PAGE_SIZE
+                )
+                .fetch_all(pool),
+            )
+            .await??;
+            let last = rows.last().map(|row| row.id.clone());
+            (render(section, rows, |row| row.id.clone()), last)
+        }
+        Section::Clicks => {
+            let after: i32 = after.parse().unwrap_or(0);
+            let rows = tokio::time::timeout(
+                PAGE_TIMEOUT,
+                sqlx::query_as!(
+                    ExportedClick,
+                    r#"
+                        SELECT id, link_id, referer, user_agent, visitor_hash, weight, language, platform, mobile, channel, prefetch, variant, click_id, created_at
+                        FROM link_statistics
+                        WHERE id > $1
+                        ORDER BY id
+                        LIMIT $2
+                    "#,
+                    after,
+                    PAGE_SIZE
+                )
+                .fetch_all(pool),
+            )
+            .await??;
+            let last = rows.last().map(|row| row.id.to_string());
+            (render(section, rows, |row| row.id.to_string()), last)
+        }
+    };
+    if last.is_some() && chunks.send(Ok(chunk)).await.is_err() {
+        // The client went away, nobody is reading any more.
+        return Ok(None);
+    }
+    Ok(last)
+}
+
+async fn write_export(
+    pool: &PgPool,
+    cursor: Option<Cursor>,
+    chunks: &Chunks,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for section in Section::ALL {
+        let mut after = match &cursor {
+            Some(cursor) if cursor.section > section => continue,
+            Some(cursor) if cursor.section == section => cursor.after.clone(),
+            _ => String::new(),
+        };
+        while let Some(last) = export_page(pool, section, &after, chunks).await? {
+            if chunks.is_closed() {
+                return Ok(());
+            }
+            after = last;
+        }
+    }
+    Ok(())
+}
+
+/// Streams runtime settings, links and clicks as newline-delimited JSON. Every line carries a
+/// cursor; passing the last one received as `?cursor=` resumes an interrupted export. Gzipped
+/// when the client accepts it.
+pub async fn export_data(
+    State(pool): State<PgPool>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let cursor = params
+        .cursor
+        .map(|raw| Cursor::decode(&raw).ok_or((StatusCode::BAD_REQUEST, "Invalid Cursor".into())))
+        .transpose()?;
+    tracing::debug!("Export requested from cursor {:?}", cursor);
+
+    let (chunks, receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(err) = write_export(&pool, cursor, &chunks).await {
+            tracing::error!("Export failed: {}", err);
+            let _ = chunks.send(Err(io::Error::other(err.to_string()))).await;
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"export.ndjson\"",
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    )
+        .into_response())
+}
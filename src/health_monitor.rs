@@ -0,0 +1,253 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde::Serialize;
+use sqlx::PgPool;
+use url::Url;
+
+use crate::{
+    config::{HealthCheckConfig, NotificationConfig, OutboundConfig, SharedConfig},
+    link_cache,
+    notify::{self, NotificationKind},
+    outbound,
+    target::serialize_display_url,
+    utils::internal_error,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrokenLink {
+    pub link_id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub active: bool,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub consecutive_failures: i32,
+    pub checked_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+pub struct CheckOutcome {
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl CheckOutcome {
+    pub fn is_healthy(&self) -> bool {
+        self.status_code.is_some_and(|status| status < 400)
+    }
+
+    fn is_gone(&self) -> bool {
+        matches!(self.status_code, Some(404) | Some(410))
+    }
+}
+
+/// Requests the target with HEAD (GET when HEAD is not supported) through the SSRF-safe client.
+pub async fn check_target(
+    target_url: &str,
+    timeout: std::time::Duration,
+    outbound: &OutboundConfig,
+) -> CheckOutcome {
+    let failed = |error: String| CheckOutcome {
+        status_code: None,
+        error: Some(error),
+    };
+    let url = match Url::parse(target_url) {
+        Ok(url) => url,
+        Err(err) => return failed(err.to_string()),
+    };
+    let client = match outbound::client(&url, outbound, timeout).await {
+        Ok(client) => client,
+        Err(err) => return failed(err.to_string()),
+    };
+
+    let mut response = client.head(url.clone()).send().await;
+    // Some servers do not implement HEAD, fall back to GET for those.
+    if let Ok(head_response) = &response {
+        if matches!(
+            head_response.status(),
+            reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+        ) {
+            response = client.get(url).send().await;
+        }
+    }
+    match response {
+        Ok(response) => CheckOutcome {
+            status_code: Some(response.status().as_u16()),
+            error: None,
+        },
+        Err(err) => failed(err.to_string()),
+    }
+}
+
+async fn record_outcome(
+    pool: &PgPool,
+    link_id: &str,
+    target_url: &str,
+    outcome: &CheckOutcome,
+    config: &HealthCheckConfig,
+    notifications: &NotificationConfig,
+    outbound: &OutboundConfig,
+) -> Result<(), sqlx::Error> {
+    let healthy = outcome.is_healthy();
+    let consecutive_failures = sqlx::query_scalar!(
+        r#"
+            INSERT INTO link_health (link_id, status_code, error, consecutive_failures, checked_at, last_success_at)
+            VALUES ($1, $2, $3, CASE WHEN $4 THEN 0 ELSE 1 END, now(), CASE WHEN $4 THEN now() END)
+            ON CONFLICT (link_id) DO UPDATE
+            SET status_code = EXCLUDED.status_code,
+                error = EXCLUDED.error,
+                consecutive_failures = CASE WHEN $4 THEN 0 ELSE link_health.consecutive_failures + 1 END,
+                checked_at = EXCLUDED.checked_at,
+                last_success_at = COALESCE(EXCLUDED.last_success_at, link_health.last_success_at)
+            RETURNING consecutive_failures
+        "#,
+        link_id,
+        outcome.status_code.map(i32::from),
+        outcome.error.as_deref(),
+        healthy
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let labels = [("outcome", if healthy { "healthy" } else { "broken" })];
+    counter!("link_health_checks", &labels).increment(1);
+    // Announced when a target starts failing, not again on every further check.
+    if consecutive_failures == 1 {
+        let problem = match (outcome.status_code, &outcome.error) {
+            (Some(status), _) => format!("status {status}"),
+            (None, Some(error)) => error.clone(),
+            (None, None) => "no response".to_string(),
+        };
+        notify::send(
+            notifications,
+            outbound,
+            NotificationKind::HealthCheckFailed,
+            format!("Target of link `{link_id}` is failing ({problem}): {target_url}"),
+        );
+    }
+
+    if let Some(threshold) = config.auto_disable_after {
+        if outcome.is_gone() && consecutive_failures >= threshold {
+            let disabled = sqlx::query!(
+                "UPDATE links SET active = FALSE, updated_at = now() WHERE id = $1 AND active",
+                link_id
+            )
+            .execute(pool)
+            .await?;
+            if disabled.rows_affected() > 0 {
+                link_cache::publish(pool, &[link_id.to_string()]).await?;
+                tracing::warn!(
+                    "Disabled link with id {} after its target was gone {} times in a row",
+                    link_id,
+                    consecutive_failures
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn check_batch(
+    pool: &PgPool,
+    config: &HealthCheckConfig,
+    notifications: &NotificationConfig,
+    outbound: &OutboundConfig,
+) -> Result<(), sqlx::Error> {
+    let links = sqlx::query!(
+        r#"
+            SELECT l.id, l.target_url
+            FROM links l
+            LEFT JOIN link_health h ON h.link_id = l.id
+            WHERE l.active
+            ORDER BY h.checked_at ASC NULLS FIRST
+            LIMIT $1
+        "#,
+        config.batch_size
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for link in links {
+        let outcome = check_target(&link.target_url, config.request_timeout, outbound).await;
+        if !outcome.is_healthy() {
+            tracing::debug!(
+                "Target {} of link with id {} looks broken: status {:?}, error {:?}",
+                link.target_url,
+                link.id,
+                outcome.status_code,
+                outcome.error
+            );
+        }
+        record_outcome(
+            pool,
+            &link.id,
+            &link.target_url,
+            &outcome,
+            config,
+            notifications,
+            outbound,
+        )
+        .await?;
+        tokio::time::sleep(config.request_delay).await;
+    }
+    Ok(())
+}
+
+/// Periodically checks the least recently checked link targets in batches. The configuration
+/// is re-read before every run so reloads apply without restarting the task.
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            let health_check = current.health_check.clone();
+            tokio::time::sleep(health_check.interval).await;
+            if !health_check.enabled {
+                continue;
+            }
+            if let Err(err) = check_batch(
+                &pool,
+                &health_check,
+                &current.notifications,
+                &current.outbound,
+            )
+            .await
+            {
+                tracing::error!("Link health check run failed: {}", err);
+            }
+        }
+    });
+}
+
+pub async fn get_broken_links(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<BrokenLink>>, (StatusCode, String)> {
+    let fetch_report_timeout = tokio::time::Duration::from_millis(1000);
+    let broken_links = tokio::time::timeout(
+        fetch_report_timeout,
+        sqlx::query_as!(
+            BrokenLink,
+            r#"
+                SELECT
+                    l.id AS link_id,
+                    l.target_url,
+                    l.active,
+                    h.status_code,
+                    h.error,
+                    h.consecutive_failures,
+                    h.checked_at,
+                    h.last_success_at
+                FROM link_health h
+                JOIN links l ON l.id = h.link_id
+                WHERE h.consecutive_failures > 0
+                ORDER BY h.consecutive_failures DESC, h.checked_at DESC
+            "#
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(broken_links))
+}
@@ -0,0 +1,177 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+
+use crate::{
+    auth::{Actor, Workspace},
+    link_cache::{self, LinkCache},
+    outbox,
+    route::Link,
+    target::serialize_display_url,
+    utils::internal_error,
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkHistoryEntry {
+    pub id: i64,
+    pub link_id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub old_target_url: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub new_target_url: String,
+    pub actor: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackRequest {
+    /// The history entry whose previous target should be restored. Defaults to
+    /// the most recent change.
+    pub history_id: Option<i64>,
+}
+
+/// Records a target URL change for a link. Unchanged targets are not recorded.
+pub async fn record_target_change(
+    conn: &mut PgConnection,
+    link_id: &str,
+    old_target_url: &str,
+    new_target_url: &str,
+    actor: &str,
+) -> Result<(), sqlx::Error> {
+    if old_target_url == new_target_url {
+        return Ok(());
+    }
+    sqlx::query!(
+        r#"
+            INSERT INTO link_history (link_id, old_target_url, new_target_url, actor)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        link_id,
+        old_target_url,
+        new_target_url,
+        actor
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// The target changes of a link of the workspace, archived or not; empty for any other link.
+pub async fn get_link_history(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Vec<LinkHistoryEntry>>, (StatusCode, String)> {
+    let fetch_history_timeout = tokio::time::Duration::from_millis(300);
+    let history = tokio::time::timeout(
+        fetch_history_timeout,
+        sqlx::query_as!(
+            LinkHistoryEntry,
+            r#"
+                SELECT id, link_id, old_target_url, new_target_url, actor, changed_at
+                FROM link_history
+                WHERE link_id = $1
+                    AND (EXISTS (SELECT 1 FROM links WHERE id = $1 AND workspace_id = $2)
+                        OR EXISTS (SELECT 1 FROM archived_links WHERE id = $1 AND workspace_id = $2))
+                ORDER BY changed_at DESC, id DESC
+            "#,
+            &link_id,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::debug!("History for link with id {} requested", link_id);
+    Ok(Json(history))
+}
+
+pub async fn rollback_link(
+    State(pool): State<PgPool>,
+    Path(link_id): Path<String>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    State(cache): State<Arc<LinkCache>>,
+    rollback: Option<Json<RollbackRequest>>,
+) -> Result<Json<Link>, (StatusCode, String)> {
+    let history_id = rollback.and_then(|Json(rollback)| rollback.history_id);
+    let rollback_timeout = tokio::time::Duration::from_millis(300);
+    let restored_link = tokio::time::timeout(rollback_timeout, async {
+        let mut tx = pool.begin().await?;
+        let Some(current) = sqlx::query!(
+            "SELECT target_url FROM links WHERE id = $1 AND workspace_id = $2 FOR UPDATE",
+            &link_id,
+            &workspace
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let Some(restored_target_url) = sqlx::query_scalar!(
+            r#"
+                SELECT old_target_url
+                FROM link_history
+                WHERE link_id = $1 AND ($2::BIGINT IS NULL OR id = $2)
+                ORDER BY changed_at DESC, id DESC
+                LIMIT 1
+            "#,
+            &link_id,
+            history_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let restored_link = sqlx::query_as!(
+            Link,
+            "UPDATE links SET target_url = $1, updated_at = now() WHERE id = $2 RETURNING id, target_url",
+            &restored_target_url,
+            &link_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        record_target_change(
+            &mut tx,
+            &link_id,
+            &current.target_url,
+            &restored_target_url,
+            &actor,
+        )
+        .await?;
+        let event = LinkEvent::new(
+            LinkEventKind::Updated,
+            workspace,
+            restored_link.clone(),
+        );
+        outbox::enqueue(&mut tx, &event).await?;
+        link_cache::publish(&mut *tx, std::slice::from_ref(&link_id)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(restored_link))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| "Not Found".to_string())
+    .map_err(|err| (StatusCode::NOT_FOUND, err))?;
+    cache.invalidate(std::slice::from_ref(&link_id));
+
+    tracing::debug!(
+        "Rolled back link with id {} to {} on behalf of {}",
+        link_id,
+        restored_link.target_url,
+        actor
+    );
+    Ok(Json(restored_link))
+}
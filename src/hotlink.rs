@@ -0,0 +1,65 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use metrics::counter;
+use url::Url;
+
+pub const MAX_ALLOWED_REFERERS: usize = 20;
+
+const HOTLINK_PAGE: &str = "<!DOCTYPE html>\n<html>\n<head><title>Link not available here</title></head>\n<body>\n<h1>This link cannot be followed from here</h1>\n<p>The short link you followed only works when opened from the site that shared it.</p>\n</body>\n</html>\n";
+
+/// Normalizes a domain of a referer allowlist the way hosts of referers are normalized:
+/// lowercase, punycode for internationalized names. `None` when it is not a domain.
+pub fn normalize_domain(raw: &str) -> Option<String> {
+    let raw = raw.trim().trim_end_matches('.');
+    if raw.is_empty() || raw.contains(['/', ':', '@', '?', '#']) {
+        return None;
+    }
+    Url::parse(&format!("https://{raw}/"))
+        .ok()?
+        .host_str()
+        .map(str::to_string)
+}
+
+/// The host of the page the visitor came from.
+pub fn referer_host(headers: &HeaderMap) -> Option<String> {
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    Url::parse(referer).ok()?.host_str().map(str::to_string)
+}
+
+/// Whether a visitor coming from `referer_host` may follow a link restricted to
+/// `allowed_referers`, subdomains included. Anyone may when the allowlist is empty.
+pub fn is_allowed(referer_host: Option<&str>, allowed_referers: &[String]) -> bool {
+    if allowed_referers.is_empty() {
+        return true;
+    }
+    let Some(host) = referer_host else {
+        return false;
+    };
+    allowed_referers.iter().any(|domain| {
+        host == domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Answer for visitors of a link arriving from a site not on its allowlist: the link's fallback
+/// page when it has one, an explanation otherwise. Never cached, it depends on the referer.
+pub fn blocked_response(fallback_url: Option<String>) -> Response {
+    counter!("hotlink_blocked_redirects").increment(1);
+    let response = Response::builder().header(header::CACHE_CONTROL, "no-store");
+    match fallback_url {
+        Some(fallback_url) => response
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, fallback_url)
+            .body(Body::empty()),
+        None => response
+            .status(StatusCode::FORBIDDEN)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(HOTLINK_PAGE)),
+    }
+    .expect("This response should always be constructable")
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::counter;
+
+use crate::{config::SharedConfig, utils::request_host};
+
+/// Whether a proxy in front received the request over HTTPS. The service itself only speaks
+/// plain HTTP, so anything else was not encrypted on its way in. The nearest proxy's word is
+/// taken; a client claiming HTTPS only spares itself the redirect.
+fn is_secure(headers: &HeaderMap) -> bool {
+    let last = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .last()
+            .map(str::trim)
+    };
+    if let Some(element) = last("forwarded") {
+        return element
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .any(|(key, value)| {
+                key.trim().eq_ignore_ascii_case("proto")
+                    && value.trim().trim_matches('"').eq_ignore_ascii_case("https")
+            });
+    }
+    last("x-forwarded-proto").is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+/// Sends plain-HTTP requests to the same URL over HTTPS when `FORCE_HTTPS` is on, and adds
+/// `Strict-Transport-Security` to HTTPS responses when `HSTS_MAX_AGE_SECS` is set. `/health`
+/// is left alone, load balancers check it over plain HTTP.
+pub async fn enforce_https(
+    State(config): State<SharedConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let https = config.current().https.clone();
+    let secure = is_secure(req.headers());
+    if https.enforce && !secure && req.uri().path() != "/health" {
+        counter!("https_upgrades").increment(1);
+        let host = request_host(req.headers());
+        // The port, if any, is the plain-HTTP one.
+        let host = host
+            .rsplit_once(':')
+            .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+            .map_or(host.as_str(), |(host, _)| host);
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        // Anything but GET and HEAD must be repeated as is, with its body.
+        let status = if matches!(*req.method(), Method::GET | Method::HEAD) {
+            StatusCode::MOVED_PERMANENTLY
+        } else {
+            StatusCode::PERMANENT_REDIRECT
+        };
+        return (
+            status,
+            [(header::LOCATION, format!("https://{host}{path}"))],
+        )
+            .into_response();
+    }
+    let mut response = next.run(req).await;
+    if let Some(max_age) = https.hsts_max_age.filter(|_| secure) {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if https.hsts_include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response
+                .headers_mut()
+                .insert(header::STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+    response
+}
@@ -0,0 +1,205 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::http::StatusCode;
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{config::Config, utils::generate_id};
+
+/// Ids of one sequential block. Each process reserves a fresh block from the `link_id_blocks`
+/// sequence when it starts, so restarts and parallel instances don't hand out the same ids
+/// unless one of them creates more than 2^24 links.
+const SEQUENCE_BLOCK_BITS: u32 = 24;
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const NANOID_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_-";
+
+/// Where the slugs of new links come from.
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> String;
+}
+
+pub type SharedIdGenerator = Arc<dyn IdGenerator>;
+
+/// How slugs are generated, from `ID_STRATEGY`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdStrategy {
+    /// A random `u32` in base64, the original slugs.
+    Random,
+    Nanoid,
+    /// Counting up in base62: the shortest slugs, but guessable.
+    Sequential,
+    /// A counter hashed with a secret salt: as short as `ID_LENGTH`, but not guessable.
+    Hash,
+}
+
+impl FromStr for IdStrategy {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "random" => Ok(Self::Random),
+            "nanoid" => Ok(Self::Nanoid),
+            "sequential" => Ok(Self::Sequential),
+            "hash" => Ok(Self::Hash),
+            _ => Err("expected random, nanoid, sequential or hash".to_string()),
+        }
+    }
+}
+
+pub struct RandomBase64;
+
+impl IdGenerator for RandomBase64 {
+    fn generate(&self) -> String {
+        generate_id()
+    }
+}
+
+pub struct Nanoid {
+    pub length: usize,
+}
+
+impl IdGenerator for Nanoid {
+    fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        (0..self.length)
+            .map(|_| NANOID_ALPHABET[rng.gen_range(0..NANOID_ALPHABET.len())] as char)
+            .collect()
+    }
+}
+
+pub struct Sequential {
+    next: AtomicU64,
+}
+
+impl Sequential {
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+        }
+    }
+}
+
+impl IdGenerator for Sequential {
+    fn generate(&self) -> String {
+        base62(self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+pub struct Hashed {
+    salt: [u8; 32],
+    counter: AtomicU64,
+    length: usize,
+}
+
+impl Hashed {
+    pub fn new(length: usize) -> Self {
+        let mut salt = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            salt,
+            counter: AtomicU64::new(0),
+            length,
+        }
+    }
+}
+
+impl IdGenerator for Hashed {
+    fn generate(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        let digest = Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(count.to_be_bytes())
+            .finalize();
+        digest
+            .iter()
+            .take(self.length)
+            .map(|byte| BASE62[usize::from(*byte) % BASE62.len()] as char)
+            .collect()
+    }
+}
+
+/// Another generator's slugs behind a fixed prefix, from `ID_PREFIX`.
+pub struct Prefixed {
+    pub prefix: String,
+    pub inner: SharedIdGenerator,
+}
+
+impl IdGenerator for Prefixed {
+    fn generate(&self) -> String {
+        format!("{}{}", self.prefix, self.inner.generate())
+    }
+}
+
+/// Whether the slug starts with `ID_PREFIX` or one of `ID_PEER_PREFIXES`.
+pub fn has_region_prefix(slug: &str, config: &Config) -> bool {
+    config
+        .id_prefix
+        .iter()
+        .chain(&config.id_peer_prefixes)
+        .any(|prefix| slug.starts_with(prefix.as_str()))
+}
+
+/// The answer for a link that is not here: 503 when another region generated its slug, as it
+/// may not have been replicated yet and is worth asking for again, 404 otherwise.
+pub fn missing_link(id: &str, config: &Config) -> (StatusCode, String) {
+    if config
+        .id_peer_prefixes
+        .iter()
+        .any(|prefix| id.starts_with(prefix.as_str()))
+    {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Link Not Replicated Yet".to_string(),
+        );
+    }
+    (StatusCode::NOT_FOUND, "Not Found".to_string())
+}
+
+fn base62(mut value: u64) -> String {
+    let mut digits = Vec::new();
+    loop {
+        digits.push(BASE62[(value % 62) as usize]);
+        value /= 62;
+        if value == 0 {
+            break;
+        }
+    }
+    digits.iter().rev().map(|digit| *digit as char).collect()
+}
+
+/// The generator picked by `ID_STRATEGY`, behind `ID_PREFIX` if set. The sequential one reserves
+/// its block here.
+pub async fn from_config(pool: &PgPool, config: &Config) -> Result<SharedIdGenerator, sqlx::Error> {
+    let generator: SharedIdGenerator = match config.id_strategy {
+        IdStrategy::Random => Arc::new(RandomBase64),
+        IdStrategy::Nanoid => Arc::new(Nanoid {
+            length: config.id_length,
+        }),
+        IdStrategy::Sequential => {
+            let block: i64 = sqlx::query_scalar!(r#"SELECT nextval('link_id_blocks') AS "block!""#)
+                .fetch_one(pool)
+                .await?;
+            tracing::debug!("Reserved sequential id block {}", block);
+            Arc::new(Sequential::starting_at(
+                (block as u64) << SEQUENCE_BLOCK_BITS,
+            ))
+        }
+        IdStrategy::Hash => Arc::new(Hashed::new(config.id_length)),
+    };
+    Ok(match &config.id_prefix {
+        Some(prefix) => Arc::new(Prefixed {
+            prefix: prefix.clone(),
+            inner: generator,
+        }),
+        None => generator,
+    })
+}
@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::{
+    auth::{Actor, Workspace},
+    config::SharedConfig,
+    outbox,
+    route::Link,
+    slug::check_custom_slug,
+    target::parse_target_url,
+    utils::{internal_error, is_valid_slug},
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+/// Largest export file accepted by the importer.
+pub const IMPORT_MAX_BYTES: usize = 20 * 1024 * 1024;
+
+/// Field names used by hosted shorteners' exports, compared after normalizing with
+/// [`normalize_key`]. Covers Bitly (`link`/`long_url`/`created_at`) and Short.io
+/// (`path`/`originalURL`/`createdAt`) CSV and JSON exports.
+const SLUG_FIELDS: [&str; 6] = ["path", "slug", "bitlink", "link", "shorturl", "id"];
+const TARGET_FIELDS: [&str; 5] = ["longurl", "originalurl", "destination", "targeturl", "url"];
+const CREATED_FIELDS: [&str; 4] = ["createdat", "created", "datecreated", "createddate"];
+const TAG_FIELDS: [&str; 1] = ["tags"];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportConflict {
+    pub slug: String,
+    pub target_url: String,
+    pub existing_target_url: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportError {
+    /// One-based position of the record in the uploaded file.
+    pub record: usize,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: usize,
+    pub conflicts: Vec<ImportConflict>,
+    pub errors: Vec<ImportError>,
+}
+
+struct ImportedLink {
+    slug: String,
+    target_url: String,
+    tags: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// `originalURL`, `Long URL` and `long_url` all become `longurl`.
+fn normalize_key(key: &str) -> String {
+    key.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+type Record = HashMap<String, Value>;
+
+fn parse_records(body: &str) -> Result<Vec<Record>, String> {
+    let trimmed = body.trim_start_matches('\u{feff}').trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        parse_json_records(trimmed)
+    } else {
+        parse_csv_records(trimmed)
+    }
+}
+
+fn parse_json_records(body: &str) -> Result<Vec<Record>, String> {
+    let value: Value = serde_json::from_str(body).map_err(|err| err.to_string())?;
+    // Bitly wraps links in `links`, Short.io exports a bare array.
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(mut object) => match object.remove("links") {
+            Some(Value::Array(items)) => items,
+            _ => return Err("Expected an array of links".into()),
+        },
+        _ => return Err("Expected an array of links".into()),
+    };
+    Ok(items
+        .into_iter()
+        .map(|item| match item {
+            Value::Object(object) => object
+                .into_iter()
+                .map(|(key, value)| (normalize_key(&key), value))
+                .collect(),
+            _ => Record::new(),
+        })
+        .collect())
+}
+
+fn parse_csv_records(body: &str) -> Result<Vec<Record>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(body.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|err| err.to_string())?
+        .iter()
+        .map(normalize_key)
+        .collect();
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|err| err.to_string())?;
+            Ok(headers
+                .iter()
+                .cloned()
+                .zip(record.iter().map(|field| Value::String(field.to_string())))
+                .collect())
+        })
+        .collect()
+}
+
+fn field<'a>(record: &'a Record, names: &[&str]) -> Option<&'a Value> {
+    names
+        .iter()
+        .filter_map(|name| record.get(*name))
+        .find(|value| !value.is_null() && value.as_str() != Some(""))
+}
+
+/// Slugs may come as full short URLs (`https://bit.ly/abc`) or domain-prefixed (`bit.ly/abc`).
+fn slug_of(raw: &str) -> Option<String> {
+    let slug = raw.trim().trim_end_matches('/').rsplit('/').next()?;
+    is_valid_slug(slug).then(|| slug.to_string())
+}
+
+fn parse_created_at(raw: &str) -> Option<DateTime<Utc>> {
+    let raw = raw.trim();
+    DateTime::parse_from_rfc3339(raw)
+        .or_else(|_| DateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S%z"))
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .map(|date| date.and_utc())
+        })
+}
+
+fn tags_of(value: Option<&Value>) -> Vec<String> {
+    let mut tags: Vec<String> = match value {
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .map(str::to_string)
+            .collect(),
+        Some(Value::String(joined)) => joined.split([',', ';', '|']).map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    tags.iter_mut()
+        .for_each(|tag| *tag = tag.trim().to_string());
+    tags.retain(|tag| !tag.is_empty() && seen.insert(tag.clone()));
+    tags
+}
+
+/// Imports the CSV or JSON link export of a hosted shortener (Bitly, Short.io). Slugs are kept;
+/// links whose slug is taken are reported as conflicts instead of being overwritten.
+pub async fn import_links(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    body: String,
+) -> Result<Json<ImportReport>, (StatusCode, String)> {
+    let config = config.current();
+    let records = parse_records(&body)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("Unreadable Export: {err}")))?;
+
+    let mut errors = Vec::new();
+    let mut links: Vec<ImportedLink> = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, record) in records.iter().enumerate() {
+        let mut fail = |message: &str| {
+            errors.push(ImportError {
+                record: index + 1,
+                message: message.to_string(),
+            })
+        };
+        let Some(slug) = field(record, &SLUG_FIELDS)
+            .and_then(Value::as_str)
+            .and_then(slug_of)
+        else {
+            fail("Missing Or Invalid Slug");
+            continue;
+        };
+        if let Err((_, message)) = check_custom_slug(&slug, &workspace, &config) {
+            fail(&message);
+            continue;
+        }
+        let Some(raw_target) = field(record, &TARGET_FIELDS).and_then(Value::as_str) else {
+            fail("Missing Target Url");
+            continue;
+        };
+        let target_url = match parse_target_url(raw_target.trim(), &config) {
+            Ok(url) => url.to_string(),
+            Err(err) => {
+                let (_, message): (StatusCode, String) = err.into();
+                fail(&message);
+                continue;
+            }
+        };
+        if !seen.insert(slug.clone()) {
+            fail("Duplicate Slug In File");
+            continue;
+        }
+        let created_at = field(record, &CREATED_FIELDS)
+            .and_then(Value::as_str)
+            .and_then(parse_created_at)
+            .unwrap_or_else(Utc::now);
+        links.push(ImportedLink {
+            slug,
+            target_url,
+            tags: tags_of(field(record, &TAG_FIELDS)),
+            created_at,
+        });
+    }
+
+    let slugs: Vec<String> = links.iter().map(|link| link.slug.clone()).collect();
+    let targets: Vec<String> = links.iter().map(|link| link.target_url.clone()).collect();
+    // Arrays of arrays cannot be unnested into rows, so tags travel as JSON.
+    let tags: Vec<Value> = links.iter().map(|link| link.tags.clone().into()).collect();
+    let created_at: Vec<DateTime<Utc>> = links.iter().map(|link| link.created_at).collect();
+
+    let import_timeout = tokio::time::Duration::from_secs(30);
+    let (imported, existing) = tokio::time::timeout(import_timeout, async {
+        let mut tx = pool.begin().await?;
+        let imported = sqlx::query_scalar!(
+            r#"
+                INSERT INTO links (id, target_url, workspace_id, tags, created_at, updated_at, created_by)
+                SELECT
+                    i.slug,
+                    i.target_url,
+                    $5,
+                    ARRAY(SELECT jsonb_array_elements_text(i.tags)),
+                    i.created_at,
+                    i.created_at,
+                    $6
+                FROM UNNEST($1::TEXT[], $2::TEXT[], $3::JSONB[], $4::TIMESTAMPTZ[])
+                    AS i (slug, target_url, tags, created_at)
+                ON CONFLICT (id) DO NOTHING
+                RETURNING id
+            "#,
+            &slugs,
+            &targets,
+            &tags,
+            &created_at,
+            &workspace,
+            &actor
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let imported: HashSet<String> = imported.into_iter().collect();
+        let conflicting: Vec<String> = slugs
+            .iter()
+            .filter(|slug| !imported.contains(*slug))
+            .cloned()
+            .collect();
+        let existing: HashMap<String, String> = sqlx::query!(
+            "SELECT id, target_url FROM links WHERE id = ANY($1)",
+            &conflicting
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|link| (link.id, link.target_url))
+        .collect();
+
+        let events: Vec<LinkEvent> = links
+            .iter()
+            .filter(|link| imported.contains(&link.slug))
+            .map(|link| {
+                LinkEvent::new(
+                    LinkEventKind::Created,
+                    workspace.clone(),
+                    Link {
+                        id: link.slug.clone(),
+                        target_url: link.target_url.clone(),
+                    },
+                )
+            })
+            .collect();
+        outbox::enqueue_all(&mut tx, &events).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>((imported, existing))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    let conflicts: Vec<ImportConflict> = links
+        .into_iter()
+        .filter(|link| !imported.contains(&link.slug))
+        .map(|link| ImportConflict {
+            existing_target_url: existing.get(&link.slug).cloned(),
+            slug: link.slug,
+            target_url: link.target_url,
+        })
+        .collect();
+    tracing::debug!(
+        "Imported {} links into workspace {}, {} conflicts, {} errors",
+        imported.len(),
+        workspace,
+        conflicts.len(),
+        errors.len()
+    );
+    Ok(Json(ImportReport {
+        imported: imported.len(),
+        conflicts,
+        errors,
+    }))
+}
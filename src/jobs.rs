@@ -0,0 +1,294 @@
+use std::{fmt::Display, future::Future};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::{
+    audit, auth::Actor, config::SharedConfig, rollup, statistics_export, utils::internal_error,
+    weekly_report,
+};
+
+/// Longest range a single rollup job may cover.
+const MAX_ROLLUP_DAYS: i64 = 366;
+/// Longest range a single statistics export job may cover.
+const MAX_EXPORT_DAYS: i64 = 92;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub params: Value,
+    /// `running`, `succeeded` or `failed`.
+    pub status: String,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+async fn fetch_job(pool: &PgPool, id: i64) -> Result<Option<Job>, sqlx::Error> {
+    sqlx::query_as!(
+        Job,
+        r#"
+            SELECT id, kind, params, status, result, error, actor, created_at, finished_at
+            FROM maintenance_jobs
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Records a job and runs `work` in the background, storing its result or error once it is
+/// done. Answers 202 with the job, to be polled under `Location`.
+async fn start_job<F, E>(
+    pool: PgPool,
+    actor: &str,
+    kind: &str,
+    params: Value,
+    work: F,
+) -> Result<Response, (StatusCode, String)>
+where
+    F: Future<Output = Result<Value, E>> + Send + 'static,
+    E: Display,
+{
+    let start_job_timeout = tokio::time::Duration::from_millis(300);
+    let job = tokio::time::timeout(start_job_timeout, async {
+        let mut tx = pool.begin().await?;
+        let job = sqlx::query_as!(
+            Job,
+            r#"
+                INSERT INTO maintenance_jobs (kind, params, actor)
+                VALUES ($1, $2, $3)
+                RETURNING id, kind, params, status, result, error, actor, created_at, finished_at
+            "#,
+            kind,
+            params,
+            actor
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            &format!("job.{kind}"),
+            Some(&job.id.to_string()),
+            job.params.clone(),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(job)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::info!("{} started {} job {}", actor, kind, job.id);
+
+    let id = job.id;
+    tokio::spawn(async move {
+        let (status, result, error) = match work.await {
+            Ok(result) => ("succeeded", Some(result), None),
+            Err(err) => {
+                tracing::error!("Maintenance job {} failed: {}", id, err);
+                ("failed", None, Some(err.to_string()))
+            }
+        };
+        let finished = sqlx::query!(
+            r#"
+                UPDATE maintenance_jobs
+                SET status = $2, result = $3, error = $4, finished_at = now()
+                WHERE id = $1
+            "#,
+            id,
+            status,
+            result,
+            error
+        )
+        .execute(&pool)
+        .await;
+        if let Err(err) = finished {
+            tracing::error!(
+                "Recording the outcome of maintenance job {} failed: {}",
+                id,
+                err
+            );
+        }
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/admin/jobs/{id}"))],
+        Json(job),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RollupRange {
+    /// First UTC day rolled up.
+    pub from: NaiveDate,
+    /// Last UTC day rolled up, included.
+    pub to: NaiveDate,
+}
+
+/// Rolls up the clicks of a range of days again, for days recorded before rollups existed or
+/// whose clicks changed since.
+pub async fn start_rollup(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Json(range): Json<RollupRange>,
+) -> Result<Response, (StatusCode, String)> {
+    let days = (range.to - range.from).num_days() + 1;
+    if !(1..=MAX_ROLLUP_DAYS).contains(&days) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("from must not be after to, and the range at most {MAX_ROLLUP_DAYS} days"),
+        ));
+    }
+    let params = json!(range);
+    let work_pool = pool.clone();
+    start_job(pool, &actor, "rollup", params, async move {
+        let rolled_up = rollup::roll_up(&work_pool, range.from, range.to).await?;
+        Ok::<_, sqlx::Error>(json!({ "linkDays": rolled_up }))
+    })
+    .await
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PruneRange {
+    pub from: DateTime<Utc>,
+    /// Clicks recorded at this instant or later are kept.
+    pub to: DateTime<Utc>,
+}
+
+/// Irreversibly deletes the clicks recorded in a range. Rollups and click counters keep them.
+pub async fn start_prune(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Json(range): Json<PruneRange>,
+) -> Result<Response, (StatusCode, String)> {
+    if range.from >= range.to {
+        return Err((StatusCode::BAD_REQUEST, "from must be before to".into()));
+    }
+    let params = json!(range);
+    let work_pool = pool.clone();
+    start_job(pool, &actor, "prune", params, async move {
+        let deleted = sqlx::query!(
+            "DELETE FROM link_statistics WHERE created_at >= $1 AND created_at < $2",
+            range.from,
+            range.to
+        )
+        .execute(&work_pool)
+        .await?
+        .rows_affected();
+        Ok::<_, sqlx::Error>(json!({ "deleted": deleted }))
+    })
+    .await
+}
+
+/// Recounts the clicks of every link from the recorded ones, for counters that drifted. Clicks
+/// already pruned are no longer counted afterwards.
+pub async fn start_counter_rebuild(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+) -> Result<Response, (StatusCode, String)> {
+    let work_pool = pool.clone();
+    start_job(pool, &actor, "rebuild_counters", json!({}), async move {
+        let updated = sqlx::query!(
+            r#"
+                UPDATE links l
+                SET click_count = c.clicks
+                FROM (
+                    SELECT l.id, COALESCE(SUM(s.weight), 0) AS clicks
+                    FROM links l
+                    LEFT JOIN link_statistics s ON s.link_id = l.id AND NOT s.prefetch
+                    GROUP BY l.id
+                ) c
+                WHERE l.id = c.id AND l.click_count IS DISTINCT FROM c.clicks
+            "#
+        )
+        .execute(&work_pool)
+        .await?
+        .rows_affected();
+        Ok::<_, sqlx::Error>(json!({ "updated": updated }))
+    })
+    .await
+}
+
+/// Sends the weekly reports of the past week still due now, rather than on the next check.
+pub async fn start_weekly_reports(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+) -> Result<Response, (StatusCode, String)> {
+    let config = config.current();
+    if config.email.host.is_none() {
+        return Err((StatusCode::CONFLICT, "SMTP_HOST is not set".into()));
+    }
+    let work_pool = pool.clone();
+    start_job(pool, &actor, "weekly_reports", json!({}), async move {
+        let sent = weekly_report::send_due(&work_pool, &config).await?;
+        Ok::<_, sqlx::Error>(json!({ "sent": sent }))
+    })
+    .await
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ExportRange {
+    /// First UTC day exported.
+    pub from: NaiveDate,
+    /// Last UTC day exported, included.
+    pub to: NaiveDate,
+}
+
+/// Exports the clicks of a range of days to `S3_BUCKET` again, for days recorded before exports
+/// were set up or whose export failed.
+pub async fn start_statistics_export(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Json(range): Json<ExportRange>,
+) -> Result<Response, (StatusCode, String)> {
+    let config = config.current();
+    if config.s3.bucket.is_none() {
+        return Err((StatusCode::CONFLICT, "S3_BUCKET is not set".into()));
+    }
+    let days = (range.to - range.from).num_days() + 1;
+    if !(1..=MAX_EXPORT_DAYS).contains(&days) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("from must not be after to, and the range at most {MAX_EXPORT_DAYS} days"),
+        ));
+    }
+    let params = json!(range);
+    let work_pool = pool.clone();
+    start_job(pool, &actor, "statistics_export", params, async move {
+        let clicks =
+            statistics_export::export_days(&work_pool, &config, range.from, range.to).await?;
+        Ok::<_, statistics_export::ExportError>(json!({ "clicks": clicks }))
+    })
+    .await
+}
+
+pub async fn get_job(
+    State(pool): State<PgPool>,
+    Path(id): Path<i64>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    let fetch_job_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(fetch_job_timeout, fetch_job(&pool, id))
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Not Found".to_string()))
+}
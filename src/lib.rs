@@ -0,0 +1,529 @@
+//! A link shortener as an axum [`Router`], to run on its own (see `main.rs`) or nested in a
+//! larger application.
+
+use crate::access_log::{log_access, AccessLog};
+use crate::admin::reload_settings;
+use crate::alias::get_alias_availability;
+use crate::archive::unarchive_link;
+use crate::audit::list_audit_log;
+use crate::auth::auth;
+use crate::bitly::{bitlink_clicks, shorten};
+use crate::campaign::{
+    add_campaign_links, create_campaign, delete_campaign, get_campaign, get_campaign_statistics,
+    list_campaigns, remove_campaign_link, update_campaign,
+};
+use crate::click::{
+    get_click, get_click_breakdown, get_daily_statistics, get_hourly_statistics, ClickSampler,
+    VisitorHasher,
+};
+use crate::click_writer::ClickWriter;
+use crate::conversion::{get_conversion_statistics, record_conversion};
+use crate::dashboard::get_dashboard;
+use crate::db::CircuitBreaker;
+use crate::error_pages::{brand_error_pages, delete_error_page, list_error_pages, set_error_page};
+use crate::error_report::{handle_panic, report_errors, ErrorReporting};
+use crate::export::export_data;
+use crate::health_monitor::get_broken_links;
+use crate::history::{get_link_history, rollback_link};
+use crate::https::enforce_https;
+use crate::importer::{import_links, IMPORT_MAX_BYTES};
+use crate::jobs::{
+    get_job, start_counter_rebuild, start_prune, start_rollup, start_statistics_export,
+    start_weekly_reports,
+};
+use crate::lifecycle::Readiness;
+use crate::link_cache::LinkCache;
+use crate::logging::SampledTrace;
+use crate::maintenance::{get_maintenance, maintenance_guard, read_only_guard, set_maintenance};
+use crate::manifest::{apply_manifest, export_manifest};
+use crate::metering::{get_usage, UsageMeter};
+use crate::namespace::{list_namespaces, release_namespace, reserve_namespace};
+use crate::pause::{pause_link, resume_link};
+use crate::prefetch::PrefetchDetector;
+use crate::probe::{favicon, robots_txt, well_known};
+use crate::public_stats::{disable_public_stats, enable_public_stats, get_public_stats};
+use crate::purge::{purge_link_statistics, purge_statistics};
+use crate::qr::get_qr_code;
+use crate::rate_limit::{limit_requests, reject_banned, LinkThrottle, RateLimiter};
+use crate::request_timeout::limit_request_time;
+use crate::resolve::{expand_link, resolve_links};
+use crate::rollout::{cancel_rollout, get_rollout, start_rollout};
+use crate::route::{
+    clone_link, count_redirects, create_link, delete_link, get_link,
+    get_link_statistics as statistics, health_check, list_links, redirect, update_link,
+    upsert_link,
+};
+use crate::shorten::shorten_get;
+use crate::signed::create_signed_link;
+use crate::unfurl::PreviewCache;
+use crate::utils::handle_overload;
+use crate::webhook::{
+    create_webhook_endpoint, delete_webhook_endpoint, list_webhook_deliveries,
+    list_webhook_endpoints,
+};
+use crate::workspace::{get_workspace_settings, set_workspace_settings};
+
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::{DefaultBodyLimit, FromRef},
+    middleware,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::{catch_panic::CatchPanicLayer, compression::CompressionLayer, trace::TraceLayer};
+
+mod access_log;
+mod admin;
+mod alias;
+mod archive;
+mod audit;
+mod auth;
+mod bitly;
+mod campaign;
+mod cdn;
+mod click;
+mod click_writer;
+mod client_ip;
+mod config;
+mod conversion;
+mod dashboard;
+mod db;
+mod email;
+mod error_pages;
+mod error_report;
+mod expiry;
+mod export;
+mod health_monitor;
+mod history;
+mod hotlink;
+mod https;
+mod id;
+mod importer;
+mod jobs;
+mod lifecycle;
+mod link_cache;
+mod logging;
+mod maintenance;
+mod manifest;
+mod metering;
+mod milestone;
+mod namespace;
+mod notify;
+mod outbound;
+mod outbox;
+mod partition;
+mod pause;
+mod prefetch;
+mod preflight;
+mod probe;
+mod public_stats;
+mod purge;
+mod qr;
+mod rate_limit;
+mod request_timeout;
+mod resolve;
+mod response_headers;
+mod rollout;
+mod rollup;
+mod route;
+mod s3;
+mod shorten;
+mod signed;
+mod slug;
+mod ssrf;
+mod statistics_export;
+mod target;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod title;
+mod unfurl;
+mod utils;
+mod utm;
+mod validation;
+mod webhook;
+mod weekly_report;
+mod workspace;
+
+pub use crate::auth::{
+    AuthError, AuthMethod, Authenticator, DatabaseKey, Jwt, NoAuth, Principal, SharedAuthenticator,
+    StaticKey,
+};
+pub use crate::cdn::{CdnProvider, CdnPurger, Cloudflare, Fastly, PurgeRequest, SharedCdnPurger};
+pub use crate::config::{Config, ConfigError, SharedConfig};
+pub use crate::error_report::{
+    ErrorReport, ErrorReporter, ErrorWebhook, RequestContext, Sentry, SentryDsn,
+    SharedErrorReporter,
+};
+pub use crate::id::{
+    Hashed, IdGenerator, IdStrategy, Nanoid, Prefixed, RandomBase64, Sequential, SharedIdGenerator,
+};
+pub use crate::preflight::{preflight, PreflightError, MIGRATOR};
+pub use crate::utils::UNMATCHED_ROUTE;
+
+#[cfg(unix)]
+pub use crate::admin::reload_on_sighup;
+pub use crate::logging::init_logging;
+
+/// Everything the router shares between requests: the pool, the live configuration and the
+/// in-memory state of the services built on them.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub config: SharedConfig,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub visitor_hasher: Arc<VisitorHasher>,
+    pub click_sampler: Arc<ClickSampler>,
+    pub click_writer: Arc<ClickWriter>,
+    pub prefetch_detector: Arc<PrefetchDetector>,
+    pub link_throttle: Arc<LinkThrottle>,
+    pub link_cache: Arc<LinkCache>,
+    pub preview_cache: Arc<PreviewCache>,
+    pub usage_meter: Arc<UsageMeter>,
+    pub access_log: Arc<AccessLog>,
+    pub id_generator: SharedIdGenerator,
+    pub authenticator: SharedAuthenticator,
+    /// Purges changed links from the CDN; `None` without `CDN_PROVIDER`.
+    pub cdn_purger: Option<SharedCdnPurger>,
+    pub error_reporting: Arc<ErrorReporting>,
+    pub readiness: Arc<Readiness>,
+}
+
+impl AppState {
+    /// Builds the state, with the id generator, authenticator, CDN purger and error reporter the
+    /// configuration asks for.
+    pub async fn new(pool: PgPool, config: Config) -> Result<Self, sqlx::Error> {
+        let circuit_breaker = Arc::new(CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_open_duration,
+        ));
+        let id_generator = id::from_config(&pool, &config).await?;
+        let authenticator = auth::from_config(pool.clone(), &config);
+        let cdn_purger = cdn::from_config(&config);
+        let error_reporting = Arc::new(ErrorReporting::new(error_report::from_config(&config)));
+        Ok(Self {
+            pool,
+            config: SharedConfig::new(config),
+            circuit_breaker,
+            rate_limiter: Arc::default(),
+            visitor_hasher: Arc::default(),
+            click_sampler: Arc::default(),
+            click_writer: Arc::default(),
+            prefetch_detector: Arc::default(),
+            link_throttle: Arc::default(),
+            link_cache: Arc::default(),
+            preview_cache: Arc::default(),
+            usage_meter: Arc::default(),
+            access_log: Arc::default(),
+            id_generator,
+            authenticator,
+            cdn_purger,
+            error_reporting,
+            readiness: Arc::default(),
+        })
+    }
+
+    /// Resolves on SIGTERM or Ctrl-C, after `/health` has failed for `SHUTDOWN_GRACE_SECS`.
+    /// Meant for `with_graceful_shutdown`.
+    pub async fn shutdown_signal(&self) {
+        let grace = self.config.current().shutdown_grace;
+        lifecycle::shutdown_signal(&self.readiness, grace).await;
+    }
+
+    /// Writes what is still buffered in memory, unless read-only. Call once the server has
+    /// stopped.
+    pub async fn flush(&self) {
+        if self.config.current().read_only {
+            return;
+        }
+        let click_writer = self.config.current().click_writer.clone();
+        if let Err(err) = self.click_writer.flush(&self.pool, &click_writer).await {
+            tracing::error!("Writing clicks on shutdown failed: {}", err);
+        }
+        if let Err(err) = self.usage_meter.flush(&self.pool).await {
+            tracing::error!("Writing API usage on shutdown failed: {}", err);
+        }
+        let access_log = self.config.current().access_log.clone();
+        if let Err(err) = self.access_log.flush(&self.pool, &access_log).await {
+            tracing::error!("Writing the access log on shutdown failed: {}", err);
+        }
+    }
+
+    /// Replaces the authenticator picked by `AUTH_METHOD`.
+    pub fn with_authenticator(self, authenticator: SharedAuthenticator) -> Self {
+        Self {
+            authenticator,
+            ..self
+        }
+    }
+
+    /// Replaces the CDN purger picked by `CDN_PROVIDER`, for example to purge another CDN. It is
+    /// given short URLs with `BASE_URL` set and surrogate keys with `CDN_SURROGATE_KEYS` on.
+    pub fn with_cdn_purger(self, cdn_purger: SharedCdnPurger) -> Self {
+        Self {
+            cdn_purger: Some(cdn_purger),
+            ..self
+        }
+    }
+
+    /// Replaces the error reporter picked by `SENTRY_DSN` or `ERROR_REPORT_URL`.
+    pub fn with_error_reporter(self, reporter: SharedErrorReporter) -> Self {
+        Self {
+            error_reporting: Arc::new(ErrorReporting::new(Some(reporter))),
+            ..self
+        }
+    }
+
+    /// Sends panics to the error reporter from now on. Call once per process.
+    pub fn report_panics(&self) {
+        error_report::report_panics(self.error_reporting.clone(), self.config.clone());
+    }
+
+    /// Replaces the generator of new slugs, for example with a deterministic one in tests.
+    pub fn with_id_generator(self, id_generator: SharedIdGenerator) -> Self {
+        Self {
+            id_generator,
+            ..self
+        }
+    }
+
+    /// Starts the background jobs: click and usage writing, link cache invalidation, health checks,
+    /// the outbox dispatcher, expiry, archiving, click partitions and rollups, milestones, title
+    /// fetching, weekly reports and statistics exports. Call once per process. Read-only instances start none,
+    /// replicas can neither be written nor listened on, so their cached links are only refreshed
+    /// once they expire.
+    pub fn spawn_background_jobs(&self) {
+        if self.config.current().read_only {
+            tracing::info!("Read-only instance, not starting background jobs");
+            return;
+        }
+        click_writer::spawn(
+            self.pool.clone(),
+            self.config.clone(),
+            self.click_writer.clone(),
+        );
+        metering::spawn(self.pool.clone(), self.usage_meter.clone());
+        access_log::spawn(
+            self.pool.clone(),
+            self.config.clone(),
+            self.access_log.clone(),
+        );
+        health_monitor::spawn(self.pool.clone(), self.config.clone());
+        link_cache::spawn(self.pool.clone(), self.link_cache.clone());
+        outbox::spawn(
+            self.pool.clone(),
+            self.config.clone(),
+            self.cdn_purger.clone(),
+        );
+        expiry::spawn(self.pool.clone(), self.config.clone());
+        archive::spawn(self.pool.clone(), self.config.clone());
+        partition::spawn(self.pool.clone(), self.config.clone());
+        rollup::spawn(self.pool.clone(), self.config.clone());
+        milestone::spawn(self.pool.clone(), self.config.clone());
+        title::spawn(self.pool.clone(), self.config.clone());
+        weekly_report::spawn(self.pool.clone(), self.config.clone());
+        statistics_export::spawn(self.pool.clone(), self.config.clone());
+        rollout::spawn(self.pool.clone(), self.config.clone());
+    }
+}
+
+/// Lets handlers and middleware take the parts of the state they use, like
+/// `State<SharedConfig>`.
+macro_rules! from_app_state {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            impl FromRef<AppState> for $ty {
+                fn from_ref(state: &AppState) -> Self {
+                    state.$field.clone()
+                }
+            }
+        )*
+    };
+}
+
+from_app_state! {
+    pool: PgPool,
+    config: SharedConfig,
+    circuit_breaker: Arc<CircuitBreaker>,
+    rate_limiter: Arc<RateLimiter>,
+    visitor_hasher: Arc<VisitorHasher>,
+    click_sampler: Arc<ClickSampler>,
+    click_writer: Arc<ClickWriter>,
+    prefetch_detector: Arc<PrefetchDetector>,
+    link_throttle: Arc<LinkThrottle>,
+    link_cache: Arc<LinkCache>,
+    preview_cache: Arc<PreviewCache>,
+    usage_meter: Arc<UsageMeter>,
+    access_log: Arc<AccessLog>,
+    id_generator: SharedIdGenerator,
+    authenticator: SharedAuthenticator,
+    error_reporting: Arc<ErrorReporting>,
+    readiness: Arc<Readiness>,
+}
+
+/// All routes of the shortener. Serve it with
+/// `into_make_service_with_connect_info::<SocketAddr>()`, clients are told apart by their address.
+pub fn build_router(state: AppState) -> Router {
+    Router::new()
+        .route("/create", post(create_link))
+        .route("/signed-links", post(create_signed_link))
+        .route(
+            "/:id/statistics",
+            get(statistics).delete(purge_link_statistics),
+        )
+        .route("/:id/statistics/daily", get(get_daily_statistics))
+        .route("/:id/statistics/hours", get(get_hourly_statistics))
+        .route("/:id/statistics/breakdown", get(get_click_breakdown))
+        .route(
+            "/:id/statistics/conversions",
+            get(get_conversion_statistics),
+        )
+        .route(
+            "/:id/public-stats",
+            post(enable_public_stats).delete(disable_public_stats),
+        )
+        .route("/:id/clone", post(clone_link))
+        .route("/:id/pause", post(pause_link))
+        .route("/:id/resume", post(resume_link))
+        .route("/:id/history", get(get_link_history))
+        .route(
+            "/:id/rollout",
+            get(get_rollout).post(start_rollout).delete(cancel_rollout),
+        )
+        .route("/:id/rollback", post(rollback_link))
+        .route("/api/links", get(list_links))
+        .route("/api/links/:id", get(get_link).put(upsert_link))
+        .route(
+            "/api/aliases/:slug/availability",
+            get(get_alias_availability),
+        )
+        .route("/api/resolve", post(resolve_links))
+        .route("/api/clicks/:click_id", get(get_click))
+        .route(
+            "/api/import",
+            post(import_links).layer(DefaultBodyLimit::max(IMPORT_MAX_BYTES)),
+        )
+        .route("/api/manifest", get(export_manifest).put(apply_manifest))
+        .route(
+            "/api/namespaces",
+            get(list_namespaces).post(reserve_namespace),
+        )
+        .route("/api/namespaces/:prefix", delete(release_namespace))
+        .route("/api/reports/broken-links", get(get_broken_links))
+        .route("/api/dashboard", get(get_dashboard))
+        .route(
+            "/api/workspace/settings",
+            get(get_workspace_settings).put(set_workspace_settings),
+        )
+        .route("/admin/reload", post(reload_settings))
+        .route("/admin/audit-log", get(list_audit_log))
+        .route("/admin/error-pages", get(list_error_pages))
+        .route(
+            "/admin/error-pages/:domain/:status",
+            put(set_error_page).delete(delete_error_page),
+        )
+        .route("/admin/usage", get(get_usage))
+        .route("/admin/links/:id/unarchive", post(unarchive_link))
+        .route("/admin/statistics", delete(purge_statistics))
+        .route("/admin/jobs/rollups", post(start_rollup))
+        .route("/admin/jobs/prune", post(start_prune))
+        .route("/admin/jobs/rebuild-counters", post(start_counter_rebuild))
+        .route("/admin/jobs/weekly-reports", post(start_weekly_reports))
+        .route(
+            "/admin/jobs/statistics-export",
+            post(start_statistics_export),
+        )
+        .route("/admin/jobs/:id", get(get_job))
+        .route(
+            "/admin/export",
+            get(export_data).layer(CompressionLayer::new()),
+        )
+        .route(
+            "/admin/maintenance",
+            get(get_maintenance).put(set_maintenance),
+        )
+        .route(
+            "/webhooks",
+            post(create_webhook_endpoint).get(list_webhook_endpoints),
+        )
+        .route("/webhooks/:id", delete(delete_webhook_endpoint))
+        .route("/webhooks/:id/deliveries", get(list_webhook_deliveries))
+        .route("/v4/shorten", post(shorten))
+        .route("/v4/bitlinks/:bitlink/clicks", get(bitlink_clicks))
+        .route("/v4/bitlinks/:domain/:hash/clicks", get(bitlink_clicks))
+        .route("/campaigns", post(create_campaign).get(list_campaigns))
+        .route(
+            "/campaigns/:id",
+            get(get_campaign)
+                .patch(update_campaign)
+                .delete(delete_campaign),
+        )
+        .route("/campaigns/:id/links", post(add_campaign_links))
+        .route(
+            "/campaigns/:id/links/:link_id",
+            delete(remove_campaign_link),
+        )
+        .route("/campaigns/:id/statistics", get(get_campaign_statistics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth))
+        .route(
+            "/:id",
+            patch(update_link)
+                .delete(delete_link)
+                .route_layer(middleware::from_fn_with_state(state.clone(), auth))
+                .get(redirect)
+                .route_layer(middleware::from_fn(count_redirects)),
+        )
+        .route("/favicon.ico", get(favicon))
+        .route("/robots.txt", get(robots_txt))
+        .route("/.well-known/*path", get(well_known))
+        .route(
+            "/:id/:slug",
+            get(redirect).route_layer(middleware::from_fn(count_redirects)),
+        )
+        .route("/:id/stats/:token", get(get_public_stats))
+        .route("/:id/qr", get(get_qr_code))
+        .route("/api/expand/:id", get(expand_link))
+        .route("/api/conversions", post(record_conversion))
+        .route("/api/shorten", get(shorten_get))
+        .route("/health", get(health_check))
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_request_time,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance_guard,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            read_only_guard,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_requests,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), reject_banned))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            brand_error_pages,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), enforce_https))
+        .layer(middleware::from_fn_with_state(state.clone(), log_access))
+        .layer(middleware::from_fn_with_state(state.clone(), report_errors))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_overload))
+                .load_shed()
+                .concurrency_limit(state.config.current().max_concurrent_requests),
+        )
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(SampledTrace::new(state.config.clone()))
+                .on_response(SampledTrace::new(state.config.clone())),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,55 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Whether this instance should be sent traffic. Turned off on shutdown so `/health` fails and
+/// load balancers stop routing here while requests already on their way are still served.
+#[derive(Default)]
+pub struct Readiness {
+    draining: AtomicBool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        !self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+}
+
+async fn terminate_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminations) => {
+                terminations.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Could not listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
+
+/// Waits for SIGTERM or Ctrl-C, then fails readiness and keeps serving for `grace` before
+/// returning, so the server stops only once no new requests are routed here.
+pub async fn shutdown_signal(readiness: &Readiness, grace: Duration) {
+    tokio::select! {
+        _ = terminate_signal() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    tracing::info!(
+        "Shutting down: draining for {}s before closing connections",
+        grace.as_secs()
+    );
+    readiness.start_draining();
+    tokio::time::sleep(grace).await;
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use sqlx::{postgres::PgListener, PgExecutor, PgPool};
+
+use crate::config::LinkCacheConfig;
+
+/// Channel changed links are announced on, with their id as the payload.
+const CHANNEL: &str = "link_changed";
+const LISTEN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// What a redirect needs to know about a link.
+#[derive(Debug)]
+pub struct CachedLink {
+    pub id: String,
+    pub workspace_id: String,
+    pub target_url: String,
+    pub title: Option<String>,
+    pub redirect_type: i32,
+    pub rate_limit: Option<i32>,
+    pub referer_fallback_url: Option<String>,
+    pub allowed_referers: Vec<String>,
+    pub response_headers: Vec<String>,
+    pub cache_tier: Option<String>,
+    pub utm_template: Option<Vec<String>>,
+    /// The target being rolled out and the percentage of redirects sent to it, while a rollout
+    /// runs.
+    pub canary_target_url: Option<String>,
+    pub canary_percent: Option<i32>,
+    /// Off when the link's workspace opted out of processing visitor addresses.
+    pub geo_enrichment: bool,
+    pub privacy_mode: bool,
+    pub track_clicks: bool,
+    pub sample_rate: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CachedLink {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+/// The active link `id`, unless it has expired.
+pub async fn fetch(pool: &PgPool, id: &str) -> Result<Option<CachedLink>, sqlx::Error> {
+    sqlx::query_as!(
+        CachedLink,
+        r#"
+            SELECT l.id, l.workspace_id, l.target_url, l.title, l.redirect_type, l.rate_limit,
+                l.referer_fallback_url, l.allowed_referers, l.response_headers, l.cache_tier,
+                l.utm_template,
+                r.target_url AS "canary_target_url?", r.percent AS "canary_percent?",
+                COALESCE(ws.geo_enrichment, TRUE) AS "geo_enrichment!", l.privacy_mode,
+                l.track_clicks, l.sample_rate, l.expires_at
+            FROM links l
+            LEFT JOIN workspace_settings ws ON ws.workspace_id = l.workspace_id
+            LEFT JOIN link_rollouts r ON r.link_id = l.id
+            WHERE l.id = $1 AND l.active AND (l.expires_at IS NULL OR l.expires_at > now())
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Links recently redirected to, so hot links are served without a database round-trip. Changes
+/// are applied by invalidating entries: right away on the instance making them, through
+/// [`publish`] on the others. Entries also expire after `LINK_CACHE_TTL_SECS`, which bounds how
+/// long a change made behind the service's back goes unnoticed.
+#[derive(Debug, Default)]
+pub struct LinkCache {
+    entries: Mutex<HashMap<String, (Arc<CachedLink>, Instant)>>,
+    /// Bumped by every invalidation, so a lookup racing with a change doesn't cache what it read
+    /// before the change.
+    generation: AtomicU64,
+}
+
+impl LinkCache {
+    pub fn get(&self, id: &str, config: &LinkCacheConfig) -> Option<Arc<CachedLink>> {
+        let entries = self.entries.lock().expect("Link cache lock poisoned");
+        let (link, cached_at) = entries.get(id)?;
+        if cached_at.elapsed() >= config.ttl || link.is_expired() {
+            return None;
+        }
+        counter!("link_cache_hits").increment(1);
+        Some(link.clone())
+    }
+
+    /// Call before looking a link up, and hand the result to [`LinkCache::insert`].
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Caches a link looked up since `generation`, unless something was invalidated meanwhile or
+    /// `LINK_CACHE_CAPACITY` fresh links are cached already.
+    pub fn insert(
+        &self,
+        link: CachedLink,
+        generation: u64,
+        config: &LinkCacheConfig,
+    ) -> Arc<CachedLink> {
+        let link = Arc::new(link);
+        let mut entries = self.entries.lock().expect("Link cache lock poisoned");
+        if self.generation() != generation {
+            return link;
+        }
+        if entries.len() >= config.capacity {
+            entries.retain(|_, (_, cached_at)| cached_at.elapsed() < config.ttl);
+        }
+        if entries.len() < config.capacity {
+            entries.insert(link.id.clone(), (link.clone(), Instant::now()));
+        }
+        link
+    }
+
+    pub fn invalidate(&self, ids: &[String]) {
+        let mut entries = self.entries.lock().expect("Link cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        for id in ids {
+            entries.remove(id);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().expect("Link cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.clear();
+    }
+}
+
+/// Announces changed links to every instance. Within a transaction, the announcement is only sent
+/// once the change is committed.
+pub async fn publish(executor: impl PgExecutor<'_>, ids: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_notify($1, id) FROM UNNEST($2::TEXT[]) AS id",
+        CHANNEL,
+        ids
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Invalidates the links other instances announce. Announcements missed while the connection was
+/// down can't be told apart, so the whole cache is dropped after reconnecting.
+pub fn spawn(pool: PgPool, cache: Arc<LinkCache>) {
+    tokio::spawn(async move {
+        loop {
+            let mut listener = match PgListener::connect_with(&pool).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    tracing::error!("Connecting the link cache listener failed: {}", err);
+                    tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if let Err(err) = listener.listen(CHANNEL).await {
+                tracing::error!("Listening for changed links failed: {}", err);
+                tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+                continue;
+            }
+            cache.clear();
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => {
+                        cache.invalidate(&[notification.payload().to_string()]);
+                    }
+                    Ok(None) => {
+                        tracing::warn!("Link cache listener reconnected, dropping the cache");
+                        cache.clear();
+                    }
+                    Err(err) => {
+                        tracing::error!("Receiving changed links failed: {}", err);
+                        break;
+                    }
+                }
+            }
+            tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+        }
+    });
+}
@@ -0,0 +1,91 @@
+use std::{sync::OnceLock, time::Duration};
+
+use axum::http::{Request, Response};
+use rand::Rng;
+use tower_http::trace::{MakeSpan, OnResponse};
+use tracing::Span;
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use crate::config::{LoggingConfig, SharedConfig};
+
+/// Swaps the filter of the subscriber installed by [`init_logging`] when the configuration
+/// changes. Unset when the application installed a subscriber of its own.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global subscriber. `RUST_LOG`, when set, is used as is and always wins;
+/// otherwise `LOG_LEVEL` and `LOG_LEVELS` apply once the configuration is loaded.
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(LoggingConfig::default().directives()));
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    let _ = FILTER.set(handle);
+}
+
+/// Applies the configured levels to the subscriber installed by [`init_logging`], if any.
+pub fn apply(config: &LoggingConfig) {
+    let Some(handle) = FILTER.get() else {
+        return;
+    };
+    if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return;
+    }
+    match EnvFilter::try_new(config.directives()) {
+        Ok(filter) => {
+            if let Err(err) = handle.reload(filter) {
+                tracing::error!("Applying the log levels failed: {}", err);
+            }
+        }
+        Err(err) => tracing::error!("Invalid LOG_LEVELS: {}", err),
+    }
+}
+
+/// Whether to trace this request, for a share of `rate` (0 to 1) of them.
+pub fn is_sampled(rate: f64) -> bool {
+    rate >= 1.0 || rate > 0.0 && rand::thread_rng().gen_bool(rate)
+}
+
+/// Traces `TRACE_SAMPLE_RATE` of the requests as an info span with an event once answered, so
+/// tracing keeps up at production traffic. Failures are still logged for every request.
+#[derive(Clone)]
+pub struct SampledTrace {
+    config: SharedConfig,
+}
+
+impl SampledTrace {
+    pub fn new(config: SharedConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl<B> MakeSpan<B> for SampledTrace {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        if !is_sampled(self.config.current().logging.trace_sample_rate) {
+            return Span::none();
+        }
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.uri().path(),
+        )
+    }
+}
+
+impl<B> OnResponse<B> for SampledTrace {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        if span.is_none() {
+            return;
+        }
+        tracing::info!(
+            parent: span,
+            status = response.status().as_u16(),
+            latency_ms = latency.as_millis() as u64,
+            "Answered request"
+        );
+    }
+}
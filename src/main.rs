@@ -1,55 +1,47 @@
-use crate::route::{
-    create_link, get_link_statistics as statistics, health_check, redirect, update_link,
-};
-
-use crate::auth::auth;
-use axum::{
-    middleware,
-    routing::{get, patch, post},
-    Router,
-};
-use axum_prometheus::PrometheusMetricLayer;
+use axum::routing::get;
+use axum_prometheus::{EndpointLabel, PrometheusMetricLayerBuilder};
 use dotenvy::dotenv;
+use link_shortener::{build_router, init_logging, preflight, AppState, UNMATCHED_ROUTE};
 use sqlx::postgres::PgPoolOptions;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod auth;
-mod route;
-mod utils;
+use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "link_shortener=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    init_logging();
 
-    let db_link: String = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let db_conn = PgPoolOptions::new().connect(&db_link).await?;
+    let Ok(db_link) = std::env::var("DATABASE_URL") else {
+        fail("DATABASE_URL must be set to the Postgres connection URL")
+    };
+    let db_conn = match PgPoolOptions::new().connect(&db_link).await {
+        Ok(db_conn) => db_conn,
+        Err(err) => fail(format!(
+            "Could not connect to the database at DATABASE_URL: {err}"
+        )),
+    };
+    let config = match preflight(&db_conn).await {
+        Ok(config) => config,
+        Err(err) => fail(err),
+    };
+    let state = AppState::new(db_conn.clone(), config).await?;
+    state.report_panics();
+    state.spawn_background_jobs();
+    #[cfg(unix)]
+    link_shortener::reload_on_sighup(db_conn, state.config.clone());
 
-    let (prometheus_layer, metrics_handle) = PrometheusMetricLayer::pair();
-    let app = Router::new()
-        .route("/create", post(create_link))
-        .route("/:id/statistics", get(statistics))
-        .route_layer(middleware::from_fn_with_state(db_conn.clone(), auth))
-        .route(
-            "/:id",
-            patch(update_link)
-                .route_layer(middleware::from_fn_with_state(db_conn.clone(), auth))
-                .get(redirect),
-        )
+    let port = state.config.current().port;
+    // Labelled by route template, never by path, which would add a label value per slug.
+    let (prometheus_layer, metrics_handle) = PrometheusMetricLayerBuilder::new()
+        .with_endpoint_label_type(EndpointLabel::MatchedPathWithFallbackFn(|_| {
+            UNMATCHED_ROUTE.to_string()
+        }))
+        .with_default_metrics()
+        .build_pair();
+    let app = build_router(state.clone())
         .route("/metrics", get(|| async move { metrics_handle.render() }))
-        .route("/health", get(health_check))
-        .layer(TraceLayer::new_for_http())
-        .layer(prometheus_layer)
-        .with_state(db_conn);
+        .layer(prometheus_layer);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
         .await
         .expect("Could not initialize server");
     tracing::debug!(
@@ -58,8 +50,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .local_addr()
             .expect("Could not convert listener address to local address")
     );
-    axum::serve(listener, app)
-        .await
-        .expect("Could not start server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown({
+        let state = state.clone();
+        async move { state.shutdown_signal().await }
+    })
+    .await
+    .expect("Could not start server");
+    state.flush().await;
+    tracing::info!("Shut down");
     Ok(())
 }
+
+/// Logs why the service cannot start and exits.
+fn fail(reason: impl std::fmt::Display) -> ! {
+    tracing::error!("{}", reason);
+    std::process::exit(1);
+}
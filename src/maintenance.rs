@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{config::SharedConfig, utils::internal_error};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceState {
+    pub enabled: bool,
+    pub retry_after_secs: Option<u64>,
+    pub cache_only: Option<bool>,
+}
+
+/// `POST` endpoints that only read, with a body for their input.
+const READ_ONLY_POSTS: [&str; 2] = ["/api/resolve", "/signed-links"];
+
+/// `GET /api/shorten` creates links despite its method.
+fn is_write(req: &Request) -> bool {
+    match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => req.uri().path() == "/api/shorten",
+        Method::POST => !READ_ONLY_POSTS.contains(&req.uri().path()),
+        _ => true,
+    }
+}
+
+/// The 503 of maintenance mode, asking clients to come back after `retry_after_secs`.
+fn unavailable_response(retry_after_secs: u64) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after_secs.to_string())],
+        "Service Under Maintenance",
+    )
+        .into_response()
+}
+
+/// Rejects writes with 503 while maintenance mode is on. Admin endpoints stay reachable so the
+/// mode can be switched off again. Other 503s meanwhile, like redirects missing the cache when
+/// serving from it only, get the same `Retry-After`.
+pub async fn maintenance_guard(
+    State(config): State<SharedConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let maintenance = config.current().maintenance.clone();
+    if maintenance.enabled && is_write(&req) && !req.uri().path().starts_with("/admin/") {
+        tracing::debug!(
+            "Rejected {} {} during maintenance",
+            req.method(),
+            req.uri().path()
+        );
+        return unavailable_response(maintenance.retry_after_secs);
+    }
+    let mut response = next.run(req).await;
+    if maintenance.enabled && response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        response
+            .headers_mut()
+            .entry(header::RETRY_AFTER)
+            .or_insert_with(|| maintenance.retry_after_secs.into());
+    }
+    response
+}
+
+/// Rejects writes with 405 on read-only instances. Reloading the settings only reads them.
+pub async fn read_only_guard(
+    State(config): State<SharedConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if config.current().read_only && is_write(&req) && req.uri().path() != "/admin/reload" {
+        tracing::debug!(
+            "Rejected {} {} on a read-only instance",
+            req.method(),
+            req.uri().path()
+        );
+        return (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, "GET, HEAD")],
+            "Read-Only Instance",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+pub async fn get_maintenance(State(config): State<SharedConfig>) -> Json<MaintenanceState> {
+    let maintenance = config.current().maintenance.clone();
+    Json(MaintenanceState {
+        enabled: maintenance.enabled,
+        retry_after_secs: Some(maintenance.retry_after_secs),
+        cache_only: Some(maintenance.cache_only),
+    })
+}
+
+/// Persists the toggle as runtime settings so it survives restarts, then reloads.
+pub async fn set_maintenance(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Json(state): Json<MaintenanceState>,
+) -> Result<Json<MaintenanceState>, (StatusCode, String)> {
+    let mut keys = vec!["MAINTENANCE_MODE".to_string()];
+    let mut values = vec![state.enabled.to_string()];
+    if let Some(retry_after_secs) = state.retry_after_secs {
+        keys.push("MAINTENANCE_RETRY_AFTER_SECS".into());
+        values.push(retry_after_secs.to_string());
+    }
+    if let Some(cache_only) = state.cache_only {
+        keys.push("MAINTENANCE_CACHE_ONLY".into());
+        values.push(cache_only.to_string());
+    }
+    let update_settings_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(
+        update_settings_timeout,
+        sqlx::query!(
+            r#"
+                INSERT INTO runtime_settings (key, value)
+                SELECT * FROM UNNEST($1::text[], $2::text[])
+                ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = now()
+            "#,
+            &keys,
+            &values
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    config.reload(&pool).await.map_err(internal_error)?;
+
+    tracing::warn!(
+        "Maintenance mode {}",
+        if state.enabled { "enabled" } else { "disabled" }
+    );
+    Ok(get_maintenance(State(config)).await)
+}
@@ -0,0 +1,292 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::{Actor, Workspace},
+    config::SharedConfig,
+    link_cache::{self, LinkCache},
+    outbox, response_headers,
+    route::{
+        apply_definition, refusable_input, validate_update, Applied, Link, LinkDefinition,
+        LinkUpdate,
+    },
+    slug::check_custom_slug,
+    utils::internal_error,
+    utm,
+    validation::{deserialize_error, ApiError, FieldError},
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+/// The links of a workspace, keyed by slug, as a file kept under version control.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// Required, so a file missing it by mistake doesn't delete every link. `links: {}` does.
+    links: BTreeMap<String, LinkDefinition>,
+}
+
+/// A manifest as exported, with the links as stored.
+#[derive(Serialize)]
+struct ExportedManifest {
+    links: BTreeMap<String, ManifestLink>,
+}
+
+/// A link as written to a manifest: fields left at their defaults are left out.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestLink {
+    #[serde(skip)]
+    id: String,
+    target_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "is_true")]
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "is_default_redirect_type")]
+    redirect_type: i32,
+    #[serde(skip_serializing_if = "is_false")]
+    privacy_mode: bool,
+    #[serde(skip_serializing_if = "is_true")]
+    track_clicks: bool,
+    #[serde(skip_serializing_if = "is_default_sample_rate")]
+    sample_rate: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limit: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    allowed_referers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    referer_fallback_url: Option<String>,
+    #[serde(
+        serialize_with = "response_headers::serialize",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    response_headers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_tier: Option<String>,
+    #[serde(
+        serialize_with = "utm::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    utm_template: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    click_milestones: Vec<i64>,
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_default_redirect_type(value: &i32) -> bool {
+    *value == 307
+}
+
+fn is_default_sample_rate(value: &i32) -> bool {
+    *value == 1
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyParams {
+    /// Report what applying the manifest would change without changing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Slugs by what applying a manifest did to them.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestReport {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// The workspace's links as a YAML manifest, which `PUT /api/manifest` applies.
+pub async fn export_manifest(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let links = tokio::time::timeout(
+        tokio::time::Duration::from_secs(10),
+        sqlx::query_as!(
+            ManifestLink,
+            r#"
+                SELECT id, target_url, title, notes, tags, active, expires_at, redirect_type,
+                    privacy_mode, track_clicks, sample_rate, rate_limit, allowed_referers,
+                    referer_fallback_url, response_headers, cache_tier, utm_template,
+                    click_milestones
+                FROM links
+                WHERE workspace_id = $1
+                ORDER BY id
+            "#,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    let links = links
+        .into_iter()
+        .map(|link| (link.id.clone(), link))
+        .collect();
+    let manifest = serde_yaml::to_string(&ExportedManifest { links }).map_err(internal_error)?;
+    let manifest =
+        format!("# Links of workspace {workspace}, applied with `PUT /api/manifest`.\n{manifest}");
+    Ok((
+        [(header::CONTENT_TYPE, "application/yaml; charset=utf-8")],
+        manifest,
+    ))
+}
+
+/// Makes the workspace's links match a YAML manifest in one transaction: listed links are
+/// created or replaced as in `PUT /api/links/:id`, links left out are deleted. Applying the same
+/// manifest again changes nothing. Every problem of the manifest is reported at once, with 422.
+pub async fn apply_manifest(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(cache): State<Arc<LinkCache>>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Query(params): Query<ApplyParams>,
+    body: String,
+) -> Result<Json<ManifestReport>, ApiError> {
+    let config = config.current();
+    let document = serde_yaml::from_str::<serde_json::Value>(&body).map_err(|err| {
+        ApiError::Status(StatusCode::BAD_REQUEST, format!("Malformed YAML: {err}"))
+    })?;
+    let manifest: Manifest =
+        serde_path_to_error::deserialize(document).map_err(deserialize_error)?;
+
+    let listed: Vec<String> = manifest.links.keys().cloned().collect();
+    let mut definitions = Vec::new();
+    let mut problems = Vec::new();
+    for (id, definition) in manifest.links {
+        if let Err((_, reason)) = check_custom_slug(&id, &workspace, &config) {
+            problems.push(FieldError::new(format!("links.{id}"), reason));
+            continue;
+        }
+        let mut update = LinkUpdate::from(definition);
+        let submitted = refusable_input(&update);
+        match validate_update(&mut update, &config) {
+            Ok(target_url) => definitions.push((id, target_url.unwrap_or_default(), update)),
+            Err(err) => {
+                let err = audit::record_refused_targets(&pool, &actor, Some(&id), &submitted, err);
+                match err.await {
+                    ApiError::Fields(_, errors) => {
+                        problems.extend(errors.into_iter().map(|mut error| {
+                            error.field = format!("links.{id}.{}", error.field);
+                            error
+                        }))
+                    }
+                    ApiError::Status(_, reason) => {
+                        problems.push(FieldError::new(format!("links.{id}"), reason))
+                    }
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(ApiError::Fields(StatusCode::UNPROCESSABLE_ENTITY, problems));
+    }
+
+    let apply_timeout = tokio::time::Duration::from_secs(30);
+    let report = tokio::time::timeout(apply_timeout, async {
+        let mut tx = pool.begin().await?;
+        let mut report = ManifestReport {
+            dry_run: params.dry_run,
+            ..Default::default()
+        };
+        for (id, target_url, update) in definitions {
+            let applied =
+                apply_definition(&mut tx, &id, &workspace, &actor, target_url, update).await?;
+            match applied {
+                None => return Ok(Err(id)),
+                Some((Applied::Created, _)) => report.created.push(id),
+                Some((Applied::Updated, _)) => report.updated.push(id),
+                Some((Applied::Unchanged, _)) => report.unchanged.push(id),
+            }
+        }
+        let deleted = sqlx::query!(
+            r#"
+                DELETE FROM links
+                WHERE workspace_id = $1 AND NOT id = ANY($2)
+                RETURNING id, target_url
+            "#,
+            &workspace,
+            &listed
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let events: Vec<LinkEvent> = deleted
+            .into_iter()
+            .map(|link| {
+                LinkEvent::new(
+                    LinkEventKind::Deleted,
+                    workspace.clone(),
+                    Link {
+                        id: link.id,
+                        target_url: link.target_url,
+                    },
+                )
+            })
+            .collect();
+        outbox::enqueue_all(&mut tx, &events).await?;
+        report.deleted = events.iter().map(|event| event.link.id.clone()).collect();
+        link_cache::publish(&mut *tx, &report.deleted).await?;
+        audit::record(
+            &mut tx,
+            &actor,
+            "links.manifest",
+            Some(&workspace),
+            json!(report),
+        )
+        .await?;
+        if params.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok::<_, sqlx::Error>(Ok(report))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .map_err(|id| ApiError::Status(StatusCode::CONFLICT, format!("Slug {id} Taken")))?;
+    if !report.dry_run {
+        cache.invalidate(&report.updated);
+        cache.invalidate(&report.deleted);
+        tracing::info!(
+            "Manifest of workspace {} applied by {}: {} created, {} updated, {} deleted",
+            workspace,
+            actor,
+            report.created.len(),
+            report.updated.len(),
+            report.deleted.len()
+        );
+    }
+    Ok(Json(report))
+}
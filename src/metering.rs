@@ -0,0 +1,190 @@
+use std::{collections::HashMap, mem, sync::Mutex, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+
+use crate::utils::internal_error;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct UsageKey {
+    day: NaiveDate,
+    key_id: String,
+    actor: String,
+    endpoint: String,
+}
+
+/// Counts authenticated requests in memory and adds them to `api_usage` periodically, so
+/// metering costs no database write per request.
+#[derive(Debug, Default)]
+pub struct UsageMeter {
+    counts: Mutex<HashMap<UsageKey, i64>>,
+}
+
+/// Identifies an API key in usage reports without revealing it.
+pub fn key_id(api_key: &str) -> String {
+    let digest = Sha3_256::digest(api_key.as_bytes());
+    format!("{digest:x}")[..12].to_string()
+}
+
+impl UsageMeter {
+    pub fn record(&self, api_key: &str, actor: &str, endpoint: String) {
+        let key = UsageKey {
+            day: Utc::now().date_naive(),
+            key_id: key_id(api_key),
+            actor: actor.to_string(),
+            endpoint,
+        };
+        *self
+            .counts
+            .lock()
+            .expect("Usage meter lock poisoned")
+            .entry(key)
+            .or_default() += 1;
+    }
+
+    pub async fn flush(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let counts = mem::take(&mut *self.counts.lock().expect("Usage meter lock poisoned"));
+        if counts.is_empty() {
+            return Ok(());
+        }
+        let mut days = Vec::with_capacity(counts.len());
+        let mut key_ids = Vec::with_capacity(counts.len());
+        let mut actors = Vec::with_capacity(counts.len());
+        let mut endpoints = Vec::with_capacity(counts.len());
+        let mut requests = Vec::with_capacity(counts.len());
+        for (key, count) in &counts {
+            days.push(key.day);
+            key_ids.push(key.key_id.clone());
+            actors.push(key.actor.clone());
+            endpoints.push(key.endpoint.clone());
+            requests.push(*count);
+        }
+        let flushed = sqlx::query!(
+            r#"
+                INSERT INTO api_usage (day, key_id, actor, endpoint, requests)
+                SELECT * FROM UNNEST($1::DATE[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::BIGINT[])
+                ON CONFLICT (day, key_id, actor, endpoint)
+                DO UPDATE SET requests = api_usage.requests + EXCLUDED.requests
+            "#,
+            &days,
+            &key_ids,
+            &actors,
+            &endpoints,
+            &requests
+        )
+        .execute(pool)
+        .await;
+        if let Err(err) = flushed {
+            // Put the counts back so they are written with the next flush.
+            let mut current = self.counts.lock().expect("Usage meter lock poisoned");
+            for (key, count) in counts {
+                *current.entry(key).or_default() += count;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+pub fn spawn(pool: PgPool, meter: std::sync::Arc<UsageMeter>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            if let Err(err) = meter.flush(&pool).await {
+                tracing::error!("Writing API usage failed: {}", err);
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct UsageParams {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// `csv` for a CSV download, JSON otherwise.
+    pub format: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRow {
+    pub day: NaiveDate,
+    pub key_id: String,
+    pub actor: String,
+    pub endpoint: String,
+    pub requests: i64,
+}
+
+/// API usage per day, key, actor and endpoint between `from` and `to` (inclusive, the last 30
+/// days by default). Counts lag behind by up to the flush interval.
+pub async fn get_usage(
+    State(pool): State<PgPool>,
+    Query(params): Query<UsageParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let to = params.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = params
+        .from
+        .unwrap_or_else(|| to - chrono::Duration::days(30));
+    let fetch_usage_timeout = tokio::time::Duration::from_millis(1000);
+    let rows = tokio::time::timeout(
+        fetch_usage_timeout,
+        sqlx::query_as!(
+            UsageRow,
+            r#"
+                SELECT day, key_id, actor, endpoint, requests
+                FROM api_usage
+                WHERE day BETWEEN $1 AND $2
+                ORDER BY day, key_id, actor, endpoint
+            "#,
+            from,
+            to
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    if params.format.as_deref() != Some("csv") {
+        return Ok(Json(rows).into_response());
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["day", "key_id", "actor", "endpoint", "requests"])
+        .map_err(internal_error)?;
+    for row in &rows {
+        writer
+            .write_record([
+                row.day.to_string(),
+                row.key_id.clone(),
+                row.actor.clone(),
+                row.endpoint.clone(),
+                row.requests.to_string(),
+            ])
+            .map_err(internal_error)?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(|err| internal_error(err.into_error()))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"usage.csv\"",
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
@@ -0,0 +1,108 @@
+use sqlx::PgPool;
+
+use crate::{
+    config::{Config, SharedConfig},
+    notify::{self, NotificationKind},
+    outbox,
+    route::Link,
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+/// Finds one batch of milestones links have reached but not announced yet, marks them as sent
+/// and announces them through webhooks and notifications. Returns how many were announced.
+async fn announce_batch(pool: &PgPool, config: &Config) -> Result<usize, sqlx::Error> {
+    let milestones = &config.milestones;
+    let smallest_default = milestones
+        .defaults
+        .iter()
+        .min()
+        .copied()
+        .unwrap_or(i64::MAX);
+    let mut tx = pool.begin().await?;
+    let reached = sqlx::query!(
+        r#"
+            SELECT l.id, l.target_url, l.workspace_id, l.click_count, m.milestone AS "milestone!"
+            FROM links l
+            CROSS JOIN LATERAL unnest(
+                CASE WHEN cardinality(l.click_milestones) > 0 THEN l.click_milestones ELSE $1 END
+            ) AS m (milestone)
+            WHERE (l.click_count >= $2 OR cardinality(l.click_milestones) > 0)
+                AND l.click_count >= m.milestone
+                AND NOT m.milestone = ANY(l.sent_milestones)
+            ORDER BY l.id
+            LIMIT $3
+            FOR UPDATE OF l SKIP LOCKED
+        "#,
+        &milestones.defaults,
+        smallest_default,
+        milestones.batch_size
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if reached.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<&str> = reached.iter().map(|row| row.id.as_str()).collect();
+    let values: Vec<i64> = reached.iter().map(|row| row.milestone).collect();
+    sqlx::query!(
+        r#"
+            UPDATE links l
+            SET sent_milestones = l.sent_milestones || r.milestones
+            FROM (
+                SELECT id, array_agg(milestone) AS milestones
+                FROM UNNEST($1::TEXT[], $2::BIGINT[]) AS r (id, milestone)
+                GROUP BY id
+            ) r
+            WHERE l.id = r.id
+        "#,
+        &ids as &[&str],
+        &values
+    )
+    .execute(&mut *tx)
+    .await?;
+    let events: Vec<LinkEvent> = reached
+        .iter()
+        .map(|row| {
+            LinkEvent::new(
+                LinkEventKind::Milestone,
+                row.workspace_id.clone(),
+                Link {
+                    id: row.id.clone(),
+                    target_url: row.target_url.clone(),
+                },
+            )
+            .with_clicks(row.milestone)
+        })
+        .collect();
+    outbox::enqueue_all(&mut tx, &events).await?;
+    tx.commit().await?;
+
+    for row in &reached {
+        notify::send(
+            &config.notifications,
+            &config.outbound,
+            NotificationKind::ClickMilestone,
+            format!(
+                "Link `{}` reached {} clicks: {}",
+                row.id, row.milestone, row.target_url
+            ),
+        );
+    }
+    Ok(reached.len())
+}
+
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            match announce_batch(&pool, &current).await {
+                // Keep going without pausing while there is a backlog.
+                Ok(count) if count > 0 => continue,
+                Ok(_) => {}
+                Err(err) => tracing::error!("Announcing click milestones failed: {}", err),
+            }
+            tokio::time::sleep(current.milestones.interval).await;
+        }
+    });
+}
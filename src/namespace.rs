@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::{Actor, Workspace},
+    config::SharedConfig,
+    slug::check_custom_slug,
+    utils::{internal_error, is_valid_slug},
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Namespace {
+    pub prefix: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NamespaceRequest {
+    pub prefix: String,
+}
+
+/// The namespaces the workspace reserved.
+pub async fn list_namespaces(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<Json<Vec<Namespace>>, (StatusCode, String)> {
+    let list_namespaces_timeout = tokio::time::Duration::from_millis(300);
+    let namespaces = tokio::time::timeout(
+        list_namespaces_timeout,
+        sqlx::query_as!(
+            Namespace,
+            r#"
+                SELECT prefix, created_by, created_at
+                FROM slug_namespaces
+                WHERE workspace_id = $1
+                ORDER BY prefix
+            "#,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(namespaces))
+}
+
+/// Reserves `prefix` for the workspace: from then on only its members create links like
+/// `prefix/launch`. The prefix is a single path segment, checked like a custom slug. Takes effect
+/// on this instance right away, on the others with their next reload.
+pub async fn reserve_namespace(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Json(request): Json<NamespaceRequest>,
+) -> Result<(StatusCode, Json<Namespace>), (StatusCode, String)> {
+    let prefix = request.prefix.trim().to_string();
+    if !is_valid_slug(&prefix) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid Namespace".into()));
+    }
+    check_custom_slug(&prefix, &workspace, &config.current())?;
+    let reserve_timeout = tokio::time::Duration::from_millis(300);
+    let namespace = tokio::time::timeout(reserve_timeout, async {
+        let mut tx = pool.begin().await?;
+        let namespace = sqlx::query_as!(
+            Namespace,
+            r#"
+                INSERT INTO slug_namespaces (prefix, workspace_id, created_by)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (prefix) DO NOTHING
+                RETURNING prefix, created_by, created_at
+            "#,
+            &prefix,
+            &workspace,
+            &actor
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if namespace.is_some() {
+            audit::record(
+                &mut tx,
+                &actor,
+                "namespace.reserve",
+                Some(&prefix),
+                json!({ "workspace": workspace }),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(namespace)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or((StatusCode::CONFLICT, "Namespace Taken".to_string()))?;
+    config
+        .reload_slug_namespaces(&pool)
+        .await
+        .map_err(internal_error)?;
+    tracing::info!(
+        "{} reserved namespace {} for workspace {}",
+        actor,
+        prefix,
+        workspace
+    );
+    Ok((StatusCode::CREATED, Json(namespace)))
+}
+
+/// Gives a namespace of the workspace back, once no link, archived or not, is left in it.
+pub async fn release_namespace(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(prefix): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let release_timeout = tokio::time::Duration::from_millis(300);
+    let released = tokio::time::timeout(release_timeout, async {
+        let mut tx = pool.begin().await?;
+        let deleted = sqlx::query!(
+            "DELETE FROM slug_namespaces WHERE prefix = $1 AND workspace_id = $2",
+            &prefix,
+            &workspace
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            return Ok(Err((StatusCode::NOT_FOUND, "Not Found".to_string())));
+        }
+        let in_use = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (SELECT 1 FROM links WHERE starts_with(id, $1 || '/'))
+                    OR EXISTS (SELECT 1 FROM archived_links WHERE starts_with(id, $1 || '/'))
+                    AS "in_use!"
+            "#,
+            &prefix
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if in_use {
+            return Ok(Err((StatusCode::CONFLICT, "Namespace In Use".to_string())));
+        }
+        audit::record(
+            &mut tx,
+            &actor,
+            "namespace.release",
+            Some(&prefix),
+            json!({ "workspace": workspace }),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(()))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    released?;
+    config
+        .reload_slug_namespaces(&pool)
+        .await
+        .map_err(internal_error)?;
+    tracing::info!("{} released namespace {}", actor, prefix);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,129 @@
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    config::{NotificationConfig, OutboundConfig},
+    outbound,
+};
+
+/// Discord rejects longer messages.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+const NOTIFICATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotificationKind {
+    LinkCreated,
+    ClickMilestone,
+    HealthCheckFailed,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 3] = [
+        NotificationKind::LinkCreated,
+        NotificationKind::ClickMilestone,
+        NotificationKind::HealthCheckFailed,
+    ];
+
+    /// Name used to toggle the notification in `NOTIFY_EVENTS`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::LinkCreated => "link.created",
+            NotificationKind::ClickMilestone => "link.milestone",
+            NotificationKind::HealthCheckFailed => "link.unhealthy",
+        }
+    }
+}
+
+/// Channels a notification is posted to, as named in [`payloads`].
+const SLACK: &str = "slack";
+const DISCORD: &str = "discord";
+
+fn channel_url<'a>(config: &'a NotificationConfig, channel: &str) -> Option<&'a str> {
+    match channel {
+        SLACK => config.slack_webhook_url.as_deref(),
+        DISCORD => config.discord_webhook_url.as_deref(),
+        _ => None,
+    }
+}
+
+/// The body to post to every configured channel, by channel, or none when notifications of this
+/// kind are disabled.
+pub fn payloads(
+    config: &NotificationConfig,
+    kind: NotificationKind,
+    mut message: String,
+) -> Vec<(&'static str, serde_json::Value)> {
+    if !config.events.iter().any(|event| event == kind.as_str()) {
+        return Vec::new();
+    }
+    if message.len() > MAX_MESSAGE_LENGTH {
+        let mut end = MAX_MESSAGE_LENGTH - 3;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+        message.push_str("...");
+    }
+    [
+        (SLACK, json!({ "text": message })),
+        (DISCORD, json!({ "content": message })),
+    ]
+    .into_iter()
+    .filter(|(channel, _)| channel_url(config, channel).is_some())
+    .collect()
+}
+
+/// Posts `message` to the configured Slack and Discord webhooks in the background, if
+/// notifications of this kind are enabled. Failures are only logged; notifications are best
+/// effort.
+pub fn send(
+    config: &NotificationConfig,
+    outbound: &OutboundConfig,
+    kind: NotificationKind,
+    message: String,
+) {
+    for (channel, payload) in payloads(config, kind, message) {
+        let config = config.clone();
+        let outbound = outbound.clone();
+        tokio::spawn(async move {
+            if let Err(err) = post_to(&config, &outbound, channel, &payload).await {
+                tracing::warn!("Sending {} notification failed: {}", kind.as_str(), err);
+            }
+        });
+    }
+}
+
+/// Posts a payload from [`payloads`] to its channel. Channels unset since are skipped.
+pub async fn post_to(
+    config: &NotificationConfig,
+    outbound: &OutboundConfig,
+    channel: &str,
+    payload: &serde_json::Value,
+) -> Result<(), String> {
+    match channel_url(config, channel) {
+        Some(url) => post(url, payload, outbound).await,
+        None => Ok(()),
+    }
+}
+
+async fn post(
+    url: &str,
+    payload: &serde_json::Value,
+    outbound: &OutboundConfig,
+) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|err| err.to_string())?;
+    let client = outbound::client(&url, outbound, NOTIFICATION_TIMEOUT)
+        .await
+        .map_err(|err| err.to_string())?;
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(payload.to_string())
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use reqwest::{header::LOCATION, redirect::Policy, Client, ClientBuilder, Proxy, RequestBuilder};
+use url::Url;
+
+use crate::{
+    config::OutboundConfig,
+    ssrf::{self, SsrfError},
+};
+
+fn builder(config: &OutboundConfig, timeout: Duration) -> ClientBuilder {
+    Client::builder()
+        .timeout(timeout)
+        .connect_timeout(config.connect_timeout.min(timeout))
+        .user_agent(&config.user_agent)
+}
+
+fn proxy(config: &OutboundConfig) -> Option<Proxy> {
+    config.proxy.as_deref().map(|proxy| {
+        Proxy::all(proxy).expect("OUTBOUND_PROXY is checked when the configuration is loaded")
+    })
+}
+
+/// Builds a client for a request to `url`, an address someone else chose. The host must only
+/// resolve to public addresses, and without `OUTBOUND_PROXY` the client can only connect to
+/// those, so a DNS answer changing between the check and the connection cannot redirect the
+/// request. Through a proxy the proxy resolves the host again, and has to enforce its own egress
+/// rules. Redirects are left to [`follow`], their destinations have not been vetted.
+pub async fn client(
+    url: &Url,
+    config: &OutboundConfig,
+    timeout: Duration,
+) -> Result<Client, SsrfError> {
+    let addrs = ssrf::resolve_public(url).await?;
+    let host = url.host_str().ok_or(SsrfError::MissingHost)?;
+    let builder = builder(config, timeout).redirect(Policy::none());
+    match proxy(config) {
+        Some(proxy) => builder.proxy(proxy),
+        // Proxies from the environment would resolve the host themselves.
+        None => builder.no_proxy().resolve_to_addrs(host, &addrs),
+    }
+    .build()
+    .map_err(SsrfError::Client)
+}
+
+/// A client for the fixed third-party APIs the service calls, like CDN purges. They go through
+/// `OUTBOUND_PROXY` like every other request but are not vetted.
+pub fn api_client(config: &OutboundConfig, timeout: Duration) -> Client {
+    let builder = builder(config, timeout);
+    match proxy(config) {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder.no_proxy(),
+    }
+    .build()
+    .expect("A client without custom TLS settings always builds")
+}
+
+/// Sends the request `request` builds for `url`, following up to `OUTBOUND_MAX_REDIRECTS`
+/// redirects with a client vetted for each hop. Returns the first response that is not a
+/// redirect, or a redirect without a `Location`.
+pub async fn follow(
+    mut url: Url,
+    config: &OutboundConfig,
+    timeout: Duration,
+    request: impl Fn(&Client, Url) -> RequestBuilder,
+) -> Result<reqwest::Response, String> {
+    for _ in 0..=config.max_redirects {
+        let client = client(&url, config, timeout)
+            .await
+            .map_err(|err| err.to_string())?;
+        let response = request(&client, url.clone())
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok());
+        let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+            return Ok(response);
+        };
+        url = url.join(location).map_err(|err| err.to_string())?;
+    }
+    Err("Too many redirects".into())
+}
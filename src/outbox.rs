@@ -0,0 +1,413 @@
+use std::time::{Duration, Instant};
+
+use sqlx::{PgConnection, PgPool};
+use tokio::task::JoinSet;
+
+use crate::{
+    cdn::{self, SharedCdnPurger},
+    config::{Config, SharedConfig},
+    notify::{self, NotificationKind},
+    webhook::{self, LinkEvent, LinkEventKind},
+};
+
+/// How long dispatched events and finished deliveries are kept around for inspection.
+const RETENTION: &str = "7 days";
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long a delivery being attempted is left to its instance, longer than any attempt takes.
+const DELIVERY_LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// Queues `event` as part of the caller's transaction, so it is only published if the change it
+/// describes is committed.
+pub async fn enqueue(conn: &mut PgConnection, event: &LinkEvent) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO outbox (event_id, event_type, workspace_id, payload)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        &event.id,
+        event.kind.as_str(),
+        &event.workspace_id,
+        serde_json::to_value(event).expect("Link events always serialize")
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Queues many events at once, see [`enqueue`].
+pub async fn enqueue_all(conn: &mut PgConnection, events: &[LinkEvent]) -> Result<(), sqlx::Error> {
+    let ids: Vec<&str> = events.iter().map(|event| event.id.as_str()).collect();
+    let types: Vec<&str> = events.iter().map(|event| event.kind.as_str()).collect();
+    let workspaces: Vec<&str> = events
+        .iter()
+        .map(|event| event.workspace_id.as_str())
+        .collect();
+    let payloads: Vec<serde_json::Value> = events
+        .iter()
+        .map(|event| serde_json::to_value(event).expect("Link events always serialize"))
+        .collect();
+    sqlx::query!(
+        r#"
+            INSERT INTO outbox (event_id, event_type, workspace_id, payload)
+            SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::JSONB[])
+        "#,
+        &ids as &[&str],
+        &types as &[&str],
+        &workspaces as &[&str],
+        &payloads
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// What an outbox delivery does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DeliveryKind {
+    /// Posts an event to the webhook endpoint in `target`.
+    Webhook,
+    /// Posts a message to the notification channel in `target`.
+    Notification,
+    /// Purges the links listed in the payload from the CDN.
+    CdnPurge,
+}
+
+impl DeliveryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryKind::Webhook => "webhook",
+            DeliveryKind::Notification => "notification",
+            DeliveryKind::CdnPurge => "cdn_purge",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        [
+            DeliveryKind::Webhook,
+            DeliveryKind::Notification,
+            DeliveryKind::CdnPurge,
+        ]
+        .into_iter()
+        .find(|known| known.as_str() == kind)
+    }
+}
+
+async fn enqueue_delivery(
+    conn: &mut PgConnection,
+    kind: DeliveryKind,
+    target: Option<&str>,
+    payload: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO outbox_deliveries (kind, target, payload) VALUES ($1, $2, $3)",
+        kind.as_str(),
+        target,
+        payload
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Turns pending events into deliveries: one per subscribed webhook endpoint, a notification of
+/// the links created and a CDN purge of the links changed. Rows stay locked until they are marked
+/// as dispatched, so several instances can drain the outbox side by side.
+async fn dispatch_batch(
+    pool: &PgPool,
+    config: &SharedConfig,
+    cdn_purger: Option<&SharedCdnPurger>,
+) -> Result<usize, sqlx::Error> {
+    let config = config.current();
+    let mut tx = pool.begin().await?;
+    let events = sqlx::query!(
+        r#"
+            SELECT id, event_id, event_type, workspace_id, payload
+            FROM outbox
+            WHERE dispatched_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        "#,
+        config.outbox.batch_size
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if events.is_empty() {
+        return Ok(0);
+    }
+
+    let mut dispatched = Vec::with_capacity(events.len());
+    let mut created = Vec::new();
+    let mut changed = Vec::new();
+    for event in events {
+        if event.event_type == LinkEventKind::Created.as_str() {
+            created.push(event.payload["link"].clone());
+        }
+        if [
+            LinkEventKind::Updated,
+            LinkEventKind::Deleted,
+            LinkEventKind::Expired,
+        ]
+        .iter()
+        .any(|kind| kind.as_str() == event.event_type)
+        {
+            if let Some(id) = event.payload["link"]["id"].as_str() {
+                changed.push(id.to_string());
+            }
+        }
+        webhook::enqueue_deliveries(
+            &mut tx,
+            &event.workspace_id,
+            &event.event_id,
+            &event.event_type,
+            &event.payload.to_string(),
+        )
+        .await?;
+        dispatched.push(event.id);
+    }
+    if !created.is_empty() {
+        let message = created_message(&created);
+        let payloads = notify::payloads(
+            &config.notifications,
+            NotificationKind::LinkCreated,
+            message,
+        );
+        for (channel, payload) in payloads {
+            let payload = payload.to_string();
+            enqueue_delivery(&mut tx, DeliveryKind::Notification, Some(channel), &payload).await?;
+        }
+    }
+    if cdn_purger.is_some() && !changed.is_empty() {
+        changed.sort();
+        changed.dedup();
+        let payload = serde_json::to_string(&changed).expect("Link ids always serialize");
+        enqueue_delivery(&mut tx, DeliveryKind::CdnPurge, None, &payload).await?;
+    }
+    sqlx::query!(
+        "UPDATE outbox SET dispatched_at = now() WHERE id = ANY($1)",
+        &dispatched
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(dispatched.len())
+}
+
+/// A delivery due, as leased by [`deliver_due`].
+struct Delivery {
+    id: i64,
+    kind: String,
+    target: Option<String>,
+    event_id: Option<String>,
+    event_type: Option<String>,
+    payload: String,
+    /// Counting the one about to be made.
+    attempts: i32,
+}
+
+/// Carries out one delivery attempt.
+async fn attempt(
+    pool: &PgPool,
+    config: &Config,
+    cdn_purger: Option<&SharedCdnPurger>,
+    kind: DeliveryKind,
+    delivery: &Delivery,
+) -> Result<(), String> {
+    let target = delivery.target.as_deref().unwrap_or_default();
+    match kind {
+        DeliveryKind::Webhook => {
+            webhook::attempt_delivery(
+                pool,
+                config,
+                target,
+                delivery.event_id.as_deref().unwrap_or_default(),
+                delivery.event_type.as_deref().unwrap_or_default(),
+                &delivery.payload,
+                delivery.attempts,
+            )
+            .await
+        }
+        DeliveryKind::Notification => {
+            let payload = serde_json::from_str(&delivery.payload).map_err(|err| err.to_string())?;
+            notify::post_to(&config.notifications, &config.outbound, target, &payload).await
+        }
+        DeliveryKind::CdnPurge => {
+            let Some(cdn_purger) = cdn_purger else {
+                return Ok(());
+            };
+            let link_ids: Vec<String> =
+                serde_json::from_str(&delivery.payload).map_err(|err| err.to_string())?;
+            cdn::purge(cdn_purger.as_ref(), &cdn::purge_request(config, &link_ids)).await
+        }
+    }
+}
+
+/// Attempts the deliveries that are due, side by side. Each is leased for [`DELIVERY_LEASE`]
+/// first, so an instance stopping halfway leaves it to be picked up again. Deliveries are done
+/// once they succeed or their `WEBHOOK_MAX_ATTEMPTS` are used up, until then retried with
+/// doubling delays.
+async fn deliver_due(
+    pool: &PgPool,
+    config: &SharedConfig,
+    cdn_purger: Option<&SharedCdnPurger>,
+) -> Result<usize, sqlx::Error> {
+    let config = config.current();
+    let due = sqlx::query_as!(
+        Delivery,
+        r#"
+            UPDATE outbox_deliveries
+            SET attempts = attempts + 1,
+                next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM outbox_deliveries
+                WHERE finished_at IS NULL AND next_attempt_at <= now()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, target, event_id, event_type, payload, attempts
+        "#,
+        config.outbox.batch_size,
+        DELIVERY_LEASE.as_secs_f64()
+    )
+    .fetch_all(pool)
+    .await?;
+    let count = due.len();
+    let mut deliveries = JoinSet::new();
+    for delivery in due {
+        let pool = pool.clone();
+        let config = config.clone();
+        let cdn_purger = cdn_purger.cloned();
+        deliveries.spawn(async move {
+            let Some(kind) = DeliveryKind::parse(&delivery.kind) else {
+                return Ok(());
+            };
+            let outcome = attempt(&pool, &config, cdn_purger.as_ref(), kind, &delivery).await;
+            let retries = &config.webhooks;
+            let used_up = delivery.attempts >= retries.max_attempts as i32;
+            if let Err(err) = &outcome {
+                if used_up {
+                    tracing::warn!(
+                        "Giving up {} delivery {} after {} attempts: {}",
+                        kind.as_str(),
+                        delivery.id,
+                        delivery.attempts,
+                        err
+                    );
+                } else {
+                    tracing::debug!(
+                        "Attempt {} of {} delivery {} failed: {}",
+                        delivery.attempts,
+                        kind.as_str(),
+                        delivery.id,
+                        err
+                    );
+                }
+            }
+            let retry_delay = retries.retry_base_delay
+                * 2u32.saturating_pow(delivery.attempts.saturating_sub(1) as u32);
+            sqlx::query!(
+                r#"
+                    UPDATE outbox_deliveries
+                    SET finished_at = CASE WHEN $2 THEN now() END,
+                        next_attempt_at = now() + make_interval(secs => $3)
+                    WHERE id = $1
+                "#,
+                delivery.id,
+                outcome.is_ok() || used_up,
+                retry_delay.as_secs_f64()
+            )
+            .execute(&pool)
+            .await
+            .map(|_| ())
+        });
+    }
+    while let Some(finished) = deliveries.join_next().await {
+        match finished {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("Recording an outbox delivery failed: {}", err),
+            Err(err) => tracing::error!("Outbox delivery panicked: {}", err),
+        }
+    }
+    Ok(count)
+}
+
+/// Announces the links created in one batch in a single message, so bulk imports do not flood
+/// the channel.
+fn created_message(links: &[serde_json::Value]) -> String {
+    const LISTED: usize = 10;
+    let describe = |link: &serde_json::Value| {
+        format!(
+            "`{}` → {}",
+            link["id"].as_str().unwrap_or_default(),
+            link["targetUrl"].as_str().unwrap_or_default()
+        )
+    };
+    match links {
+        [link] => format!("Link created: {}", describe(link)),
+        _ => {
+            let mut message = format!("{} links created:", links.len());
+            for link in links.iter().take(LISTED) {
+                message.push('\n');
+                message.push_str(&describe(link));
+            }
+            if links.len() > LISTED {
+                message.push_str(&format!("\n… and {} more", links.len() - LISTED));
+            }
+            message
+        }
+    }
+}
+
+/// Drains the outbox in the background, polling while it is empty, and carries out the
+/// deliveries of dispatched events: webhooks, notifications and CDN purges of changed links.
+pub fn spawn(pool: PgPool, config: SharedConfig, cdn_purger: Option<SharedCdnPurger>) {
+    {
+        let pool = pool.clone();
+        let config = config.clone();
+        let cdn_purger = cdn_purger.clone();
+        tokio::spawn(async move {
+            loop {
+                match deliver_due(&pool, &config, cdn_purger.as_ref()).await {
+                    Ok(count) if count > 0 => continue,
+                    Ok(_) => {}
+                    Err(err) => tracing::error!("Delivering outbox events failed: {}", err),
+                }
+                tokio::time::sleep(config.current().outbox.poll_interval).await;
+            }
+        });
+    }
+    tokio::spawn(async move {
+        let mut last_cleanup = Instant::now();
+        loop {
+            match dispatch_batch(&pool, &config, cdn_purger.as_ref()).await {
+                // Keep going without pausing while there is a backlog.
+                Ok(count) if count > 0 => continue,
+                Ok(_) => {}
+                Err(err) => tracing::error!("Dispatching outbox events failed: {}", err),
+            }
+            if last_cleanup.elapsed() >= CLEANUP_INTERVAL {
+                last_cleanup = Instant::now();
+                let cleanup = sqlx::query!(
+                    "DELETE FROM outbox WHERE dispatched_at < now() - $1::TEXT::INTERVAL",
+                    RETENTION
+                )
+                .execute(&pool)
+                .await;
+                if let Err(err) = cleanup {
+                    tracing::error!("Cleaning up dispatched outbox events failed: {}", err);
+                }
+                let cleanup = sqlx::query!(
+                    "DELETE FROM outbox_deliveries WHERE finished_at < now() - $1::TEXT::INTERVAL",
+                    RETENTION
+                )
+                .execute(&pool)
+                .await;
+                if let Err(err) = cleanup {
+                    tracing::error!("Cleaning up finished outbox deliveries failed: {}", err);
+                }
+            }
+            tokio::time::sleep(config.current().outbox.poll_interval).await;
+        }
+    });
+}
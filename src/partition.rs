@@ -0,0 +1,124 @@
+use chrono::{DateTime, Datelike, Months, TimeZone, Utc};
+use sqlx::{PgConnection, PgPool};
+
+use crate::config::{SharedConfig, StatisticsConfig};
+
+/// Taken while partitions are maintained, so instances don't create the same ones concurrently.
+const MAINTENANCE_LOCK: i64 = 0x6c69_6e6b_5f70_6172;
+
+fn month_start(instant: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(instant.year(), instant.month(), 1, 0, 0, 0)
+        .single()
+        .expect("The first of a month is a valid date")
+}
+
+/// Creates the monthly partitions of `link_statistics` following the newest one, up to
+/// `partitions_ahead` months past the current one.
+async fn create_partitions(
+    conn: &mut PgConnection,
+    statistics: &StatisticsConfig,
+) -> Result<(), sqlx::Error> {
+    let newest_bound = sqlx::query_scalar!(
+        r#"
+            SELECT max(substring(pg_get_expr(c.relpartbound, c.oid) FROM 'TO \(''([^'']+)''\)')::TIMESTAMPTZ)
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = 'link_statistics'::regclass
+        "#
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let current_month = month_start(Utc::now());
+    let until = current_month + Months::new(statistics.partitions_ahead + 1);
+    let mut start = newest_bound.unwrap_or(current_month);
+    while start < until {
+        let end = start + Months::new(1);
+        let name = format!("link_statistics_p{}", start.format("%Y%m"));
+        sqlx::query(&format!(
+            "CREATE TABLE {name} PARTITION OF link_statistics FOR VALUES FROM ('{}') TO ('{}')",
+            start.to_rfc3339(),
+            end.to_rfc3339()
+        ))
+        .execute(&mut *conn)
+        .await?;
+        tracing::info!("Created click partition {}", name);
+        start = end;
+    }
+    Ok(())
+}
+
+/// Drops the partitions of `link_statistics` whose clicks are all older than the retention, and
+/// the referers and user agents rolled up from clicks past it.
+async fn drop_expired_partitions(
+    conn: &mut PgConnection,
+    statistics: &StatisticsConfig,
+) -> Result<(), sqlx::Error> {
+    let Some(retention) = statistics.retention else {
+        return Ok(());
+    };
+    let expired = sqlx::query_scalar!(
+        r#"
+            SELECT c.relname AS "name!"
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = 'link_statistics'::regclass
+                AND substring(pg_get_expr(c.relpartbound, c.oid) FROM 'TO \(''([^'']+)''\)')::TIMESTAMPTZ
+                    <= now() - make_interval(secs => $1)
+        "#,
+        retention.as_secs_f64()
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for name in &expired {
+        sqlx::query(&format!("DROP TABLE \"{}\"", name.replace('"', "\"\"")))
+            .execute(&mut *conn)
+            .await?;
+        tracing::info!("Dropped click partition {} past the retention", name);
+    }
+    sqlx::query!(
+        "DELETE FROM link_daily_sources WHERE day < (now() - make_interval(secs => $1))::DATE",
+        retention.as_secs_f64()
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Makes sure clicks of the coming months have a partition to go to and drops expired ones.
+/// Does nothing while another instance is at it.
+pub async fn maintain_partitions(
+    pool: &PgPool,
+    statistics: &StatisticsConfig,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        MAINTENANCE_LOCK
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(());
+    }
+    create_partitions(&mut tx, statistics).await?;
+    drop_expired_partitions(&mut tx, statistics).await?;
+    tx.commit().await
+}
+
+/// The oldest clicks still kept, when a retention is configured.
+pub fn retained_since(statistics: &StatisticsConfig) -> Option<DateTime<Utc>> {
+    let retention = chrono::Duration::from_std(statistics.retention?).ok()?;
+    Utc::now().checked_sub_signed(retention)
+}
+
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let statistics = config.current().statistics.clone();
+            if let Err(err) = maintain_partitions(&pool, &statistics).await {
+                tracing::error!("Maintaining click partitions failed: {}", err);
+            }
+            tokio::time::sleep(statistics.maintenance_interval).await;
+        }
+    });
+}
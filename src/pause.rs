@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::Actor,
+    link_cache::{self, LinkCache},
+    outbox,
+    route::{fetch_link_details, Link, LinkDetails},
+    utils::internal_error,
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+/// `POST /:id/pause`: stops redirecting a link right away, keeping it and its statistics.
+/// Visitors are sent to `PAUSED_LINK_URL`, or answered 404 without one.
+pub async fn pause_link(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+    State(cache): State<Arc<LinkCache>>,
+    Path(id): Path<String>,
+) -> Result<Json<LinkDetails>, (StatusCode, String)> {
+    set_active(&pool, &cache, &actor, &id, false).await
+}
+
+/// `POST /:id/resume`: redirects a paused link again.
+pub async fn resume_link(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+    State(cache): State<Arc<LinkCache>>,
+    Path(id): Path<String>,
+) -> Result<Json<LinkDetails>, (StatusCode, String)> {
+    set_active(&pool, &cache, &actor, &id, true).await
+}
+
+/// Pausing a paused link or resuming an active one changes nothing and announces nothing.
+async fn set_active(
+    pool: &PgPool,
+    cache: &LinkCache,
+    actor: &str,
+    id: &str,
+    active: bool,
+) -> Result<Json<LinkDetails>, (StatusCode, String)> {
+    let set_active_timeout = tokio::time::Duration::from_millis(300);
+    let link = tokio::time::timeout(set_active_timeout, async {
+        let mut tx = pool.begin().await?;
+        let changed = sqlx::query!(
+            r#"
+                UPDATE links
+                SET active = $2, updated_at = now()
+                WHERE id = $1 AND active IS DISTINCT FROM $2
+                RETURNING workspace_id, target_url
+            "#,
+            id,
+            active
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(changed) = changed {
+            let action = if active { "link.resume" } else { "link.pause" };
+            audit::record(&mut tx, actor, action, Some(id), json!({})).await?;
+            let event = LinkEvent::new(
+                LinkEventKind::Updated,
+                changed.workspace_id,
+                Link {
+                    id: id.to_string(),
+                    target_url: changed.target_url,
+                },
+            );
+            outbox::enqueue(&mut tx, &event).await?;
+            link_cache::publish(&mut *tx, &[id.to_string()]).await?;
+        }
+        let link = fetch_link_details(&mut *tx, id).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(link)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    cache.invalidate(&[id.to_string()]);
+    let verb = if active { "Resumed" } else { "Paused" };
+    tracing::info!("{} link {} on behalf of {}", verb, id, actor);
+    Ok(Json(link))
+}
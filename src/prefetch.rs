@@ -0,0 +1,84 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Instant};
+
+use axum::http::{HeaderMap, Method};
+
+use crate::config::PrefetchConfig;
+
+/// Pending `HEAD` requests above which expired ones are dropped.
+const MAX_TRACKED_HEADS: usize = 10_000;
+
+/// Headers browsers and previews send with requests nobody asked to see yet.
+const PURPOSE_HEADERS: [&str; 4] = ["sec-purpose", "purpose", "x-purpose", "x-moz"];
+
+/// Tells hits by email scanners and prefetching browsers from clicks by people, so a campaign
+/// doesn't register a click per recipient the moment it is sent. Scanners are recognized by
+/// their user agent or network, by asking for a prefetch, or by checking a link with `HEAD`
+/// before following it with `GET`. Without the client's address only the headers are looked at.
+#[derive(Debug, Default)]
+pub struct PrefetchDetector {
+    /// When each client last sent a `HEAD` for a link.
+    heads: Mutex<HashMap<(IpAddr, String), Instant>>,
+}
+
+impl PrefetchDetector {
+    pub fn is_prefetch(
+        &self,
+        method: &Method,
+        client: Option<IpAddr>,
+        link_id: &str,
+        headers: &HeaderMap,
+        config: &PrefetchConfig,
+    ) -> bool {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_ascii_lowercase)
+        };
+        let asks_for_prefetch = PURPOSE_HEADERS.iter().any(|name| {
+            header(name)
+                .is_some_and(|purpose| purpose.contains("prefetch") || purpose.contains("preview"))
+        });
+        let is_scanner = header("user-agent").is_some_and(|user_agent| {
+            config
+                .user_agents
+                .iter()
+                .any(|fragment| user_agent.contains(fragment.as_str()))
+        });
+        let Some(client) = client else {
+            return asks_for_prefetch || is_scanner || *method == Method::HEAD;
+        };
+        let from_scanner_network = config.ranges.iter().any(|range| range.contains(client));
+        asks_for_prefetch
+            || is_scanner
+            || from_scanner_network
+            || self.follows_head(method, client, link_id, config)
+    }
+
+    /// Remembers `HEAD` requests, which are checks themselves, and recognizes the `GET` following
+    /// one within `PREFETCH_HEAD_WINDOW_SECS`.
+    fn follows_head(
+        &self,
+        method: &Method,
+        client: IpAddr,
+        link_id: &str,
+        config: &PrefetchConfig,
+    ) -> bool {
+        if config.head_window.is_zero() {
+            return *method == Method::HEAD;
+        }
+        let now = Instant::now();
+        let mut heads = self.heads.lock().expect("Prefetch detector lock poisoned");
+        if heads.len() > MAX_TRACKED_HEADS {
+            heads.retain(|_, seen| now.duration_since(*seen) < config.head_window);
+        }
+        let key = (client, link_id.to_string());
+        if *method == Method::HEAD {
+            heads.insert(key, now);
+            return true;
+        }
+        heads
+            .remove(&key)
+            .is_some_and(|seen| now.duration_since(seen) < config.head_window)
+    }
+}
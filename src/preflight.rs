@@ -0,0 +1,163 @@
+use std::fmt;
+
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::{
+    auth::{AuthMethod, SETTINGS_ID},
+    config::{Config, ConfigError},
+};
+
+/// The migrations in `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Tables queried by the service. Checked as well as the migrations, since the schema may have
+/// been set up without sqlx.
+const REQUIRED_TABLES: [&str; 33] = [
+    "links",
+    "link_statistics",
+    "link_daily_clicks",
+    "link_daily_referers",
+    "link_hourly_clicks",
+    "link_daily_sources",
+    "link_daily_breakdown",
+    "conversions",
+    "link_history",
+    "link_health",
+    "link_rollouts",
+    "settings",
+    "runtime_settings",
+    "blocked_domains",
+    "honeypot_slugs",
+    "slug_namespaces",
+    "domain_error_pages",
+    "campaigns",
+    "campaign_links",
+    "webhook_endpoints",
+    "webhook_deliveries",
+    "outbox",
+    "outbox_deliveries",
+    "audit_log",
+    "api_usage",
+    "archived_links",
+    "archived_link_statistics",
+    "archived_link_history",
+    "archived_conversions",
+    "maintenance_jobs",
+    "access_log",
+    "workspace_settings",
+    "statistics_exports",
+];
+
+/// Why the service cannot start. Each problem says what to do about it.
+#[derive(Debug)]
+pub enum PreflightError {
+    Problems(Vec<String>),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::Problems(problems) => {
+                write!(f, "Cannot start:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+            PreflightError::Database(err) => write!(f, "Could not check the database: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+impl From<sqlx::Error> for PreflightError {
+    fn from(err: sqlx::Error) -> Self {
+        PreflightError::Database(err)
+    }
+}
+
+async fn check_schema(pool: &PgPool, problems: &mut Vec<String>) -> Result<(), sqlx::Error> {
+    let tracked =
+        sqlx::query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "tracked!""#)
+            .fetch_one(pool)
+            .await?;
+    if tracked {
+        // Not a macro: the table only exists where sqlx applied the migrations.
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(pool)
+                .await?;
+        let pending: Vec<String> = MIGRATOR
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| format!("{} ({})", migration.version, migration.description))
+            .collect();
+        if !pending.is_empty() {
+            problems.push(format!(
+                "The database schema is out of date, these migrations are not applied: {}. Apply them with `sqlx migrate run`.",
+                pending.join(", ")
+            ));
+        }
+    } else {
+        tracing::debug!("Migrations were not applied by sqlx, only checking tables");
+    }
+
+    let missing = sqlx::query_scalar!(
+        r#"SELECT name AS "name!" FROM UNNEST($1::TEXT[]) AS name WHERE to_regclass(name) IS NULL"#,
+        &REQUIRED_TABLES.map(str::to_string)
+    )
+    .fetch_all(pool)
+    .await?;
+    if !missing.is_empty() {
+        problems.push(format!(
+            "Tables are missing: {}. Apply the migrations in `migrations/`.",
+            missing.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+async fn check_settings(
+    pool: &PgPool,
+    config: &Config,
+    problems: &mut Vec<String>,
+) -> Result<(), sqlx::Error> {
+    if config.auth.method != AuthMethod::Database {
+        return Ok(());
+    }
+    let configured = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM settings WHERE id = $1) AS "configured!""#,
+        SETTINGS_ID
+    )
+    .fetch_one(pool)
+    .await?;
+    if !configured {
+        problems.push(format!(
+            "AUTH_METHOD is `database` but `settings` has no `{SETTINGS_ID}` row. Insert one with the SHA3-256 hex digest of the API key as `encrypted_global_api_key`, or choose another AUTH_METHOD."
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the schema, loads the configuration and checks the settings it depends on, reporting
+/// every problem at once instead of failing requests later.
+pub async fn preflight(pool: &PgPool) -> Result<Config, PreflightError> {
+    let mut problems = Vec::new();
+    check_schema(pool, &mut problems).await?;
+    if !problems.is_empty() {
+        // Loading the configuration would only fail on the missing tables.
+        return Err(PreflightError::Problems(problems));
+    }
+    let config = match Config::load(pool).await {
+        Ok(config) => config,
+        Err(ConfigError::Invalid(invalid)) => return Err(PreflightError::Problems(invalid)),
+        Err(ConfigError::Database(err)) => return Err(PreflightError::Database(err)),
+    };
+    check_settings(pool, &config, &mut problems).await?;
+    if !problems.is_empty() {
+        return Err(PreflightError::Problems(problems));
+    }
+    Ok(config)
+}
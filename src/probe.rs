@@ -0,0 +1,62 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::config::SharedConfig;
+
+const PROBE_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Browsers and crawlers ask for these paths on every host. Answering them here keeps them from
+/// being looked up as link ids, which would mean a database query and a 404 for each.
+fn no_content() -> Response {
+    (
+        StatusCode::NO_CONTENT,
+        [(header::CACHE_CONTROL, PROBE_CACHE_CONTROL)],
+    )
+        .into_response()
+}
+
+fn icon_content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        _ => "image/x-icon",
+    }
+}
+
+pub async fn favicon(State(config): State<SharedConfig>) -> Response {
+    let Some(path) = config.current().favicon_path.clone() else {
+        return no_content();
+    };
+    match tokio::fs::read(&path).await {
+        Ok(icon) => (
+            [
+                (header::CONTENT_TYPE, icon_content_type(&path)),
+                (header::CACHE_CONTROL, PROBE_CACHE_CONTROL),
+            ],
+            icon,
+        )
+            .into_response(),
+        Err(err) => {
+            tracing::warn!("Could not read favicon from {}: {}", path, err);
+            no_content()
+        }
+    }
+}
+
+pub async fn robots_txt(State(config): State<SharedConfig>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CACHE_CONTROL, PROBE_CACHE_CONTROL),
+        ],
+        config.current().robots_txt.clone(),
+    )
+        .into_response()
+}
+
+pub async fn well_known() -> Response {
+    no_content()
+}
@@ -0,0 +1,183 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    config::SharedConfig,
+    rollup,
+    utils::{accepts_json, base_url, escape_html, internal_error},
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicStatsLink {
+    pub token: String,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyCount {
+    pub date: DateTime<Utc>,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicStats {
+    pub id: String,
+    pub total_clicks: i64,
+    pub created_at: DateTime<Utc>,
+    pub daily: Vec<DailyCount>,
+}
+
+/// Shares the statistics of a link under a new secret URL. Calling it again rotates the token,
+/// which revokes the previous URL.
+pub async fn enable_public_stats(
+    State(pool): State<PgPool>,
+    Path(id): Path<String>,
+    State(config): State<SharedConfig>,
+    headers: HeaderMap,
+) -> Result<Json<PublicStatsLink>, (StatusCode, String)> {
+    let mut token = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut token);
+    let token = URL_SAFE_NO_PAD.encode(token);
+    let enable_timeout = tokio::time::Duration::from_millis(300);
+    let updated = tokio::time::timeout(
+        enable_timeout,
+        sqlx::query!(
+            "UPDATE links SET stats_token = $2 WHERE id = $1",
+            &id,
+            &token
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if updated.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    tracing::debug!("Shared statistics of link with id {}", id);
+    Ok(Json(PublicStatsLink {
+        url: format!(
+            "{}/{}/stats/{}",
+            base_url(&config.current(), &headers),
+            id,
+            token
+        ),
+        token,
+    }))
+}
+
+pub async fn disable_public_stats(
+    State(pool): State<PgPool>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let disable_timeout = tokio::time::Duration::from_millis(300);
+    let updated = tokio::time::timeout(
+        disable_timeout,
+        sqlx::query!("UPDATE links SET stats_token = NULL WHERE id = $1", &id).execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if updated.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    tracing::debug!("Stopped sharing statistics of link with id {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn render_html(stats: &PublicStats) -> String {
+    let id = escape_html(&stats.id);
+    let rows: String = stats
+        .daily
+        .iter()
+        .map(|day| {
+            format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                day.date.format("%Y-%m-%d"),
+                day.clicks
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Statistics for {id}</title></head>\n<body>\n<h1>Statistics for {id}</h1>\n<p>{} clicks since {}</p>\n<table>\n<tr><th>Day</th><th>Clicks</th></tr>\n{rows}</table>\n</body>\n</html>\n",
+        stats.total_clicks,
+        stats.created_at.format("%Y-%m-%d"),
+    )
+}
+
+/// Read-only statistics of a link for whoever holds its stats URL, as HTML or, when asked for,
+/// JSON. Unknown links and wrong tokens look the same.
+pub async fn get_public_stats(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Path((id, token)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    let fetch_stats_timeout = tokio::time::Duration::from_millis(1000);
+    let link = tokio::time::timeout(
+        fetch_stats_timeout,
+        sqlx::query!(
+            r#"
+                SELECT id, click_count, created_at
+                FROM links
+                WHERE id = $1 AND stats_token = $2
+            "#,
+            &id,
+            &token
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+    let daily = tokio::time::timeout(
+        fetch_stats_timeout,
+        sqlx::query_as!(
+            DailyCount,
+            r#"
+                SELECT day::TIMESTAMP AT TIME ZONE 'UTC' AS "date!", clicks AS "clicks!"
+                FROM link_daily_clicks
+                WHERE link_id = $1 AND day < $2 AND clicks > 0
+                UNION ALL
+                SELECT date_trunc('day', created_at, 'UTC'), SUM(weight)
+                FROM link_statistics
+                WHERE link_id = $1 AND NOT prefetch AND created_at >= $3
+                GROUP BY 1
+                ORDER BY 1 DESC
+            "#,
+            &id,
+            raw_since.date_naive(),
+            raw_since
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    let stats = PublicStats {
+        id: link.id,
+        total_clicks: link.click_count,
+        created_at: link.created_at,
+        daily,
+    };
+    let cache_control = [(header::CACHE_CONTROL, "private, max-age=60")];
+    if accepts_json(&headers) {
+        return Ok((cache_control, Json(stats)).into_response());
+    }
+    Ok((cache_control, Html(render_html(&stats))).into_response())
+}
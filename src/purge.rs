@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::{Actor, Workspace},
+    click_writer::ClickWriter,
+    utils::internal_error,
+};
+
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    /// Clicks recorded before this instant are deleted.
+    pub before: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeResult {
+    pub deleted: u64,
+}
+
+/// Irreversibly deletes the recorded clicks of a link, archived or not, for data erasure requests,
+/// along with the conversions tracked from them and their referers and user agents rolled up.
+/// The click counter and the other rollups are kept; they hold nothing personal. Clicks still
+/// buffered by this instance are dropped as well; those of other instances, a few seconds' worth
+/// at most, are written after the purge.
+pub async fn purge_link_statistics(
+    State(pool): State<PgPool>,
+    State(clicks): State<Arc<ClickWriter>>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(link_id): Path<String>,
+) -> Result<Json<PurgeResult>, (StatusCode, String)> {
+    let purge_timeout = tokio::time::Duration::from_secs(30);
+    let deleted = tokio::time::timeout(purge_timeout, async {
+        let mut tx = pool.begin().await?;
+        let exists = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (SELECT 1 FROM links WHERE id = $1 AND workspace_id = $2)
+                    OR EXISTS (SELECT 1 FROM archived_links WHERE id = $1 AND workspace_id = $2)
+                    AS "exists!"
+            "#,
+            &link_id,
+            &workspace
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists {
+            return Ok(None);
+        }
+        let deleted = sqlx::query!("DELETE FROM link_statistics WHERE link_id = $1", &link_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            + sqlx::query!(
+                "DELETE FROM archived_link_statistics WHERE link_id = $1",
+                &link_id
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query!("DELETE FROM conversions WHERE link_id = $1", &link_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM archived_conversions WHERE link_id = $1",
+            &link_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM link_daily_sources WHERE link_id = $1",
+            &link_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut tx,
+            &actor,
+            "statistics.purge",
+            Some(&link_id),
+            json!({ "deleted": deleted }),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(deleted))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| "Not Found".to_string())
+    .map_err(|err| (StatusCode::NOT_FOUND, err))?;
+    let deleted = deleted + clicks.discard(&link_id) as u64;
+    tracing::info!("{} purged {} clicks of link {}", actor, deleted, link_id);
+    Ok(Json(PurgeResult { deleted }))
+}
+
+/// Irreversibly deletes every click recorded before `?before=`, across all links including the
+/// archived ones, the conversions tracked from them and the referers and user agents rolled up
+/// from them.
+pub async fn purge_statistics(
+    State(pool): State<PgPool>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Query(params): Query<PurgeParams>,
+) -> Result<Json<PurgeResult>, (StatusCode, String)> {
+    let purge_timeout = tokio::time::Duration::from_secs(300);
+    let deleted = tokio::time::timeout(purge_timeout, async {
+        let mut tx = pool.begin().await?;
+        let deleted = sqlx::query!(
+            "DELETE FROM link_statistics WHERE created_at < $1",
+            params.before
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            + sqlx::query!(
+                "DELETE FROM archived_link_statistics WHERE created_at < $1",
+                params.before
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query!(
+            "DELETE FROM conversions WHERE clicked_at < $1",
+            params.before
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM archived_conversions WHERE clicked_at < $1",
+            params.before
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM link_daily_sources WHERE day < ($1 AT TIME ZONE 'UTC')::DATE",
+            params.before
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut tx,
+            &actor,
+            "statistics.purge",
+            None,
+            json!({ "before": params.before, "deleted": deleted }),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(deleted)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::info!(
+        "{} purged {} clicks recorded before {}",
+        actor,
+        deleted,
+        params.before
+    );
+    Ok(Json(PurgeResult { deleted }))
+}
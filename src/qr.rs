@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+
+use crate::{
+    click::QR_CHANNEL,
+    config::SharedConfig,
+    route::ensure_link_exists,
+    utils::{internal_error, short_url},
+};
+
+const QR_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// `GET /:id/qr`: the short URL as an SVG QR code, for print. Scans are attributed to the `qr`
+/// channel.
+pub async fn get_qr_code(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    ensure_link_exists(&pool, &id).await?;
+    let url = format!(
+        "{}?src={QR_CHANNEL}",
+        short_url(&config.current(), &headers, &id)
+    );
+    let code = QrCode::new(url).map_err(internal_error)?;
+    let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            (header::CACHE_CONTROL, QR_CACHE_CONTROL),
+        ],
+        image,
+    )
+        .into_response())
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::counter;
+
+use crate::{
+    client_ip::ClientIp,
+    config::{RateLimitConfig, SharedConfig},
+};
+
+/// Clients tracked above which counters of finished windows are dropped.
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+/// Keeps track of clients that are temporarily refused service, and of the requests each client
+/// made in the current window.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    bans: Mutex<HashMap<IpAddr, Instant>>,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+/// Where a client stands against its limit, as told in the `RateLimit-*` headers.
+#[derive(Debug)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the window starts over.
+    pub reset: Duration,
+    pub allowed: bool,
+}
+
+impl RateLimitStatus {
+    fn add_headers(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(reset_secs(self.reset)));
+    }
+}
+
+/// Whole seconds, rounded up so clients never retry early.
+fn reset_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl RateLimiter {
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut bans = self.bans.lock().expect("Rate limiter lock poisoned");
+        let entry = bans.entry(ip).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    /// Time left on the ban of `ip`, if any. Expired bans are dropped on the way.
+    pub fn banned_for(&self, ip: IpAddr) -> Option<Duration> {
+        let mut bans = self.bans.lock().expect("Rate limiter lock poisoned");
+        let until = *bans.get(&ip)?;
+        let now = Instant::now();
+        if now >= until {
+            bans.remove(&ip);
+            return None;
+        }
+        Some(until - now)
+    }
+
+    /// Counts a request of `ip` against a fixed window limit. `None` while no limit is set.
+    pub fn check(&self, ip: IpAddr, config: &RateLimitConfig) -> Option<RateLimitStatus> {
+        let limit = config.requests?;
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("Rate limiter lock poisoned");
+        if windows.len() > MAX_TRACKED_CLIENTS {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < config.window);
+        }
+        let (started, count) = windows.entry(ip).or_insert((now, 0));
+        if now.duration_since(*started) >= config.window {
+            *started = now;
+            *count = 0;
+        }
+        let allowed = *count < limit;
+        if allowed {
+            *count += 1;
+        }
+        Some(RateLimitStatus {
+            limit,
+            remaining: limit - *count,
+            reset: config.window.saturating_sub(now.duration_since(*started)),
+            allowed,
+        })
+    }
+}
+
+pub async fn reject_banned(
+    State(limiter): State<Arc<RateLimiter>>,
+    ClientIp(client): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(remaining) = limiter.banned_for(client) {
+        counter!("banned_requests").increment(1);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, remaining.as_secs().max(1).to_string())],
+            "Too Many Requests",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// Refuses clients over the configured request rate with 429, and tells every client where it
+/// stands through `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`.
+pub async fn limit_requests(
+    State(limiter): State<Arc<RateLimiter>>,
+    State(config): State<SharedConfig>,
+    ClientIp(client): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(status) = limiter.check(client, &config.current().rate_limit) else {
+        return next.run(req).await;
+    };
+    let mut response = if status.allowed {
+        next.run(req).await
+    } else {
+        counter!("rate_limited_requests").increment(1);
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, reset_secs(status.reset).to_string())],
+            "Too Many Requests",
+        )
+            .into_response()
+    };
+    status.add_headers(&mut response);
+    response
+}
+
+const THROTTLED_PAGE: &str = "<!DOCTYPE html>\n<html>\n<head><title>Try again shortly</title></head>\n<body>\n<h1>This link is busy</h1>\n<p>Too many people are following this link right now. Please try again in a moment.</p>\n</body>\n</html>\n";
+
+/// Token buckets of links with a redirect rate limit, holding up to one second worth of
+/// redirects. Limits are learned from redirects as they happen, so a link is only throttled
+/// once it has been looked up and a changed limit applies from its next redirect on.
+#[derive(Debug, Default)]
+pub struct LinkThrottle {
+    buckets: Mutex<HashMap<String, (u32, f64, Instant)>>,
+}
+
+impl LinkThrottle {
+    /// Takes a token for a redirect of `link_id`. False when the link has run out.
+    pub fn try_acquire(&self, link_id: &str) -> bool {
+        let mut buckets = self.buckets.lock().expect("Link throttle lock poisoned");
+        let Some((rate, tokens, refilled)) = buckets.get_mut(link_id) else {
+            return true;
+        };
+        let now = Instant::now();
+        let rate = f64::from(*rate);
+        *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * rate).min(rate);
+        *refilled = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    /// Records the current limit of a link after it was looked up.
+    pub fn set_limit(&self, link_id: &str, rate_limit: Option<i32>) {
+        let mut buckets = self.buckets.lock().expect("Link throttle lock poisoned");
+        match rate_limit.and_then(|rate| u32::try_from(rate).ok()) {
+            Some(rate) => {
+                let (current, tokens, _) = buckets
+                    .entry(link_id.to_string())
+                    // The redirect that taught us the limit used the first token.
+                    .or_insert((rate, f64::from(rate) - 1.0, Instant::now()));
+                *current = rate;
+                *tokens = tokens.min(f64::from(rate));
+            }
+            None => {
+                buckets.remove(link_id);
+            }
+        }
+    }
+}
+
+/// Answer for redirects of a link over its rate limit.
+pub fn throttled_response() -> Response {
+    counter!("throttled_redirects").increment(1);
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::RETRY_AFTER, "1")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(THROTTLED_PAGE))
+        .expect("This response should always be constructable")
+}
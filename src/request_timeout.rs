@@ -0,0 +1,89 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::counter;
+use tokio::time::{Instant, Sleep};
+use tokio_stream::Stream;
+
+use crate::{config::SharedConfig, utils::route_template};
+
+/// Routes moving a lot of data at once, given `BULK_REQUEST_TIMEOUT_SECS`.
+const BULK_ROUTES: [&str; 3] = ["/admin/export", "/api/import", "/admin/statistics"];
+
+/// Answers 504 once a handler has taken longer than `REQUEST_TIMEOUT_SECS`, so a slow request
+/// gives its connection back instead of holding it indefinitely. Bulk endpoints get
+/// `BULK_REQUEST_TIMEOUT_SECS`, which also bounds streaming their responses. Database queries
+/// keep their own, shorter timeouts.
+pub async fn limit_request_time(
+    State(config): State<SharedConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let route = route_template(&req);
+    let bulk = BULK_ROUTES.contains(&route.as_str());
+    let timeout = if bulk {
+        config.current().bulk_request_timeout
+    } else {
+        config.current().request_timeout
+    };
+    let deadline = Instant::now() + timeout;
+    let Ok(response) = tokio::time::timeout_at(deadline, next.run(req)).await else {
+        counter!("requests_timed_out", "route" => route.clone()).increment(1);
+        tracing::warn!("Request to {} timed out after {:?}", route, timeout);
+        return (StatusCode::GATEWAY_TIMEOUT, "Request Timed Out").into_response();
+    };
+    if !bulk {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = Deadline {
+        inner: body.into_data_stream(),
+        sleep: Box::pin(tokio::time::sleep_until(deadline)),
+        timeout,
+        expired: false,
+    };
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// A response body failing once the deadline has passed, which aborts the connection so the
+/// client sees the response was cut short.
+struct Deadline<S> {
+    inner: S,
+    sleep: Pin<Box<Sleep>>,
+    timeout: Duration,
+    expired: bool,
+}
+
+impl<S> Stream for Deadline<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+{
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            self.expired = true;
+            counter!("responses_timed_out").increment(1);
+            let message = format!("Response not finished within {:?}", self.timeout);
+            return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::TimedOut, message))));
+        }
+        Pin::new(&mut self.inner)
+            .poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(io::Error::other)))
+    }
+}
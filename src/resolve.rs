@@ -0,0 +1,157 @@
+use std::collections::{BTreeSet, HashSet};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use url::Url;
+
+use crate::{
+    config::SharedConfig,
+    signed::{self, SignedLinkError, SignedTarget},
+    target::serialize_display_url,
+    utils::internal_error,
+};
+
+/// Upper bound on slugs per resolve call, keeping the `ANY($1)` lookup within the timeout.
+const MAX_RESOLVE_IDS: usize = 10_000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedLink {
+    pub id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub active: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveResponse {
+    pub links: Vec<ResolvedLink>,
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpandedLink {
+    pub id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub target_host: Option<String>,
+    pub signed: bool,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Looks up the targets of many links at once. Unlike redirects this records no clicks.
+pub async fn resolve_links(
+    State(pool): State<PgPool>,
+    Json(request): Json<ResolveRequest>,
+) -> Result<Json<ResolveResponse>, (StatusCode, String)> {
+    if request.ids.len() > MAX_RESOLVE_IDS {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("At Most {MAX_RESOLVE_IDS} Ids Allowed"),
+        ));
+    }
+    let resolve_timeout = tokio::time::Duration::from_millis(1000);
+    let links = tokio::time::timeout(
+        resolve_timeout,
+        sqlx::query_as!(
+            ResolvedLink,
+            "SELECT id, target_url, active FROM links WHERE id = ANY($1)",
+            &request.ids
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+
+    let found: HashSet<&str> = links.iter().map(|link| link.id.as_str()).collect();
+    let missing: Vec<String> = request
+        .ids
+        .iter()
+        .filter(|id| !found.contains(id.as_str()))
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    tracing::debug!("Resolved {} links, {} missing", links.len(), missing.len());
+    Ok(Json(ResolveResponse { links, missing }))
+}
+
+/// Tells where a link leads without redirecting or counting a click. Public, so that link
+/// scanners can inspect links before following them.
+pub async fn expand_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Path(id): Path<String>,
+) -> Result<Json<ExpandedLink>, (StatusCode, String)> {
+    let config = config.current();
+    let not_found = || (StatusCode::NOT_FOUND, "Not Found".to_string());
+    let mut link_id = id.clone();
+    if signed::is_signed(&id) {
+        let key = config.signed_links.key.as_deref().ok_or_else(not_found)?;
+        match signed::verify(key, &id) {
+            Ok(SignedTarget::Url(target_url)) => {
+                return Ok(Json(ExpandedLink {
+                    target_host: host_of(&target_url),
+                    id,
+                    target_url,
+                    signed: true,
+                    created_at: None,
+                }))
+            }
+            Ok(SignedTarget::Link(signed_link_id)) => link_id = signed_link_id,
+            Err(SignedLinkError::Expired) => return Err((StatusCode::GONE, "Link Expired".into())),
+            Err(SignedLinkError::Invalid) => return Err(not_found()),
+        }
+    }
+
+    let expand_timeout = tokio::time::Duration::from_millis(300);
+    let link = tokio::time::timeout(
+        expand_timeout,
+        sqlx::query!(
+            r#"
+                SELECT target_url, created_at, COALESCE(expires_at <= now(), false) AS "expired!"
+                FROM links
+                WHERE id = $1 AND active
+            "#,
+            &link_id
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(not_found)?;
+    // Answered like the redirect, even before the expiry sweep marks the link.
+    if link.expired {
+        return Err((StatusCode::GONE, "Link Expired".into()));
+    }
+    tracing::debug!("Expanded link with id {}", id);
+    Ok(Json(ExpandedLink {
+        signed: link_id != id,
+        id,
+        target_host: host_of(&link.target_url),
+        target_url: link.target_url,
+        created_at: Some(link.created_at),
+    }))
+}
+
+fn host_of(target_url: &str) -> Option<String> {
+    Url::parse(target_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue},
+    response::Response,
+};
+use serde::{ser::SerializeMap, Serializer};
+
+/// Headers a link may add to its redirects. Nothing that changes where the visitor ends up or
+/// what their browser keeps, like `Location` or `Set-Cookie`.
+pub const ALLOWED_HEADERS: [&str; 5] = [
+    "x-robots-tag",
+    "cache-control",
+    "cdn-cache-control",
+    "surrogate-control",
+    "referrer-policy",
+];
+pub const MAX_VALUE_LENGTH: usize = 256;
+
+/// Lowercases the names and trims the values of headers given for a link, or says what is wrong
+/// with them.
+pub fn normalize(headers: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    let mut normalized = BTreeMap::new();
+    for (name, value) in headers {
+        let name = name.trim().to_ascii_lowercase();
+        if !ALLOWED_HEADERS.contains(&name.as_str()) {
+            return Err(format!(
+                "can only hold {}, not {name}",
+                ALLOWED_HEADERS.join(", ")
+            ));
+        }
+        let value = value.trim();
+        if value.is_empty()
+            || value.len() > MAX_VALUE_LENGTH
+            || HeaderValue::from_str(value).is_err()
+        {
+            return Err(format!(
+                "{name} must be non-empty, printable and at most {MAX_VALUE_LENGTH} characters"
+            ));
+        }
+        normalized.insert(name, value.to_string());
+    }
+    Ok(normalized)
+}
+
+/// Headers as stored with a link.
+pub fn to_lines(headers: &BTreeMap<String, String>) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    line.split_once(": ")
+}
+
+/// Writes stored headers as an object of names and values.
+pub fn serialize<S: Serializer>(lines: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(lines.len()))?;
+    for (name, value) in lines.iter().filter_map(|line| parse_line(line)) {
+        map.serialize_entry(name, value)?;
+    }
+    map.end()
+}
+
+/// Adds a link's headers to its redirect, replacing any the shortener set itself.
+pub fn apply(response: &mut Response, lines: &[String]) {
+    let headers = response.headers_mut();
+    for (name, value) in lines.iter().filter_map(|line| parse_line(line)) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Drops the cache directives only CDNs read, which would otherwise override `Cache-Control`.
+pub fn remove_cdn_directives(headers: &mut HeaderMap) {
+    headers.remove("cdn-cache-control");
+    headers.remove("surrogate-control");
+}
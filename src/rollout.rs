@@ -0,0 +1,484 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::{
+    audit,
+    auth::Actor,
+    config::{Config, SharedConfig},
+    health_monitor::check_target,
+    history::record_target_change,
+    link_cache::{self, CachedLink, LinkCache},
+    outbox,
+    route::Link,
+    target::{parse_target_url, serialize_display_url},
+    utils::internal_error,
+    validation::{ApiError, FieldError, ValidJson},
+    webhook::{LinkEvent, LinkEventKind},
+};
+
+/// Taken while the targets of running rollouts are probed, so instances don't count the same
+/// probes twice.
+const PROBE_LOCK: i64 = 0x6c69_6e6b_5f72_6f6c;
+/// Finished rollouts promoted per transaction.
+const PROMOTE_BATCH_SIZE: i64 = 100;
+
+/// Which target of a rollout a redirect went to.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    /// The link's target before the rollout.
+    Stable,
+    /// The target being rolled out.
+    Canary,
+}
+
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Stable => "stable",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+/// Where a redirect of `link` goes: while a rollout runs, to the canary for its percentage of
+/// redirects, drawn anew every time, and to the link's target otherwise.
+pub fn pick_target(link: &CachedLink) -> (&str, Option<Variant>) {
+    match (&link.canary_target_url, link.canary_percent) {
+        (Some(canary), Some(percent)) if rand::thread_rng().gen_range(0..100) < percent => {
+            (canary, Some(Variant::Canary))
+        }
+        (Some(_), Some(_)) => (&link.target_url, Some(Variant::Stable)),
+        _ => (&link.target_url, None),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RolloutRequest {
+    pub target_url: String,
+    /// Share of redirects sent to the new target, from 1 to 99.
+    pub percent: i32,
+    /// How long both targets are served before the new one replaces the link's target.
+    pub duration_secs: u64,
+}
+
+/// How one target of a rollout has fared since the rollout started.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantStatistics {
+    pub variant: Variant,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub percent: i32,
+    pub clicks: i64,
+    /// Probes of the target, made while `HEALTH_CHECK_ENABLED` is set.
+    pub checks: i32,
+    pub failures: i32,
+    /// Share of failed probes; missing before the first one.
+    pub error_rate: Option<f64>,
+}
+
+impl VariantStatistics {
+    fn new(
+        variant: Variant,
+        target_url: String,
+        percent: i32,
+        clicks: i64,
+        checks: i32,
+        failures: i32,
+    ) -> Self {
+        Self {
+            variant,
+            target_url,
+            percent,
+            clicks,
+            checks,
+            failures,
+            error_rate: (checks > 0).then(|| f64::from(failures) / f64::from(checks)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rollout {
+    pub link_id: String,
+    pub started_by: String,
+    pub started_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub variants: [VariantStatistics; 2],
+}
+
+async fn fetch_rollout(
+    executor: impl PgExecutor<'_>,
+    id: &str,
+) -> Result<Option<Rollout>, sqlx::Error> {
+    let rollout = sqlx::query!(
+        r#"
+            SELECT r.link_id, l.target_url AS stable_target_url, r.target_url AS canary_target_url,
+                r.percent, r.started_by, r.started_at, r.ends_at, r.stable_checks,
+                r.stable_failures, r.canary_checks, r.canary_failures,
+                COALESCE(c.stable_clicks, 0) AS "stable_clicks!",
+                COALESCE(c.canary_clicks, 0) AS "canary_clicks!"
+            FROM link_rollouts r
+            JOIN links l ON l.id = r.link_id
+            LEFT JOIN LATERAL (
+                SELECT
+                    SUM(weight) FILTER (WHERE variant = 'stable')::BIGINT AS stable_clicks,
+                    SUM(weight) FILTER (WHERE variant = 'canary')::BIGINT AS canary_clicks
+                FROM link_statistics
+                WHERE link_id = r.link_id AND created_at >= r.started_at AND NOT prefetch
+            ) c ON TRUE
+            WHERE r.link_id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(rollout.map(|rollout| Rollout {
+        link_id: rollout.link_id,
+        started_by: rollout.started_by,
+        started_at: rollout.started_at,
+        ends_at: rollout.ends_at,
+        variants: [
+            VariantStatistics::new(
+                Variant::Stable,
+                rollout.stable_target_url,
+                100 - rollout.percent,
+                rollout.stable_clicks,
+                rollout.stable_checks,
+                rollout.stable_failures,
+            ),
+            VariantStatistics::new(
+                Variant::Canary,
+                rollout.canary_target_url,
+                rollout.percent,
+                rollout.canary_clicks,
+                rollout.canary_checks,
+                rollout.canary_failures,
+            ),
+        ],
+    }))
+}
+
+/// `GET /:id/rollout`: the running rollout of a link, with the clicks and error rate of both
+/// targets to compare them.
+pub async fn get_rollout(
+    State(pool): State<PgPool>,
+    Path(id): Path<String>,
+) -> Result<Json<Rollout>, (StatusCode, String)> {
+    let rollout = tokio::time::timeout(
+        tokio::time::Duration::from_millis(1000),
+        fetch_rollout(&pool, &id),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "No Rollout".to_string()))?;
+    Ok(Json(rollout))
+}
+
+/// `POST /:id/rollout`: starts sending `percent` of the link's redirects to a new target, which
+/// becomes the link's target once `durationSecs` have passed. Changing the link's target
+/// meanwhile changes the other side of the rollout.
+pub async fn start_rollout(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(cache): State<Arc<LinkCache>>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Path(id): Path<String>,
+    ValidJson(request): ValidJson<RolloutRequest>,
+) -> Result<(StatusCode, Json<Rollout>), ApiError> {
+    let config = config.current();
+    let target_url = match parse_target_url(&request.target_url, &config) {
+        Ok(url) => url.to_string(),
+        Err(err) => {
+            let submitted = json!({ "targetUrl": request.target_url });
+            let err = err.on_field("targetUrl");
+            return Err(
+                audit::record_refused_targets(&pool, &actor, Some(&id), &submitted, err).await,
+            );
+        }
+    };
+    let mut problems = Vec::new();
+    if !(1..=99).contains(&request.percent) {
+        problems.push(FieldError::new("percent", "must be between 1 and 99"));
+    }
+    let max_duration = config.rollouts.max_duration.as_secs();
+    if !(1..=max_duration).contains(&request.duration_secs) {
+        problems.push(FieldError::new(
+            "durationSecs",
+            format!("must be between 1 and {max_duration}"),
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(ApiError::Fields(StatusCode::UNPROCESSABLE_ENTITY, problems));
+    }
+
+    let start_rollout_timeout = tokio::time::Duration::from_millis(300);
+    let rollout = tokio::time::timeout(start_rollout_timeout, async {
+        let mut tx = pool.begin().await?;
+        let link = sqlx::query!("SELECT target_url FROM links WHERE id = $1 FOR UPDATE", &id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(link) = link else {
+            return Ok(Err(ApiError::Status(
+                StatusCode::NOT_FOUND,
+                "Not Found".into(),
+            )));
+        };
+        if link.target_url == target_url {
+            return Ok(Err(ApiError::Fields(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                vec![FieldError::new("targetUrl", "is the link's target already")],
+            )));
+        }
+        let started = sqlx::query!(
+            r#"
+                INSERT INTO link_rollouts (link_id, target_url, percent, started_by, ends_at)
+                VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+                ON CONFLICT (link_id) DO NOTHING
+            "#,
+            &id,
+            &target_url,
+            request.percent,
+            &actor,
+            request.duration_secs as f64
+        )
+        .execute(&mut *tx)
+        .await?;
+        if started.rows_affected() == 0 {
+            return Ok(Err(ApiError::Status(
+                StatusCode::CONFLICT,
+                "Rollout Running".into(),
+            )));
+        }
+        audit::record(
+            &mut tx,
+            &actor,
+            "link.rollout.start",
+            Some(&id),
+            json!({
+                "targetUrl": target_url,
+                "percent": request.percent,
+                "durationSecs": request.duration_secs,
+            }),
+        )
+        .await?;
+        link_cache::publish(&mut *tx, std::slice::from_ref(&id)).await?;
+        let rollout = fetch_rollout(&mut *tx, &id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(rollout))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)??;
+    cache.invalidate(std::slice::from_ref(&id));
+    tracing::info!(
+        "Rolling out {} to {}% of link {} on behalf of {}",
+        target_url,
+        request.percent,
+        id,
+        actor
+    );
+    Ok((StatusCode::CREATED, Json(rollout)))
+}
+
+/// `DELETE /:id/rollout`: stops a rollout, sending every redirect to the link's target again.
+pub async fn cancel_rollout(
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LinkCache>>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let cancel_rollout_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(cancel_rollout_timeout, async {
+        let mut tx = pool.begin().await?;
+        let cancelled = sqlx::query_scalar!(
+            "DELETE FROM link_rollouts WHERE link_id = $1 RETURNING target_url",
+            &id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(target_url) = cancelled else {
+            return Ok(None);
+        };
+        audit::record(
+            &mut tx,
+            &actor,
+            "link.rollout.cancel",
+            Some(&id),
+            json!({ "targetUrl": target_url }),
+        )
+        .await?;
+        link_cache::publish(&mut *tx, std::slice::from_ref(&id)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(()))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "No Rollout".to_string()))?;
+    cache.invalidate(std::slice::from_ref(&id));
+    tracing::info!(
+        "Cancelled the rollout of link {} on behalf of {}",
+        id,
+        actor
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Makes the new target of one batch of finished rollouts the target of their link, recorded in
+/// its history on behalf of whoever started the rollout. Returns how many were promoted.
+async fn promote_finished(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let finished = sqlx::query!(
+        r#"
+            SELECT r.link_id, r.target_url, r.started_by, l.target_url AS previous_target_url,
+                l.workspace_id
+            FROM link_rollouts r
+            JOIN links l ON l.id = r.link_id
+            WHERE r.ends_at <= now()
+            ORDER BY r.ends_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        "#,
+        PROMOTE_BATCH_SIZE
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if finished.is_empty() {
+        return Ok(0);
+    }
+    let mut events = Vec::with_capacity(finished.len());
+    for rollout in finished {
+        sqlx::query!(
+            "UPDATE links SET target_url = $2, updated_at = now() WHERE id = $1",
+            &rollout.link_id,
+            &rollout.target_url
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_target_change(
+            &mut tx,
+            &rollout.link_id,
+            &rollout.previous_target_url,
+            &rollout.target_url,
+            &rollout.started_by,
+        )
+        .await?;
+        events.push(LinkEvent::new(
+            LinkEventKind::Updated,
+            rollout.workspace_id,
+            Link {
+                id: rollout.link_id,
+                target_url: rollout.target_url,
+            },
+        ));
+    }
+    let ids: Vec<String> = events.iter().map(|event| event.link.id.clone()).collect();
+    sqlx::query!("DELETE FROM link_rollouts WHERE link_id = ANY($1)", &ids)
+        .execute(&mut *tx)
+        .await?;
+    outbox::enqueue_all(&mut tx, &events).await?;
+    link_cache::publish(&mut *tx, &ids).await?;
+    tx.commit().await?;
+    Ok(ids.len())
+}
+
+/// Probes both targets of every running rollout once, the way the health monitor probes link
+/// targets. Does nothing while another instance is at it.
+async fn probe_targets(pool: &PgPool, config: &Config) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        PROBE_LOCK
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(());
+    }
+    let running = sqlx::query!(
+        r#"
+            SELECT r.link_id, l.target_url AS stable_target_url, r.target_url AS canary_target_url
+            FROM link_rollouts r
+            JOIN links l ON l.id = r.link_id
+            WHERE r.ends_at > now()
+            ORDER BY r.link_id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    let health_check = &config.health_check;
+    for rollout in running {
+        let stable = check_target(
+            &rollout.stable_target_url,
+            health_check.request_timeout,
+            &config.outbound,
+        )
+        .await;
+        let canary = check_target(
+            &rollout.canary_target_url,
+            health_check.request_timeout,
+            &config.outbound,
+        )
+        .await;
+        sqlx::query!(
+            r#"
+                UPDATE link_rollouts
+                SET stable_checks = stable_checks + 1,
+                    stable_failures = stable_failures + $2,
+                    canary_checks = canary_checks + 1,
+                    canary_failures = canary_failures + $3
+                WHERE link_id = $1
+            "#,
+            &rollout.link_id,
+            i32::from(!stable.is_healthy()),
+            i32::from(!canary.is_healthy())
+        )
+        .execute(pool)
+        .await?;
+        tokio::time::sleep(health_check.request_delay).await;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Every `ROLLOUT_INTERVAL_SECS`, promotes finished rollouts and, while `HEALTH_CHECK_ENABLED`
+/// is set, probes the targets of running ones.
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            match promote_finished(&pool).await {
+                // Keep going without pausing while there is a backlog.
+                Ok(count) if count > 0 => {
+                    tracing::debug!("Promoted {} finished rollouts", count);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!("Promoting finished rollouts failed: {}", err),
+            }
+            if current.health_check.enabled {
+                if let Err(err) = probe_targets(&pool, &current).await {
+                    tracing::error!("Probing rollout targets failed: {}", err);
+                }
+            }
+            tokio::time::sleep(current.rollouts.interval).await;
+        }
+    });
+}
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::config::{SharedConfig, StatisticsConfig};
+
+/// Where statistics switch from rollups to recorded clicks: the first UTC midnight within
+/// `STATISTICS_RAW_WINDOW_HOURS`. Days before it are read from their rollups, so a statistics
+/// request never scans clicks older than the window, however popular the link.
+pub fn raw_since(statistics: &StatisticsConfig) -> DateTime<Utc> {
+    let window = chrono::Duration::from_std(statistics.raw_window).unwrap_or(chrono::Duration::MAX);
+    let earliest = Utc::now()
+        .checked_sub_signed(window)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let midnight = earliest
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc();
+    if midnight < earliest {
+        midnight + Days::new(1)
+    } else {
+        midnight
+    }
+}
+
+/// Recomputes the rollups of every link clicked between `from` and `to` (UTC days, both
+/// included) from the recorded clicks: clicks per day, hour, referring host, referer and user
+/// agent, and breakdown dimension. Prefetches are only counted apart, per day and per referer.
+/// Returns how many link days were written.
+///
+/// Days whose clicks were all pruned keep their rollups, but rolling up a partly pruned day
+/// again lowers its counts.
+pub async fn roll_up(
+    executor: impl PgExecutor<'_>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<u64, sqlx::Error> {
+    let start = from.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    let end = (to + Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc();
+    let rolled_up = sqlx::query!(
+        r#"
+            WITH referers AS (
+                INSERT INTO link_daily_referers (link_id, day, referer_host, clicks)
+                SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE,
+                    COALESCE(substring(lower(referer) FROM '^[a-z][a-z0-9+.-]*://([^/?#:@]+)'), ''),
+                    SUM(weight)
+                FROM link_statistics
+                WHERE created_at >= $1 AND created_at < $2 AND NOT prefetch
+                GROUP BY 1, 2, 3
+                ON CONFLICT (link_id, day, referer_host) DO UPDATE SET clicks = EXCLUDED.clicks
+            ), hours AS (
+                INSERT INTO link_hourly_clicks (link_id, hour, clicks)
+                SELECT link_id, date_trunc('hour', created_at), SUM(weight)
+                FROM link_statistics
+                WHERE created_at >= $1 AND created_at < $2 AND NOT prefetch
+                GROUP BY 1, 2
+                ON CONFLICT (link_id, hour) DO UPDATE SET clicks = EXCLUDED.clicks
+            ), sources AS (
+                INSERT INTO link_daily_sources (link_id, day, referer, user_agent, clicks, prefetches)
+                SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE, COALESCE(referer, ''),
+                    COALESCE(user_agent, ''),
+                    COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+                    COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+                FROM link_statistics
+                WHERE created_at >= $1 AND created_at < $2
+                GROUP BY 1, 2, 3, 4
+                ON CONFLICT (link_id, day, md5(referer), md5(user_agent)) DO UPDATE
+                SET clicks = EXCLUDED.clicks, prefetches = EXCLUDED.prefetches
+            ), breakdown AS (
+                INSERT INTO link_daily_breakdown (link_id, day, dimension, value, clicks)
+                SELECT s.link_id, (s.created_at AT TIME ZONE 'UTC')::DATE, d.dimension, d.value,
+                    SUM(s.weight)
+                FROM link_statistics s
+                CROSS JOIN LATERAL (
+                    VALUES
+                        ('language', COALESCE(s.language, 'unknown')),
+                        ('platform', COALESCE(s.platform, 'unknown')),
+                        (
+                            'device',
+                            CASE s.mobile WHEN true THEN 'mobile' WHEN false THEN 'desktop' ELSE 'unknown' END
+                        ),
+                        ('channel', s.channel)
+                ) AS d (dimension, value)
+                WHERE s.created_at >= $1 AND s.created_at < $2 AND NOT s.prefetch
+                GROUP BY 1, 2, 3, 4
+                ON CONFLICT (link_id, day, dimension, value) DO UPDATE SET clicks = EXCLUDED.clicks
+            )
+            INSERT INTO link_daily_clicks (link_id, day, clicks, unique_visitors, prefetches)
+            SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE,
+                COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+                COUNT(DISTINCT visitor_hash) FILTER (WHERE NOT prefetch),
+                COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+            FROM link_statistics
+            WHERE created_at >= $1 AND created_at < $2
+            GROUP BY 1, 2
+            ON CONFLICT (link_id, day) DO UPDATE
+            SET clicks = EXCLUDED.clicks,
+                unique_visitors = EXCLUDED.unique_visitors,
+                prefetches = EXCLUDED.prefetches
+        "#,
+        start,
+        end
+    )
+    .execute(executor)
+    .await?;
+    Ok(rolled_up.rows_affected())
+}
+
+/// Rebuilds the rollups of one link from its clicks, for links coming back from the archive.
+pub async fn roll_up_link(executor: impl PgExecutor<'_>, link_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            WITH referers AS (
+                INSERT INTO link_daily_referers (link_id, day, referer_host, clicks)
+                SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE,
+                    COALESCE(substring(lower(referer) FROM '^[a-z][a-z0-9+.-]*://([^/?#:@]+)'), ''),
+                    SUM(weight)
+                FROM link_statistics
+                WHERE link_id = $1 AND NOT prefetch
+                GROUP BY 1, 2, 3
+                ON CONFLICT (link_id, day, referer_host) DO UPDATE SET clicks = EXCLUDED.clicks
+            ), hours AS (
+                INSERT INTO link_hourly_clicks (link_id, hour, clicks)
+                SELECT link_id, date_trunc('hour', created_at), SUM(weight)
+                FROM link_statistics
+                WHERE link_id = $1 AND NOT prefetch
+                GROUP BY 1, 2
+                ON CONFLICT (link_id, hour) DO UPDATE SET clicks = EXCLUDED.clicks
+            ), sources AS (
+                INSERT INTO link_daily_sources (link_id, day, referer, user_agent, clicks, prefetches)
+                SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE, COALESCE(referer, ''),
+                    COALESCE(user_agent, ''),
+                    COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+                    COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+                FROM link_statistics
+                WHERE link_id = $1
+                GROUP BY 1, 2, 3, 4
+                ON CONFLICT (link_id, day, md5(referer), md5(user_agent)) DO UPDATE
+                SET clicks = EXCLUDED.clicks, prefetches = EXCLUDED.prefetches
+            ), breakdown AS (
+                INSERT INTO link_daily_breakdown (link_id, day, dimension, value, clicks)
+                SELECT s.link_id, (s.created_at AT TIME ZONE 'UTC')::DATE, d.dimension, d.value,
+                    SUM(s.weight)
+                FROM link_statistics s
+                CROSS JOIN LATERAL (
+                    VALUES
+                        ('language', COALESCE(s.language, 'unknown')),
+                        ('platform', COALESCE(s.platform, 'unknown')),
+                        (
+                            'device',
+                            CASE s.mobile WHEN true THEN 'mobile' WHEN false THEN 'desktop' ELSE 'unknown' END
+                        ),
+                        ('channel', s.channel)
+                ) AS d (dimension, value)
+                WHERE s.link_id = $1 AND NOT s.prefetch
+                GROUP BY 1, 2, 3, 4
+                ON CONFLICT (link_id, day, dimension, value) DO UPDATE SET clicks = EXCLUDED.clicks
+            )
+            INSERT INTO link_daily_clicks (link_id, day, clicks, unique_visitors, prefetches)
+            SELECT link_id, (created_at AT TIME ZONE 'UTC')::DATE,
+                COALESCE(SUM(weight) FILTER (WHERE NOT prefetch), 0),
+                COUNT(DISTINCT visitor_hash) FILTER (WHERE NOT prefetch),
+                COALESCE(SUM(weight) FILTER (WHERE prefetch), 0)
+            FROM link_statistics
+            WHERE link_id = $1
+            GROUP BY 1, 2
+            ON CONFLICT (link_id, day) DO UPDATE
+            SET clicks = EXCLUDED.clicks,
+                unique_visitors = EXCLUDED.unique_visitors,
+                prefetches = EXCLUDED.prefetches
+        "#,
+        link_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Rolls up the current and the previous UTC day every `ROLLUP_INTERVAL_SECS`, so clicks written
+/// late around midnight are included.
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let today = Utc::now().date_naive();
+            let yesterday = today.pred_opt().unwrap_or(today);
+            if let Err(err) = roll_up(&pool, yesterday, today).await {
+                tracing::error!("Rolling up clicks failed: {}", err);
+            }
+            tokio::time::sleep(config.current().statistics.rollup_interval).await;
+        }
+    });
+}
@@ -1,204 +1,1822 @@
 use axum::{
     body::Body,
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query, RawQuery, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
-use base64::{engine::general_purpose, Engine};
-use rand::Rng;
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use url::Url;
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 
-use crate::utils::internal_error;
-
-const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
-    "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300";
+use crate::{
+    archive, audit,
+    auth::{Actor, Workspace},
+    cdn,
+    click::{self, is_sampled, ClickSampler, ClientHints, StatisticsRange, VisitorHasher},
+    click_writer::{Click, ClickWriter},
+    client_ip::ClientIp,
+    config::{Config, SharedConfig},
+    conversion,
+    db::CircuitBreaker,
+    expiry::gone_response,
+    health_monitor::check_target,
+    history::record_target_change,
+    hotlink::{self, MAX_ALLOWED_REFERERS},
+    id::{missing_link, IdGenerator, SharedIdGenerator},
+    lifecycle::Readiness,
+    link_cache::{self, CachedLink, LinkCache},
+    logging, outbox,
+    prefetch::PrefetchDetector,
+    rate_limit::{throttled_response, LinkThrottle, RateLimiter},
+    response_headers, rollout, rollup,
+    signed::{self, SignedLinkError, SignedTarget},
+    slug::{check_custom_slug, generate_slug},
+    target::{chained_slug, parse_target_url, serialize_display_url},
+    title::MAX_TITLE_LENGTH,
+    unfurl::{self, PreviewCache},
+    utils::{base_url, database_unavailable, escape_html, internal_error, short_url},
+    utm,
+    validation::{ApiError, FieldError, ValidJson},
+    webhook::{LinkEvent, LinkEventKind},
+};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Link {
     pub id: String,
+    #[serde(serialize_with = "serialize_display_url")]
     pub target_url: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct LinkDetails {
+    pub id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub title: Option<String>,
+    pub notes: Option<String>,
+    /// Who created the link; missing for links older than this field.
+    pub created_by: Option<String>,
+    pub campaign_ids: Vec<String>,
+    pub tags: Vec<String>,
+    pub active: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub redirect_type: i32,
+    pub privacy_mode: bool,
+    pub track_clicks: bool,
+    pub sample_rate: i32,
+    /// Redirects per second the link serves; unlimited when missing.
+    pub rate_limit: Option<i32>,
+    /// Domains visitors must come from to be redirected; anyone when empty.
+    pub allowed_referers: Vec<String>,
+    /// Where visitors from other sites are sent instead.
+    pub referer_fallback_url: Option<String>,
+    /// Extra headers sent with the link's redirects.
+    #[serde(serialize_with = "response_headers::serialize")]
+    pub response_headers: Vec<String>,
+    /// How long redirects may be cached, one of `CACHE_TIERS`; the default when missing.
+    pub cache_tier: Option<String>,
+    /// The `utm_*` parameters of every redirect, replacing the destination's own; missing when
+    /// those are left alone.
+    #[serde(serialize_with = "utm::serialize")]
+    pub utm_template: Option<Vec<String>>,
+    /// Whether the statistics are shared under a public stats URL.
+    pub public_stats: bool,
+    pub click_milestones: Vec<i64>,
+    pub total_clicks: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct LinkTarget {
     pub target_url: String,
+    /// Request the target before creating the link, rejecting it when it cannot be reached.
+    #[serde(default)]
+    pub validate: bool,
+}
+
+/// Where a link and the resources around it are found, so clients don't have to piece the URLs
+/// together from the id.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkUrls {
+    pub short_url: String,
+    pub statistics_url: String,
+    pub qr_url: String,
+}
+
+impl LinkUrls {
+    pub fn new(config: &Config, headers: &HeaderMap, id: &str) -> Self {
+        let short_url = short_url(config, headers, id);
+        Self {
+            statistics_url: format!("{short_url}/statistics"),
+            qr_url: format!("{short_url}/qr"),
+            short_url,
+        }
+    }
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedLink {
+    #[serde(flatten)]
+    pub link: Link,
+    #[serde(flatten)]
+    pub urls: LinkUrls,
+    /// What the target answered, when the link was created with `validate`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_status: Option<u16>,
+}
+
+#[derive(Serialize)]
+pub struct UpdatedLink {
+    #[serde(flatten)]
+    pub link: LinkDetails,
+    #[serde(flatten)]
+    pub urls: LinkUrls,
+}
+
+/// `201 Created` with the short URL as `Location`.
+fn created<T: Serialize>(short_url: String, body: T) -> Response {
+    (
+        StatusCode::CREATED,
+        [(header::LOCATION, short_url)],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// Fields changed by `PATCH /:id`; missing fields are left alone. `expiresAt: null` removes the
+/// expiry.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LinkUpdate {
+    pub target_url: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub tags: Option<Vec<String>>,
+    pub redirect_type: Option<i32>,
+    pub active: Option<bool>,
+    pub privacy_mode: Option<bool>,
+    pub track_clicks: Option<bool>,
+    pub sample_rate: Option<i32>,
+    pub click_milestones: Option<Vec<i64>>,
+    /// `null` removes the title.
+    #[serde(default, deserialize_with = "present")]
+    pub title: Option<Option<String>>,
+    /// `null` removes the limit.
+    #[serde(default, deserialize_with = "present")]
+    pub rate_limit: Option<Option<i32>>,
+    pub allowed_referers: Option<Vec<String>>,
+    /// `null` removes the fallback.
+    #[serde(default, deserialize_with = "present")]
+    pub referer_fallback_url: Option<Option<String>>,
+    /// Replaces every extra header; `{}` removes them.
+    pub response_headers: Option<BTreeMap<String, String>>,
+    /// `null` removes the notes.
+    #[serde(default, deserialize_with = "present")]
+    pub notes: Option<Option<String>>,
+    /// `null` goes back to the default.
+    #[serde(default, deserialize_with = "present")]
+    pub cache_tier: Option<Option<String>>,
+    /// Replaces the destination's `utm_*` parameters; `{}` removes them and `null` leaves them
+    /// alone.
+    #[serde(default, deserialize_with = "present")]
+    pub utm_template: Option<Option<BTreeMap<String, String>>>,
+}
+
+/// Tells an explicit `null` apart from a missing field.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Full definition of a link for `PUT /api/links/:id`; missing optional fields get their
+/// defaults.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LinkDefinition {
+    pub target_url: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_redirect_type")]
+    pub redirect_type: i32,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default)]
+    pub privacy_mode: bool,
+    #[serde(default = "default_track_clicks")]
+    pub track_clicks: bool,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: i32,
+    #[serde(default)]
+    pub click_milestones: Vec<i64>,
+    pub title: Option<String>,
+    pub rate_limit: Option<i32>,
+    #[serde(default)]
+    pub allowed_referers: Vec<String>,
+    pub referer_fallback_url: Option<String>,
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    pub notes: Option<String>,
+    pub cache_tier: Option<String>,
+    pub utm_template: Option<BTreeMap<String, String>>,
+}
+
+fn default_redirect_type() -> i32 {
+    307
+}
+
+fn default_active() -> bool {
+    true
+}
+
+fn default_track_clicks() -> bool {
+    true
+}
+
+fn default_sample_rate() -> i32 {
+    1
+}
+
+impl From<LinkDefinition> for LinkUpdate {
+    fn from(definition: LinkDefinition) -> Self {
+        Self {
+            target_url: Some(definition.target_url),
+            expires_at: Some(definition.expires_at),
+            tags: Some(definition.tags),
+            redirect_type: Some(definition.redirect_type),
+            active: Some(definition.active),
+            privacy_mode: Some(definition.privacy_mode),
+            track_clicks: Some(definition.track_clicks),
+            sample_rate: Some(definition.sample_rate),
+            click_milestones: Some(definition.click_milestones),
+            title: Some(definition.title),
+            rate_limit: Some(definition.rate_limit),
+            allowed_referers: Some(definition.allowed_referers),
+            referer_fallback_url: Some(definition.referer_fallback_url),
+            response_headers: Some(definition.response_headers),
+            notes: Some(definition.notes),
+            cache_tier: Some(definition.cache_tier),
+            utm_template: Some(definition.utm_template),
+        }
+    }
+}
+
+const REDIRECT_TYPES: [i32; 4] = [301, 302, 307, 308];
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 50;
+const MAX_CLICK_MILESTONES: usize = 20;
+const MAX_NOTES_LENGTH: usize = 2000;
+/// Generated slugs tried before creating a link fails as taken.
+const MAX_INSERT_ATTEMPTS: usize = 3;
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CountedLinkStatistics {
     pub amount: Option<i64>,
+    /// Hits by email scanners and prefetching browsers, left out of `amount`.
+    pub prefetches: i64,
     pub referer: Option<String>,
     pub user_agent: Option<String>,
 }
 
-fn generate_id() -> String {
-    let random_number: u32 = rand::thread_rng().gen_range(0..u32::MAX);
-    general_purpose::URL_SAFE_NO_PAD.encode(random_number.to_string())
-}
-
-pub async fn health_check() -> impl IntoResponse {
+/// Fails once shutdown has begun, so the instance is taken out of rotation before it stops.
+pub async fn health_check(State(readiness): State<Arc<Readiness>>) -> impl IntoResponse {
+    if !readiness.is_ready() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Service is shutting down");
+    }
     (StatusCode::OK, "Service is healthy")
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn redirect(
     State(pool): State<PgPool>,
-    Path(requested_link): Path<String>,
+    State(config): State<SharedConfig>,
+    State(breaker): State<Arc<CircuitBreaker>>,
+    State(limiter): State<Arc<RateLimiter>>,
+    State(visitors): State<Arc<VisitorHasher>>,
+    State(sampler): State<Arc<ClickSampler>>,
+    State(clicks): State<Arc<ClickWriter>>,
+    State(prefetches): State<Arc<PrefetchDetector>>,
+    State(throttle): State<Arc<LinkThrottle>>,
+    State(cache): State<Arc<LinkCache>>,
+    State(previews): State<Arc<PreviewCache>>,
+    ClientIp(client): ClientIp,
+    Path(segments): Path<Vec<String>>,
+    RawQuery(query): RawQuery,
+    method: Method,
     headers: HeaderMap,
 ) -> Result<Response, (StatusCode, String)> {
-    let select_timeout = tokio::time::Duration::from_millis(300);
-    let link = tokio::time::timeout(
-        select_timeout,
-        sqlx::query_as!(
-            Link,
-            "SELECT id, target_url FROM links WHERE id = $1",
+    let config = config.current();
+    // `/eng/launch` is the link `eng/launch` of a namespace.
+    let mut requested_link = segments.join("/");
+    if config.honeypot_slugs.contains(&requested_link) {
+        tracing::warn!(
+            "Client {} requested honeypot slug {}",
+            client,
             requested_link
-        )
-        .fetch_optional(&pool),
-    )
-    .await
-    .map_err(internal_error)?
-    .map_err(internal_error)?
-    .ok_or_else(|| "Not Found".to_string())
-    .map_err(|err| (StatusCode::NOT_FOUND, err))?;
-
-    tracing::debug!(
-        "Redirecting link id {} to {}",
-        requested_link,
-        link.target_url
-    );
+        );
+        counter!("honeypot_hits").increment(1);
+        if let Some(ban_duration) = config.honeypot_ban_duration {
+            limiter.ban(client, ban_duration);
+        }
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    if signed::is_signed(&requested_link) {
+        let Some(key) = config.signed_links.key.as_deref() else {
+            return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+        };
+        match signed::verify(key, &requested_link) {
+            Ok(SignedTarget::Url(target_url)) => {
+                counter!("signed_link_redirects").increment(1);
+                return Ok(redirect_response(
+                    target_url,
+                    StatusCode::TEMPORARY_REDIRECT,
+                    &config,
+                ));
+            }
+            Ok(SignedTarget::Link(link_id)) => requested_link = link_id,
+            Err(SignedLinkError::Expired) => return Ok(gone_response()),
+            Err(SignedLinkError::Invalid) => {
+                return Err((StatusCode::NOT_FOUND, "Not Found".into()))
+            }
+        }
+    }
+    // Clicks of visitors asking not to be tracked are counted without anything about them.
+    let track = !(config.honor_do_not_track && requests_no_tracking(&headers));
     let referer_header = headers
         .get("referer")
+        .filter(|_| track)
         .map(|v| v.to_str().unwrap_or_default().to_string());
     let user_agent_header = headers
         .get("user-agent")
+        .filter(|_| track)
         .map(|v| v.to_str().unwrap_or_default().to_string());
+    let hints = if track {
+        ClientHints::from_headers(&headers)
+    } else {
+        ClientHints::default()
+    };
+    let sample_rate = sampler.sample_rate(&requested_link, &config.click_sampling);
+    // Checked against allowlists even when the referer is not recorded.
+    let referer_host = hotlink::referer_host(&headers);
 
-    let statistic_duration = tokio::time::Duration::from_millis(300);
-    let saved_statistics = tokio::time::timeout(
-        statistic_duration,
-        sqlx::query(
-            r#"
-                INSERT INTO link_statistics(link_id, referer, user_agent) 
-                VALUES ($1, $2, $3)
-            "#,
+    if !throttle.try_acquire(&requested_link) {
+        return Ok(throttled_response());
+    }
+    // Hot links are served from the cache without touching the database, their clicks and
+    // counters are written with the next batch.
+    let Some(link) = lookup_link(&pool, &cache, &breaker, &config, &requested_link).await? else {
+        return missing_link_response(&pool, &requested_link, &config).await;
+    };
+    throttle.set_limit(&link.id, link.rate_limit);
+    // Workspaces opted out of geo enrichment have nothing derived from their visitors' addresses.
+    let visitor_address = link.geo_enrichment.then_some(client);
+    let visitor_hash = visitor_address
+        .filter(|_| track)
+        .map(|client| visitors.hash(client, user_agent_header.as_deref()));
+    let prefetch = prefetches.is_prefetch(
+        &method,
+        visitor_address,
+        &requested_link,
+        &headers,
+        &config.prefetch,
+    );
+    // Visitors turned away by the referer allowlist are not counted.
+    if !hotlink::is_allowed(referer_host.as_deref(), &link.allowed_referers) {
+        return Ok(hotlink::blocked_response(link.referer_fallback_url.clone()));
+    }
+    // Chat apps unfurling the link get a preview of it instead, which is no click.
+    if config.unfurl.enabled && unfurl::is_unfurl_bot(&headers, &config.unfurl) {
+        let (target_url, _) = resolve_chain(
+            &pool,
+            &cache,
+            &breaker,
+            &config,
+            &headers,
+            &link,
+            &link.target_url,
         )
-        .bind(&requested_link)
-        .bind(&referer_header)
-        .bind(&user_agent_header)
-        .execute(&pool),
-    )
-    .await;
+        .await?;
+        let preview = previews.preview(&target_url, &config).await;
+        counter!("unfurls").increment(1);
+        return Ok(unfurl::page(
+            &short_url(&config, &headers, &link.id),
+            &target_url,
+            link.title.as_deref(),
+            &preview,
+        ));
+    }
+    // Scanner and prefetch hits are recorded apart from clicks, and not counted as ones.
+    if prefetch {
+        counter!("prefetch_hits").increment(1);
+    } else if !config.read_only {
+        clicks.count(&link.id);
+    }
+    let (entry_target, variant) = rollout::pick_target(&link);
+    let weight = link.sample_rate.max(sample_rate);
+    let mut click_id = None;
+    if link.track_clicks
+        && !(config.privacy_mode || link.privacy_mode || config.read_only)
+        && is_sampled(weight)
+    {
+        // Only clicks that are recorded get an ID, nothing could be joined to the others.
+        click_id =
+            (track && !prefetch && config.click_id_param.is_some()).then(click::new_click_id);
+        clicks.record(
+            Click {
+                link_id: link.id.clone(),
+                referer: referer_header.clone(),
+                user_agent: user_agent_header.clone(),
+                visitor_hash,
+                weight,
+                hints,
+                channel: click::channel(query.as_deref()),
+                prefetch,
+                variant: variant.map(|variant| variant.as_str().to_string()),
+                click_id: click_id.clone(),
+                created_at: Utc::now(),
+            },
+            &config.click_writer,
+        );
+    }
 
-    match saved_statistics {
-        Err(elasped) => tracing::error!("Saving new link click resulted in a timeout: {}", elasped),
-        Ok(Err(err)) => tracing::error!(
-            "Saving a new link click failed with the following error: {}",
-            err
-        ),
-        _ => tracing::debug!(
-            "Persisted new link click for link with id {}, referer {}, and user agent {}",
+    let (target_url, chained) = resolve_chain(
+        &pool,
+        &cache,
+        &breaker,
+        &config,
+        &headers,
+        &link,
+        entry_target,
+    )
+    .await?;
+    // The link's UTM template wins over parameters a partner tagged the destination with.
+    let target_url = match &link.utm_template {
+        Some(template) => utm::apply(&target_url, template),
+        None => target_url,
+    };
+    if logging::is_sampled(config.logging.trace_sample_rate) {
+        tracing::info!(
+            "Redirecting link id {} to {} with referer {} and user agent {}",
             requested_link,
+            target_url,
             referer_header.unwrap_or_default(),
             user_agent_header.unwrap_or_default()
-        ),
+        );
+    }
+    // Each visitor gets a click token of their own to report conversions with.
+    let tagged_target =
+        (track && !prefetch && link.track_clicks && !(config.privacy_mode || link.privacy_mode))
+            .then(|| conversion::tag_target(&target_url, &link.id, &config.conversions))
+            .flatten();
+    let personal = tagged_target.is_some() || click_id.is_some();
+    let mut target_url = tagged_target.unwrap_or(target_url);
+    if let (Some(param), Some(click_id)) = (&config.click_id_param, &click_id) {
+        target_url = click::tag_target(&target_url, param, click_id);
+    }
+    let mut response = redirect_response(target_url, redirect_status(link.redirect_type), &config);
+    if let Some(cache_control) = link
+        .cache_tier
+        .as_ref()
+        .and_then(|tier| config.cache_tiers.get(tier))
+        .and_then(|cache_control| HeaderValue::from_str(cache_control).ok())
+    {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, cache_control);
+    }
+    response_headers::apply(&mut response, &link.response_headers);
+    if !link.allowed_referers.is_empty() || personal || variant.is_some() {
+        // Shared caches must not hand the redirect to visitors from other sites, one visitor's
+        // click token or click ID to everyone, or one side of a rollout to all visitors.
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-store"),
+        );
+        response_headers::remove_cdn_directives(headers);
+    } else {
+        let links = std::iter::once(&link).chain(&chained);
+        cdn::add_surrogate_keys(
+            &mut response,
+            &config.cdn,
+            links.map(|link| (link.id.as_str(), link.workspace_id.as_str())),
+        );
+    }
+    Ok(response)
+}
+
+/// Counts how redirects were answered: visitors sent on, either by a redirect or the
+/// interstitial page, slugs not found and expired links. Previews served to unfurl bots are
+/// counted by the handler as `unfurls`. Other methods on the route pass through.
+pub async fn count_redirects(req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.run(req).await;
+    }
+    let response = next.run(req).await;
+    if response.extensions().get::<unfurl::Unfurled>().is_some() {
+        return response;
+    }
+    match response.status() {
+        status if status.is_redirection() || status == StatusCode::OK => {
+            counter!("redirect_hits").increment(1)
+        }
+        StatusCode::NOT_FOUND => counter!("redirect_not_found").increment(1),
+        StatusCode::GONE => counter!("redirect_expired").increment(1),
+        _ => {}
+    }
+    response
+}
+
+/// The active link `id`, from the cache or else the database. In maintenance mode serving from
+/// the cache only, a link not in the cache answers 503.
+async fn lookup_link(
+    pool: &PgPool,
+    cache: &LinkCache,
+    breaker: &CircuitBreaker,
+    config: &Config,
+    id: &str,
+) -> Result<Option<Arc<CachedLink>>, (StatusCode, String)> {
+    if let Some(link) = cache.get(id, &config.link_cache) {
+        return Ok(Some(link));
+    }
+    if config.maintenance.enabled && config.maintenance.cache_only {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Service Under Maintenance".into(),
+        ));
+    }
+    breaker.try_acquire().map_err(database_unavailable)?;
+    let generation = cache.generation();
+    let lookup = tokio::time::timeout(
+        tokio::time::Duration::from_millis(300),
+        config
+            .db_retry
+            .run("redirect", || link_cache::fetch(pool, id)),
+    )
+    .await;
+    breaker.record(&lookup);
+    Ok(lookup
+        .map_err(internal_error)?
+        .map_err(internal_error)?
+        .map(|link| cache.insert(link, generation, &config.link_cache)))
+}
+
+/// Follows a link whose target is another short link on this instance, up to
+/// `LINK_CHAIN_MAX_DEPTH` links further, so the visitor is sent to the end of the chain in a
+/// single redirect, starting from `target_url`, the target `link` was picked to redirect to.
+/// Returns the final target and the links followed after `link`. The chain ends
+/// early, as a plain redirect to the next short link, at a link that is not active or only
+/// redirects visitors from allowed referers. Chains coming back to a link they passed, or longer
+/// than allowed, answer 508.
+async fn resolve_chain(
+    pool: &PgPool,
+    cache: &LinkCache,
+    breaker: &CircuitBreaker,
+    config: &Config,
+    headers: &HeaderMap,
+    link: &Arc<CachedLink>,
+    target_url: &str,
+) -> Result<(String, Vec<Arc<CachedLink>>), (StatusCode, String)> {
+    let mut target_url = target_url.to_string();
+    let mut chained: Vec<Arc<CachedLink>> = Vec::new();
+    if config.link_chain_max_depth == 0 {
+        return Ok((target_url, chained));
+    }
+    let base_url = base_url(config, headers);
+    while let Some(slug) = chained_slug(&target_url, &base_url).map(str::to_string) {
+        if slug == link.id || chained.iter().any(|next| next.id == slug) {
+            tracing::warn!("Link {} chains back to {}", link.id, slug);
+            counter!("link_chain_loops").increment(1);
+            return Err((StatusCode::LOOP_DETECTED, "Link Chain Loops".into()));
+        }
+        if chained.len() >= config.link_chain_max_depth {
+            tracing::warn!(
+                "Link {} chains through more than {} links",
+                link.id,
+                config.link_chain_max_depth
+            );
+            counter!("link_chain_too_long").increment(1);
+            return Err((StatusCode::LOOP_DETECTED, "Link Chain Too Long".into()));
+        }
+        let Some(next) = lookup_link(pool, cache, breaker, config, &slug).await? else {
+            break;
+        };
+        if !next.allowed_referers.is_empty() {
+            break;
+        }
+        target_url = next.target_url.clone();
+        chained.push(next);
+    }
+    Ok((target_url, chained))
+}
+
+/// Answers for a slug that is not an active link: the redirect of an archived link, 410 for an
+/// expired link, a redirect to `PAUSED_LINK_URL` for a paused one and 404 otherwise. Only misses
+/// pay for these lookups.
+async fn missing_link_response(
+    pool: &PgPool,
+    id: &str,
+    config: &Config,
+) -> Result<Response, (StatusCode, String)> {
+    let lookup_timeout = tokio::time::Duration::from_millis(300);
+    let archived = tokio::time::timeout(lookup_timeout, archive::archived_target(pool, id))
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?;
+    if let Some(archived) = archived {
+        let mut response = redirect_response(
+            archived.target_url,
+            redirect_status(archived.redirect_type),
+            config,
+        );
+        cdn::add_surrogate_keys(
+            &mut response,
+            &config.cdn,
+            [(id, archived.workspace_id.as_str())],
+        );
+        return Ok(response);
+    }
+    let link = tokio::time::timeout(
+        lookup_timeout,
+        sqlx::query!(
+            r#"SELECT COALESCE(expires_at <= now(), false) AS "expired!", active FROM links WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    match (link, &config.paused_link_url) {
+        (Some(link), _) if link.expired => Ok(gone_response()),
+        (Some(link), Some(paused_link_url)) if !link.active => {
+            let mut response = redirect_response(
+                paused_link_url.clone(),
+                StatusCode::TEMPORARY_REDIRECT,
+                config,
+            );
+            // The link may be resumed any moment.
+            response
+                .headers_mut()
+                .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            Ok(response)
+        }
+        _ => Err((StatusCode::NOT_FOUND, "Not Found".into())),
+    }
+}
+
+fn redirect_status(redirect_type: i32) -> StatusCode {
+    u16::try_from(redirect_type)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::TEMPORARY_REDIRECT)
+}
+
+/// `DNT: 1` or `Sec-GPC: 1`.
+fn requests_no_tracking(headers: &HeaderMap) -> bool {
+    ["dnt", "sec-gpc"]
+        .iter()
+        .any(|name| headers.get(*name).is_some_and(|value| value == "1"))
+}
+
+/// Whether redirects come with an HTML page, from `REDIRECT_PAGE`. The page refreshes to the
+/// target and links to it, for clients that don't follow redirects themselves, like some in-app
+/// browsers and email link scanners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectPage {
+    /// Bare redirects.
+    None,
+    /// The page as the body of the redirect.
+    Body,
+    /// The page on its own, answered with 200 and refreshing after
+    /// `REDIRECT_INTERSTITIAL_DELAY_SECS`.
+    Interstitial,
+}
+
+impl FromStr for RedirectPage {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "none" => Ok(Self::None),
+            "body" => Ok(Self::Body),
+            "interstitial" => Ok(Self::Interstitial),
+            _ => Err("expected none, body or interstitial".to_string()),
+        }
+    }
+}
+
+fn redirect_page(target_url: &str, delay_secs: u32) -> String {
+    let target_url = escape_html(target_url);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"{delay_secs}; url={target_url}\"><title>Redirecting</title></head>\n<body>\n<p>Redirecting to <a href=\"{target_url}\">{target_url}</a></p>\n</body>\n</html>\n"
+    )
+}
+
+fn redirect_response(target_url: String, status: StatusCode, config: &Config) -> Response {
+    let response = Response::builder().header("Cache-Control", &config.redirect_cache_control);
+    match config.redirect_page {
+        RedirectPage::None => response
+            .status(status)
+            .header("Location", target_url)
+            .body(Body::empty()),
+        RedirectPage::Body => response
+            .status(status)
+            .header("Location", &target_url)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(redirect_page(&target_url, 0))),
+        RedirectPage::Interstitial => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(redirect_page(
+                &target_url,
+                config.interstitial_delay_secs,
+            ))),
     }
-    Ok(Response::builder()
-        .status(StatusCode::TEMPORARY_REDIRECT)
-        .header("Location", link.target_url)
-        .header("Cache-Control", DEFAULT_CACHE_CONTROL_HEADER_VALUE)
-        .body(Body::empty())
-        .expect("This response should always be constructable"))
+    .expect("This response should always be constructable")
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_link(
     State(pool): State<PgPool>,
-    Json(new_link): Json<LinkTarget>,
-) -> Result<Json<Link>, (StatusCode, String)> {
-    let url: String = Url::parse(&new_link.target_url)
-        .map_err(|_| (StatusCode::CONFLICT, "Url Malformed".into()))?
-        .to_string();
-    let new_link_id = generate_id();
+    State(config): State<SharedConfig>,
+    State(breaker): State<Arc<CircuitBreaker>>,
+    State(ids): State<SharedIdGenerator>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    headers: HeaderMap,
+    ValidJson(new_link): ValidJson<LinkTarget>,
+) -> Result<Response, ApiError> {
+    let config = config.current();
+    let url = match parse_target_url(&new_link.target_url, &config) {
+        Ok(url) => url,
+        Err(err) => {
+            let submitted = json!({ "targetUrl": new_link.target_url });
+            let err = err.on_field("targetUrl");
+            return Err(audit::record_refused_targets(&pool, &actor, None, &submitted, err).await);
+        }
+    };
+    let mut target_status = None;
+    if new_link.validate {
+        let outcome =
+            check_target(url.as_str(), config.target_check_timeout, &config.outbound).await;
+        if let Some(error) = outcome.error {
+            tracing::debug!("Rejected unreachable target url {}: {}", url, error);
+            return Err(ApiError::Fields(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                vec![FieldError::new(
+                    "targetUrl",
+                    format!("Target Unreachable: {error}"),
+                )],
+            ));
+        }
+        target_status = outcome.status_code;
+    }
+    let link = insert_link(
+        &pool,
+        &config,
+        &breaker,
+        ids.as_ref(),
+        &workspace,
+        &actor,
+        &new_link.target_url,
+    )
+    .await?;
+    let urls = LinkUrls::new(&config, &headers, &link.id);
+    Ok(created(
+        urls.short_url.clone(),
+        CreatedLink {
+            link,
+            urls,
+            target_status,
+        },
+    ))
+}
+
+/// Validates `target_url` and stores a new link for it on behalf of `actor`. Shared by every
+/// endpoint that creates links. A generated slug that is taken, by a link or an archived one, is
+/// replaced by a fresh one; only when every attempt collides is the link refused as taken.
+pub async fn insert_link(
+    pool: &PgPool,
+    config: &Config,
+    breaker: &CircuitBreaker,
+    ids: &dyn IdGenerator,
+    workspace: &str,
+    actor: &str,
+    target_url: &str,
+) -> Result<Link, (StatusCode, String)> {
+    let url: String = parse_target_url(target_url, config)?.to_string();
     let insert_link_timeout = tokio::time::Duration::from_millis(300);
-    let new_link = tokio::time::timeout(
-        insert_link_timeout,
+    for attempt in 1..=MAX_INSERT_ATTEMPTS {
+        breaker.try_acquire().map_err(database_unavailable)?;
+        let new_link_id = generate_slug(ids, config);
+        let inserted_link = tokio::time::timeout(
+            insert_link_timeout,
+            config.db_retry.run("create_link", || async {
+                let mut tx = pool.begin().await?;
+                let Some(new_link) = sqlx::query_as!(
+                    Link,
+                    r#"
+                    INSERT INTO links (id, target_url, workspace_id, created_by)
+                    SELECT $1, $2, $3, $4
+                    WHERE NOT EXISTS (SELECT 1 FROM archived_links WHERE id = $1)
+                    ON CONFLICT (id) DO NOTHING
+                    RETURNING id, target_url
+                    "#,
+                    &new_link_id,
+                    &url,
+                    workspace,
+                    actor
+                )
+                .fetch_optional(&mut *tx)
+                .await?
+                else {
+                    return Ok(None);
+                };
+                let event = LinkEvent::new(
+                    LinkEventKind::Created,
+                    workspace.to_string(),
+                    new_link.clone(),
+                );
+                outbox::enqueue(&mut tx, &event).await?;
+                tx.commit().await?;
+                Ok(Some(new_link))
+            }),
+        )
+        .await;
+        breaker.record(&inserted_link);
+        let inserted_link = inserted_link
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+        if let Some(new_link) = inserted_link {
+            tracing::debug!("Created new link with id {} targeting {}", new_link_id, url);
+            return Ok(new_link);
+        }
+        counter!("slug_collisions").increment(1);
+        tracing::warn!(
+            "Generated slug {} is taken, attempt {} of {}",
+            new_link_id,
+            attempt,
+            MAX_INSERT_ATTEMPTS
+        );
+    }
+    Err((StatusCode::CONFLICT, "Slug Taken".to_string()))
+}
+
+/// Answers 404 unless a link with this id exists, for endpoints whose own query can't tell a
+/// missing link from one without data.
+pub async fn ensure_link_exists(pool: &PgPool, id: &str) -> Result<(), (StatusCode, String)> {
+    let fetch_link_timeout = tokio::time::Duration::from_millis(300);
+    let exists = tokio::time::timeout(
+        fetch_link_timeout,
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM links WHERE id = $1) AS "exists!""#,
+            id
+        )
+        .fetch_one(pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    Ok(())
+}
+
+/// Like [`ensure_link_exists`], but a link of another workspace is missing as well.
+pub async fn ensure_workspace_link_exists(
+    pool: &PgPool,
+    id: &str,
+    workspace: &str,
+) -> Result<(), (StatusCode, String)> {
+    let fetch_link_timeout = tokio::time::Duration::from_millis(300);
+    let exists = tokio::time::timeout(
+        fetch_link_timeout,
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM links WHERE id = $1 AND workspace_id = $2) AS "exists!""#,
+            id,
+            workspace
+        )
+        .fetch_one(pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    Ok(())
+}
+
+pub async fn fetch_link_details<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+) -> Result<Option<LinkDetails>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkDetails,
+        r#"
+            SELECT
+                l.id,
+                l.target_url,
+                l.title,
+                l.notes,
+                l.created_by,
+                COALESCE(
+                    array_agg(cl.campaign_id ORDER BY cl.campaign_id)
+                        FILTER (WHERE cl.campaign_id IS NOT NULL),
+                    '{}'
+                ) AS "campaign_ids!",
+                l.tags,
+                l.active,
+                l.expires_at,
+                l.redirect_type,
+                l.privacy_mode,
+                l.track_clicks,
+                l.sample_rate,
+                l.rate_limit,
+                l.allowed_referers,
+                l.referer_fallback_url,
+                l.response_headers,
+                l.cache_tier,
+                l.utm_template,
+                l.stats_token IS NOT NULL AS "public_stats!",
+                l.click_milestones,
+                l.click_count AS total_clicks,
+                l.created_at,
+                l.updated_at
+            FROM links l
+            LEFT JOIN campaign_links cl ON cl.link_id = l.id
+            WHERE l.id = $1
+            GROUP BY l.id
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn get_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<Json<LinkDetails>, (StatusCode, String)> {
+    let fetch_link_timeout = tokio::time::Duration::from_millis(300);
+    let link = tokio::time::timeout(fetch_link_timeout, async {
+        // Links of other workspaces are answered like missing ones.
+        let owned = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM links WHERE id = $1 AND workspace_id = $2) AS "owned!""#,
+            &id,
+            &workspace
+        )
+        .fetch_one(&pool)
+        .await?;
+        if !owned {
+            return Ok(None);
+        }
+        fetch_link_details(&pool, &id).await
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| missing_link(&id, &config.current()))?;
+    tracing::debug!("Details for link with id {} requested", id);
+    Ok(Json(link))
+}
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+/// Filters of `GET /api/links`; all of them must match.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkFilter {
+    pub created_by: Option<String>,
+    pub tag: Option<String>,
+    /// Case-insensitive search in the target, title and notes.
+    pub q: Option<String>,
+    /// Id of the last link of the previous page.
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// The workspace's links ordered by id, a page at a time.
+pub async fn list_links(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Query(filter): Query<LinkFilter>,
+) -> Result<Json<Vec<LinkDetails>>, (StatusCode, String)> {
+    let limit = filter.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if !(1..=MAX_LIST_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIST_LIMIT}"),
+        ));
+    }
+    let pattern = filter.q.as_deref().map(|q| {
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    });
+    let list_links_timeout = tokio::time::Duration::from_millis(300);
+    let links = tokio::time::timeout(
+        list_links_timeout,
         sqlx::query_as!(
-            Link,
+            LinkDetails,
             r#"
-            WITH inserted_link AS (
-                INSERT INTO links (id, target_url)
-                VALUES ($1, $2)
-                RETURNING id, target_url
-            )
-            SELECT id, target_url FROM inserted_link
+                SELECT
+                    l.id,
+                    l.target_url,
+                    l.title,
+                    l.notes,
+                    l.created_by,
+                    COALESCE(
+                        array_agg(cl.campaign_id ORDER BY cl.campaign_id)
+                            FILTER (WHERE cl.campaign_id IS NOT NULL),
+                        '{}'
+                    ) AS "campaign_ids!",
+                    l.tags,
+                    l.active,
+                    l.expires_at,
+                    l.redirect_type,
+                    l.privacy_mode,
+                    l.track_clicks,
+                    l.sample_rate,
+                    l.rate_limit,
+                    l.allowed_referers,
+                    l.referer_fallback_url,
+                    l.response_headers,
+                    l.cache_tier,
+                    l.utm_template,
+                    l.stats_token IS NOT NULL AS "public_stats!",
+                    l.click_milestones,
+                    l.click_count AS total_clicks,
+                    l.created_at,
+                    l.updated_at
+                FROM links l
+                LEFT JOIN campaign_links cl ON cl.link_id = l.id
+                WHERE l.workspace_id = $1
+                    AND ($2::TEXT IS NULL OR l.created_by = $2)
+                    AND ($3::TEXT IS NULL OR $3 = ANY(l.tags))
+                    AND (
+                        $4::TEXT IS NULL
+                        OR l.target_url ILIKE $4
+                        OR l.title ILIKE $4
+                        OR l.notes ILIKE $4
+                    )
+                    AND ($5::TEXT IS NULL OR l.id > $5)
+                GROUP BY l.id
+                ORDER BY l.id
+                LIMIT $6
             "#,
-            &new_link_id,
-            &url
+            &workspace,
+            filter.created_by.as_deref(),
+            filter.tag.as_deref(),
+            pattern.as_deref(),
+            filter.after.as_deref(),
+            limit
         )
-        .fetch_one(&pool),
+        .fetch_all(&pool),
     )
     .await
     .map_err(internal_error)?
     .map_err(internal_error)?;
-    tracing::debug!("Created new link with id {} targeting {}", new_link_id, url);
-    Ok(Json(new_link))
+    tracing::debug!("Listed {} links of workspace {}", links.len(), workspace);
+    Ok(Json(links))
+}
+
+pub async fn clone_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(ids): State<SharedIdGenerator>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let config = config.current();
+    let clone_link_timeout = tokio::time::Duration::from_millis(300);
+    let mut cloned = None;
+    for attempt in 1..=MAX_INSERT_ATTEMPTS {
+        let new_link_id = generate_slug(ids.as_ref(), &config);
+        // `None` when the workspace has no such link, `Some(None)` when the slug is taken.
+        let cloned_link = tokio::time::timeout(clone_link_timeout, async {
+            let mut tx = pool.begin().await?;
+            let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM links WHERE id = $1 AND workspace_id = $2) AS "exists!""#,
+                &id,
+                &workspace
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if !exists {
+                return Ok(None);
+            }
+            let Some(cloned_link) = sqlx::query_as!(
+                Link,
+                r#"
+                INSERT INTO links (id, target_url, workspace_id, tags, expires_at, redirect_type, privacy_mode, track_clicks, sample_rate, click_milestones, title, title_checked_at, rate_limit, allowed_referers, referer_fallback_url, response_headers, notes, cache_tier, utm_template, created_by)
+                SELECT $1, target_url, workspace_id, tags, expires_at, redirect_type, privacy_mode, track_clicks, sample_rate, click_milestones, title, title_checked_at, rate_limit, allowed_referers, referer_fallback_url, response_headers, notes, cache_tier, utm_template, $3
+                FROM links
+                WHERE id = $2 AND workspace_id = $4
+                    AND NOT EXISTS (SELECT 1 FROM archived_links WHERE id = $1)
+                ON CONFLICT (id) DO NOTHING
+                RETURNING id, target_url
+                "#,
+                &new_link_id,
+                &id,
+                &actor,
+                &workspace
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            else {
+                return Ok(Some(None));
+            };
+            let event = LinkEvent::new(
+                LinkEventKind::Created,
+                workspace.clone(),
+                cloned_link.clone(),
+            );
+            outbox::enqueue(&mut tx, &event).await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(Some(Some(cloned_link)))
+        })
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Not Found".to_string()))?;
+        if cloned_link.is_some() {
+            cloned = cloned_link;
+            break;
+        }
+        counter!("slug_collisions").increment(1);
+        tracing::warn!(
+            "Generated slug {} is taken, attempt {} of {}",
+            new_link_id,
+            attempt,
+            MAX_INSERT_ATTEMPTS
+        );
+    }
+    let cloned_link = cloned.ok_or((StatusCode::CONFLICT, "Slug Taken".to_string()))?;
+    tracing::debug!(
+        "Cloned link with id {} into new link {}",
+        id,
+        cloned_link.id
+    );
+    let urls = LinkUrls::new(&config, &headers, &cloned_link.id);
+    Ok(created(
+        urls.short_url.clone(),
+        CreatedLink {
+            link: cloned_link,
+            urls,
+            target_status: None,
+        },
+    ))
 }
 
+/// Reports an error about a single input field under the field's name, keeping its status.
+fn field_error(field: &'static str) -> impl Fn((StatusCode, String)) -> ApiError {
+    move |(status, reason)| ApiError::Fields(status, vec![FieldError::new(field, reason)])
+}
+
+/// The URLs of an update that may be refused, as submitted, for the audit log.
+pub fn refusable_input(update: &LinkUpdate) -> serde_json::Value {
+    json!({
+        "targetUrl": update.target_url,
+        "refererFallbackUrl": update.referer_fallback_url,
+    })
+}
+
+/// Checks every field of a partial update and normalizes the referer allowlist and fallback.
+/// Target problems keep their own status codes, other problems are reported together.
+pub fn validate_update(
+    update: &mut LinkUpdate,
+    config: &Config,
+) -> Result<Option<String>, ApiError> {
+    let target_url = update
+        .target_url
+        .as_deref()
+        .map(|target_url| parse_target_url(target_url, config).map(|url| url.to_string()))
+        .transpose()
+        .map_err(|err| err.on_field("targetUrl"))?;
+
+    let mut problems = Vec::new();
+    if let Some(Some(expires_at)) = update.expires_at {
+        if expires_at <= Utc::now() {
+            problems.push(FieldError::new("expiresAt", "must be in the future"));
+        }
+    }
+    if let Some(tags) = &update.tags {
+        if tags.len() > MAX_TAGS {
+            problems.push(FieldError::new(
+                "tags",
+                format!("can hold at most {MAX_TAGS} entries"),
+            ));
+        }
+        if tags
+            .iter()
+            .any(|tag| tag.trim().is_empty() || tag.len() > MAX_TAG_LENGTH)
+        {
+            problems.push(FieldError::new(
+                "tags",
+                format!("must be non-empty and at most {MAX_TAG_LENGTH} characters"),
+            ));
+        }
+    }
+    if let Some(redirect_type) = update.redirect_type {
+        if !REDIRECT_TYPES.contains(&redirect_type) {
+            problems.push(FieldError::new(
+                "redirectType",
+                "must be one of 301, 302, 307, 308",
+            ));
+        }
+    }
+    if update
+        .sample_rate
+        .is_some_and(|sample_rate| sample_rate < 1)
+    {
+        problems.push(FieldError::new("sampleRate", "must be at least 1"));
+    }
+    if let Some(click_milestones) = &update.click_milestones {
+        if click_milestones.len() > MAX_CLICK_MILESTONES
+            || click_milestones.iter().any(|milestone| *milestone < 1)
+        {
+            problems.push(FieldError::new(
+                "clickMilestones",
+                format!("can hold at most {MAX_CLICK_MILESTONES} positive counts"),
+            ));
+        }
+    }
+    if update
+        .title
+        .as_ref()
+        .and_then(Option::as_ref)
+        .is_some_and(|title| title.trim().is_empty() || title.chars().count() > MAX_TITLE_LENGTH)
+    {
+        problems.push(FieldError::new(
+            "title",
+            format!("must be non-empty and at most {MAX_TITLE_LENGTH} characters"),
+        ));
+    }
+    if update
+        .notes
+        .as_ref()
+        .and_then(Option::as_ref)
+        .is_some_and(|notes| notes.chars().count() > MAX_NOTES_LENGTH)
+    {
+        problems.push(FieldError::new(
+            "notes",
+            format!("must be at most {MAX_NOTES_LENGTH} characters"),
+        ));
+    }
+    if update.rate_limit.flatten().is_some_and(|rate| rate < 1) {
+        problems.push(FieldError::new("rateLimit", "must be at least 1"));
+    }
+    if let Some(allowed_referers) = &mut update.allowed_referers {
+        let normalized: Option<Vec<String>> = allowed_referers
+            .iter()
+            .map(|domain| hotlink::normalize_domain(domain))
+            .collect();
+        match normalized {
+            Some(normalized) if normalized.len() <= MAX_ALLOWED_REFERERS => {
+                *allowed_referers = normalized
+            }
+            _ => problems.push(FieldError::new(
+                "allowedReferers",
+                format!("must be at most {MAX_ALLOWED_REFERERS} valid domains"),
+            )),
+        }
+    }
+    if let Some(Some(fallback_url)) = &mut update.referer_fallback_url {
+        match parse_target_url(fallback_url, config) {
+            Ok(url) => *fallback_url = url.to_string(),
+            Err(err) => problems.push(err.into_field_error("refererFallbackUrl")),
+        }
+    }
+    if let Some(headers) = &mut update.response_headers {
+        match response_headers::normalize(headers) {
+            Ok(normalized) => *headers = normalized,
+            Err(message) => problems.push(FieldError::new("responseHeaders", message)),
+        }
+    }
+    if let Some(Some(tier)) = &update.cache_tier {
+        if !config.cache_tiers.contains_key(tier) {
+            let tiers: Vec<&str> = config.cache_tiers.keys().map(String::as_str).collect();
+            problems.push(FieldError::new(
+                "cacheTier",
+                format!("must be one of {}", tiers.join(", ")),
+            ));
+        }
+    }
+    if let Some(Some(template)) = &mut update.utm_template {
+        match utm::normalize(template) {
+            Ok(normalized) => *template = normalized,
+            Err(message) => problems.push(FieldError::new("utmTemplate", message)),
+        }
+    }
+    if target_url.is_none()
+        && update.expires_at.is_none()
+        && update.tags.is_none()
+        && update.redirect_type.is_none()
+        && update.active.is_none()
+        && update.privacy_mode.is_none()
+        && update.track_clicks.is_none()
+        && update.sample_rate.is_none()
+        && update.click_milestones.is_none()
+        && update.title.is_none()
+        && update.rate_limit.is_none()
+        && update.allowed_referers.is_none()
+        && update.referer_fallback_url.is_none()
+        && update.response_headers.is_none()
+        && update.notes.is_none()
+        && update.cache_tier.is_none()
+        && update.utm_template.is_none()
+    {
+        return Err(ApiError::Status(
+            StatusCode::BAD_REQUEST,
+            "No fields to update".into(),
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(ApiError::Fields(StatusCode::BAD_REQUEST, problems));
+    }
+    Ok(target_url)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn update_link(
     State(pool): State<PgPool>,
     Path(id): Path<String>,
-    Json(update_link): Json<LinkTarget>,
-) -> Result<Json<Link>, (StatusCode, String)> {
-    let url: String = Url::parse(&update_link.target_url)
-        .map_err(|_| (StatusCode::CONFLICT, "Url Malformed".into()))?
-        .to_string();
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    State(cache): State<Arc<LinkCache>>,
+    headers: HeaderMap,
+    ValidJson(mut update): ValidJson<LinkUpdate>,
+) -> Result<Json<UpdatedLink>, ApiError> {
+    let config = config.current();
+    let submitted = refusable_input(&update);
+    let target_url = match validate_update(&mut update, &config) {
+        Ok(target_url) => target_url,
+        Err(err) => {
+            let err = audit::record_refused_targets(&pool, &actor, Some(&id), &submitted, err);
+            return Err(err.await);
+        }
+    };
+    let response_headers = update
+        .response_headers
+        .as_ref()
+        .map(response_headers::to_lines);
+    let utm_template = update
+        .utm_template
+        .as_ref()
+        .map(|template| template.as_ref().map(utm::to_lines));
+    let tags: Option<Vec<String>> = update
+        .tags
+        .map(|tags| tags.iter().map(|tag| tag.trim().to_string()).collect());
     let update_link_timeout = tokio::time::Duration::from_millis(300);
-    let updated_link = tokio::time::timeout(
-        update_link_timeout,
-        sqlx::query_as!(
-            Link,
+    let updated_link = tokio::time::timeout(update_link_timeout, async {
+        let mut tx = pool.begin().await?;
+        let Some(previous) = sqlx::query!(
+            r#"
+                SELECT target_url, workspace_id FROM links
+                WHERE id = $1 AND workspace_id = $2
+                FOR UPDATE
+            "#,
+            &id,
+            &workspace
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        sqlx::query!(
             r#"
-            WITH updated_link AS (
+            UPDATE links
+            SET target_url = COALESCE($2, target_url),
+                expires_at = CASE WHEN $3 THEN $4 ELSE expires_at END,
+                expired_at = CASE WHEN $3 THEN NULL ELSE expired_at END,
+                tags = COALESCE($5, tags),
+                redirect_type = COALESCE($6, redirect_type),
+                active = COALESCE($7, active),
+                privacy_mode = COALESCE($8, privacy_mode),
+                track_clicks = COALESCE($9, track_clicks),
+                sample_rate = COALESCE($10, sample_rate),
+                click_milestones = COALESCE($11, click_milestones),
+                title = CASE WHEN $12 THEN $13 ELSE title END,
+                rate_limit = CASE WHEN $14 THEN $15 ELSE rate_limit END,
+                allowed_referers = COALESCE($16, allowed_referers),
+                referer_fallback_url = CASE WHEN $17 THEN $18 ELSE referer_fallback_url END,
+                response_headers = COALESCE($19, response_headers),
+                notes = CASE WHEN $20 THEN $21 ELSE notes END,
+                cache_tier = CASE WHEN $22 THEN $23 ELSE cache_tier END,
+                utm_template = CASE WHEN $24 THEN $25 ELSE utm_template END,
+                updated_at = now()
+            WHERE id = $1
+            "#,
+            &id,
+            target_url.as_deref(),
+            update.expires_at.is_some(),
+            update.expires_at.flatten(),
+            tags.as_deref(),
+            update.redirect_type,
+            update.active,
+            update.privacy_mode,
+            update.track_clicks,
+            update.sample_rate,
+            update.click_milestones.as_deref(),
+            update.title.is_some(),
+            update
+                .title
+                .as_ref()
+                .and_then(Option::as_deref)
+                .map(str::trim),
+            update.rate_limit.is_some(),
+            update.rate_limit.flatten(),
+            update.allowed_referers.as_deref(),
+            update.referer_fallback_url.is_some(),
+            update.referer_fallback_url.clone().flatten(),
+            response_headers.as_deref(),
+            update.notes.is_some(),
+            update.notes.clone().flatten(),
+            update.cache_tier.is_some(),
+            update.cache_tier.clone().flatten(),
+            utm_template.is_some(),
+            utm_template.as_ref().and_then(Option::as_deref)
+        )
+        .execute(&mut *tx)
+        .await?;
+        if let Some(target_url) = &target_url {
+            record_target_change(&mut tx, &id, &previous.target_url, target_url, &actor).await?;
+        }
+        let updated_link = fetch_link_details(&mut *tx, &id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let event = LinkEvent::new(
+            LinkEventKind::Updated,
+            previous.workspace_id,
+            Link {
+                id: updated_link.id.clone(),
+                target_url: updated_link.target_url.clone(),
+            },
+        );
+        outbox::enqueue(&mut tx, &event).await?;
+        link_cache::publish(&mut *tx, std::slice::from_ref(&id)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(updated_link))
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| missing_link(&id, &config))?;
+    cache.invalidate(std::slice::from_ref(&id));
+    tracing::debug!("Updated link with id {}", id);
+    Ok(Json(UpdatedLink {
+        urls: LinkUrls::new(&config, &headers, &updated_link.id),
+        link: updated_link,
+    }))
+}
+
+/// What applying a link definition changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Applied {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+/// Creates link `id` in `workspace` as `update`, already checked by [`validate_update`], or
+/// replaces it when the workspace owns it, within the caller's transaction. `None` when the slug
+/// is used by another workspace or an archived link.
+pub async fn apply_definition(
+    conn: &mut PgConnection,
+    id: &str,
+    workspace: &str,
+    actor: &str,
+    target_url: String,
+    update: LinkUpdate,
+) -> Result<Option<(Applied, LinkDetails)>, sqlx::Error> {
+    let tags: Vec<String> = update
+        .tags
+        .unwrap_or_default()
+        .iter()
+        .map(|tag| tag.trim().to_string())
+        .collect();
+    let expires_at = update.expires_at.flatten();
+    let redirect_type = update.redirect_type.unwrap_or_else(default_redirect_type);
+    let active = update.active.unwrap_or_else(default_active);
+    let privacy_mode = update.privacy_mode.unwrap_or_default();
+    let track_clicks = update.track_clicks.unwrap_or_else(default_track_clicks);
+    let sample_rate = update.sample_rate.unwrap_or_else(default_sample_rate);
+    let click_milestones = update.click_milestones.unwrap_or_default();
+    let title = update.title.clone().flatten();
+    let title = title.as_deref().map(str::trim);
+    let rate_limit = update.rate_limit.flatten();
+    let allowed_referers = update.allowed_referers.clone().unwrap_or_default();
+    let referer_fallback_url = update.referer_fallback_url.clone().flatten();
+    let response_headers = response_headers::to_lines(&update.response_headers.unwrap_or_default());
+    let notes = update.notes.flatten();
+    let cache_tier = update.cache_tier.flatten();
+    let utm_template = update
+        .utm_template
+        .flatten()
+        .map(|template| utm::to_lines(&template));
+
+    let existing = sqlx::query!(
+        "SELECT target_url, workspace_id FROM links WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let (applied, event_kind) = match existing {
+        None => {
+            let archived = sqlx::query_scalar!("SELECT id FROM archived_links WHERE id = $1", id)
+                .fetch_optional(&mut *conn)
+                .await?;
+            if archived.is_some() {
+                // Unarchiving it later would collide with the new link.
+                return Ok(None);
+            }
+            let inserted = sqlx::query!(
+                r#"
+                INSERT INTO links (id, target_url, workspace_id, tags, expires_at, redirect_type, active, privacy_mode, track_clicks, sample_rate, click_milestones, title, rate_limit, allowed_referers, referer_fallback_url, response_headers, notes, cache_tier, utm_template, created_by)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
+                ON CONFLICT (id) DO NOTHING
+                "#,
+                id,
+                &target_url,
+                workspace,
+                &tags,
+                expires_at,
+                redirect_type,
+                active,
+                privacy_mode,
+                track_clicks,
+                sample_rate,
+                &click_milestones,
+                title,
+                rate_limit,
+                &allowed_referers,
+                referer_fallback_url.as_deref(),
+                &response_headers,
+                notes.as_deref(),
+                cache_tier.as_deref(),
+                utm_template.as_deref(),
+                actor
+            )
+            .execute(&mut *conn)
+            .await?;
+            if inserted.rows_affected() == 0 {
+                // Created concurrently by someone else.
+                return Ok(None);
+            }
+            (Applied::Created, Some(LinkEventKind::Created))
+        }
+        Some(existing) if existing.workspace_id != workspace => return Ok(None),
+        Some(existing) => {
+            let updated = sqlx::query!(
+                r#"
                 UPDATE links
-                SET target_url = $1
-                WHERE id = $2
-                RETURNING id, target_url
+                SET target_url = $2,
+                    expired_at = CASE WHEN expires_at IS DISTINCT FROM $3 THEN NULL ELSE expired_at END,
+                    expires_at = $3,
+                    tags = $4,
+                    redirect_type = $5,
+                    active = $6,
+                    privacy_mode = $7,
+                    track_clicks = $8,
+                    sample_rate = $9,
+                    click_milestones = $10,
+                    title = $11,
+                    rate_limit = $12,
+                    allowed_referers = $13,
+                    referer_fallback_url = $14,
+                    response_headers = $15,
+                    notes = $16,
+                    cache_tier = $17,
+                    utm_template = $18,
+                    updated_at = now()
+                WHERE id = $1
+                    AND (target_url, expires_at, tags, redirect_type, active, privacy_mode, track_clicks, sample_rate, click_milestones, title, rate_limit, allowed_referers, referer_fallback_url, response_headers, notes, cache_tier, utm_template)
+                        IS DISTINCT FROM ($2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+                "#,
+                id,
+                &target_url,
+                expires_at,
+                &tags,
+                redirect_type,
+                active,
+                privacy_mode,
+                track_clicks,
+                sample_rate,
+                &click_milestones,
+                title,
+                rate_limit,
+                &allowed_referers,
+                referer_fallback_url.as_deref(),
+                &response_headers,
+                notes.as_deref(),
+                cache_tier.as_deref(),
+                utm_template.as_deref()
             )
-            SELECT id, target_url FROM updated_link
+            .execute(&mut *conn)
+            .await?;
+            if existing.target_url != target_url {
+                record_target_change(conn, id, &existing.target_url, &target_url, actor).await?;
+            }
+            if updated.rows_affected() == 0 {
+                (Applied::Unchanged, None)
+            } else {
+                (Applied::Updated, Some(LinkEventKind::Updated))
+            }
+        }
+    };
+    if let Some(event_kind) = event_kind {
+        let event = LinkEvent::new(
+            event_kind,
+            workspace.to_string(),
+            Link {
+                id: id.to_string(),
+                target_url: target_url.clone(),
+            },
+        );
+        outbox::enqueue(conn, &event).await?;
+        link_cache::publish(&mut *conn, &[id.to_string()]).await?;
+    }
+    let link = fetch_link_details(&mut *conn, id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    Ok(Some((applied, link)))
+}
+
+/// Creates the link under the given slug, or replaces it when the caller's workspace already
+/// owns it. Repeating the same request changes nothing, so well-known slugs can be provisioned
+/// declaratively.
+#[allow(clippy::too_many_arguments)]
+pub async fn upsert_link(
+    State(pool): State<PgPool>,
+    Path(id): Path<String>,
+    State(config): State<SharedConfig>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    State(cache): State<Arc<LinkCache>>,
+    headers: HeaderMap,
+    ValidJson(definition): ValidJson<LinkDefinition>,
+) -> Result<Response, ApiError> {
+    let config = config.current();
+    check_custom_slug(&id, &workspace, &config).map_err(field_error("id"))?;
+    let mut update = LinkUpdate::from(definition);
+    let submitted = refusable_input(&update);
+    let target_url = match validate_update(&mut update, &config) {
+        Ok(target_url) => target_url.unwrap_or_default(),
+        Err(err) => {
+            let err = audit::record_refused_targets(&pool, &actor, Some(&id), &submitted, err);
+            return Err(err.await);
+        }
+    };
+    let upsert_link_timeout = tokio::time::Duration::from_millis(300);
+    let (applied, link) = tokio::time::timeout(upsert_link_timeout, async {
+        let mut tx = pool.begin().await?;
+        let applied =
+            apply_definition(&mut tx, &id, &workspace, &actor, target_url, update).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(applied)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .ok_or_else(|| "Slug Taken".to_string())
+    .map_err(|err| (StatusCode::CONFLICT, err))?;
+    if applied != Applied::Created {
+        cache.invalidate(std::slice::from_ref(&id));
+    }
+    tracing::debug!("Upserted link with id {} in workspace {}", id, workspace);
+    let urls = LinkUrls::new(&config, &headers, &link.id);
+    if applied == Applied::Created {
+        return Ok(created(urls.short_url.clone(), UpdatedLink { link, urls }));
+    }
+    Ok(Json(UpdatedLink { link, urls }).into_response())
+}
+
+pub async fn delete_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(cache): State<Arc<LinkCache>>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let delete_link_timeout = tokio::time::Duration::from_millis(300);
+    tokio::time::timeout(delete_link_timeout, async {
+        let mut tx = pool.begin().await?;
+        let Some(deleted_link) = sqlx::query!(
+            r#"
+                DELETE FROM links
+                WHERE id = $1 AND workspace_id = $2
+                RETURNING id, target_url, workspace_id
             "#,
-            &url,
-            &id
+            &id,
+            &workspace
         )
-        .fetch_one(&pool),
-    )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let event = LinkEvent::new(
+            LinkEventKind::Deleted,
+            deleted_link.workspace_id,
+            Link {
+                id: deleted_link.id,
+                target_url: deleted_link.target_url,
+            },
+        );
+        outbox::enqueue(&mut tx, &event).await?;
+        link_cache::publish(&mut *tx, std::slice::from_ref(&id)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(()))
+    })
     .await
     .map_err(internal_error)?
-    .map_err(internal_error)?;
-    tracing::debug!("Updated link with id {} targeting {}", id, url);
-    Ok(Json(updated_link))
+    .map_err(internal_error)?
+    .ok_or_else(|| missing_link(&id, &config.current()))?;
+    cache.invalidate(std::slice::from_ref(&id));
+    tracing::debug!("Deleted link with id {}", id);
+    Ok(StatusCode::NO_CONTENT)
 }
 
+/// Clicks of a link by referer and user agent, over all time or between `from` and `to`. Days
+/// before the raw window count whole when they overlap the range.
 pub async fn get_link_statistics(
     State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
     Path(link_id): Path<String>,
+    Query(range): Query<StatisticsRange>,
 ) -> Result<Json<Vec<CountedLinkStatistics>>, (StatusCode, String)> {
+    let bounds = range.bounds()?;
+    let raw_since = rollup::raw_since(&config.current().statistics);
+    ensure_workspace_link_exists(&pool, &link_id, &workspace).await?;
     let fetch_statistics_timeout = tokio::time::Duration::from_millis(300);
     let link_statistics = tokio::time::timeout(
         fetch_statistics_timeout,
         sqlx::query_as!(
             CountedLinkStatistics,
             r#"
-                SELECT COUNT(*) AS amount, referer, user_agent
-                FROM link_statistics
-                GROUP BY link_id, referer, user_agent
-                HAVING link_id = $1
+                SELECT
+                    COALESCE(SUM(clicks), 0)::BIGINT AS amount,
+                    COALESCE(SUM(prefetches), 0)::BIGINT AS "prefetches!",
+                    referer,
+                    user_agent
+                FROM (
+                    SELECT
+                        clicks,
+                        prefetches,
+                        NULLIF(referer, '') AS referer,
+                        NULLIF(user_agent, '') AS user_agent
+                    FROM link_daily_sources
+                    WHERE link_id = $1
+                        AND day < $4
+                        AND ($2::TIMESTAMPTZ IS NULL OR (day + 1)::TIMESTAMP AT TIME ZONE 'UTC' > $2)
+                        AND ($3::TIMESTAMPTZ IS NULL OR day::TIMESTAMP AT TIME ZONE 'UTC' < $3)
+                    UNION ALL
+                    SELECT
+                        CASE WHEN prefetch THEN 0 ELSE weight END,
+                        CASE WHEN prefetch THEN weight ELSE 0 END,
+                        NULLIF(referer, ''),
+                        NULLIF(user_agent, '')
+                    FROM link_statistics
+                    WHERE link_id = $1
+                        AND created_at >= $5
+                        AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+                        AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+                ) AS sources
+                GROUP BY referer, user_agent
             "#,
-            &link_id
+            &link_id,
+            bounds.map(|(from, _)| from),
+            bounds.map(|(_, to)| to),
+            raw_since.date_naive(),
+            raw_since
         )
         .fetch_all(&pool),
     )
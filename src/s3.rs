@@ -0,0 +1,108 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::config::S3Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, as SigV4 canonical URIs are.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Where `key` lives: `{endpoint}/{bucket}/{key}` with path-style addressing, which every
+/// S3-compatible store understands, or `{bucket}.{endpoint host}/{key}` otherwise.
+fn object_url(config: &S3Config, bucket: &str, key: &str) -> Result<Url, String> {
+    let mut url = Url::parse(&config.endpoint).map_err(|err| err.to_string())?;
+    let path = if config.path_style {
+        format!("/{bucket}/{}", encode_path(key))
+    } else {
+        let host = url.host_str().ok_or("S3_ENDPOINT has no host")?;
+        url.set_host(Some(&format!("{bucket}.{host}")))
+            .map_err(|err| err.to_string())?;
+        format!("/{}", encode_path(key))
+    };
+    url.set_path(&path);
+    Ok(url)
+}
+
+/// Uploads `body` as `key` of `S3_BUCKET`, signed with AWS Signature Version 4.
+pub async fn put_object(
+    client: &Client,
+    config: &S3Config,
+    key: &str,
+    content_type: &str,
+    body: Vec<u8>,
+) -> Result<(), String> {
+    let (Some(bucket), Some(access_key_id), Some(secret_access_key)) = (
+        config.bucket.as_deref(),
+        config.access_key_id.as_deref(),
+        config.secret_access_key.as_deref(),
+    ) else {
+        return Err("S3_BUCKET and S3 credentials are not set".to_string());
+    };
+    let url = object_url(config, bucket, key)?;
+    // As the client will send it: with the port only when it is not the scheme's default.
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err("S3_ENDPOINT has no host".to_string()),
+    };
+    let now = Utc::now();
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = format!("{:x}", Sha256::digest(&body));
+    let signed_headers = "content-type;host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "PUT\n{}\n\ncontent-type:{content_type}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\n\n{signed_headers}\n{payload_hash}",
+        url.path()
+    );
+    let scope = format!("{date}/{}/s3/aws4_request", config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{:x}",
+        Sha256::digest(canonical_request.as_bytes())
+    );
+    let signing_key = [date.as_str(), config.region.as_str(), "s3", "aws4_request"]
+        .iter()
+        .fold(
+            format!("AWS4{secret_access_key}").into_bytes(),
+            |key, part| hmac(&key, part),
+        );
+    let signature: String = hmac(&signing_key, &string_to_sign)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let response = client
+        .put(url)
+        .header("Content-Type", content_type)
+        .header("X-Amz-Content-Sha256", &payload_hash)
+        .header("X-Amz-Date", &timestamp)
+        .header(
+            "Authorization",
+            format!("AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"),
+        )
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("the bucket answered {}", response.status()));
+    }
+    Ok(())
+}
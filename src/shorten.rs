@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{authenticate, SharedAuthenticator},
+    config::SharedConfig,
+    db::CircuitBreaker,
+    id::SharedIdGenerator,
+    metering::UsageMeter,
+    route::insert_link,
+    target::serialize_display_url,
+    utils::{accepts_json, short_url},
+};
+
+#[derive(Deserialize)]
+pub struct ShortenParams {
+    pub url: String,
+    pub key: Option<String>,
+    pub workspace: Option<String>,
+    /// `json` or `text`; defaults to what the `Accept` header asks for, plain text otherwise.
+    pub format: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortenedLink {
+    pub id: String,
+    #[serde(serialize_with = "serialize_display_url")]
+    pub target_url: String,
+    pub short_url: String,
+}
+
+/// TinyURL-style `GET /api/shorten?url=...&key=...` for bookmarklets and shell one-liners.
+/// Answers with the bare short URL unless JSON is requested.
+#[allow(clippy::too_many_arguments)]
+pub async fn shorten_get(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    State(breaker): State<Arc<CircuitBreaker>>,
+    State(ids): State<SharedIdGenerator>,
+    State(authenticator): State<SharedAuthenticator>,
+    State(meter): State<Arc<UsageMeter>>,
+    headers: HeaderMap,
+    Query(params): Query<ShortenParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let labels = [("uri", "/api/shorten!".to_string())];
+    let principal = authenticate(authenticator.as_ref(), params.key.as_deref(), &labels).await?;
+    let actor = principal.actor.as_deref().unwrap_or("global-api-key");
+    meter.record(
+        params.key.as_deref().unwrap_or_default(),
+        actor,
+        "GET /api/shorten".to_string(),
+    );
+    let workspace = principal
+        .workspace
+        .as_deref()
+        .or(params.workspace.as_deref())
+        .unwrap_or("default");
+    let config = config.current();
+    let link = insert_link(
+        &pool,
+        &config,
+        &breaker,
+        ids.as_ref(),
+        workspace,
+        actor,
+        &params.url,
+    )
+    .await?;
+    let short_url = short_url(&config, &headers, &link.id);
+
+    let wants_json = match params.format.as_deref() {
+        Some(format) => format == "json",
+        None => accepts_json(&headers),
+    };
+    if wants_json {
+        return Ok(Json(ShortenedLink {
+            id: link.id,
+            target_url: link.target_url,
+            short_url,
+        })
+        .into_response());
+    }
+    Ok(short_url.into_response())
+}
@@ -0,0 +1,149 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha3::Sha3_256;
+use sqlx::PgPool;
+
+use crate::{
+    auth::Workspace, config::SharedConfig, route::ensure_workspace_link_exists,
+    target::parse_target_url,
+};
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// Length of the truncated MAC appended to signed slugs.
+const SIGNATURE_LEN: usize = 16;
+const KIND_LINK: u8 = b'L';
+const KIND_URL: u8 = b'U';
+
+/// What a signed slug points at once its signature and expiry checked out.
+#[derive(Debug)]
+pub enum SignedTarget {
+    /// An existing link, which still has to be looked up.
+    Link(String),
+    /// A target embedded in the slug itself; no lookup needed.
+    Url(String),
+}
+
+#[derive(Debug)]
+pub enum SignedLinkError {
+    Invalid,
+    Expired,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewSignedLink {
+    pub link_id: Option<String>,
+    pub target_url: Option<String>,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedLink {
+    pub id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Signed slugs are `<payload>.<signature>`; regular ids never contain a dot.
+pub fn is_signed(slug: &str) -> bool {
+    slug.contains('.')
+}
+
+fn mac(key: &str) -> HmacSha3 {
+    HmacSha3::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length")
+}
+
+/// Payload layout: expiry as big-endian unix seconds, a kind byte and the link id or target URL.
+fn sign(key: &str, target: &SignedTarget, expires_at: DateTime<Utc>) -> String {
+    let (kind, value) = match target {
+        SignedTarget::Link(id) => (KIND_LINK, id),
+        SignedTarget::Url(url) => (KIND_URL, url),
+    };
+    let mut payload = expires_at.timestamp().to_be_bytes().to_vec();
+    payload.push(kind);
+    payload.extend_from_slice(value.as_bytes());
+
+    let mut mac = mac(key);
+    mac.update(&payload);
+    let signature = mac.finalize().into_bytes();
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(&payload),
+        URL_SAFE_NO_PAD.encode(&signature[..SIGNATURE_LEN])
+    )
+}
+
+/// Checks signature and expiry of a signed slug without touching the database.
+pub fn verify(key: &str, slug: &str) -> Result<SignedTarget, SignedLinkError> {
+    let (payload, signature) = slug.split_once('.').ok_or(SignedLinkError::Invalid)?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| SignedLinkError::Invalid)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| SignedLinkError::Invalid)?;
+    if signature.len() != SIGNATURE_LEN || payload.len() < 9 {
+        return Err(SignedLinkError::Invalid);
+    }
+
+    let mut mac = mac(key);
+    mac.update(&payload);
+    mac.verify_truncated_left(&signature)
+        .map_err(|_| SignedLinkError::Invalid)?;
+
+    let (expiry, rest) = payload.split_at(8);
+    let expiry = i64::from_be_bytes(expiry.try_into().expect("Split at eight bytes"));
+    if Utc::now().timestamp() >= expiry {
+        return Err(SignedLinkError::Expired);
+    }
+    let value = String::from_utf8(rest[1..].to_vec()).map_err(|_| SignedLinkError::Invalid)?;
+    match rest[0] {
+        KIND_LINK => Ok(SignedTarget::Link(value)),
+        KIND_URL => Ok(SignedTarget::Url(value)),
+        _ => Err(SignedLinkError::Invalid),
+    }
+}
+
+pub async fn create_signed_link(
+    State(pool): State<PgPool>,
+    State(config): State<SharedConfig>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Json(new_link): Json<NewSignedLink>,
+) -> Result<Json<SignedLink>, (StatusCode, String)> {
+    let config = config.current();
+    let Some(key) = config.signed_links.key.as_deref() else {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "Signed Links Not Configured".into(),
+        ));
+    };
+    if new_link.expires_in_secs == 0 || new_link.expires_in_secs > config.signed_links.max_ttl_secs
+    {
+        return Err((StatusCode::BAD_REQUEST, "Invalid Expiry".into()));
+    }
+
+    let target = match (new_link.link_id, new_link.target_url) {
+        (Some(link_id), None) => {
+            ensure_workspace_link_exists(&pool, &link_id, &workspace).await?;
+            SignedTarget::Link(link_id)
+        }
+        (None, Some(target_url)) => {
+            SignedTarget::Url(parse_target_url(&target_url, &config)?.to_string())
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Either linkId Or targetUrl Required".into(),
+            ))
+        }
+    };
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(new_link.expires_in_secs as i64);
+    let id = sign(key, &target, expires_at);
+    tracing::debug!("Issued signed link {:?} valid until {}", target, expires_at);
+    Ok(Json(SignedLink { id, expires_at }))
+}
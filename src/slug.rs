@@ -0,0 +1,184 @@
+use axum::http::StatusCode;
+
+use crate::{
+    config::Config,
+    id::{has_region_prefix, IdGenerator},
+    utils::is_valid_slug,
+};
+
+/// First path segments of other routes; links with these slugs could never be reached.
+const RESERVED_SLUGS: [&str; 11] = [
+    "admin",
+    "api",
+    "campaigns",
+    "create",
+    "favicon",
+    "health",
+    "metrics",
+    "reports",
+    "signed-links",
+    "v4",
+    "webhooks",
+];
+
+/// Second path segments of routes of a link; links in a namespace with these names could never be
+/// reached.
+const LINK_ROUTES: [&str; 9] = [
+    "clone",
+    "history",
+    "pause",
+    "public-stats",
+    "qr",
+    "resume",
+    "rollback",
+    "rollout",
+    "statistics",
+];
+
+/// Generated slugs matching the blocklist are thrown away; after this many tries the last one is
+/// kept rather than failing the request.
+const MAX_GENERATE_ATTEMPTS: usize = 10;
+
+/// Cyrillic and Greek letters that render like Latin ones, with the letter they imitate.
+const HOMOGLYPHS: [(char, char); 20] = [
+    ('а', 'a'),
+    ('в', 'b'),
+    ('е', 'e'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('н', 'h'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('ѕ', 's'),
+    ('α', 'a'),
+    ('ε', 'e'),
+    ('ι', 'i'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+];
+
+/// Reads digits as the letters they commonly stand in for, so `5h1t` matches `shit`.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' => 'a',
+        '5' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        _ => c,
+    }
+}
+
+/// Whether the slug contains a blocklisted word, ignoring case, separators and leetspeak.
+pub fn is_blocklisted(slug: &str, config: &Config) -> bool {
+    let normalized: String = slug
+        .chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| unleet(c.to_ascii_lowercase()))
+        .collect();
+    config
+        .slug_blocklist
+        .iter()
+        .any(|word| normalized.contains(word.as_str()))
+}
+
+/// Whether the slug mixes Latin letters with letters of another script, like `pаypal` with a
+/// Cyrillic `а`.
+fn is_mixed_script(slug: &str) -> bool {
+    slug.chars().any(|c| c.is_ascii_alphabetic())
+        && slug.chars().any(|c| c.is_alphabetic() && !c.is_ascii())
+}
+
+/// What the slug looks like: homoglyphs, case and look-alike digits folded together, so `AdM1n`
+/// and `admin` compare equal.
+fn skeleton(slug: &str) -> String {
+    slug.chars()
+        .map(|c| {
+            HOMOGLYPHS
+                .iter()
+                .find(|(glyph, _)| *glyph == c)
+                .map_or(c, |(_, latin)| *latin)
+        })
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | 'i' | '|' => 'l',
+            '5' => 's',
+            lower => lower,
+        })
+        .collect::<String>()
+        .replace("rn", "m")
+        .replace("vv", "w")
+}
+
+/// A random slug that spells nothing on the blocklist.
+pub fn generate_slug(ids: &dyn IdGenerator, config: &Config) -> String {
+    let mut slug = ids.generate();
+    for _ in 1..MAX_GENERATE_ATTEMPTS {
+        if !is_blocklisted(&slug, config) {
+            break;
+        }
+        tracing::debug!("Regenerating blocklisted slug {}", slug);
+        slug = ids.generate();
+    }
+    slug
+}
+
+/// Checks a slug chosen by a client of `workspace`: well-formed, not confusable with a reserved
+/// slug, not in the space of generated slugs of a region and not on the blocklist. Slugs in a
+/// namespace, like `eng/launch`, are only for the workspace that reserved it.
+pub fn check_custom_slug(
+    slug: &str,
+    workspace: &str,
+    config: &Config,
+) -> Result<(), (StatusCode, String)> {
+    let (namespace, name) = match slug.split_once('/') {
+        Some((namespace, name)) => (Some(namespace), name),
+        None => (None, slug),
+    };
+    if is_mixed_script(slug) {
+        return Err((StatusCode::BAD_REQUEST, "Confusable Slug".into()));
+    }
+    if !is_valid_slug(name) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid Slug".into()));
+    }
+    if let Some(namespace) = namespace {
+        match config.slug_namespaces.get(namespace) {
+            None => return Err((StatusCode::BAD_REQUEST, "Unknown Namespace".into())),
+            Some(owner) if owner != workspace => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "Namespace Of Another Workspace".into(),
+                ))
+            }
+            Some(_) => {}
+        }
+        if LINK_ROUTES.contains(&name) {
+            return Err((StatusCode::CONFLICT, "Slug Reserved".into()));
+        }
+    } else {
+        let slug_skeleton = skeleton(slug);
+        if RESERVED_SLUGS
+            .iter()
+            .any(|reserved| skeleton(reserved) == slug_skeleton)
+            || has_region_prefix(slug, config)
+        {
+            return Err((StatusCode::CONFLICT, "Slug Reserved".into()));
+        }
+    }
+    if config.honeypot_slugs.contains(slug) {
+        return Err((StatusCode::CONFLICT, "Slug Reserved".into()));
+    }
+    if is_blocklisted(name, config) {
+        return Err((StatusCode::BAD_REQUEST, "Slug Not Allowed".into()));
+    }
+    Ok(())
+}
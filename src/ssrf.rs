@@ -0,0 +1,88 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use url::Url;
+
+#[derive(Debug)]
+pub enum SsrfError {
+    UnsupportedScheme(String),
+    MissingHost,
+    Resolution(std::io::Error),
+    NonPublicAddress(IpAddr),
+    Client(reqwest::Error),
+}
+
+impl fmt::Display for SsrfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SsrfError::UnsupportedScheme(scheme) => write!(f, "Unsupported scheme {scheme}"),
+            SsrfError::MissingHost => write!(f, "Url has no host"),
+            SsrfError::Resolution(err) => write!(f, "Could not resolve host: {err}"),
+            SsrfError::NonPublicAddress(ip) => {
+                write!(f, "Host resolves to non-public address {ip}")
+            }
+            SsrfError::Client(err) => write!(f, "Could not build HTTP client: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SsrfError {}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // "This network" and reserved space, 0.0.0.0/8 and 240.0.0.0/4
+        || a == 0
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(mapped) = ip.to_ipv4_mapped() {
+        return is_public_ipv4(mapped);
+    }
+    let first_segment = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first_segment & 0xfe00) == 0xfc00
+        // Link local, fe80::/10
+        || (first_segment & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (first_segment == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+/// Resolves the host of `url` and makes sure every address it points to is publicly routable.
+pub async fn resolve_public(url: &Url) -> Result<Vec<SocketAddr>, SsrfError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(SsrfError::UnsupportedScheme(url.scheme().to_string()));
+    }
+    let host = url.host_str().ok_or(SsrfError::MissingHost)?;
+    let port = url.port_or_known_default().ok_or(SsrfError::MissingHost)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(SsrfError::Resolution)?
+        .collect();
+    if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        return Err(SsrfError::NonPublicAddress(addr.ip()));
+    }
+    Ok(addrs)
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    config::{Config, SharedConfig},
+    outbound, s3,
+};
+
+/// Taken while pending days are exported, so instances don't upload the same ones concurrently.
+const EXPORT_LOCK: i64 = 0x6c69_6e6b_5f65_7870;
+
+/// A recorded click as exported, one CSV row each.
+#[derive(Serialize)]
+struct ExportedClick {
+    id: i32,
+    link_id: String,
+    workspace_id: String,
+    created_at: DateTime<Utc>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    visitor_hash: Option<String>,
+    weight: i32,
+    language: Option<String>,
+    platform: Option<String>,
+    mobile: Option<bool>,
+    channel: String,
+    prefetch: bool,
+    variant: Option<String>,
+    click_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(sqlx::Error),
+    Upload(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Database(err) => write!(f, "Reading clicks failed: {err}"),
+            ExportError::Upload(err) => write!(f, "Uploading clicks failed: {err}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(err: sqlx::Error) -> Self {
+        ExportError::Database(err)
+    }
+}
+
+/// Partitioned by day the way Athena and Hive expect, like
+/// `analytics/clicks/day=2024-05-01/clicks.csv`.
+fn object_key(config: &Config, day: NaiveDate) -> String {
+    format!(
+        "{}clicks/day={day}/clicks.csv",
+        config.statistics_export.prefix
+    )
+}
+
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
+}
+
+/// Uploads the clicks recorded on `day`, replacing an earlier export of it. Returns how many
+/// clicks were exported.
+async fn export_day(
+    pool: &PgPool,
+    client: &Client,
+    config: &Config,
+    day: NaiveDate,
+) -> Result<usize, ExportError> {
+    let clicks = sqlx::query_as!(
+        ExportedClick,
+        r#"
+            SELECT s.id, s.link_id, l.workspace_id, s.created_at, s.referer, s.user_agent,
+                s.visitor_hash, s.weight, s.language, s.platform, s.mobile, s.channel, s.prefetch,
+                s.variant, s.click_id
+            FROM link_statistics s
+            JOIN links l ON l.id = s.link_id
+            WHERE s.created_at >= $1 AND s.created_at < $2
+            ORDER BY s.created_at, s.id
+        "#,
+        start_of(day),
+        start_of(day + Days::new(1))
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for click in &clicks {
+        writer
+            .serialize(click)
+            .map_err(|err| ExportError::Upload(err.to_string()))?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(|err| ExportError::Upload(err.into_error().to_string()))?;
+    let key = object_key(config, day);
+    s3::put_object(client, &config.s3, &key, "text/csv; charset=utf-8", csv)
+        .await
+        .map_err(ExportError::Upload)?;
+    sqlx::query!(
+        r#"
+            INSERT INTO statistics_exports (day, object_key, clicks)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (day) DO UPDATE
+            SET object_key = EXCLUDED.object_key, clicks = EXCLUDED.clicks, exported_at = now()
+        "#,
+        day,
+        &key,
+        clicks.len() as i64
+    )
+    .execute(pool)
+    .await?;
+    tracing::info!("Exported {} clicks of {} to {}", clicks.len(), day, key);
+    Ok(clicks.len())
+}
+
+/// Exports every day from `from` to `to`, included, whether it was exported before or not.
+/// Returns how many clicks were exported.
+pub async fn export_days(
+    pool: &PgPool,
+    config: &Config,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<usize, ExportError> {
+    let client = outbound::api_client(&config.outbound, config.statistics_export.timeout);
+    let mut exported = 0;
+    for day in from.iter_days().take_while(|day| *day <= to) {
+        exported += export_day(pool, &client, config, day).await?;
+    }
+    Ok(exported)
+}
+
+/// Exports the finished days not exported yet: those since the last one exported, or yesterday
+/// on the first run. Older days can be exported with `POST /admin/jobs/statistics-export`.
+/// Does nothing while another instance is at it.
+async fn export_pending(pool: &PgPool, config: &Config) -> Result<(), ExportError> {
+    let mut tx = pool.begin().await?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        EXPORT_LOCK
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(());
+    }
+    let today = Utc::now().date_naive();
+    let yesterday = today.pred_opt().unwrap_or(today);
+    let last_exported = sqlx::query_scalar!("SELECT max(day) FROM statistics_exports")
+        .fetch_one(&mut *tx)
+        .await?;
+    let from = last_exported
+        .and_then(|day| day.succ_opt())
+        .unwrap_or(yesterday);
+    if from <= yesterday {
+        export_days(pool, config, from, yesterday).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Exports finished days every `STATISTICS_EXPORT_INTERVAL_SECS`, while `S3_BUCKET` is set.
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            if current.s3.bucket.is_some() {
+                if let Err(err) = export_pending(&pool, &current).await {
+                    tracing::error!("Exporting statistics failed: {}", err);
+                }
+            }
+            tokio::time::sleep(current.statistics_export.interval).await;
+        }
+    });
+}
@@ -0,0 +1,180 @@
+use std::borrow::Cow;
+
+use axum::http::StatusCode;
+use serde::Serializer;
+use url::Url;
+
+use crate::{
+    config::Config,
+    utils::is_valid_link_id,
+    validation::{ApiError, FieldError},
+};
+
+/// Why a target was refused, with a code clients and support can act on.
+#[derive(Clone, Copy, Debug)]
+pub enum BlockReason {
+    /// The host is on `BLOCKED_DOMAINS` or in `blocked_domains`.
+    BlockedDomain,
+    /// The host mixes scripts, see `REJECT_HOMOGRAPH_DOMAINS`.
+    HomographDomain,
+}
+
+impl BlockReason {
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::BlockedDomain => "BLOCKED_DOMAIN",
+            Self::HomographDomain => "HOMOGRAPH_DOMAIN",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::BlockedDomain => "Target Domain Blocked",
+            Self::HomographDomain => "Homograph Domain",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TargetError {
+    Malformed,
+    Blocked(BlockReason),
+}
+
+impl TargetError {
+    /// The error as a problem with `field` of a link input. Blocks carry their reason code.
+    pub fn into_field_error(self, field: &str) -> FieldError {
+        let (_, message) = self.into();
+        let mut error = FieldError::new(field, message);
+        if let Self::Blocked(reason) = self {
+            error.code = Some(reason.code());
+        }
+        error
+    }
+
+    pub fn on_field(self, field: &str) -> ApiError {
+        let (status, _) = self.into();
+        ApiError::Fields(status, vec![self.into_field_error(field)])
+    }
+}
+
+impl From<TargetError> for (StatusCode, String) {
+    fn from(err: TargetError) -> Self {
+        match err {
+            TargetError::Malformed => (StatusCode::BAD_REQUEST, "Url Malformed".to_string()),
+            TargetError::Blocked(reason) => (StatusCode::FORBIDDEN, reason.message().to_string()),
+        }
+    }
+}
+
+/// Prefixes targets typed without a scheme, like `example.com/page` or `localhost:8080`, with the
+/// default scheme. Anything that already names a scheme (`mailto:`) is left alone.
+fn with_default_scheme<'a>(raw: &'a str, config: &Config) -> Cow<'a, str> {
+    let Some(scheme) = config.default_target_scheme.as_deref() else {
+        return Cow::Borrowed(raw);
+    };
+    if raw.contains("://") {
+        return Cow::Borrowed(raw);
+    }
+    let scheme_less = match Url::parse(raw) {
+        Err(url::ParseError::RelativeUrlWithoutBase) => true,
+        // `example.com:8080/page` parses with `example.com` as its scheme.
+        Ok(url) => {
+            url.scheme().contains('.')
+                || url.cannot_be_a_base() && url.path().starts_with(|c: char| c.is_ascii_digit())
+        }
+        Err(_) => false,
+    };
+    if scheme_less {
+        Cow::Owned(format!("{scheme}://{raw}"))
+    } else {
+        Cow::Borrowed(raw)
+    }
+}
+
+/// Parses a target URL submitted for a link and checks it against the blocklist. Internationalized
+/// hosts come back in punycode, which is how targets are stored.
+pub fn parse_target_url(raw: &str, config: &Config) -> Result<Url, TargetError> {
+    let url =
+        Url::parse(&with_default_scheme(raw.trim(), config)).map_err(|_| TargetError::Malformed)?;
+    if config.reject_homograph_domains
+        && url
+            .host_str()
+            .is_some_and(|host| is_homograph(&idna::domain_to_unicode(host).0))
+    {
+        tracing::warn!("Rejected homograph target url {}", url);
+        return Err(TargetError::Blocked(BlockReason::HomographDomain));
+    }
+    if url
+        .host_str()
+        .is_some_and(|host| config.is_blocked_host(host))
+    {
+        tracing::warn!("Rejected blocked target url {}", url);
+        return Err(TargetError::Blocked(BlockReason::BlockedDomain));
+    }
+    Ok(url)
+}
+
+/// Scripts domains are commonly spoofed with. Letters of other scripts are not considered.
+#[derive(Clone, Copy, PartialEq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+}
+
+fn script_of(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00c0}'..='\u{024f}' => Some(Script::Latin),
+        '\u{0370}'..='\u{03ff}' => Some(Script::Greek),
+        '\u{0400}'..='\u{052f}' => Some(Script::Cyrillic),
+        '\u{0530}'..='\u{058f}' => Some(Script::Armenian),
+        _ => None,
+    }
+}
+
+/// Whether a label of the (Unicode) domain mixes scripts, like `pаypal` with a Cyrillic `а`.
+fn is_homograph(domain: &str) -> bool {
+    domain.split('.').any(|label| {
+        let mut scripts = label.chars().filter_map(script_of);
+        scripts
+            .next()
+            .is_some_and(|first| scripts.any(|script| script != first))
+    })
+}
+
+/// The target as people read it, with an internationalized host in Unicode instead of the
+/// punycode it is stored and redirected to.
+pub fn display_url(target_url: &str) -> String {
+    let Some(host) = Url::parse(target_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .filter(|host| host.split('.').any(|label| label.starts_with("xn--")))
+    else {
+        return target_url.to_string();
+    };
+    match idna::domain_to_unicode(&host) {
+        (unicode, Ok(())) => target_url.replacen(&host, &unicode, 1),
+        (_, Err(_)) => target_url.to_string(),
+    }
+}
+
+pub fn serialize_display_url<S: Serializer>(
+    target_url: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&display_url(target_url))
+}
+
+/// The slug a target points at when it is another short link under `base_url`, whichever scheme
+/// it was saved with. Targets with a further path, a query or a fragment are not followed.
+pub fn chained_slug<'a>(target_url: &'a str, base_url: &str) -> Option<&'a str> {
+    let base_address = base_url.split_once("://")?.1;
+    let slug = target_url
+        .split_once("://")?
+        .1
+        .strip_prefix(base_address)?
+        .strip_prefix('/')?;
+    is_valid_link_id(slug).then_some(slug)
+}
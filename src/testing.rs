@@ -0,0 +1,165 @@
+//! Integration test helpers, behind the `test-util` feature: a throwaway Postgres in a container
+//! with every migration applied, and a router to send requests through without a server.
+//!
+//! ```ignore
+//! let app = TestApp::start().await;
+//! let response = app
+//!     .post_json("/create", serde_json::json!({ "targetUrl": "https://example.com" }))
+//!     .await;
+//! assert_eq!(response.status(), StatusCode::CREATED);
+//! ```
+
+use std::net::SocketAddr;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ConnectInfo,
+    http::{header, Method, Request},
+    response::Response,
+    Router,
+};
+use sha3::{Digest, Sha3_256};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use testcontainers_modules::{
+    postgres::Postgres,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+use tower::ServiceExt;
+
+use crate::{auth::SETTINGS_ID, build_router, preflight, AppState, MIGRATOR};
+
+/// The API key [`TestApp`] seeds as the global key and sends with every request.
+pub const TEST_API_KEY: &str = "secret";
+
+/// The address requests sent through [`TestApp`] appear to come from.
+pub const TEST_CLIENT_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 40000);
+
+/// Starts Postgres in a container and applies the migrations. The database is gone once the
+/// container is dropped.
+pub async fn start_postgres() -> (ContainerAsync<Postgres>, PgPool) {
+    let container = Postgres::default()
+        .start()
+        .await
+        .expect("Could not start Postgres container");
+    let host = container
+        .get_host()
+        .await
+        .expect("Could not get Postgres container host");
+    let port = container
+        .get_host_port_ipv4(5432)
+        .await
+        .expect("Could not get Postgres container port");
+    let pool = PgPoolOptions::new()
+        .connect(&format!(
+            "postgres://postgres:postgres@{host}:{port}/postgres"
+        ))
+        .await
+        .expect("Could not connect to Postgres container");
+    MIGRATOR
+        .run(&pool)
+        .await
+        .expect("Could not apply migrations");
+    (container, pool)
+}
+
+/// The router on a fresh database, with [`TEST_API_KEY`] as the global API key. Background jobs
+/// are not started.
+pub struct TestApp {
+    pub state: AppState,
+    pub router: Router,
+    _container: ContainerAsync<Postgres>,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        Self::start_with(|state| state).await
+    }
+
+    /// Like [`TestApp::start`], letting `customize` swap parts of the state, like the id
+    /// generator, before the router is built.
+    pub async fn start_with(customize: impl FnOnce(AppState) -> AppState) -> Self {
+        let (container, pool) = start_postgres().await;
+        sqlx::query!(
+            "INSERT INTO settings (id, encrypted_global_api_key) VALUES ($1, $2)",
+            SETTINGS_ID,
+            format!("{:x}", Sha3_256::digest(TEST_API_KEY.as_bytes()))
+        )
+        .execute(&pool)
+        .await
+        .expect("Could not seed the API key");
+        let config = preflight(&pool).await.expect("Preflight checks failed");
+        let state = customize(
+            AppState::new(pool, config)
+                .await
+                .expect("Could not build application state"),
+        );
+        Self {
+            router: build_router(state.clone()),
+            state,
+            _container: container,
+        }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.state.pool
+    }
+
+    /// Sends a request through the router as if it came from [`TEST_CLIENT_ADDR`].
+    pub async fn request(&self, mut request: Request<Body>) -> Response {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(TEST_CLIENT_ADDR)));
+        self.router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("The router never fails")
+    }
+
+    /// An authenticated request without a body.
+    pub async fn send(&self, method: Method, uri: &str) -> Response {
+        self.request(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .body(Body::empty())
+                .expect("Test request should be valid"),
+        )
+        .await
+    }
+
+    pub async fn get(&self, uri: &str) -> Response {
+        self.send(Method::GET, uri).await
+    }
+
+    /// An authenticated request with a JSON body.
+    pub async fn send_json(&self, method: Method, uri: &str, body: serde_json::Value) -> Response {
+        self.request(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("Test request should be valid"),
+        )
+        .await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: serde_json::Value) -> Response {
+        self.send_json(Method::POST, uri, body).await
+    }
+
+    pub async fn patch_json(&self, uri: &str, body: serde_json::Value) -> Response {
+        self.send_json(Method::PATCH, uri, body).await
+    }
+}
+
+/// Reads a whole response body as JSON.
+pub async fn json_body(response: Response) -> serde_json::Value {
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Could not read response body");
+    serde_json::from_slice(&bytes).expect("Response body should be JSON")
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use url::Url;
+
+use crate::{
+    config::{OutboundConfig, SharedConfig, TitleConfig},
+    outbound,
+};
+
+/// Longest title kept, in characters.
+pub const MAX_TITLE_LENGTH: usize = 200;
+/// Only the start of a page is read; the title lives in its head.
+const MAX_BODY_BYTES: usize = 256 * 1024;
+
+const ENTITIES: [(&str, &str); 6] = [
+    ("&lt;", "<"),
+    ("&gt;", ">"),
+    ("&quot;", "\""),
+    ("&#39;", "'"),
+    ("&nbsp;", " "),
+    // Last, so `&amp;lt;` becomes `&lt;` and not `<`.
+    ("&amp;", "&"),
+];
+
+/// `text` with whitespace collapsed and common entities decoded.
+pub fn decode_text(text: &str) -> String {
+    let mut text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    for (entity, replacement) in ENTITIES {
+        text = text.replace(entity, replacement);
+    }
+    text
+}
+
+/// The text of the first `<title>` element, with whitespace collapsed and common entities
+/// decoded.
+pub fn extract_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title: String = decode_text(&html[start..end])
+        .chars()
+        .take(MAX_TITLE_LENGTH)
+        .collect();
+    (!title.is_empty()).then_some(title)
+}
+
+/// Fetches the target through the SSRF-safe client, following redirects one vetted hop at a
+/// time, and returns the start of it when it is an HTML page.
+pub async fn fetch_page(
+    target_url: &str,
+    timeout: Duration,
+    outbound: &OutboundConfig,
+) -> Result<Option<String>, String> {
+    let url = Url::parse(target_url).map_err(|err| err.to_string())?;
+    let mut response = outbound::follow(url, outbound, timeout, |client, url| {
+        client.get(url).header("Accept", "text/html")
+    })
+    .await?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("text/html"));
+    if !response.status().is_success() || !is_html {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_BODY_BYTES {
+            break;
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn fetch_title(
+    target_url: &str,
+    config: &TitleConfig,
+    outbound: &OutboundConfig,
+) -> Result<Option<String>, String> {
+    let page = fetch_page(target_url, config.timeout, outbound).await?;
+    Ok(page.as_deref().and_then(extract_title))
+}
+
+/// Claims one batch of links that have no title and were not looked at yet, and stores the
+/// titles of their targets. Returns how many links were looked at.
+async fn fetch_batch(
+    pool: &PgPool,
+    config: &TitleConfig,
+    outbound: &OutboundConfig,
+) -> Result<usize, sqlx::Error> {
+    let claimed = sqlx::query!(
+        r#"
+            UPDATE links
+            SET title_checked_at = now()
+            WHERE id IN (
+                SELECT id FROM links
+                WHERE title IS NULL AND title_checked_at IS NULL
+                ORDER BY created_at DESC
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, target_url
+        "#,
+        config.batch_size
+    )
+    .fetch_all(pool)
+    .await?;
+    for link in &claimed {
+        let title = match fetch_title(&link.target_url, config, outbound).await {
+            Ok(Some(title)) => title,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::debug!("Could not fetch title of {}: {}", link.target_url, err);
+                continue;
+            }
+        };
+        // A title typed in meanwhile wins.
+        sqlx::query!(
+            "UPDATE links SET title = $2 WHERE id = $1 AND title IS NULL",
+            &link.id,
+            &title
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(claimed.len())
+}
+
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            let titles = current.titles.clone();
+            if titles.enabled {
+                match fetch_batch(&pool, &titles, &current.outbound).await {
+                    // Keep going without pausing while there is a backlog.
+                    Ok(count) if count > 0 => continue,
+                    Ok(_) => {}
+                    Err(err) => tracing::error!("Fetching link titles failed: {}", err),
+                }
+            }
+            tokio::time::sleep(titles.interval).await;
+        }
+    });
+}
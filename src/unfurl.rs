@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use url::Url;
+
+use crate::{
+    config::{Config, UnfurlConfig},
+    title,
+    utils::escape_html,
+};
+
+/// Cached destinations above which expired ones are dropped.
+const MAX_CACHED_PREVIEWS: usize = 10_000;
+/// Longest value of a tag taken over from a destination, in characters.
+const MAX_TAG_LENGTH: usize = 500;
+
+/// Whether the request comes from a chat app or social network fetching a preview of the link.
+pub fn is_unfurl_bot(headers: &HeaderMap, config: &UnfurlConfig) -> bool {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_ascii_lowercase)
+        .is_some_and(|user_agent| {
+            config
+                .user_agents
+                .iter()
+                .any(|fragment| user_agent.contains(fragment.as_str()))
+        })
+}
+
+/// What a destination's OpenGraph and Twitter card tags say about it.
+#[derive(Clone, Debug, Default)]
+pub struct Preview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute `http(s)` URL.
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// The value of attribute `name` of an HTML tag, unquoted.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find(name) {
+        let start = from + found;
+        from = start + name.len();
+        let rest = lower[from..].trim_start();
+        if !lower[..start].ends_with(|c: char| c.is_ascii_whitespace()) || !rest.starts_with('=') {
+            continue;
+        }
+        let value = tag[tag.len() - rest.len() + 1..].trim_start();
+        let value = match value.chars().next()? {
+            quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+            _ => value
+                .split(|c: char| c.is_ascii_whitespace() || c == '>')
+                .next()?,
+        };
+        return Some(title::decode_text(value));
+    }
+    None
+}
+
+/// The preview tags of a page, the OpenGraph ones winning over the Twitter card ones, falling back
+/// to its `<title>`.
+fn extract_preview(html: &str, page_url: &str) -> Preview {
+    let lower = html.to_ascii_lowercase();
+    let mut tags: HashMap<String, String> = HashMap::new();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("<meta") {
+        let start = from + found;
+        let Some(length) = lower[start..].find('>') else {
+            break;
+        };
+        let tag = &html[start..start + length];
+        from = start + length;
+        let key = attribute(tag, "property").or_else(|| attribute(tag, "name"));
+        if let (Some(key), Some(content)) = (key, attribute(tag, "content")) {
+            let content: String = content.chars().take(MAX_TAG_LENGTH).collect();
+            if !content.is_empty() {
+                tags.entry(key.to_ascii_lowercase()).or_insert(content);
+            }
+        }
+    }
+    let tag = |keys: &[&str]| keys.iter().find_map(|key| tags.get(*key).cloned());
+    let image = tag(&["og:image", "og:image:url", "twitter:image"]).and_then(|image| {
+        let image = Url::parse(page_url).ok()?.join(&image).ok()?;
+        matches!(image.scheme(), "http" | "https").then(|| image.to_string())
+    });
+    Preview {
+        title: tag(&["og:title", "twitter:title"]).or_else(|| title::extract_title(html)),
+        description: tag(&["og:description", "twitter:description", "description"]),
+        image,
+        site_name: tag(&["og:site_name"]),
+    }
+}
+
+/// Previews of destinations recently unfurled, so a link shared in a busy channel doesn't have
+/// its destination fetched for every bot asking.
+#[derive(Debug, Default)]
+pub struct PreviewCache {
+    previews: Mutex<HashMap<String, (Instant, Preview)>>,
+}
+
+impl PreviewCache {
+    fn cached(&self, target_url: &str, ttl: Duration) -> Option<Preview> {
+        let previews = self.previews.lock().expect("Preview cache lock poisoned");
+        previews
+            .get(target_url)
+            .filter(|(fetched, _)| fetched.elapsed() < ttl)
+            .map(|(_, preview)| preview.clone())
+    }
+
+    /// The preview of `target_url`, empty unless `UNFURL_PROXY_DESTINATION` is on. Destinations
+    /// that can't be fetched get an empty one as well, which is cached like the others.
+    pub async fn preview(&self, target_url: &str, config: &Config) -> Preview {
+        let unfurl = &config.unfurl;
+        if !unfurl.proxy_destination {
+            return Preview::default();
+        }
+        if let Some(preview) = self.cached(target_url, unfurl.cache_ttl) {
+            return preview;
+        }
+        let preview = match title::fetch_page(target_url, unfurl.timeout, &config.outbound).await {
+            Ok(page) => page
+                .map(|page| extract_preview(&page, target_url))
+                .unwrap_or_default(),
+            Err(err) => {
+                tracing::debug!("Could not fetch preview of {}: {}", target_url, err);
+                Preview::default()
+            }
+        };
+        let now = Instant::now();
+        let mut previews = self.previews.lock().expect("Preview cache lock poisoned");
+        if previews.len() > MAX_CACHED_PREVIEWS {
+            previews.retain(|_, (fetched, _)| now.duration_since(*fetched) < unfurl.cache_ttl);
+        }
+        previews.insert(target_url.to_string(), (now, preview.clone()));
+        preview
+    }
+}
+
+/// Marks responses that are previews for unfurl bots rather than visits.
+#[derive(Clone, Copy, Debug)]
+pub struct Unfurled;
+
+/// A page of preview tags for the link at `short_url`, titled with the link's own title when it
+/// has one. Whoever opens it anyway is sent on to the destination.
+pub fn page(short_url: &str, target_url: &str, title: Option<&str>, preview: &Preview) -> Response {
+    let title = title.or(preview.title.as_deref()).unwrap_or(target_url);
+    let card = if preview.image.is_some() {
+        "summary_large_image"
+    } else {
+        "summary"
+    };
+    let mut tags = vec![
+        ("og:type", "website"),
+        ("og:url", short_url),
+        ("og:title", title),
+        ("twitter:card", card),
+        ("twitter:title", title),
+    ];
+    if let Some(description) = &preview.description {
+        tags.extend([
+            ("og:description", description.as_str()),
+            ("twitter:description", description.as_str()),
+        ]);
+    }
+    if let Some(image) = &preview.image {
+        tags.extend([
+            ("og:image", image.as_str()),
+            ("twitter:image", image.as_str()),
+        ]);
+    }
+    if let Some(site_name) = &preview.site_name {
+        tags.push(("og:site_name", site_name.as_str()));
+    }
+    let meta: String = tags
+        .into_iter()
+        .map(|(key, content)| {
+            // Twitter reads its tags from `name`, OpenGraph readers from `property`.
+            let attribute = if key.starts_with("twitter:") {
+                "name"
+            } else {
+                "property"
+            };
+            format!(
+                "<meta {attribute}=\"{key}\" content=\"{}\">\n",
+                escape_html(content)
+            )
+        })
+        .collect();
+    let target_url = escape_html(target_url);
+    let body = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n{meta}<meta http-equiv=\"refresh\" content=\"0; url={target_url}\">\n</head>\n<body><a href=\"{target_url}\">{target_url}</a></body>\n</html>\n",
+        escape_html(title)
+    );
+    let mut response = (StatusCode::OK, body).into_response();
+    response.extensions_mut().insert(Unfurled);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    // Shared caches must not hand the page to visitors in place of the redirect.
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-store"),
+    );
+    response
+}
@@ -1,13 +1,138 @@
-use axum::http::StatusCode;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use base64::{engine::general_purpose, Engine};
 use metrics::counter;
+use rand::Rng;
 
+use crate::{config::Config, db::is_transient};
+
+/// Stands in for the route of requests no route matched, so scanners probing random paths
+/// don't add a label value each.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+pub fn generate_id() -> String {
+    let random_number: u32 = rand::thread_rng().gen_range(0..u32::MAX);
+    general_purpose::URL_SAFE_NO_PAD.encode(random_number.to_string())
+}
+
+/// Slugs chosen by clients: up to 64 URL-safe characters.
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 64
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether `id` is a slug, or a slug in a namespace like `eng/launch`.
+pub fn is_valid_link_id(id: &str) -> bool {
+    match id.split_once('/') {
+        Some((namespace, slug)) => is_valid_slug(namespace) && is_valid_slug(slug),
+        None => is_valid_slug(id),
+    }
+}
+
+/// The host the request was addressed to, used to build short URLs.
+pub fn request_host(headers: &HeaderMap) -> String {
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost")
+        .to_string()
+}
+
+/// Whether the `Accept` header asks for JSON.
+pub fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Where short links are served from: `BASE_URL`, or the host the request was addressed to.
+pub fn base_url(config: &Config, headers: &HeaderMap) -> String {
+    config
+        .base_url
+        .clone()
+        .unwrap_or_else(|| format!("https://{}", request_host(headers)))
+}
+
+pub fn short_url(config: &Config, headers: &HeaderMap, id: &str) -> String {
+    format!("{}/{}", base_url(config, headers), id)
+}
+
+/// The template of the route the request matched, like `/:id`, to label metrics and reports
+/// with instead of the path.
+pub fn route_template(req: &Request) -> String {
+    req.extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, MatchedPath::as_str)
+        .to_string()
+}
+
+/// Makes text safe to put in HTML, inside an element or a quoted attribute.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Turns a failed query or timeout into a response: an unreachable or slow database is a 503,
+/// anything else is logged and answered with a 500 that doesn't repeat the error.
 pub fn internal_error<E>(err: E) -> (StatusCode, String)
 where
-    E: std::error::Error,
+    E: std::error::Error + 'static,
 {
+    let source: &(dyn std::error::Error + 'static) = &err;
+    if let Some(db_err) = source.downcast_ref::<sqlx::Error>() {
+        if is_transient(db_err) {
+            tracing::warn!("Database unavailable: {}", err);
+            counter!("request_error", "error" => "database_unavailable").increment(1);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Database Unavailable".into(),
+            );
+        }
+    }
+    if source.is::<tokio::time::error::Elapsed>() {
+        tracing::warn!("Database call timed out");
+        counter!("request_error", "error" => "timeout").increment(1);
+        return (StatusCode::SERVICE_UNAVAILABLE, "Timed Out".into());
+    }
     tracing::error!("{}", err);
 
     let labels = [("error", format!("{}!", err))];
     counter!("request_error", &labels).increment(1);
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".into(),
+    )
+}
+
+pub fn database_unavailable<T>(_: T) -> (StatusCode, String) {
+    tracing::warn!("Rejected call while the database circuit is open");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Database Unavailable".into(),
+    )
+}
+
+/// Turns errors of the load shedding layers into responses.
+pub async fn handle_overload(err: BoxError) -> Response {
+    if err.is::<tower::load_shed::error::Overloaded>() {
+        counter!("requests_shed").increment(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+            "Service Overloaded".to_string(),
+        )
+            .into_response();
+    }
+    tracing::error!("Unhandled middleware error: {}", err);
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
 }
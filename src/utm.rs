@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use serde::{ser::SerializeMap, Serializer};
+use url::Url;
+
+/// Parameters a link's UTM template may set.
+pub const PARAMETERS: [&str; 6] = [
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "utm_id",
+];
+pub const MAX_VALUE_LENGTH: usize = 256;
+
+/// Lowercases the names and trims the values of a UTM template given for a link, or says what is
+/// wrong with it.
+pub fn normalize(template: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    let mut normalized = BTreeMap::new();
+    for (name, value) in template {
+        let name = name.trim().to_ascii_lowercase();
+        if !PARAMETERS.contains(&name.as_str()) {
+            return Err(format!(
+                "can only hold {}, not {name}",
+                PARAMETERS.join(", ")
+            ));
+        }
+        let value = value.trim();
+        if value.is_empty() || value.len() > MAX_VALUE_LENGTH {
+            return Err(format!(
+                "{name} must be non-empty and at most {MAX_VALUE_LENGTH} characters"
+            ));
+        }
+        normalized.insert(name, value.to_string());
+    }
+    Ok(normalized)
+}
+
+/// A template as stored with a link.
+pub fn to_lines(template: &BTreeMap<String, String>) -> Vec<String> {
+    template
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    line.split_once('=')
+}
+
+/// Writes a stored template as an object of names and values, or `null` when there is none.
+pub fn serialize<S: Serializer>(
+    lines: &Option<Vec<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let Some(lines) = lines else {
+        return serializer.serialize_none();
+    };
+    let mut map = serializer.serialize_map(Some(lines.len()))?;
+    for (name, value) in lines.iter().filter_map(|line| parse_line(line)) {
+        map.serialize_entry(name, value)?;
+    }
+    map.end()
+}
+
+fn is_utm(name: &str) -> bool {
+    name.get(..4)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("utm_"))
+}
+
+/// `target_url` with its `utm_*` parameters, whoever added them, replaced by a link's template.
+/// Other parameters are kept in order; the template's come last.
+pub fn apply(target_url: &str, lines: &[String]) -> String {
+    let Ok(mut url) = Url::parse(target_url) else {
+        return target_url.to_string();
+    };
+    let mut tagged = false;
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| {
+            let utm = is_utm(name);
+            tagged |= utm;
+            !utm
+        })
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if !tagged && lines.is_empty() {
+        return target_url.to_string();
+    }
+    if kept.is_empty() && lines.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(kept)
+            .extend_pairs(lines.iter().filter_map(|line| parse_line(line)));
+    }
+    url.into()
+}
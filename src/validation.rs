@@ -0,0 +1,133 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A problem with one input field, named as in the JSON body (`targetUrl`, `tags[2]`).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+    /// Stable code of a refused target, like `BLOCKED_DOMAIN`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    /// Quoted to support to appeal a refused target, found in the audit log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub appeal_reference: Option<String>,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+            code: None,
+            appeal_reference: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FieldErrors<'a> {
+    errors: &'a [FieldError],
+}
+
+/// Errors of the endpoints taking link input. Problems with the input are answered with an
+/// `errors` array naming each field, so forms can point at the right one; anything else keeps
+/// its plain-text message.
+#[derive(Debug)]
+pub enum ApiError {
+    Fields(StatusCode, Vec<FieldError>),
+    Status(StatusCode, String),
+}
+
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        ApiError::Status(status, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::Fields(status, errors) => {
+                (status, Json(FieldErrors { errors: &errors })).into_response()
+            }
+            ApiError::Status(status, message) => (status, message).into_response(),
+        }
+    }
+}
+
+/// The field a serde error is about. Missing fields are reported on the object that should
+/// hold them, so their name is taken from the message.
+fn error_field(path: &str, message: &str) -> String {
+    let missing = message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.split('`').next());
+    match (path, missing) {
+        (".", Some(name)) => name.to_string(),
+        (path, Some(name)) => format!("{path}.{name}"),
+        (path, None) => path.to_string(),
+    }
+}
+
+/// Like [`Json`], but bodies that don't fit `T` (unknown fields, wrong types, missing fields) are
+/// answered with 422 and the offending field.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+        if !is_json {
+            return Err(ApiError::Status(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`".into(),
+            ));
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|rejection| ApiError::Status(rejection.status(), rejection.body_text()))?;
+        let deserializer = &mut serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(deserializer)
+            .map(ValidJson)
+            .map_err(deserialize_error)
+    }
+}
+
+/// A body that doesn't fit its type: 400 when it isn't JSON at all, 422 with the offending
+/// field otherwise.
+pub fn deserialize_error(err: serde_path_to_error::Error<serde_json::Error>) -> ApiError {
+    if err.inner().is_syntax() || err.inner().is_eof() {
+        return ApiError::Status(
+            StatusCode::BAD_REQUEST,
+            format!("Malformed JSON: {}", err.inner()),
+        );
+    }
+    let path = err.path().to_string();
+    let message = err.inner().to_string();
+    // serde_json appends the position, which means nothing to a form.
+    let reason = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(reason, _)| reason);
+    ApiError::Fields(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        vec![FieldError::new(error_field(&path, reason), reason)],
+    )
+}
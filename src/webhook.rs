@@ -0,0 +1,376 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use metrics::counter;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{PgConnection, PgPool};
+use url::Url;
+
+use crate::{
+    auth::Workspace,
+    config::{Config, OutboundConfig, WebhookConfig},
+    outbound,
+    route::Link,
+    utils::{generate_id, internal_error},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum LinkEventKind {
+    #[serde(rename = "link.created")]
+    Created,
+    #[serde(rename = "link.updated")]
+    Updated,
+    #[serde(rename = "link.deleted")]
+    Deleted,
+    #[serde(rename = "link.expired")]
+    Expired,
+    #[serde(rename = "link.milestone")]
+    Milestone,
+}
+
+impl LinkEventKind {
+    const ALL: [LinkEventKind; 5] = [
+        LinkEventKind::Created,
+        LinkEventKind::Updated,
+        LinkEventKind::Deleted,
+        LinkEventKind::Expired,
+        LinkEventKind::Milestone,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkEventKind::Created => "link.created",
+            LinkEventKind::Updated => "link.updated",
+            LinkEventKind::Deleted => "link.deleted",
+            LinkEventKind::Expired => "link.expired",
+            LinkEventKind::Milestone => "link.milestone",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: LinkEventKind,
+    pub workspace_id: String,
+    pub occurred_at: DateTime<Utc>,
+    pub link: Link,
+    /// The click count reached, for `link.milestone` events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clicks: Option<i64>,
+}
+
+impl LinkEvent {
+    pub fn new(kind: LinkEventKind, workspace_id: String, link: Link) -> Self {
+        Self {
+            id: generate_id(),
+            kind,
+            workspace_id,
+            occurred_at: Utc::now(),
+            link,
+            clicks: None,
+        }
+    }
+
+    pub fn with_clicks(mut self, clicks: i64) -> Self {
+        self.clicks = Some(clicks);
+        self
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewWebhookEndpoint {
+    pub url: String,
+    /// Event types to receive; all of them when empty or missing.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEndpoint {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    /// Only returned when the endpoint is registered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub event_id: String,
+    pub event_type: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+struct Target {
+    id: String,
+    url: String,
+    secret: String,
+}
+
+/// Queues a delivery of the event to every endpoint of its workspace subscribed to it, as part
+/// of the outbox dispatcher's transaction. The outbox delivers and retries them from there.
+pub async fn enqueue_deliveries(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    event_id: &str,
+    event_type: &str,
+    body: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO outbox_deliveries (kind, target, event_id, event_type, payload)
+            SELECT 'webhook', id, $3, $2, $4
+            FROM webhook_endpoints
+            WHERE workspace_id = $1 AND active AND (cardinality(events) = 0 OR $2 = ANY(events))
+        "#,
+        workspace_id,
+        event_type,
+        event_id,
+        body
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Posts the event to one endpoint once, logging the attempt. Endpoints deleted or disabled since
+/// the event was queued are skipped.
+pub async fn attempt_delivery(
+    pool: &PgPool,
+    config: &Config,
+    endpoint_id: &str,
+    event_id: &str,
+    event_type: &str,
+    body: &str,
+    attempt: i32,
+) -> Result<(), String> {
+    let target = sqlx::query_as!(
+        Target,
+        "SELECT id, url, secret FROM webhook_endpoints WHERE id = $1 AND active",
+        endpoint_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|err| err.to_string())?;
+    let Some(target) = target else {
+        return Ok(());
+    };
+    let outcome = send(&config.webhooks, &config.outbound, &target, event_id, body).await;
+    let (status_code, error) = match &outcome {
+        Ok(status) => (Some(i32::from(*status)), None),
+        Err(err) => (None, Some(err.as_str())),
+    };
+    let delivered = status_code.is_some_and(|status| (200..300).contains(&status));
+    let labels = [("outcome", if delivered { "delivered" } else { "failed" })];
+    counter!("webhook_deliveries", &labels).increment(1);
+    let logged = sqlx::query!(
+        r#"
+            INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, attempt, status_code, error)
+            VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        &target.id,
+        event_id,
+        event_type,
+        attempt,
+        status_code,
+        error
+    )
+    .execute(pool)
+    .await;
+    if let Err(err) = logged {
+        tracing::error!("Could not log webhook delivery: {}", err);
+    }
+    match outcome {
+        Ok(_) if delivered => Ok(()),
+        Ok(status) => Err(format!("status {status}")),
+        Err(err) => Err(err),
+    }
+}
+
+/// Signature over `<timestamp>.<body>` so receivers can reject replayed deliveries.
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+async fn send(
+    config: &WebhookConfig,
+    outbound: &OutboundConfig,
+    target: &Target,
+    event_id: &str,
+    body: &str,
+) -> Result<u16, String> {
+    let url = Url::parse(&target.url).map_err(|err| err.to_string())?;
+    let client = outbound::client(&url, outbound, config.timeout)
+        .await
+        .map_err(|err| err.to_string())?;
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", event_id)
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header("X-Webhook-Signature", sign(&target.secret, timestamp, body))
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(response.status().as_u16())
+}
+
+pub async fn create_webhook_endpoint(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Json(new_endpoint): Json<NewWebhookEndpoint>,
+) -> Result<Json<WebhookEndpoint>, (StatusCode, String)> {
+    let url = Url::parse(&new_endpoint.url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Url Malformed".to_string()))?;
+    if let Some(unknown) = new_endpoint.events.iter().find(|event| {
+        !LinkEventKind::ALL
+            .iter()
+            .any(|kind| kind.as_str() == *event)
+    }) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown Event {unknown}")));
+    }
+
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = URL_SAFE_NO_PAD.encode(secret);
+    let create_endpoint_timeout = tokio::time::Duration::from_millis(300);
+    let endpoint = tokio::time::timeout(
+        create_endpoint_timeout,
+        sqlx::query!(
+            r#"
+                INSERT INTO webhook_endpoints (id, workspace_id, url, secret, events)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING id, url, events, active, created_at
+            "#,
+            generate_id(),
+            &workspace,
+            url.as_str(),
+            &secret,
+            &new_endpoint.events
+        )
+        .fetch_one(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    tracing::debug!(
+        "Registered webhook endpoint {} for workspace {}",
+        endpoint.id,
+        workspace
+    );
+    Ok(Json(WebhookEndpoint {
+        id: endpoint.id,
+        url: endpoint.url,
+        events: endpoint.events,
+        active: endpoint.active,
+        created_at: endpoint.created_at,
+        secret: Some(secret),
+    }))
+}
+
+pub async fn list_webhook_endpoints(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<Json<Vec<WebhookEndpoint>>, (StatusCode, String)> {
+    let fetch_endpoints_timeout = tokio::time::Duration::from_millis(300);
+    let endpoints = tokio::time::timeout(
+        fetch_endpoints_timeout,
+        sqlx::query_as!(
+            WebhookEndpoint,
+            r#"
+                SELECT id, url, events, active, created_at, NULL AS "secret?"
+                FROM webhook_endpoints
+                WHERE workspace_id = $1
+                ORDER BY created_at
+            "#,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(endpoints))
+}
+
+pub async fn delete_webhook_endpoint(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let delete_endpoint_timeout = tokio::time::Duration::from_millis(300);
+    let deleted = tokio::time::timeout(
+        delete_endpoint_timeout,
+        sqlx::query!(
+            "DELETE FROM webhook_endpoints WHERE id = $1 AND workspace_id = $2",
+            &id,
+            &workspace
+        )
+        .execute(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if deleted.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Not Found".into()));
+    }
+    tracing::debug!("Deleted webhook endpoint {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The most recent delivery attempts of an endpoint.
+pub async fn list_webhook_deliveries(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<WebhookDelivery>>, (StatusCode, String)> {
+    let fetch_deliveries_timeout = tokio::time::Duration::from_millis(300);
+    let deliveries = tokio::time::timeout(
+        fetch_deliveries_timeout,
+        sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+                SELECT d.id, d.event_id, d.event_type, d.attempt, d.status_code, d.error, d.created_at
+                FROM webhook_deliveries d
+                JOIN webhook_endpoints e ON e.id = d.endpoint_id
+                WHERE d.endpoint_id = $1 AND e.workspace_id = $2
+                ORDER BY d.created_at DESC, d.id DESC
+                LIMIT 100
+            "#,
+            &id,
+            &workspace
+        )
+        .fetch_all(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    Ok(Json(deliveries))
+}
@@ -0,0 +1,288 @@
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    config::{Config, SharedConfig},
+    email::{self, Email},
+    rollup,
+    utils::escape_html,
+};
+
+pub struct ReportedLink {
+    pub id: String,
+    pub target_url: String,
+    pub clicks: i64,
+    pub previous_clicks: i64,
+}
+
+/// A workspace's week, Monday to Sunday in UTC, next to the week before.
+pub struct WeeklyReport {
+    pub workspace: String,
+    pub week: NaiveDate,
+    pub clicks: i64,
+    pub previous_clicks: i64,
+    pub links_created: i64,
+    pub top_links: Vec<ReportedLink>,
+}
+
+/// `+12, +25%`, with the percentage left out when there were no clicks before.
+fn delta(clicks: i64, previous_clicks: i64) -> String {
+    let change = clicks - previous_clicks;
+    if previous_clicks == 0 {
+        return format!("{change:+}");
+    }
+    let percent = change as f64 * 100.0 / previous_clicks as f64;
+    format!("{change:+}, {percent:+.0}%")
+}
+
+fn short_url(config: &Config, id: &str) -> String {
+    match &config.base_url {
+        Some(base_url) => format!("{base_url}/{id}"),
+        None => id.to_string(),
+    }
+}
+
+fn render_text(report: &WeeklyReport, config: &Config) -> String {
+    let mut text = format!(
+        "Week of {} in workspace {}\n\nClicks: {} ({} from the week before)\nLinks created: {}\n",
+        report.week,
+        report.workspace,
+        report.clicks,
+        delta(report.clicks, report.previous_clicks),
+        report.links_created,
+    );
+    if !report.top_links.is_empty() {
+        text.push_str("\nTop links:\n");
+        for link in &report.top_links {
+            text.push_str(&format!(
+                "- {} -> {}: {} clicks ({})\n",
+                short_url(config, &link.id),
+                link.target_url,
+                link.clicks,
+                delta(link.clicks, link.previous_clicks),
+            ));
+        }
+    }
+    text
+}
+
+fn render_html(report: &WeeklyReport, config: &Config) -> String {
+    let rows: String = report
+        .top_links
+        .iter()
+        .map(|link| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&short_url(config, &link.id)),
+                escape_html(&link.target_url),
+                link.clicks,
+                delta(link.clicks, link.previous_clicks),
+            )
+        })
+        .collect();
+    let top_links = if rows.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<h2>Top links</h2><table><tr><th>Link</th><th>Target</th><th>Clicks</th><th>Change</th></tr>{rows}</table>"
+        )
+    };
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Weekly report</title></head><body><h1>Week of {} in workspace {}</h1><p>Clicks: <strong>{}</strong> ({} from the week before)<br>Links created: {}</p>{top_links}</body></html>"#,
+        report.week,
+        escape_html(&report.workspace),
+        report.clicks,
+        delta(report.clicks, report.previous_clicks),
+        report.links_created,
+    )
+}
+
+/// The email for `report`, rendered as text and HTML.
+pub fn render(report: &WeeklyReport, config: &Config, to: Vec<String>) -> Email {
+    Email {
+        to,
+        subject: format!(
+            "Weekly report for {}: {} clicks",
+            report.workspace, report.clicks
+        ),
+        text: render_text(report, config),
+        html: render_html(report, config),
+    }
+}
+
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
+}
+
+async fn build_report(
+    pool: &PgPool,
+    config: &Config,
+    workspace: String,
+    week: NaiveDate,
+) -> Result<WeeklyReport, sqlx::Error> {
+    let previous_week = week - Days::new(7);
+    let next_week = week + Days::new(7);
+    let totals = sqlx::query!(
+        r#"
+            SELECT
+                (
+                    SELECT COUNT(*) FROM links
+                    WHERE workspace_id = $1 AND created_at >= $2 AND created_at < $3
+                ) AS "links_created!",
+                COALESCE(SUM(d.clicks) FILTER (WHERE d.day >= $4), 0)::BIGINT AS "clicks!",
+                COALESCE(SUM(d.clicks) FILTER (WHERE d.day < $4), 0)::BIGINT AS "previous_clicks!"
+            FROM link_daily_clicks d
+            JOIN links l ON l.id = d.link_id
+            WHERE l.workspace_id = $1 AND d.day >= $5 AND d.day < $6
+        "#,
+        &workspace,
+        start_of(week),
+        start_of(next_week),
+        week,
+        previous_week,
+        next_week
+    )
+    .fetch_one(pool)
+    .await?;
+    let top_links = sqlx::query_as!(
+        ReportedLink,
+        r#"
+            SELECT
+                l.id,
+                l.target_url,
+                COALESCE(SUM(d.clicks) FILTER (WHERE d.day >= $2), 0)::BIGINT AS "clicks!",
+                COALESCE(SUM(d.clicks) FILTER (WHERE d.day < $2), 0)::BIGINT AS "previous_clicks!"
+            FROM link_daily_clicks d
+            JOIN links l ON l.id = d.link_id
+            WHERE l.workspace_id = $1 AND d.day >= $3 AND d.day < $4
+            GROUP BY l.id
+            HAVING SUM(d.clicks) FILTER (WHERE d.day >= $2) > 0
+            ORDER BY 3 DESC, 1
+            LIMIT $5
+        "#,
+        &workspace,
+        week,
+        previous_week,
+        next_week,
+        config.weekly_reports.top_links
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(WeeklyReport {
+        workspace,
+        week,
+        clicks: totals.clicks,
+        previous_clicks: totals.previous_clicks,
+        links_created: totals.links_created,
+        top_links,
+    })
+}
+
+/// Emails one batch of the workspaces that opted in and haven't been sent a report of the past
+/// week, leaving out those in `failed`. A workspace is marked as reported before its email is
+/// sent, so instances don't send the same report twice; when sending fails the mark is taken
+/// back and the workspace added to `failed`. Returns how many workspaces were due.
+async fn send_batch(
+    pool: &PgPool,
+    config: &Config,
+    failed: &mut Vec<String>,
+) -> Result<usize, sqlx::Error> {
+    let today = Utc::now().date_naive();
+    let this_week = today - Days::new(today.weekday().num_days_from_monday().into());
+    let week = this_week - Days::new(7);
+    let due = sqlx::query!(
+        r#"
+            UPDATE workspace_settings
+            SET report_sent_for = $1
+            WHERE workspace_id IN (
+                SELECT workspace_id
+                FROM workspace_settings
+                WHERE weekly_report
+                    AND cardinality(report_recipients) > 0
+                    AND (report_sent_for IS NULL OR report_sent_for < $1)
+                    AND NOT workspace_id = ANY($3)
+                ORDER BY workspace_id
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING workspace_id, report_recipients
+        "#,
+        week,
+        config.weekly_reports.batch_size,
+        failed.as_slice()
+    )
+    .fetch_all(pool)
+    .await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    // Clicks of late Sunday may not have been rolled up yet.
+    let sunday = this_week.pred_opt().unwrap_or(this_week);
+    rollup::roll_up(pool, sunday, sunday).await?;
+    for workspace in &due {
+        let sent = match build_report(pool, config, workspace.workspace_id.clone(), week).await {
+            Ok(report) => {
+                let email = render(&report, config, workspace.report_recipients.clone());
+                email::send(&config.email, &email).await
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        match sent {
+            Ok(()) => tracing::info!(
+                "Sent the weekly report of workspace {} to {} recipients",
+                workspace.workspace_id,
+                workspace.report_recipients.len()
+            ),
+            Err(err) => {
+                tracing::warn!(
+                    "Sending the weekly report of workspace {} failed: {}",
+                    workspace.workspace_id,
+                    err
+                );
+                sqlx::query!(
+                    r#"
+                        UPDATE workspace_settings
+                        SET report_sent_for = $2
+                        WHERE workspace_id = $1 AND report_sent_for = $3
+                    "#,
+                    &workspace.workspace_id,
+                    week - Days::new(7),
+                    week
+                )
+                .execute(pool)
+                .await?;
+                failed.push(workspace.workspace_id.clone());
+            }
+        }
+    }
+    Ok(due.len())
+}
+
+/// Sends every weekly report due, batch after batch. Those failing are tried again on the next
+/// call. Returns how many were sent.
+pub async fn send_due(pool: &PgPool, config: &Config) -> Result<usize, sqlx::Error> {
+    let mut due = 0;
+    let mut failed = Vec::new();
+    loop {
+        let batch = send_batch(pool, config, &mut failed).await?;
+        if batch == 0 {
+            return Ok(due - failed.len());
+        }
+        due += batch;
+    }
+}
+
+/// Checks for weekly reports due every `WEEKLY_REPORT_INTERVAL_SECS`, while `SMTP_HOST` is set.
+pub fn spawn(pool: PgPool, config: SharedConfig) {
+    tokio::spawn(async move {
+        loop {
+            let current = config.current();
+            if current.email.host.is_some() {
+                if let Err(err) = send_due(&pool, &current).await {
+                    tracing::error!("Sending weekly reports failed: {}", err);
+                }
+            }
+            tokio::time::sleep(current.weekly_reports.interval).await;
+        }
+    });
+}
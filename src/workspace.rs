@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit,
+    auth::{Actor, Workspace},
+    email,
+    link_cache::{self, LinkCache},
+    utils::internal_error,
+};
+
+/// Recipients of a workspace's weekly report at most.
+const MAX_REPORT_RECIPIENTS: usize = 10;
+
+fn default_geo_enrichment() -> bool {
+    true
+}
+
+/// Settings left out of a `PUT` go back to their defaults.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSettings {
+    /// Whether visitor addresses of the workspace's clicks are processed at all: hashed to count
+    /// unique visitors and matched against scanner networks. Off, clicks are recorded without a
+    /// visitor hash, so they don't count towards unique visitors. Rate limits and bans, which
+    /// protect the service rather than describe visitors, still apply.
+    #[serde(default = "default_geo_enrichment")]
+    pub geo_enrichment: bool,
+    /// Email a summary of each week, Monday to Sunday in UTC, to `report_recipients`.
+    #[serde(default)]
+    pub weekly_report: bool,
+    #[serde(default)]
+    pub report_recipients: Vec<String>,
+}
+
+pub async fn get_workspace_settings(
+    State(pool): State<PgPool>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+) -> Result<Json<WorkspaceSettings>, (StatusCode, String)> {
+    let settings = tokio::time::timeout(
+        tokio::time::Duration::from_millis(300),
+        sqlx::query_as!(
+            WorkspaceSettings,
+            r#"
+                SELECT geo_enrichment, weekly_report, report_recipients
+                FROM workspace_settings
+                WHERE workspace_id = $1
+            "#,
+            &workspace
+        )
+        .fetch_optional(&pool),
+    )
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?
+    .unwrap_or(WorkspaceSettings {
+        geo_enrichment: default_geo_enrichment(),
+        weekly_report: false,
+        report_recipients: Vec::new(),
+    });
+    Ok(Json(settings))
+}
+
+/// Saves the workspace's settings. Redirects of its links follow them once the change is
+/// announced to every instance.
+pub async fn set_workspace_settings(
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LinkCache>>,
+    Extension(Actor(actor)): Extension<Actor>,
+    Extension(Workspace(workspace)): Extension<Workspace>,
+    Json(mut settings): Json<WorkspaceSettings>,
+) -> Result<Json<WorkspaceSettings>, (StatusCode, String)> {
+    settings.report_recipients = settings
+        .report_recipients
+        .iter()
+        .map(|address| address.trim().to_string())
+        .collect();
+    if let Some(address) = settings
+        .report_recipients
+        .iter()
+        .find(|address| !email::is_valid_address(address))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid recipient {address:?}"),
+        ));
+    }
+    if settings.report_recipients.len() > MAX_REPORT_RECIPIENTS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {MAX_REPORT_RECIPIENTS} report recipients"),
+        ));
+    }
+    if settings.weekly_report && settings.report_recipients.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Weekly reports need recipients".into(),
+        ));
+    }
+    let update_settings_timeout = tokio::time::Duration::from_millis(1000);
+    let ids = tokio::time::timeout(update_settings_timeout, async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO workspace_settings (workspace_id, geo_enrichment, weekly_report, report_recipients)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (workspace_id) DO UPDATE
+                SET geo_enrichment = EXCLUDED.geo_enrichment,
+                    weekly_report = EXCLUDED.weekly_report,
+                    report_recipients = EXCLUDED.report_recipients,
+                    updated_at = now()
+            "#,
+            &workspace,
+            settings.geo_enrichment,
+            settings.weekly_report,
+            &settings.report_recipients
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut tx,
+            &actor,
+            "workspace.settings",
+            Some(&workspace),
+            json!(settings),
+        )
+        .await?;
+        let ids = sqlx::query_scalar!("SELECT id FROM links WHERE workspace_id = $1", &workspace)
+            .fetch_all(&mut *tx)
+            .await?;
+        link_cache::publish(&mut *tx, &ids).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(ids)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    cache.invalidate(&ids);
+    tracing::info!("Settings of workspace {} changed by {}", workspace, actor);
+    Ok(Json(settings))
+}
@@ -0,0 +1,1917 @@
+//! End-to-end tests against Postgres in a container. Run with
+//! `cargo test --features test-util` and Docker available.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Datelike, Days, Timelike};
+use link_shortener::{
+    preflight,
+    testing::{json_body, start_postgres, TestApp, TEST_API_KEY},
+    CdnPurger, ErrorReport, ErrorReporter, IdGenerator, Prefixed, PreflightError, PurgeRequest,
+};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+async fn create_link(app: &TestApp, target_url: &str) -> String {
+    let response = app
+        .post_json("/create", json!({ "targetUrl": target_url }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    json_body(response).await["id"]
+        .as_str()
+        .expect("Created link should have an id")
+        .to_string()
+}
+
+async fn follow(app: &TestApp, id: &str, referer: &str) -> axum::response::Response {
+    app.request(
+        Request::builder()
+            .uri(format!("/{id}"))
+            .header(header::REFERER, referer)
+            .header(header::USER_AGENT, "integration-test")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+}
+
+/// Runs a rollup job for today and waits for it to finish.
+async fn roll_up_today(app: &TestApp) -> serde_json::Value {
+    let today = chrono::Utc::now().date_naive().to_string();
+    let response = app
+        .post_json("/admin/jobs/rollups", json!({ "from": today, "to": today }))
+        .await;
+    finish_job(app, response).await
+}
+
+/// Polls the job a `/admin/jobs/...` request started until it is done.
+async fn finish_job(app: &TestApp, response: axum::response::Response) -> serde_json::Value {
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let mut job = json!(null);
+    for _ in 0..50 {
+        job = json_body(app.get(&location).await).await;
+        if job["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    job
+}
+
+#[tokio::test]
+async fn creates_and_fetches_a_link() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+
+    let response = app.get(&format!("/api/links/{id}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let details = json_body(response).await;
+    assert_eq!(details["id"], id);
+    assert_eq!(details["targetUrl"], "https://example.com/page");
+}
+
+#[tokio::test]
+async fn create_points_at_the_short_url() {
+    let app = TestApp::start().await;
+    let response = app
+        .post_json(
+            "/create",
+            json!({ "targetUrl": "https://example.com/page" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let created = json_body(response).await;
+    assert_eq!(created["shortUrl"], location);
+    assert_eq!(created["statisticsUrl"], format!("{location}/statistics"));
+    assert_eq!(created["qrUrl"], format!("{location}/qr"));
+}
+
+#[tokio::test]
+async fn rejects_calls_without_the_api_key() {
+    let app = TestApp::start().await;
+    let response = app
+        .request(
+            Request::builder()
+                .method("POST")
+                .uri("/create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"targetUrl":"https://example.com"}"#))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn redirects_to_the_target() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page"
+    );
+
+    let response = follow(&app, "missing", "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tags_redirects_with_surrogate_keys() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert!(response.headers().get("surrogate-key").is_none());
+
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('CDN_SURROGATE_KEYS', 'true')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()["surrogate-key"],
+        format!("link-{id} workspace-default").as_str()
+    );
+    assert_eq!(
+        response.headers()["cache-tag"],
+        format!("link-{id},workspace-default").as_str()
+    );
+}
+
+#[tokio::test]
+async fn serves_a_fallback_page_with_redirects() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/a?b=1&c=2").await;
+
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('REDIRECT_PAGE', 'body')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/a?b=1&c=2"
+    );
+    let page = String::from_utf8(
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+    assert!(page.contains(r#"content="0; url=https://example.com/a?b=1&amp;c=2""#));
+
+    sqlx::query("UPDATE runtime_settings SET value = 'interstitial' WHERE key = 'REDIRECT_PAGE'")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::LOCATION).is_none());
+}
+
+#[tokio::test]
+async fn resolves_chained_links_in_one_redirect() {
+    let app = TestApp::start().await;
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('BASE_URL', 'https://sho.rt')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let last = create_link(&app, "https://example.com/end").await;
+    let middle = create_link(&app, &format!("https://sho.rt/{last}")).await;
+    let first = create_link(&app, &format!("http://sho.rt/{middle}")).await;
+
+    let response = follow(&app, &first, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/end"
+    );
+
+    let response = app
+        .patch_json(
+            &format!("/{last}"),
+            json!({ "targetUrl": format!("https://sho.rt/{first}") }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = follow(&app, &first, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::LOOP_DETECTED);
+}
+
+#[tokio::test]
+async fn upgrades_plain_http_requests() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    sqlx::query(
+        "INSERT INTO runtime_settings (key, value) VALUES ('FORCE_HTTPS', 'true'), ('HSTS_MAX_AGE_SECS', '31536000')",
+    )
+    .execute(app.pool())
+    .await
+    .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let response = app
+        .request(
+            Request::builder()
+                .uri(format!("/{id}?utm_source=mail"))
+                .header(header::HOST, "sho.rt:80")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        format!("https://sho.rt/{id}?utm_source=mail")
+    );
+
+    let response = app
+        .request(
+            Request::builder()
+                .uri(format!("/{id}"))
+                .header("x-forwarded-proto", "https")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::STRICT_TRANSPORT_SECURITY],
+        "max-age=31536000"
+    );
+}
+
+#[tokio::test]
+async fn records_requests_in_the_access_log() {
+    let app = TestApp::start().await;
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('ACCESS_LOG', 'database')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = app.get("/missing?token=secret").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    app.state.flush().await;
+
+    let (method, path, status): (String, String, i16) =
+        sqlx::query_as("SELECT method, path, status FROM access_log")
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!(
+        (method.as_str(), path.as_str(), status),
+        ("GET", "/missing", 404)
+    );
+}
+
+#[tokio::test]
+async fn updates_the_target() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/old").await;
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "targetUrl": "https://example.com/new", "redirectType": 301 }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/new"
+    );
+}
+
+#[tokio::test]
+async fn rolls_a_new_target_out_to_part_of_the_visitors() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/old").await;
+    let rollout = json!({
+        "targetUrl": "https://example.com/new",
+        "percent": 50,
+        "durationSecs": 3600,
+    });
+
+    let response = app
+        .post_json(&format!("/{id}/rollout"), rollout.clone())
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app.post_json(&format!("/{id}/rollout"), rollout).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let mut targets = Vec::new();
+    for _ in 0..40 {
+        let response = follow(&app, &id, "https://referrer.example/").await;
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "private, no-store"
+        );
+        targets.push(
+            response.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+    }
+    let canaries = targets
+        .iter()
+        .filter(|target| *target == "https://example.com/new")
+        .count();
+    assert!(canaries > 0 && canaries < targets.len(), "{targets:?}");
+    app.state.flush().await;
+
+    let rollout = json_body(app.get(&format!("/{id}/rollout")).await).await;
+    assert_eq!(rollout["variants"][0]["variant"], "stable");
+    assert_eq!(rollout["variants"][0]["percent"], 50);
+    assert_eq!(rollout["variants"][0]["clicks"], 40 - canaries);
+    assert_eq!(
+        rollout["variants"][1]["targetUrl"],
+        "https://example.com/new"
+    );
+    assert_eq!(rollout["variants"][1]["clicks"], canaries);
+
+    let response = app.send(Method::DELETE, &format!("/{id}/rollout")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/old"
+    );
+    let response = app.get(&format!("/{id}/rollout")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn lists_links_with_their_notes_and_creator() {
+    let app = TestApp::start().await;
+    let own = create_link(&app, "https://example.com/own").await;
+    let response = app
+        .request(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/create")
+                .header("x-api", TEST_API_KEY)
+                .header("x-actor", "alice")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "targetUrl": "https://example.com/alice" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alices = json_body(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = app
+        .patch_json(
+            &format!("/{alices}"),
+            json!({ "notes": "Printed on the spring flyer, ask before changing" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let link = json_body(response).await;
+    assert_eq!(link["createdBy"], "alice");
+    assert_eq!(
+        link["notes"],
+        "Printed on the spring flyer, ask before changing"
+    );
+
+    let links = json_body(app.get("/api/links?createdBy=alice").await).await;
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    assert_eq!(links[0]["id"], alices.as_str());
+    let links = json_body(app.get("/api/links?q=FLYER").await).await;
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    let links = json_body(app.get("/api/links?createdBy=global-api-key").await).await;
+    assert_eq!(links[0]["id"], own.as_str());
+    assert_eq!(links[0]["notes"], json!(null));
+    let links = json_body(app.get("/api/links?limit=1").await).await;
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    let after = links[0]["id"].as_str().unwrap();
+    let links = json_body(app.get(&format!("/api/links?after={after}")).await).await;
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    assert_ne!(links[0]["id"], after);
+
+    let response = app
+        .patch_json(&format!("/{alices}"), json!({ "notes": null }))
+        .await;
+    assert_eq!(json_body(response).await["notes"], json!(null));
+    let response = app
+        .patch_json(&format!("/{own}"), json!({ "notes": "x".repeat(2001) }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app.get("/api/links?limit=0").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn applies_link_manifests_idempotently() {
+    let app = TestApp::start().await;
+    let stray = create_link(&app, "https://example.com/stray").await;
+    let apply = |uri: &str, manifest: String| {
+        app.request(
+            Request::builder()
+                .method(Method::PUT)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header(header::CONTENT_TYPE, "application/yaml")
+                .body(Body::from(manifest))
+                .unwrap(),
+        )
+    };
+    let manifest = r#"
+# Evergreen links
+links:
+  docs-home:
+    targetUrl: https://example.com/docs  # moved in 2024
+    tags: [docs, "evergreen"]
+  pricing-page:
+    targetUrl: 'https://example.com/pricing'
+    redirectType: 301
+"#;
+
+    let report = json_body(apply("/api/manifest?dryRun=true", manifest.into()).await).await;
+    assert_eq!(report["created"], json!(["docs-home", "pricing-page"]));
+    assert_eq!(report["deleted"], json!([stray]));
+    let response = app.get("/api/links/docs-home").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let report = json_body(apply("/api/manifest", manifest.into()).await).await;
+    assert_eq!(report["created"], json!(["docs-home", "pricing-page"]));
+    assert_eq!(report["deleted"], json!([stray]));
+    let link = json_body(app.get("/api/links/docs-home").await).await;
+    assert_eq!(link["tags"], json!(["docs", "evergreen"]));
+    let response = app.get(&format!("/api/links/{stray}")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let exported = app.get("/api/manifest").await;
+    let bytes = to_bytes(exported.into_body(), usize::MAX).await.unwrap();
+    let exported = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(exported.contains("redirectType: 301"), "{exported}");
+    let report = json_body(apply("/api/manifest", exported).await).await;
+    assert_eq!(report["unchanged"], json!(["docs-home", "pricing-page"]));
+    assert_eq!(report["created"], json!([]));
+    assert_eq!(report["updated"], json!([]));
+    assert_eq!(report["deleted"], json!([]));
+
+    let response = apply("/api/manifest", "links:\n  docs-home: {}\n".into()).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let errors = json_body(response).await;
+    assert_eq!(errors["errors"][0]["field"], "links.docs-home.targetUrl");
+}
+
+#[tokio::test]
+async fn pauses_and_resumes_links() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    follow(&app, &id, "https://referrer.example/").await;
+
+    let response = app.send(Method::POST, &format!("/{id}/pause")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["active"], false);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    sqlx::query(
+        "INSERT INTO runtime_settings (key, value) VALUES ('PAUSED_LINK_URL', 'https://example.com/paused')",
+    )
+    .execute(app.pool())
+    .await
+    .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/paused"
+    );
+
+    let response = app.send(Method::POST, &format!("/{id}/resume")).await;
+    assert_eq!(json_body(response).await["active"], true);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page"
+    );
+    let response = app.send(Method::POST, "/missing/pause").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sends_custom_headers_with_redirects() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "responseHeaders": { "Set-Cookie": "session=1" } }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "responseHeaders": { "X-Robots-Tag": " noindex " } }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response).await["responseHeaders"],
+        json!({ "x-robots-tag": "noindex" })
+    );
+
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.headers()["x-robots-tag"], "noindex");
+}
+
+#[tokio::test]
+async fn replaces_the_utm_parameters_of_the_destination_with_the_link_template() {
+    let app = TestApp::start().await;
+    let id = create_link(
+        &app,
+        "https://example.com/page?utm_source=partner&ref=7&UTM_Medium=cpc",
+    )
+    .await;
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "utmTemplate": { "utm_tracker": "x" } }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "utmTemplate": { "UTM_Source": " newsletter ", "utm_medium": "email" } }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response).await["utmTemplate"],
+        json!({ "utm_medium": "email", "utm_source": "newsletter" })
+    );
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page?ref=7&utm_medium=email&utm_source=newsletter"
+    );
+
+    let response = app
+        .patch_json(&format!("/{id}"), json!({ "utmTemplate": {} }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page?ref=7"
+    );
+
+    let response = app
+        .patch_json(&format!("/{id}"), json!({ "utmTemplate": null }))
+        .await;
+    assert_eq!(json_body(response).await["utmTemplate"], json!(null));
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page?utm_source=partner&ref=7&UTM_Medium=cpc"
+    );
+}
+
+#[tokio::test]
+async fn caches_redirects_for_as_long_as_the_link_tier_allows() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert!(response.headers()[header::CACHE_CONTROL]
+        .to_str()
+        .unwrap()
+        .starts_with("public, max-age=300"));
+
+    let response = app
+        .patch_json(&format!("/{id}"), json!({ "cacheTier": "forever" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .patch_json(&format!("/{id}"), json!({ "cacheTier": "immutable" }))
+        .await;
+    assert_eq!(json_body(response).await["cacheTier"], "immutable");
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "public, max-age=31536000, immutable"
+    );
+
+    sqlx::query(
+        "INSERT INTO runtime_settings (key, value) VALUES ('CACHE_TIERS', 'immutable=public, max-age=86400')",
+    )
+    .execute(app.pool())
+    .await
+    .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "public, max-age=86400"
+    );
+}
+
+#[tokio::test]
+async fn stops_serving_cached_links_once_changed() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/old").await;
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/old"
+    );
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "targetUrl": "https://example.com/new" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/new"
+    );
+
+    let response = app.send(Method::DELETE, &format!("/{id}")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn counts_clicks_in_the_statistics() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..3 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    app.state.flush().await;
+
+    let response = app.get(&format!("/{id}/statistics")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let statistics = json_body(response).await;
+    assert_eq!(
+        statistics,
+        json!([{
+            "amount": 3,
+            "prefetches": 0,
+            "referer": "https://referrer.example/",
+            "userAgent": "integration-test",
+        }])
+    );
+
+    let yesterday = (chrono::Utc::now() - chrono::Duration::days(1)).format("%Y-%m-%dT%H:%M:%SZ");
+    let statistics = json_body(app.get(&format!("/{id}/statistics?from={yesterday}")).await).await;
+    assert_eq!(statistics[0]["amount"], 3);
+    let statistics = json_body(
+        app.get(&format!(
+            "/{id}/statistics?from=2020-01-01T00:00:00Z&to=2020-02-01T00:00:00Z"
+        ))
+        .await,
+    )
+    .await;
+    assert_eq!(statistics, json!([]));
+    for query in [
+        "from=2020-02-01T00:00:00Z&to=2020-01-01T00:00:00Z",
+        "from=2020-01-01T00:00:00Z&to=2022-01-01T00:00:00Z",
+        "from=2020-01-01T00:00:00Z",
+        "from=yesterday",
+    ] {
+        let response = app.get(&format!("/{id}/statistics?{query}")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn buckets_clicks_by_hour_and_weekday() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..2 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    app.state.flush().await;
+
+    let response = app.get(&format!("/{id}/statistics/hours")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let now = chrono::Utc::now();
+    assert_eq!(
+        json_body(response).await,
+        json!([{
+            "dayOfWeek": now.weekday().number_from_monday(),
+            "hour": now.hour(),
+            "clicks": 2,
+        }])
+    );
+}
+
+#[tokio::test]
+async fn leaves_visitor_addresses_alone_for_opted_out_workspaces() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let settings = json_body(app.get("/api/workspace/settings").await).await;
+    assert_eq!(settings["geoEnrichment"], true);
+    follow(&app, &id, "https://referrer.example/").await;
+
+    let response = app
+        .send_json(
+            Method::PUT,
+            "/api/workspace/settings",
+            json!({ "geoEnrichment": false }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let settings = json_body(app.get("/api/workspace/settings").await).await;
+    assert_eq!(settings["geoEnrichment"], false);
+    follow(&app, &id, "https://referrer.example/").await;
+    app.state.flush().await;
+
+    let hashed: Vec<bool> = sqlx::query_scalar(
+        "SELECT visitor_hash IS NOT NULL FROM link_statistics WHERE link_id = $1 ORDER BY created_at",
+    )
+    .bind(&id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(hashed, [true, false]);
+}
+
+/// Accepts one email the way an SMTP relay would and returns what was sent after `DATA`.
+async fn receive_email(listener: TcpListener) -> String {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = BufReader::new(stream);
+    stream.get_mut().write_all(b"220 relay\r\n").await.unwrap();
+    let mut message = String::new();
+    let mut in_data = false;
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap() == 0 {
+            return message;
+        }
+        let reply: &[u8] = if in_data {
+            if line != ".\r\n" {
+                message.push_str(&line);
+                continue;
+            }
+            in_data = false;
+            b"250 queued\r\n"
+        } else if line.starts_with("EHLO") {
+            b"250-relay\r\n250 8BITMIME\r\n"
+        } else if line.starts_with("RCPT") {
+            b"251 will forward\r\n"
+        } else if line.starts_with("DATA") {
+            in_data = true;
+            b"354 go ahead\r\n"
+        } else if line.starts_with("QUIT") {
+            stream.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+            return message;
+        } else {
+            b"250 ok\r\n"
+        };
+        stream.get_mut().write_all(reply).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn emails_weekly_reports_to_workspaces_that_opted_in() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let today = chrono::Utc::now().date_naive();
+    let this_week = today - Days::new(today.weekday().num_days_from_monday().into());
+    let week = this_week - Days::new(7);
+    sqlx::query(
+        "INSERT INTO link_daily_clicks (link_id, day, clicks, unique_visitors) VALUES ($1, $2, 6, 3), ($1, $3, 4, 2)",
+    )
+    .bind(&id)
+    .bind(week)
+    .bind(week - Days::new(7))
+    .execute(app.pool())
+    .await
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    for (key, value) in [
+        ("SMTP_HOST", "127.0.0.1"),
+        ("SMTP_PORT", port.as_str()),
+        ("SMTP_TLS", "none"),
+        ("EMAIL_FROM", "reports@example.com"),
+    ] {
+        sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ($1, $2)")
+            .bind(key)
+            .bind(value)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let response = app
+        .send_json(
+            Method::PUT,
+            "/api/workspace/settings",
+            json!({ "weeklyReport": true }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .send_json(
+            Method::PUT,
+            "/api/workspace/settings",
+            json!({ "weeklyReport": true, "reportRecipients": ["owner@example.com"] }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let email = tokio::spawn(receive_email(listener));
+    let response = app.send(Method::POST, "/admin/jobs/weekly-reports").await;
+    let job = finish_job(&app, response).await;
+    assert_eq!(job["result"], json!({ "sent": 1 }));
+    let email = email.await.unwrap();
+    assert!(email.contains("To: owner@example.com\r\n"));
+    assert!(email.contains("Subject: Weekly report for default: 6 clicks\r\n"));
+    let text = email
+        .split("Content-Transfer-Encoding: base64\r\n\r\n")
+        .nth(1)
+        .and_then(|part| part.split("\r\n--").next())
+        .unwrap()
+        .replace("\r\n", "");
+    let text = String::from_utf8(STANDARD.decode(text).unwrap()).unwrap();
+    assert!(text.contains("Clicks: 6 (+2, +50% from the week before)"));
+    assert!(text.contains(&format!("- {id} -> https://example.com/page: 6 clicks")));
+
+    // Each week is reported once.
+    let response = app.send(Method::POST, "/admin/jobs/weekly-reports").await;
+    let job = finish_job(&app, response).await;
+    assert_eq!(job["result"], json!({ "sent": 0 }));
+}
+
+#[tokio::test]
+async fn exports_clicks_of_a_day_to_the_bucket() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..2 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    app.state.flush().await;
+
+    let uploads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = uploads.clone();
+    let bucket = axum::Router::new().route(
+        "/*key",
+        axum::routing::put(
+            move |uri: axum::http::Uri, headers: axum::http::HeaderMap, body: String| {
+                let recorded = recorded.clone();
+                async move {
+                    let upload = (uri.path().to_string(), headers, body);
+                    recorded.lock().unwrap().push(upload);
+                    StatusCode::OK
+                }
+            },
+        ),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, bucket).await });
+    for (key, value) in [
+        ("S3_ENDPOINT", endpoint.as_str()),
+        ("S3_BUCKET", "exports"),
+        ("S3_PATH_STYLE", "true"),
+        ("S3_ACCESS_KEY_ID", "AKIDEXAMPLE"),
+        ("S3_SECRET_ACCESS_KEY", "secret"),
+        ("STATISTICS_EXPORT_PREFIX", "analytics/"),
+    ] {
+        sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ($1, $2)")
+            .bind(key)
+            .bind(value)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let today = chrono::Utc::now().date_naive().to_string();
+    let response = app
+        .post_json(
+            "/admin/jobs/statistics-export",
+            json!({ "from": today, "to": today }),
+        )
+        .await;
+    let job = finish_job(&app, response).await;
+    assert_eq!(job["result"], json!({ "clicks": 2 }));
+
+    let uploads = uploads.lock().unwrap();
+    let (path, headers, body) = &uploads[0];
+    assert_eq!(
+        path,
+        &format!("/exports/analytics/clicks/day%3D{today}/clicks.csv")
+    );
+    assert!(headers[header::AUTHORIZATION]
+        .to_str()
+        .unwrap()
+        .starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+    let lines: Vec<&str> = body.lines().collect();
+    assert!(lines[0].starts_with("id,link_id,workspace_id,created_at,referer"));
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains(&format!(",{id},default,")));
+}
+
+#[tokio::test]
+async fn attributes_clicks_to_their_channel() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..2 {
+        let response = follow(&app, &format!("{id}?src=QR"), "https://referrer.example/").await;
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/page"
+        );
+    }
+    follow(&app, &id, "https://referrer.example/").await;
+    follow(
+        &app,
+        &format!("{id}?src=not%20a%20channel"),
+        "https://referrer.example/",
+    )
+    .await;
+    app.state.flush().await;
+
+    let breakdown = json_body(app.get(&format!("/{id}/statistics/breakdown")).await).await;
+    assert_eq!(
+        breakdown["channels"],
+        json!([
+            { "value": "direct", "clicks": 2 },
+            { "value": "qr", "clicks": 2 },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn records_conversions_of_tagged_clicks() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page?ref=1").await;
+    sqlx::query(
+        "INSERT INTO runtime_settings (key, value) VALUES ('CONVERSION_KEY', 'conversions')",
+    )
+    .execute(app.pool())
+    .await
+    .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "private, no-store"
+    );
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    let token = location
+        .strip_prefix("https://example.com/page?ref=1&click_token=")
+        .expect("The target should carry a click token")
+        .to_string();
+    follow(&app, &id, "https://referrer.example/").await;
+    app.state.flush().await;
+
+    for _ in 0..2 {
+        let response = app
+            .post_json("/api/conversions", json!({ "token": token }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let response = app
+        .post_json("/api/conversions", json!({ "token": format!("{token}x") }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let statistics = json_body(app.get(&format!("/{id}/statistics/conversions")).await).await;
+    assert_eq!(
+        statistics,
+        json!({ "clicks": 2, "conversions": 1, "conversionRate": 0.5 })
+    );
+}
+
+#[tokio::test]
+async fn appends_a_click_id_that_finds_the_recorded_click() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page?lsid=stale&ref=1").await;
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page?lsid=stale&ref=1"
+    );
+
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('CLICK_ID_PARAM', 'lsid')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "private, no-store"
+    );
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    let click_id = location
+        .strip_prefix("https://example.com/page?ref=1&lsid=")
+        .expect("The target should carry a click ID")
+        .to_string();
+    app.state.flush().await;
+
+    let click = json_body(app.get(&format!("/api/clicks/{click_id}")).await).await;
+    assert_eq!(click["linkId"], id.as_str());
+    assert_eq!(click["referer"], "https://referrer.example/");
+    let response = app.get("/api/clicks/unknown").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn serves_unfurl_bots_a_preview_instead_of_a_redirect() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page?a=1&b=2").await;
+    app.patch_json(&format!("/{id}"), json!({ "title": "Launch <day>" }))
+        .await;
+    let unfurl = || {
+        app.request(
+            Request::builder()
+                .uri(format!("/{id}"))
+                .header(header::USER_AGENT, "Slackbot-LinkExpanding 1.0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+    let response = unfurl().await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('UNFURL_PREVIEWS', 'true')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let response = unfurl().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "private, no-store"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let page = String::from_utf8(body.to_vec()).unwrap();
+    assert!(page.contains(r#"<meta property="og:title" content="Launch &lt;day&gt;">"#));
+    assert!(page.contains(r#"<meta name="twitter:card" content="summary">"#));
+    assert!(page.contains(r#"content="0; url=https://example.com/page?a=1&amp;b=2""#));
+
+    let response = follow(&app, &id, "https://referrer.example/").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    app.state.flush().await;
+    let daily = json_body(app.get(&format!("/{id}/statistics/daily")).await).await;
+    assert_eq!(daily[0]["clicks"], 2);
+}
+
+#[tokio::test]
+async fn flags_scanner_and_prefetch_hits() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let visit = |method: Method, headers: &[(&'static str, &'static str)]| {
+        let mut request = Request::builder().method(method).uri(format!("/{id}"));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.request(request.body(Body::empty()).unwrap())
+    };
+    let browser = ("user-agent", "integration-test");
+    visit(Method::GET, &[browser, ("sec-purpose", "prefetch")]).await;
+    visit(Method::GET, &[("user-agent", "Barracuda Sentinel")]).await;
+    visit(Method::HEAD, &[browser]).await;
+    let response = visit(Method::GET, &[browser]).await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/page"
+    );
+    visit(Method::GET, &[browser]).await;
+    app.state.flush().await;
+
+    let daily = json_body(app.get(&format!("/{id}/statistics/daily")).await).await;
+    assert_eq!(daily[0]["clicks"], 1);
+    assert_eq!(daily[0]["prefetches"], 4);
+    let link = json_body(app.get(&format!("/api/links/{id}")).await).await;
+    assert_eq!(link["totalClicks"], 1);
+}
+
+#[tokio::test]
+async fn writes_large_click_batches_with_copy() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    let deleted = create_link(&app, "https://example.com/deleted").await;
+    for _ in 0..3 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    follow(&app, &deleted, "https://referrer.example/").await;
+    assert_eq!(
+        app.send(Method::DELETE, &format!("/{deleted}"))
+            .await
+            .status(),
+        StatusCode::NO_CONTENT
+    );
+
+    let mut click_writer = app.state.config.current().click_writer.clone();
+    click_writer.copy_threshold = 1;
+    let written = app
+        .state
+        .click_writer
+        .flush(app.pool(), &click_writer)
+        .await
+        .expect("Clicks should be written");
+    assert_eq!(written, 3);
+
+    let statistics = json_body(app.get(&format!("/{id}/statistics")).await).await;
+    assert_eq!(statistics[0]["amount"], 3);
+}
+
+#[tokio::test]
+async fn streams_the_export_within_its_time_budget() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+
+    let response = app.get("/admin/export").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let export = String::from_utf8(body.to_vec()).unwrap();
+    assert!(export.lines().any(|line| line.contains(&id)));
+}
+
+#[tokio::test]
+async fn runs_rollup_jobs_to_completion() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..2 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    app.state.flush().await;
+
+    let job = roll_up_today(&app).await;
+    assert_eq!(job["status"], "succeeded");
+    assert_eq!(job["result"], json!({ "linkDays": 1 }));
+    let clicks: i64 = sqlx::query_scalar("SELECT clicks FROM link_daily_clicks WHERE link_id = $1")
+        .bind(&id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(clicks, 2);
+}
+
+#[tokio::test]
+async fn reads_statistics_past_the_raw_window_from_rollups() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/page").await;
+    for _ in 0..2 {
+        follow(&app, &id, "https://referrer.example/").await;
+    }
+    app.state.flush().await;
+    assert_eq!(roll_up_today(&app).await["status"], "succeeded");
+    // Pretend the clicks are a week old and pruned, leaving only their rollups.
+    for statement in [
+        "UPDATE link_daily_clicks SET day = day - 7 WHERE link_id = $1",
+        "UPDATE link_daily_sources SET day = day - 7 WHERE link_id = $1",
+        "UPDATE link_daily_breakdown SET day = day - 7 WHERE link_id = $1",
+        "UPDATE link_hourly_clicks SET hour = hour - INTERVAL '7 days' WHERE link_id = $1",
+        "DELETE FROM link_statistics WHERE link_id = $1",
+    ] {
+        sqlx::query(statement)
+            .bind(&id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    follow(&app, &id, "https://referrer.example/").await;
+    app.state.flush().await;
+
+    let statistics = json_body(app.get(&format!("/{id}/statistics")).await).await;
+    assert_eq!(statistics[0]["amount"], 3);
+    assert_eq!(statistics[0]["userAgent"], "integration-test");
+    let daily = json_body(app.get(&format!("/{id}/statistics/daily")).await).await;
+    assert_eq!(daily.as_array().unwrap().len(), 2);
+    assert_eq!(daily[0]["clicks"], 1);
+    assert_eq!(daily[1]["clicks"], 2);
+    let hours = json_body(app.get(&format!("/{id}/statistics/hours")).await).await;
+    let clicks: i64 = hours
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|bucket| bucket["clicks"].as_i64().unwrap())
+        .sum();
+    assert_eq!(clicks, 3);
+    let breakdown = json_body(app.get(&format!("/{id}/statistics/breakdown")).await).await;
+    assert_eq!(
+        breakdown["channels"],
+        json!([{ "value": "direct", "clicks": 3 }])
+    );
+}
+
+#[tokio::test]
+async fn sums_up_the_workspace_on_the_dashboard() {
+    let app = TestApp::start().await;
+    let popular = create_link(&app, "https://example.com/popular").await;
+    let quiet = create_link(&app, "https://example.com/quiet").await;
+    for _ in 0..3 {
+        follow(&app, &popular, "https://news.example/story?id=1").await;
+    }
+    follow(&app, &quiet, "https://blog.example/").await;
+    app.state.flush().await;
+    assert_eq!(roll_up_today(&app).await["status"], "succeeded");
+
+    let response = app.get("/api/dashboard").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let dashboard = json_body(response).await;
+    assert_eq!(dashboard["linksCreated"], 2);
+    assert_eq!(dashboard["totalClicks"], 4);
+    assert_eq!(dashboard["topLinks"][0]["id"], popular.as_str());
+    assert_eq!(dashboard["topLinks"][0]["clicks"], 3);
+    assert_eq!(
+        dashboard["topReferers"],
+        json!([
+            { "host": "news.example", "clicks": 3 },
+            { "host": "blog.example", "clicks": 1 },
+        ])
+    );
+    let daily_clicks = dashboard["dailyClicks"].as_array().unwrap();
+    assert_eq!(daily_clicks.len(), 30);
+    assert_eq!(daily_clicks[29]["clicks"], 4);
+    assert_eq!(daily_clicks[0]["clicks"], 0);
+}
+
+#[tokio::test]
+async fn preflight_reports_missing_settings() {
+    let (_container, pool) = start_postgres().await;
+    let Err(PreflightError::Problems(problems)) = preflight(&pool).await else {
+        panic!("Preflight passed without a settings row");
+    };
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("DEFAULT_SETTINGS"));
+}
+
+struct Counting(AtomicU64);
+
+impl IdGenerator for Counting {
+    fn generate(&self) -> String {
+        format!("test-{}", self.0.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[tokio::test]
+async fn uses_the_injected_id_generator() {
+    let app =
+        TestApp::start_with(|state| state.with_id_generator(Arc::new(Counting(AtomicU64::new(1)))))
+            .await;
+    assert_eq!(create_link(&app, "https://example.com/a").await, "test-1");
+    assert_eq!(create_link(&app, "https://example.com/b").await, "test-2");
+}
+
+#[derive(Default)]
+struct Recording(Mutex<Vec<ErrorReport>>);
+
+#[async_trait]
+impl ErrorReporter for Recording {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        self.0.lock().unwrap().push(report.clone());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn reports_server_errors_with_their_request() {
+    let recording = Arc::new(Recording::default());
+    let app = TestApp::start_with(|state| state.with_error_reporter(recording.clone())).await;
+    let id = create_link(&app, "https://example.com/reported").await;
+    assert!(recording.0.lock().unwrap().is_empty());
+
+    sqlx::query("ALTER TABLE link_history RENAME TO link_history_moved")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    let response = app.get(&format!("/{id}/history")).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let reports = recording.0.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].status, Some(500));
+    let request = reports[0].request.as_ref().unwrap();
+    assert_eq!(request.route, "/:id/history");
+    assert_eq!(request.request_id, request_id);
+    assert_eq!(request.link_id.as_deref(), Some(id.as_str()));
+}
+
+struct Panicking;
+
+impl IdGenerator for Panicking {
+    fn generate(&self) -> String {
+        panic!("No ids left")
+    }
+}
+
+#[tokio::test]
+async fn answers_a_panicking_handler_with_a_server_error() {
+    let recording = Arc::new(Recording::default());
+    let app = TestApp::start_with(|state| {
+        state
+            .with_id_generator(Arc::new(Panicking))
+            .with_error_reporter(recording.clone())
+    })
+    .await;
+    let response = app
+        .post_json(
+            "/create",
+            json!({ "targetUrl": "https://example.com/panic" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.headers().contains_key("x-request-id"));
+    assert_eq!(app.get("/health").await.status(), StatusCode::OK);
+
+    // The panic hook reports the panic itself, not the response to it.
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(recording.0.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn tells_whether_an_alias_is_available() {
+    let app = TestApp::start().await;
+    let availability = |slug: &'static str| {
+        let app = &app;
+        async move {
+            let response = app.get(&format!("/api/aliases/{slug}/availability")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            json_body(response).await
+        }
+    };
+    assert_eq!(
+        availability("launch").await,
+        json!({ "slug": "launch", "status": "available" })
+    );
+
+    let response = app
+        .send_json(
+            Method::PUT,
+            "/api/links/launch",
+            json!({ "targetUrl": "https://example.com/launch" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let taken = availability("launch").await;
+    assert_eq!(taken["status"], "taken");
+    assert_eq!(taken["reason"], "Slug Taken");
+
+    assert_eq!(availability("adm1n").await["status"], "reserved");
+    assert_eq!(availability("not.valid").await["status"], "invalid");
+}
+
+#[tokio::test]
+async fn replacing_a_link_clears_fields_left_out() {
+    let app = TestApp::start().await;
+    let put = |body: serde_json::Value| app.send_json(Method::PUT, "/api/links/launch", body);
+    let response =
+        put(json!({ "targetUrl": "https://example.com/launch", "title": "Launch" })).await;
+    assert_eq!(json_body(response).await["title"], "Launch");
+
+    let response = put(json!({ "targetUrl": "https://example.com/launch" })).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["title"], serde_json::Value::Null);
+
+    app.patch_json("/launch", json!({ "title": "Launch" }))
+        .await;
+    let response = app.patch_json("/launch", json!({ "title": null })).await;
+    assert_eq!(json_body(response).await["title"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn refuses_blocked_targets_with_a_code_and_an_appeal_reference() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/fine").await;
+    sqlx::query("INSERT INTO blocked_domains (domain) VALUES ('blocked.example')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let response = app
+        .post_json(
+            "/create",
+            json!({ "targetUrl": "https://www.blocked.example/x" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let error = &json_body(response).await["errors"][0];
+    assert_eq!(error["field"], "targetUrl");
+    assert_eq!(error["reason"], "Target Domain Blocked");
+    assert_eq!(error["code"], "BLOCKED_DOMAIN");
+    let reference = error["appealReference"].as_str().unwrap().to_string();
+
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "refererFallbackUrl": "https://blocked.example/" }),
+        )
+        .await;
+    let error = &json_body(response).await["errors"][0];
+    assert_eq!(error["field"], "refererFallbackUrl");
+    assert_eq!(error["code"], "BLOCKED_DOMAIN");
+    assert!(error["appealReference"].is_string());
+
+    let audit_log = json_body(app.get("/admin/audit-log").await).await;
+    let refused = audit_log
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["details"]["appealReference"] == reference.as_str())
+        .expect("The refused target should be in the audit log");
+    assert_eq!(refused["action"], "link.target_refused");
+    assert_eq!(refused["details"]["url"], "https://www.blocked.example/x");
+    assert_eq!(refused["details"]["code"], "BLOCKED_DOMAIN");
+}
+
+#[tokio::test]
+async fn read_only_instances_refuse_writes_and_keep_redirecting() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/read-only").await;
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('READ_ONLY', 'true')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://example.com/new" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()[header::ALLOW], "GET, HEAD");
+    let response = app.send(Method::DELETE, &format!("/{id}")).await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let response = app
+        .get("/api/shorten?longUrl=https://example.com/get")
+        .await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let response = follow(&app, &id, "https://referer.example").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        app.get(&format!("/api/links/{id}")).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        app.send(Method::POST, "/admin/reload").await.status(),
+        StatusCode::NO_CONTENT
+    );
+
+    // Nothing about the click was kept to be written later.
+    sqlx::query("UPDATE runtime_settings SET value = 'false' WHERE key = 'READ_ONLY'")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    app.state.flush().await;
+    let clicks: i64 = sqlx::query_scalar("SELECT count(*) FROM link_statistics")
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(clicks, 0);
+}
+
+#[tokio::test]
+async fn serves_cached_links_only_during_maintenance() {
+    let app = TestApp::start().await;
+    let cached = create_link(&app, "https://example.com/cached").await;
+    let uncached = create_link(&app, "https://example.com/uncached").await;
+    follow(&app, &cached, "https://referer.example").await;
+    let maintenance = json!({ "enabled": true, "retryAfterSecs": 30, "cacheOnly": true });
+    let response = app
+        .send_json(Method::PUT, "/admin/maintenance", maintenance)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://example.com/new" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    let response = app
+        .post_json("/api/resolve", json!({ "ids": [cached] }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = follow(&app, &cached, "https://referer.example").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    let response = follow(&app, &uncached, "https://referer.example").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+}
+
+#[tokio::test]
+async fn keeps_the_slugs_of_regions_apart() {
+    let app = TestApp::start_with(|state| {
+        state.with_id_generator(Arc::new(Prefixed {
+            prefix: "eu-".to_string(),
+            inner: Arc::new(Counting(AtomicU64::new(1))),
+        }))
+    })
+    .await;
+    sqlx::query(
+        "INSERT INTO runtime_settings (key, value) VALUES ('ID_PREFIX', 'eu-'), ('ID_PEER_PREFIXES', 'us-,ap-')",
+    )
+    .execute(app.pool())
+    .await
+    .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    assert_eq!(
+        create_link(&app, "https://example.com/eu").await,
+        "eu-test-1"
+    );
+
+    let response = app
+        .send_json(
+            Method::PUT,
+            "/api/links/us-taken-here",
+            json!({ "targetUrl": "https://example.com/us" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Links of other regions may still be on their way.
+    let response = app.get("/api/links/us-test-1").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let response = app.send(Method::DELETE, "/ap-test-1").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let response = app.get("/api/links/eu-test-2").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn answers_failed_redirects_with_the_pages_of_their_domain() {
+    let app = TestApp::start().await;
+    let set_page = |uri: &str, html: &str| {
+        app.request(
+            Request::builder()
+                .method(Method::PUT)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header(header::CONTENT_TYPE, "text/html")
+                .body(Body::from(html.to_string()))
+                .unwrap(),
+        )
+    };
+    let visit = |host: &'static str| {
+        app.request(
+            Request::builder()
+                .uri("/missing")
+                .header(header::HOST, host)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+    let page = "<h1>Nothing here, but see brand.example</h1>";
+
+    let response = set_page("/admin/error-pages/go.brand.example/500", page).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = set_page("/admin/error-pages/Go.Brand.Example/404", page).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let pages = json_body(app.get("/admin/error-pages").await).await;
+    assert_eq!(pages[0]["domain"], "go.brand.example");
+    assert_eq!(pages[0]["status"], 404);
+
+    let response = visit("go.brand.example:8443").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/html; charset=utf-8"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, page);
+    let response = visit("other.example").await;
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, "Not Found");
+
+    let uri = "/admin/error-pages/go.brand.example/404";
+    let response = app.send(Method::DELETE, uri).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = visit("go.brand.example").await;
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, "Not Found");
+    let response = app.send(Method::DELETE, uri).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn keeps_slugs_of_a_namespace_to_the_workspace_that_reserved_it() {
+    let app = TestApp::start().await;
+    let put_link = |uri: &'static str, workspace: &'static str| {
+        app.request(
+            Request::builder()
+                .method(Method::PUT)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header("x-workspace", workspace)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "targetUrl": "https://example.com/launch" }).to_string(),
+                ))
+                .unwrap(),
+        )
+    };
+
+    let response = put_link("/api/links/eng%2Flaunch", "default").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .post_json("/api/namespaces", json!({ "prefix": "eng" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app
+        .post_json("/api/namespaces", json!({ "prefix": "eng" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app
+        .post_json("/api/namespaces", json!({ "prefix": "api" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app
+        .post_json("/api/namespaces", json!({ "prefix": "eng/web" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = put_link("/api/links/eng%2Flaunch", "default").await;
+    assert!(response.status().is_success());
+    let response = put_link("/api/links/eng%2Fplan", "marketing").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .request(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/eng%2Flaunch")
+                .header("x-api", TEST_API_KEY)
+                .header("x-workspace", "marketing")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = put_link("/api/links/eng%2Fqr", "default").await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = follow(&app, "eng/launch", "https://chat.example").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/launch"
+    );
+    let response = follow(&app, "eng/missing", "https://chat.example").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let namespaces = json_body(app.get("/api/namespaces").await).await;
+    assert_eq!(namespaces[0]["prefix"], "eng");
+    let response = app.send(Method::DELETE, "/api/namespaces/eng").await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app.send(Method::DELETE, "/eng%2Flaunch").await;
+    assert!(response.status().is_success());
+    let response = app.send(Method::DELETE, "/api/namespaces/eng").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn keeps_links_of_other_workspaces_out_of_reach() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/private").await;
+    let as_marketing = |method: Method, uri: String, body: serde_json::Value| {
+        app.request(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header("x-workspace", "marketing")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+
+    let response = as_marketing(Method::GET, format!("/api/links/{id}"), json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let update = json!({ "targetUrl": "https://example.com/hijacked" });
+    let response = as_marketing(Method::PATCH, format!("/{id}"), update).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    for statistics in [
+        "statistics",
+        "statistics/daily",
+        "statistics/hours",
+        "statistics/breakdown",
+    ] {
+        let response = as_marketing(Method::GET, format!("/{id}/{statistics}"), json!({})).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{statistics}");
+    }
+    let response = as_marketing(Method::DELETE, format!("/{id}/statistics"), json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let update = json!({ "targetUrl": "https://example.com/moved" });
+    app.patch_json(&format!("/{id}"), update).await;
+    let response = as_marketing(Method::GET, format!("/{id}/history"), json!({})).await;
+    assert_eq!(json_body(response).await, json!([]));
+    let response = as_marketing(Method::POST, format!("/{id}/rollback"), json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ('LINK_SIGNING_KEY', 'secret')")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    app.state.config.reload(app.pool()).await.unwrap();
+    let signed = json!({ "linkId": id, "expiresInSecs": 60 });
+    let response = as_marketing(Method::POST, "/signed-links".into(), signed).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = as_marketing(Method::DELETE, format!("/{id}"), json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let link = json_body(app.get(&format!("/api/links/{id}")).await).await;
+    assert_eq!(link["targetUrl"], "https://example.com/moved");
+}
+
+#[tokio::test]
+async fn keeps_campaigns_to_their_workspace() {
+    let app = TestApp::start().await;
+    let id = create_link(&app, "https://example.com/private").await;
+    let as_marketing = |method: Method, uri: &str, body: serde_json::Value| {
+        app.request(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-api", TEST_API_KEY)
+                .header("x-workspace", "marketing")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+
+    let campaign = json!({ "name": "Launch", "linkIds": [id] });
+    let response = as_marketing(Method::POST, "/campaigns", campaign.clone()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.post_json("/campaigns", campaign).await;
+    let campaign_id = json_body(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = as_marketing(Method::GET, "/campaigns", json!({})).await;
+    assert_eq!(json_body(response).await, json!([]));
+    let uri = format!("/campaigns/{campaign_id}");
+    let response = as_marketing(Method::GET, &uri, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = as_marketing(Method::PATCH, &uri, json!({ "name": "Ours" })).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = as_marketing(Method::DELETE, &format!("{uri}/links/{id}"), json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = as_marketing(Method::DELETE, &uri, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let campaign = json_body(app.get(&uri).await).await;
+    assert_eq!(campaign["name"], "Launch");
+    assert_eq!(campaign["linkIds"], json!([id]));
+}
+
+/// Fails the first purge, records the ones after.
+#[derive(Default)]
+struct FlakyPurger {
+    attempts: AtomicU64,
+    purged: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl CdnPurger for FlakyPurger {
+    async fn purge(&self, request: &PurgeRequest) -> Result<(), String> {
+        if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Err("CDN unavailable".to_string());
+        }
+        self.purged.lock().unwrap().extend(request.urls.clone());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn retries_deliveries_of_outbox_events_from_the_database() {
+    let purger = Arc::new(FlakyPurger::default());
+    let app = TestApp::start_with(|state| state.with_cdn_purger(purger.clone())).await;
+    for (key, value) in [
+        ("BASE_URL", "https://sho.rt"),
+        ("OUTBOX_POLL_INTERVAL_MS", "20"),
+        ("WEBHOOK_RETRY_BASE_DELAY_MS", "20"),
+    ] {
+        sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ($1, $2)")
+            .bind(key)
+            .bind(value)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    app.state.config.reload(app.pool()).await.unwrap();
+    let id = create_link(&app, "https://example.com/old").await;
+    let response = app
+        .patch_json(
+            &format!("/{id}"),
+            json!({ "targetUrl": "https://example.com/new" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    app.state.spawn_background_jobs();
+    let mut attempts = None;
+    for _ in 0..100 {
+        attempts = sqlx::query_scalar(
+            "SELECT attempts FROM outbox_deliveries WHERE kind = 'cdn_purge' AND finished_at IS NOT NULL",
+        )
+        .fetch_optional(app.pool())
+        .await
+        .unwrap();
+        if attempts.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(attempts, Some(2));
+    assert_eq!(
+        *purger.purged.lock().unwrap(),
+        vec![format!("https://sho.rt/{id}")]
+    );
+}
+
+#[tokio::test]
+async fn tells_clients_behind_a_trusted_proxy_apart() {
+    let app = TestApp::start().await;
+    for (key, value) in [
+        ("TRUSTED_PROXIES", "127.0.0.1"),
+        ("RATE_LIMIT_REQUESTS", "1"),
+    ] {
+        sqlx::query("INSERT INTO runtime_settings (key, value) VALUES ($1, $2)")
+            .bind(key)
+            .bind(value)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    app.state.config.reload(app.pool()).await.unwrap();
+    let forwarded_for = |client: &'static str| {
+        app.request(
+            Request::builder()
+                .uri("/health")
+                .header("x-forwarded-for", client)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(forwarded_for("203.0.113.1").await.status(), StatusCode::OK);
+    assert_eq!(forwarded_for("203.0.113.2").await.status(), StatusCode::OK);
+    assert_eq!(
+        forwarded_for("203.0.113.1").await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
@@ -0,0 +1,245 @@
+//! The status codes clients can rely on: 400 and 422 for invalid requests, 404 for unknown links
+//! and 409 only for genuine conflicts. Run with `cargo test --features test-util`.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+};
+use link_shortener::{
+    testing::{json_body, TestApp, TEST_API_KEY},
+    IdGenerator,
+};
+use serde_json::json;
+
+fn raw_create(body: &'static str) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri("/create")
+        .header("x-api", TEST_API_KEY)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn malformed_target_is_a_bad_request() {
+    let app = TestApp::start().await;
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://exa mple.com" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unparsable_body_is_a_bad_request() {
+    let app = TestApp::start().await;
+    let response = app.request(raw_create("{")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn missing_field_is_unprocessable() {
+    let app = TestApp::start().await;
+    let response = app.request(raw_create("{}")).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn invalid_field_is_a_bad_request() {
+    let app = TestApp::start().await;
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://example.com" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    let id = location.rsplit('/').next().unwrap().to_string();
+
+    let response = app
+        .patch_json(&format!("/{id}"), json!({ "redirectType": 303 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn invalid_fields_are_named() {
+    let app = TestApp::start().await;
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://exa mple.com" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        json_body(response).await,
+        json!({ "errors": [{ "field": "targetUrl", "reason": "Url Malformed" }] })
+    );
+
+    let response = app
+        .post_json(
+            "/create",
+            json!({ "targetUrl": "https://example.com", "targetURL": "https://example.com" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json_body(response).await["errors"][0]["field"], "targetURL");
+
+    let response = app
+        .patch_json("/missing", json!({ "sampleRate": 0, "tags": [""] }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let fields: Vec<_> = json_body(response).await["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].clone())
+        .collect();
+    assert_eq!(fields, [json!("tags"), json!("sampleRate")]);
+}
+
+#[tokio::test]
+async fn updating_an_unknown_link_is_not_found() {
+    let app = TestApp::start().await;
+    let response = app
+        .patch_json("/missing", json!({ "title": "Missing" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn statistics_of_an_unknown_link_are_not_found() {
+    let app = TestApp::start().await;
+    for uri in [
+        "/missing/statistics",
+        "/missing/statistics/daily",
+        "/missing/statistics/hours",
+        "/missing/statistics/conversions",
+        "/missing/statistics/breakdown",
+    ] {
+        let response = app.get(uri).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn slug_owned_by_another_workspace_is_a_conflict() {
+    let app = TestApp::start().await;
+    let put = |workspace: &'static str| {
+        Request::builder()
+            .method(Method::PUT)
+            .uri("/api/links/launch")
+            .header("x-api", TEST_API_KEY)
+            .header("x-workspace", workspace)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"targetUrl":"https://example.com"}"#))
+            .unwrap()
+    };
+    assert_eq!(
+        app.request(put("first")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        app.request(put("second")).await.status(),
+        StatusCode::CONFLICT
+    );
+}
+
+struct Constant;
+
+impl IdGenerator for Constant {
+    fn generate(&self) -> String {
+        "always-the-same".to_string()
+    }
+}
+
+#[tokio::test]
+async fn duplicate_id_is_a_conflict() {
+    let app = TestApp::start_with(|state| state.with_id_generator(Arc::new(Constant))).await;
+    let create = || app.post_json("/create", json!({ "targetUrl": "https://example.com" }));
+    assert_eq!(create().await.status(), StatusCode::CREATED);
+    let response = create().await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, "Slug Taken");
+}
+
+/// Hands out the same slug twice before a fresh one.
+struct Repeating(AtomicU64);
+
+impl IdGenerator for Repeating {
+    fn generate(&self) -> String {
+        match self.0.fetch_add(1, Ordering::Relaxed) {
+            0 | 1 => "repeated".to_string(),
+            _ => "fresh".to_string(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn taken_generated_id_is_replaced() {
+    let app = TestApp::start_with(|state| {
+        state.with_id_generator(Arc::new(Repeating(AtomicU64::new(0))))
+    })
+    .await;
+    let create = || app.post_json("/create", json!({ "targetUrl": "https://example.com" }));
+    assert_eq!(json_body(create().await).await["id"], "repeated");
+    let response = create().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(json_body(response).await["id"], "fresh");
+}
+
+#[tokio::test]
+async fn taken_id_of_a_clone_is_replaced() {
+    let app = TestApp::start_with(|state| {
+        state.with_id_generator(Arc::new(Repeating(AtomicU64::new(0))))
+    })
+    .await;
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://example.com" }))
+        .await;
+    assert_eq!(json_body(response).await["id"], "repeated");
+    let response = app.send(Method::POST, "/repeated/clone").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(json_body(response).await["id"], "fresh");
+
+    let response = app
+        .request(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/repeated/clone")
+                .header("x-api", TEST_API_KEY)
+                .header("x-workspace", "marketing")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn expanding_an_expired_link_is_gone() {
+    let app = TestApp::start().await;
+    let response = app
+        .post_json("/create", json!({ "targetUrl": "https://example.com" }))
+        .await;
+    let id = json_body(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        app.get(&format!("/api/expand/{id}")).await.status(),
+        StatusCode::OK
+    );
+    // Past its expiry, before the sweep gets to it.
+    sqlx::query("UPDATE links SET expires_at = now() - interval '1 minute' WHERE id = $1")
+        .bind(&id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(
+        app.get(&format!("/api/expand/{id}")).await.status(),
+        StatusCode::GONE
+    );
+}